@@ -0,0 +1,199 @@
+use crate::config::Config;
+use crate::err;
+use crate::forge;
+use crate::git::{GitStore, Head};
+
+pub const SUBCOMMAND: &str = "abandon";
+
+/// Prefix of the remote refs under which `--archive` keeps abandoned branches.
+const ABANDONED_PREFIX: &str = "refs/dx/abandoned/";
+
+const CLI_ARG_ARCHIVE: &str = "archive";
+const CLI_ARG_BRANCH: &str = "branch";
+const CLI_ARG_DROP_LOCAL: &str = "drop_local";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_TRUNK: &str = "trunk";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Retires a change: deletes its remote branch and closes its pull request")
+        .long_about(
+            "Retires a change: closes its pull request, if the remote is on a known code host, \
+             and deletes its remote target branch, or with `--archive` moves it to \
+             `refs/dx/abandoned/` on the remote. With `--drop-local`, the change's commit is \
+             also removed from the current stack by rebasing the commits after it onto its \
+             parent. A change that other dx branches are built on can't be abandoned.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_BRANCH)
+                .help("Target branch of the change (e.g., wchargin-foo)")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_ARCHIVE)
+                .help("Keep the branch on the remote under `refs/dx/abandoned/`")
+                .long("--archive"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DROP_LOCAL)
+                .help("Also drop the change's commit from the current stack")
+                .long("--drop-local"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help("Remote branch that the stack is based on (default: the remote's HEAD)")
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote that holds the branch")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(crate::push::push_option_arg())
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let branch = matches.value_of(CLI_ARG_BRANCH).unwrap();
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let remotes = crate::Remotes::new(&remote, None).per_branch(matches.is_present(CLI_ARG_REMOTE));
+    let cannot_abandon = |reason: String| err::Error::CannotAbandon {
+        branch: branch.to_string(),
+        reason,
+    };
+    let key = config
+        .branch_key_of(branch)
+        .ok_or_else(|| cannot_abandon("not a dx target branch".to_string()))?;
+    let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+        Some(trunk) => trunk.to_string(),
+        None => crate::default_trunk(git, &remote)?,
+    };
+    let trunk_tip = crate::trunk_oid(git, &remote, &trunk)?;
+
+    // Check everything before changing anything.
+    let local = if matches.is_present(CLI_ARG_DROP_LOCAL) {
+        if crate::autostash::is_dirty(git)? {
+            return Err(cannot_abandon(
+                "you have uncommitted changes; commit or stash them to drop the local commit"
+                    .to_string(),
+            ));
+        }
+        let mut commits = crate::stack::commits_with_key(git, config, &trunk_tip, key)?;
+        if commits.len() != 1 {
+            return Err(cannot_abandon(format!(
+                "{} commit(s) between {}/{} and HEAD have trailer \"{}: {}\"; expected one",
+                commits.len(),
+                remote,
+                trunk,
+                config.branch_trailer,
+                key
+            )));
+        }
+        commits.pop()
+    } else {
+        None
+    };
+    let target_remote = remotes.target_remote(git, branch)?;
+    let tip = remotes.target_oid(git, branch)?;
+    if tip.is_none() && local.is_none() {
+        return Err(cannot_abandon(format!(
+            "no such branch on {}",
+            target_remote
+        )));
+    }
+    let dependents: Vec<String> =
+        crate::drop::dependents(git, config, &target_remote, &trunk_tip, branch)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+    if !dependents.is_empty() {
+        return Err(cannot_abandon(format!(
+            "{} depend(s) on it; abandon them first",
+            dependents.join(", ")
+        )));
+    }
+
+    if let Some(tip) = tip {
+        close_pull_request(git, &remote, &target_remote, branch)?;
+        let archive = matches.is_present(CLI_ARG_ARCHIVE);
+        let mut args = vec!["--atomic".to_string()];
+        for option in crate::push::push_options(config, matches) {
+            args.push(format!("--push-option={}", option));
+        }
+        // Only delete the branch if it still points to the commit that we checked.
+        args.push(format!("--force-with-lease=refs/heads/{}:{}", branch, tip));
+        args.push(target_remote.clone());
+        args.push(format!(":refs/heads/{}", branch));
+        if archive {
+            // A change abandoned again under the same name replaces the earlier archive.
+            args.push(format!("+{}:{}{}", tip, ABANDONED_PREFIX, branch));
+        }
+        crate::push::push(git, &args)?.print();
+        git.invalidate_refs();
+        git.delete_ref(
+            &crate::push::pushed_ref(&target_remote, branch),
+            &format!("dx abandon: {}", branch),
+        )?;
+        if archive {
+            info!(
+                "archived {} on {} as {}{}",
+                branch, target_remote, ABANDONED_PREFIX, branch
+            );
+        } else {
+            info!("deleted {} from {}", branch, target_remote);
+        }
+    }
+
+    if let Some(commit) = local {
+        let head = match git.head()? {
+            Head::Branch(name) => name,
+            Head::Detached(_) => "HEAD".to_string(),
+        };
+        let parent = format!("{}~", commit.oid);
+        if !git.rebase_onto(&parent, &commit.oid, &head)? {
+            return Err(cannot_abandon(format!(
+                "dropping {} from the stack stopped on conflicts; to resolve them by hand, run \
+                 `git rebase --onto {} {}`",
+                commit.oid, parent, commit.oid
+            )));
+        }
+        info!("dropped {} from the stack", commit.oid);
+    }
+    Ok(())
+}
+
+/// Close the pull request for `branch`, if the upstream remote is on a known code host and one is
+/// recorded or open, and forget it.
+fn close_pull_request(
+    git: &GitStore,
+    upstream: &str,
+    target_remote: &str,
+    branch: &str,
+) -> err::Result<()> {
+    let forge = match forge::for_remote(git, upstream)? {
+        Some(forge) => forge,
+        None => return Ok(()),
+    };
+    let number = match crate::pr::recorded(git, branch)? {
+        Some(number) => Some(number),
+        None => {
+            let remotes = crate::Remotes::new(upstream, Some(target_remote));
+            let head_owner = forge::head_owner(git, forge.as_ref(), &remotes)?;
+            forge
+                .find_pull_request(&head_owner, branch)?
+                .map(|pull| pull.number)
+        }
+    };
+    if let Some(number) = number {
+        forge.close_pull_request(number)?;
+        info!("closed {}", forge.pull_request_url(number));
+        git.delete_ref(
+            &format!("{}{}", crate::pr::PULL_PREFIX, branch),
+            &format!("dx abandon: {}", branch),
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,380 @@
+//! `git dx absorb`: fold uncommitted changes into the changes of the stack that they belong to.
+//! Each hunk of the working tree's diff against `HEAD` goes to the change that last touched the
+//! lines around it, as `git blame` tells, by way of a `fixup!` commit; the fixups are then
+//! squashed in with `git rebase --autosquash`. Hunks that touch lines of more than one change,
+//! or of no change in the stack, are left in the working tree.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::process::Stdio;
+
+use crate::config::Config;
+use crate::err;
+use crate::git::{GitStore, Oid};
+use crate::log::CommandExt;
+
+pub const SUBCOMMAND: &str = "absorb";
+
+const CLI_ARG_DRY_RUN: &str = "dry_run";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_TRUNK: &str = "trunk";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Folds uncommitted changes into the stack commits that they touch")
+        .long_about(
+            "Folds uncommitted changes into the changes of the current stack. Each hunk of the \
+             working tree's diff against HEAD is attributed to the change that last touched its \
+             lines (or, for added lines, the lines around them), committed as a fixup of that \
+             change, and squashed into it with `git rebase --autosquash`. Hunks that touch \
+             lines of several changes, or of commits outside the stack, are left uncommitted.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DRY_RUN)
+                .help("Show where each hunk would go without committing anything")
+                .long("--dry-run")
+                .short("-n"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help("Remote branch that the stack is based on (default: the remote's HEAD)")
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote whose trunk the stack is based on")
+                .short("-r")
+                .takes_value(true),
+        )
+}
+
+/// A hunk of the working tree's diff against `HEAD`, without context lines.
+#[derive(Debug, Clone)]
+struct Hunk {
+    path: String,
+    /// First changed line of `HEAD`'s version of the file, or for a pure insertion, the line
+    /// after which the new lines go (0 for the start of the file).
+    old_start: u64,
+    old_len: u64,
+    new_len: u64,
+    /// The hunk's `-` and `+` lines, each with its newline.
+    body: String,
+}
+
+impl Hunk {
+    /// How many lines this hunk adds to the file, net.
+    fn delta(&self) -> i64 {
+        self.new_len as i64 - self.old_len as i64
+    }
+
+    /// Where the hunk is, like `src/main.rs:12`.
+    fn location(&self) -> String {
+        format!("{}:{}", self.path, self.old_start)
+    }
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+        Some(trunk) => trunk.to_string(),
+        None => crate::default_trunk(git, &remote)?,
+    };
+    let trunk_tip = crate::trunk_oid(git, &remote, &trunk)?;
+    let head = git.rev_parse_commit_ok("HEAD")?;
+    let mut stack: HashMap<Oid, String> = HashMap::new();
+    for oid in git.rev_list(&trunk_tip, &head)? {
+        let commit = git.commit(&oid)?.clone();
+        if crate::branch_key(config, &commit.oid, &commit.trailers)?.is_some() {
+            let subject = commit.message.lines().next().unwrap_or("").to_string();
+            stack.insert(oid, subject);
+        }
+    }
+
+    let hunks = worktree_hunks(git)?;
+    if hunks.is_empty() {
+        info!("no uncommitted changes to absorb");
+        return Ok(());
+    }
+    // Hunks for each change, keyed by the change's place in the stack so that fixups are made
+    // oldest first.
+    let mut targets: BTreeMap<usize, (Oid, Vec<Hunk>)> = BTreeMap::new();
+    let order: HashMap<Oid, usize> = git
+        .rev_list(&trunk_tip, &head)?
+        .into_iter()
+        .enumerate()
+        .map(|(i, oid)| (oid, i))
+        .collect();
+    let dry_run = matches.is_present(CLI_ARG_DRY_RUN);
+    let mut left = 0;
+    for hunk in hunks {
+        match attribute(git, &head, &hunk, &stack)? {
+            Ok(oid) => {
+                if dry_run {
+                    println!("{}\t{}", hunk.location(), oid);
+                } else {
+                    info!("{}: {} {}", hunk.location(), oid, stack[&oid]);
+                }
+                targets
+                    .entry(order[&oid])
+                    .or_insert_with(|| (oid, Vec::new()))
+                    .1
+                    .push(hunk);
+            }
+            Err(reason) => {
+                warn!("leaving {} uncommitted: {}", hunk.location(), reason);
+                left += 1;
+            }
+        }
+    }
+    if targets.is_empty() || dry_run {
+        return Ok(());
+    }
+
+    // Start the index from `HEAD`, so that each fixup holds just its own hunks.
+    run_git(git, &["reset", "--quiet"], None)?;
+    let mut applied: Vec<Hunk> = Vec::new();
+    for (oid, hunks) in targets.values() {
+        run_git(
+            git,
+            &["apply", "--cached", "--unidiff-zero", "-"],
+            Some(&patch(hunks, &applied)),
+        )?;
+        run_git(
+            git,
+            &[
+                "commit",
+                "--quiet",
+                "--no-verify",
+                &format!("--fixup={}", oid),
+            ],
+            None,
+        )?;
+        applied.extend(hunks.iter().cloned());
+        info!("absorbed {} hunk(s) into {}", hunks.len(), oid);
+    }
+
+    let base = git.merge_base(&trunk_tip, &head)?.unwrap_or(trunk_tip);
+    let out = git
+        .command()
+        .env("GIT_SEQUENCE_EDITOR", ":")
+        .args([
+            "rebase",
+            "--quiet",
+            "--interactive",
+            "--autosquash",
+            "--autostash",
+            base.as_str(),
+        ])
+        .logged_output()?;
+    git.invalidate_refs();
+    if !out.status.success() {
+        let _ = crate::cancel::shield(|| git.command().args(["rebase", "--abort"]).logged_output());
+        return Err(err::Error::Conflicts {
+            action: "absorb changes".to_string(),
+            reason: format!(
+                "squashing the fixup commits stopped on conflicts; they are still on HEAD, for \
+                 `git rebase -i --autosquash {}`",
+                base
+            ),
+        });
+    }
+    if left > 0 {
+        info!("left {} hunk(s) uncommitted", left);
+    }
+    Ok(())
+}
+
+/// Diff the working tree (and index) against `HEAD`, without context. Files that are added,
+/// deleted, binary, or changed in mode have no hunks to attribute, and are skipped.
+fn worktree_hunks(git: &GitStore) -> err::Result<Vec<Hunk>> {
+    let out = git
+        .command()
+        .args([
+            "diff",
+            "--no-color",
+            "--no-ext-diff",
+            "--no-renames",
+            "--unified=0",
+            "HEAD",
+            "--",
+        ])
+        .logged_output()?;
+    err::from_git(&out, || "failed to diff the working tree".to_string())?;
+    let diff = err::Error::require_utf8(out.stdout, "diff output")?;
+    let mut hunks = Vec::new();
+    let mut path: Option<String> = None;
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") {
+            path = None;
+        } else if let Some(rest) = line.strip_prefix("+++ b/") {
+            path = Some(rest.to_string());
+        } else if line.starts_with("new file mode")
+            || line.starts_with("deleted file mode")
+            || line.starts_with("old mode")
+            || line.starts_with("Binary files")
+        {
+            path = None;
+        } else if let Some(header) = line.strip_prefix("@@ -") {
+            let path = match path {
+                Some(ref path) => path.clone(),
+                None => continue,
+            };
+            let (old, new) = header
+                .split_once(" +")
+                .and_then(|(old, rest)| Some((old, rest.split_once(" @@")?.0)))
+                .ok_or_else(|| err::Error::GitContract(format!("bad hunk header: {:?}", line)))?;
+            let (old_start, old_len) = parse_range(old)?;
+            let (_, new_len) = parse_range(new)?;
+            hunks.push(Hunk {
+                path,
+                old_start,
+                old_len,
+                new_len,
+                body: String::new(),
+            });
+        } else if line.starts_with('-') || line.starts_with('+') || line.starts_with('\\') {
+            if let (Some(_), Some(hunk)) = (&path, hunks.last_mut()) {
+                hunk.body.push_str(line);
+                hunk.body.push('\n');
+            }
+        }
+    }
+    Ok(hunks)
+}
+
+/// Parse a hunk range like `12,3` or `12` (one line).
+fn parse_range(range: &str) -> err::Result<(u64, u64)> {
+    let bad = || err::Error::GitContract(format!("bad hunk range: {:?}", range));
+    let (start, len) = range.split_once(',').unwrap_or((range, "1"));
+    Ok((
+        start.parse().map_err(|_| bad())?,
+        len.parse().map_err(|_| bad())?,
+    ))
+}
+
+/// Find the change in `stack` that a hunk belongs to: the one that last touched the lines that it
+/// changes, or for a pure insertion, the lines around it. Gives the reason if there isn't exactly
+/// one.
+fn attribute(
+    git: &GitStore,
+    head: &Oid,
+    hunk: &Hunk,
+    stack: &HashMap<Oid, String>,
+) -> err::Result<Result<Oid, String>> {
+    let (first, last) = if hunk.old_len > 0 {
+        (hunk.old_start, hunk.old_start + hunk.old_len - 1)
+    } else {
+        (std::cmp::max(hunk.old_start, 1), hunk.old_start + 1)
+    };
+    let blamed = blame(git, head, &hunk.path, first, last)?;
+    let mut owners: Vec<&Oid> = Vec::new();
+    for oid in &blamed {
+        let in_stack = stack.contains_key(oid);
+        // Lines around an insertion may belong to anything; lines that a hunk changes must all
+        // belong to the stack.
+        if !in_stack && hunk.old_len > 0 {
+            return Ok(Err(format!(
+                "it changes lines from {}, outside the stack",
+                oid
+            )));
+        }
+        if in_stack && !owners.contains(&oid) {
+            owners.push(oid);
+        }
+    }
+    Ok(match owners.as_slice() {
+        [owner] => Ok((*owner).clone()),
+        [] => Err("no change in the stack touched the lines around it".to_string()),
+        _ => Err(format!("it touches lines of {} changes", owners.len())),
+    })
+}
+
+/// Find the commit that last touched each of lines `first` through `last` (counting from 1) of
+/// `path` as of `head`. Lines past the end of the file are ignored.
+fn blame(git: &GitStore, head: &Oid, path: &str, first: u64, last: u64) -> err::Result<Vec<Oid>> {
+    let out = git
+        .command()
+        .args(["blame", "--porcelain", head.as_str(), "--", path])
+        .logged_output()?;
+    err::from_git(&out, || format!("failed to blame {}", path))?;
+    let stdout = err::Error::require_utf8(out.stdout, "blame output")?;
+    let mut result = Vec::new();
+    // Each line's header is `<oid> <original line> <final line>[ <group size>]`.
+    for line in stdout.lines() {
+        let mut fields = line.split(' ');
+        let (oid, final_line) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(oid), Some(_), Some(final_line)) => (oid, final_line),
+            _ => continue,
+        };
+        let (oid, final_line) = match (Oid::parse(oid), final_line.parse::<u64>()) {
+            (Some(oid), Ok(final_line)) => (oid, final_line),
+            _ => continue,
+        };
+        if (first..=last).contains(&final_line) {
+            result.push(oid);
+        }
+    }
+    Ok(result)
+}
+
+/// Form a patch of `hunks` to apply to an index that already has the `applied` hunks, whose
+/// lines shift the hunks that come after them in the same file.
+fn patch(hunks: &[Hunk], applied: &[Hunk]) -> String {
+    let mut by_path: BTreeMap<&str, Vec<&Hunk>> = BTreeMap::new();
+    for hunk in hunks {
+        by_path.entry(&hunk.path).or_default().push(hunk);
+    }
+    let mut result = String::new();
+    for (path, mut hunks) in by_path {
+        hunks.sort_by_key(|hunk| hunk.old_start);
+        result.push_str(&format!(
+            "diff --git a/{0} b/{0}\n--- a/{0}\n+++ b/{0}\n",
+            path
+        ));
+        // Lines added by earlier hunks of this patch.
+        let mut delta = 0;
+        for hunk in hunks {
+            let shift: i64 = applied
+                .iter()
+                .filter(|other| other.path == path && other.old_start < hunk.old_start)
+                .map(Hunk::delta)
+                .sum();
+            let old_start = hunk.old_start as i64 + shift;
+            let new_start = old_start
+                + delta
+                + match (hunk.old_len, hunk.new_len) {
+                    (0, _) => 1,
+                    (_, 0) => -1,
+                    _ => 0,
+                };
+            result.push_str(&format!(
+                "@@ -{},{} +{},{} @@\n{}",
+                old_start, hunk.old_len, new_start, hunk.new_len, hunk.body
+            ));
+            delta += hunk.delta();
+        }
+    }
+    result
+}
+
+/// Run a `git` command in the repository, with `input` on stdin if given, failing if
+/// it fails.
+fn run_git(git: &GitStore, args: &[&str], input: Option<&str>) -> err::Result<()> {
+    let mut child = git
+        .command()
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged_spawn()?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    if let Some(input) = input {
+        stdin.write_all(input.as_bytes())?;
+    }
+    std::mem::drop(stdin);
+    let out = crate::cancel::wait_with_output(child)?;
+    err::from_git(&out, || format!("`git {}` failed", args.join(" ")))?;
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use crate::config::Config;
+use crate::err::{self, ResultExt};
+use crate::git::{GitStore, MergeTree};
+
+pub const SUBCOMMAND: &str = "adopt";
+
+const CLI_ARG_BRANCH: &str = "branch";
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_REMOTE: &str = "remote";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Brings an existing remote branch under git-dx management")
+        .long_about(
+            "Brings an existing remote branch, such as a review branch pushed by hand, under \
+             git-dx management: adds the branch trailer for it to a commit in the current \
+             stack, and records the branch's current head as what git-dx last pushed, so that \
+             the next integration builds on it. Fails without changing anything if the branch \
+             shares no history with the commit's remote diffbase, or (with Git 2.38 or newer) \
+             if merging that diffbase into it would conflict.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_BRANCH)
+                .help("Remote target branch to adopt (e.g., wchargin-foo)")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Commit to amend; must be HEAD or one of its first-parent ancestors")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote that holds the branch")
+                .short("-r")
+                .takes_value(true),
+        )
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let branch = matches.value_of(CLI_ARG_BRANCH).unwrap();
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let remotes = crate::Remotes::new(&remote, None).per_branch(matches.is_present(CLI_ARG_REMOTE));
+    let cannot_adopt = |reason: String| err::Error::CannotAdopt {
+        branch: branch.to_string(),
+        reason,
+    };
+    let target = git
+        .commit(matches.value_of(CLI_ARG_COMMIT).unwrap())?
+        .clone();
+
+    let key = config
+        .branch_key_of(branch)
+        .ok_or_else(|| cannot_adopt("not a dx target branch name".to_string()))?;
+    let resolved = crate::valid_target_branch(git, config, &target.oid, key)?;
+    if resolved != branch {
+        return Err(cannot_adopt(format!(
+            "branch key {} names {} instead",
+            key, resolved
+        )));
+    }
+    let tracked = match crate::branch_key(config, &target.oid, &target.trailers)? {
+        None => false,
+        Some(existing) if existing == key => true,
+        Some(existing) => {
+            return Err(cannot_adopt(format!(
+                "{} already has trailer \"{}: {}\"",
+                target.oid, config.branch_trailer, existing
+            )))
+        }
+    };
+    let tip = remotes
+        .target_oid(git, branch)?
+        .ok_or_else(|| cannot_adopt(format!("no such branch on {}", remote)))?;
+
+    // Check what the first integration will do with the branch: merge the remote diffbase into
+    // it, then commit the tree of the source commit, which always succeeds.
+    let remote_diffbase = match target.parents.first() {
+        Some(parent) => {
+            let local_diffbase = git
+                .commit(parent)
+                .context(|| format!("reading local diffbase of {}", target.oid))?
+                .clone();
+            crate::remote_diffbase(git, config, &remotes, &local_diffbase)?
+        }
+        // A root commit is integrated onto the trunk, or onto an empty commit if there is none.
+        None => crate::root_diffbase(git, &remotes, &target, key)?,
+    };
+    if git.merge_base(&tip, &remote_diffbase)?.is_none() {
+        return Err(cannot_adopt(format!(
+            "it shares no history with remote diffbase {}",
+            remote_diffbase
+        )));
+    }
+    if git.version()? >= crate::plan::MERGE_TREE_VERSION {
+        if let MergeTree::Conflicts(paths) = git.merge_tree(&tip, &remote_diffbase)? {
+            return Err(cannot_adopt(format!(
+                "merging remote diffbase {} into it would conflict in {}",
+                remote_diffbase,
+                paths.join(", ")
+            )));
+        }
+    }
+
+    let source = if tracked {
+        target.oid.clone()
+    } else {
+        let source = crate::track::set_branch_trailer(git, config, &target, key)?;
+        info!(
+            "added trailer \"{}: {}\" to {}",
+            config.branch_trailer, key, target.oid
+        );
+        source
+    };
+    let target_remote = remotes.target_remote(git, branch)?;
+    git.update_ref(
+        &crate::push::pushed_ref(&target_remote, branch),
+        &tip,
+        &format!("dx adopt: {}", branch),
+    )?;
+    info!("adopted {} at {}", branch, tip);
+    println!("{}", source);
+    Ok(())
+}
@@ -0,0 +1,89 @@
+//! Entry points for programs that embed git-dx. Each runs in the repository at a given directory
+//! and takes the flags of the corresponding command, as it would on the command line, along with
+//! a `cancel::Token` that stops it: see `cancel`. They leave process-wide state alone, so flags
+//! that would set it (e.g., `--quiet`, `--color`, and `--progress`) have no effect: messages go
+//! to stderr uncolored, without progress, and no transcript is kept.
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use crate::cancel::Token;
+use crate::err;
+use crate::output::Collect;
+
+/// Integrate a source commit in the repository at `directory`, as `git dx <args>` would (e.g.,
+/// with `args` of `["HEAD~", "--push"]`), returning its `--format=porcelain` line.
+pub fn integrate(directory: &Path, args: &[&str], cancel: &Token) -> err::Result<Vec<String>> {
+    let matches = parse(directory, args)?;
+    if matches.is_present(crate::CLI_ARG_STACK) || matches.occurrences_of(crate::CLI_ARG_COMMIT) > 1
+    {
+        return Err(err::Error::InvalidArguments(
+            "integrates more than one commit; use `stack`".to_string(),
+        ));
+    }
+    run_integration(&matches, cancel)
+}
+
+/// Integrate the changes of the stacks with the given `tips` in the repository at `directory`, as
+/// `git dx --stack <tips> <args>` would, returning a `--format=porcelain` line for each change.
+pub fn stack(
+    directory: &Path,
+    tips: &[&str],
+    args: &[&str],
+    cancel: &Token,
+) -> err::Result<Vec<String>> {
+    let args: Vec<&str> = std::iter::once("--stack")
+        .chain(tips.iter().copied())
+        .chain(args.iter().copied())
+        .collect();
+    let matches = parse(directory, &args)?;
+    run_integration(&matches, cancel)
+}
+
+/// Show what integrating a source commit in the repository at `directory` would do, as `git dx
+/// plan <args>` would, printing it to standard output.
+pub fn plan(directory: &Path, args: &[&str], cancel: &Token) -> err::Result<()> {
+    let args: Vec<&str> = std::iter::once(crate::plan::SUBCOMMAND)
+        .chain(args.iter().copied())
+        .collect();
+    let matches = parse(directory, &args)?;
+    crate::cancel::with(Some(cancel), || {
+        crate::run_matches(crate::app(), &matches, None, false)
+    })
+}
+
+/// Parse `args` as the command line of `git dx -C <directory>`.
+fn parse(directory: &Path, args: &[&str]) -> err::Result<clap::ArgMatches<'static>> {
+    let mut argv = vec![
+        OsString::from("git-dx"),
+        OsString::from("-C"),
+        directory.as_os_str().to_owned(),
+    ];
+    argv.extend(args.iter().map(OsString::from));
+    crate::app()
+        .setting(clap::AppSettings::ColorNever)
+        .get_matches_from_safe(argv)
+        .map_err(|e| {
+            let message = e.message.lines().next().unwrap_or_default();
+            err::Error::InvalidArguments(message.trim_start_matches("error: ").to_string())
+        })
+}
+
+/// Run the integration that `matches` describes, collecting its results.
+fn run_integration(matches: &clap::ArgMatches, cancel: &Token) -> err::Result<Vec<String>> {
+    if matches.subcommand_name().is_some() {
+        return Err(err::Error::InvalidArguments(
+            "not an integration".to_string(),
+        ));
+    }
+    let collect = Collect::default();
+    crate::cancel::with(Some(cancel), || {
+        crate::run_matches(
+            crate::app(),
+            matches,
+            Some(Box::new(collect.clone())),
+            false,
+        )
+    })?;
+    Ok(collect.take())
+}
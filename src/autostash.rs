@@ -0,0 +1,60 @@
+//! Integration checks out and merges in the working tree, so uncommitted changes would be mixed
+//! into its commits or lost. Integration refuses to start with a dirty working tree unless given
+//! `--autostash`, in which case the changes are stashed for the run and re-applied afterward.
+
+use std::process::Command;
+
+use crate::err;
+use crate::git::GitStore;
+use crate::log::CommandExt;
+
+/// Changes stashed for the duration of a run, re-applied when this is dropped, so that they come
+/// back on every exit path that unwinds.
+pub struct AutoStash {
+    /// The `git stash pop` to run, in the repository that was stashed in.
+    pop: Command,
+}
+
+/// Whether the index or working tree has uncommitted changes to tracked files. Untracked files
+/// don't count, since Git won't overwrite them when checking out.
+pub fn is_dirty(git: &GitStore) -> err::Result<bool> {
+    let out = git
+        .command()
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .logged_output()?;
+    err::from_git(&out, || {
+        "failed to check for uncommitted changes".to_string()
+    })?;
+    Ok(!out.stdout.is_empty())
+}
+
+impl AutoStash {
+    /// Stash uncommitted changes, if any, returning `None` if there were none.
+    pub fn stash(git: &GitStore) -> err::Result<Option<AutoStash>> {
+        if !is_dirty(git)? {
+            return Ok(None);
+        }
+        let out = git
+            .command()
+            .args(["stash", "push", "--quiet", "-m", "git-dx autostash"])
+            .logged_output()?;
+        err::from_git(&out, || "failed to stash uncommitted changes".to_string())?;
+        info!("stashed uncommitted changes");
+        let mut pop = git.command();
+        pop.args(["stash", "pop", "--quiet", "--index"]);
+        Ok(Some(AutoStash { pop }))
+    }
+}
+
+impl Drop for AutoStash {
+    fn drop(&mut self) {
+        let out = crate::cancel::shield(|| self.pop.logged_output());
+        match out {
+            Ok(ref out) if out.status.success() => info!("re-applied stashed changes"),
+            _ => warn!(
+                "could not re-apply stashed changes; they are still in `git stash list`, as \
+                 \"git-dx autostash\""
+            ),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use crate::err;
+use crate::output::{Collect, OutputSink};
+
+pub const SUBCOMMAND: &str = "batch";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Runs integrations read from stdin, printing one result line for each")
+        .long_about(
+            "Runs integrations read from standard input, one per line or NUL-separated, in one \
+             process. Each instruction is a source commit followed by any flags of `git dx` \
+             itself, separated by whitespace (e.g., `HEAD~2 --push --bump`); instructions \
+             that integrate several commits at once (`--stack`, `--stdin`) are not allowed. \
+             For each instruction, in order, one tab-separated line is printed: the \
+             `--format=porcelain` line of its integration (whatever `--format` it gives), or \
+             `error`, the instruction, and why it failed. A failed instruction doesn't stop \
+             the rest, but the command fails at the end.",
+        )
+}
+
+/// Run each instruction on standard input with `integrate`, which is given the instruction's
+/// arguments and a sink for its results, printing one line for each.
+pub fn run<F>(_matches: &clap::ArgMatches, mut integrate: F) -> err::Result<()>
+where
+    F: FnMut(&[String], Box<dyn OutputSink>) -> err::Result<()>,
+{
+    let instructions = crate::stdin_revisions()?;
+    let mut failed = 0;
+    for instruction in &instructions {
+        let args: Vec<String> = instruction.split_whitespace().map(str::to_string).collect();
+        let collect = Collect::default();
+        match integrate(&args, Box::new(collect.clone())) {
+            Ok(()) => {
+                for line in collect.take() {
+                    println!("{}", line);
+                }
+            }
+            Err(e) => {
+                let instruction = args.join(" ");
+                warn!("instruction `{}` failed: {}", instruction, e);
+                // Keep each result on one line, and its fields apart.
+                let reason = e.to_string().replace(['\n', '\t'], " ");
+                println!("error\t{}\t{}", instruction, reason);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(err::Error::BatchIncomplete {
+            failed,
+            total: instructions.len(),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,165 @@
+//! Size budgets flag changes that are too big to review comfortably before they are pushed, so
+//! that they can be split up first. A change is measured against its local diffbase, and may
+//! raise (or lift) the repository's limits with a budget trailer, like:
+//!
+//! ```text
+//! wchargin-budget: files=60, lines=unlimited
+//! ```
+
+use crate::config::Config;
+use crate::err;
+use crate::git::{Commit, GitStore};
+
+const FILES_CONFIG: &str = "dx.maxChangedFiles";
+const LINES_CONFIG: &str = "dx.maxDiffLines";
+const BINARY_BYTES_CONFIG: &str = "dx.maxBinaryBytes";
+
+/// Value that lifts a limit in a budget trailer.
+const UNLIMITED: &str = "unlimited";
+
+/// How big a change is, relative to its local diffbase.
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+pub struct Size {
+    /// Number of paths added, deleted, or modified.
+    pub changed_files: u64,
+    /// Lines added plus lines deleted, in text files.
+    pub diff_lines: u64,
+    /// Total size of the binary files, as they are after the change.
+    pub binary_bytes: u64,
+}
+
+/// Limits on the size of a change. `None` means no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    pub max_changed_files: Option<u64>,
+    pub max_diff_lines: Option<u64>,
+    pub max_binary_bytes: Option<u64>,
+}
+
+impl Budget {
+    /// Read the repository's budget from `dx.maxChangedFiles`, `dx.maxDiffLines`, and
+    /// `dx.maxBinaryBytes`.
+    pub fn load(git: &GitStore) -> err::Result<Budget> {
+        let limit = |key: &str| -> err::Result<Option<u64>> {
+            match git.config_get(key)? {
+                None => Ok(None),
+                Some(value) => value.parse().map(Some).map_err(|_| {
+                    err::Error::InvalidConfig(format!(
+                        "{} must be a non-negative integer, not {:?}",
+                        key, value
+                    ))
+                }),
+            }
+        };
+        Ok(Budget {
+            max_changed_files: limit(FILES_CONFIG)?,
+            max_diff_lines: limit(LINES_CONFIG)?,
+            max_binary_bytes: limit(BINARY_BYTES_CONFIG)?,
+        })
+    }
+
+    /// Apply the overrides of a budget trailer value, like `files=60, lines=unlimited`.
+    fn with_overrides(mut self, value: &str) -> Result<Budget, String> {
+        for item in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (name, limit) = match item.find('=') {
+                Some(i) => (item[..i].trim(), item[i + 1..].trim()),
+                None => return Err(format!("expected `name=limit`, found {:?}", item)),
+            };
+            let limit = if limit == UNLIMITED {
+                None
+            } else {
+                Some(
+                    limit
+                        .parse()
+                        .map_err(|_| format!("bad limit for {:?}: {:?}", name, limit))?,
+                )
+            };
+            match name {
+                "files" => self.max_changed_files = limit,
+                "lines" => self.max_diff_lines = limit,
+                "binary-bytes" => self.max_binary_bytes = limit,
+                _ => {
+                    return Err(format!(
+                        "unknown limit {:?} (expected files, lines, or binary-bytes)",
+                        name
+                    ))
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// Describe each way in which `size` exceeds this budget.
+    fn violations(&self, size: &Size) -> Vec<String> {
+        let mut result = Vec::new();
+        let mut check = |actual: u64, limit: Option<u64>, what: &str| {
+            if let Some(limit) = limit {
+                if actual > limit {
+                    result.push(format!("{} {} (limit {})", actual, what, limit));
+                }
+            }
+        };
+        check(size.changed_files, self.max_changed_files, "changed files");
+        check(size.diff_lines, self.max_diff_lines, "changed lines");
+        check(
+            size.binary_bytes,
+            self.max_binary_bytes,
+            "bytes of binary files",
+        );
+        result
+    }
+}
+
+/// Measure the change from `base` to `tip`.
+pub fn measure(git: &GitStore, base: &str, tip: &str) -> err::Result<Size> {
+    let mut size = Size::default();
+    for (path, lines) in git.diff_numstat(base, tip)? {
+        size.changed_files += 1;
+        match lines {
+            Some(lines) => size.diff_lines += lines,
+            // A deleted binary file has no size after the change.
+            None => {
+                size.binary_bytes += git.object_size(&format!("{}:{}", tip, path))?.unwrap_or(0)
+            }
+        }
+    }
+    Ok(size)
+}
+
+/// Measure the change made by `source_commit` on top of `local_diffbase`, failing with
+/// `BudgetExceeded` if it is over the repository's budget as adjusted by the commit's budget
+/// trailer.
+pub fn check(
+    git: &GitStore,
+    config: &Config,
+    source_commit: &Commit,
+    local_diffbase: &str,
+    target_branch: &str,
+) -> err::Result<Size> {
+    let size = measure(git, local_diffbase, &source_commit.oid)?;
+    let all_trailers = crate::trailers(config, &source_commit.message);
+    let budget = match crate::look_up_trailer(&config.budget_trailer, &all_trailers)
+        .unique(&source_commit.oid)
+    {
+        Ok(value) => {
+            config
+                .budget
+                .with_overrides(value)
+                .map_err(|reason| err::Error::InvalidBudget {
+                    oid: source_commit.oid.to_string(),
+                    reason,
+                })?
+        }
+        Err(err::Error::MissingTrailer { .. }) => config.budget,
+        Err(other) => return Err(other), // duplicate trailer
+    };
+    let violations = budget.violations(&size);
+    if !violations.is_empty() {
+        return Err(err::Error::BudgetExceeded {
+            branch: target_branch.to_string(),
+            violations,
+            trailer: config.budget_trailer.clone(),
+        });
+    }
+    Ok(size)
+}
@@ -0,0 +1,238 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::clock;
+use crate::config::Config;
+use crate::doctor::Performance;
+use crate::err::{self, ResultExt};
+use crate::git::GitStore;
+use crate::journal;
+use crate::log::CommandExt;
+
+pub const SUBCOMMAND: &str = "bugreport";
+
+/// Where each run's transcript is kept, relative to the Git directory, for the next bug report.
+pub const TRANSCRIPT_PATH: &str = "dx/last-command.log";
+
+const CLI_ARG_OUTPUT_DIRECTORY: &str = "output-directory";
+
+/// Parts of config keys (compared case-insensitively) whose values may be credentials.
+const SECRET_KEY_PARTS: &[&str] = &[
+    "auth", "cookie", "header", "key", "password", "secret", "token",
+];
+/// Config sections that identify the user.
+const IDENTITY_SECTIONS: &[&str] = &["author", "committer", "sendemail", "user"];
+
+const REDACTED: &str = "<redacted>";
+
+/// How many of the most recent integration journal entries to include.
+const JOURNAL_ENTRIES: usize = 20;
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Collects information about this setup for a bug report")
+        .long_about(
+            "Collects information about this setup into a tarball to attach to a bug report: \
+             the versions of git-dx and Git, what this repository's Git can do, its config (with \
+             credentials and identities redacted), git-dx's refs under `refs/dx/`, the latest \
+             entries of the integration journal, and a transcript of the last git-dx command \
+             run in this repository. Look over the tarball before sharing it: commit messages \
+             and branch names are not redacted.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_OUTPUT_DIRECTORY)
+                .help("Directory to write the tarball to (default: the current directory)")
+                .value_name("path")
+                .long("--output-directory")
+                .short("-o")
+                .takes_value(true),
+        )
+}
+
+pub fn run(git: &mut GitStore, _config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let name = format!("git-dx-bugreport-{}", clock::now());
+    let mut output_directory = std::env::current_dir()?;
+    if let Some(dir) = matches.value_of(CLI_ARG_OUTPUT_DIRECTORY) {
+        output_directory.push(dir);
+    }
+    let tarball = output_directory.join(format!("{}.tar.gz", name));
+    let staging = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let result =
+        write_report(git, &staging.join(&name)).and_then(|()| archive(&staging, &name, &tarball));
+    // Best effort: the temporary directory is only clutter.
+    let _ = std::fs::remove_dir_all(&staging);
+    result?;
+    info!("review the report for anything private before attaching it to an issue");
+    println!("{}", tarball.display());
+    Ok(())
+}
+
+/// Write the files of the report into `dir`.
+fn write_report(git: &GitStore, dir: &Path) -> err::Result<()> {
+    let writing = || format!("writing bug report to {}", dir.display());
+    std::fs::create_dir_all(dir).context(writing)?;
+    let files = [
+        ("version.txt", version(git)),
+        ("capabilities.txt", capabilities(git)?),
+        ("config.txt", config(git)?),
+        ("refs.txt", capture(git, &["for-each-ref", "refs/dx/"])),
+        ("journal.txt", journal(git)),
+        ("transcript.txt", transcript(git)?),
+    ];
+    for (file, contents) in files.iter() {
+        std::fs::write(dir.join(file), contents).context(writing)?;
+    }
+    Ok(())
+}
+
+/// Pack `staging/name` into a gzipped tarball at `tarball`.
+fn archive(staging: &Path, name: &str, tarball: &Path) -> err::Result<()> {
+    let out = Command::new("tar")
+        .arg("-czf")
+        .arg(tarball)
+        .arg("-C")
+        .arg(staging)
+        .arg(name)
+        .logged_output()
+        .context(|| "running `tar`".to_string())?;
+    if !out.status.success() {
+        return Err(err::Error::GitContract(format!(
+            "failed to write {}: {}",
+            tarball.display(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+fn version(git: &GitStore) -> String {
+    format!(
+        "git-dx {} ({}, {}; libgit2 support {})\n{}",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        if cfg!(feature = "libgit2") {
+            "compiled in"
+        } else {
+            "not compiled in"
+        },
+        capture(git, &["version", "--build-options"])
+    )
+}
+
+fn capabilities(git: &GitStore) -> err::Result<String> {
+    let perf = Performance::detect(git)?;
+    let yes_no = |b: bool| if b { "yes" } else { "no" };
+    let mut result = format!(
+        "git version: {}.{} (git-dx needs {}.{})\ncommit-graph: {}\n",
+        perf.version.0,
+        perf.version.1,
+        crate::init::MIN_GIT_VERSION.0,
+        crate::init::MIN_GIT_VERSION.1,
+        yes_no(perf.commit_graph),
+    );
+    if perf.fsmonitor_supported() {
+        result.push_str(&format!("fsmonitor: {}\n", yes_no(perf.fsmonitor)));
+    } else {
+        result.push_str("fsmonitor: not supported\n");
+    }
+    for key in &[
+        "dx.backend",
+        "gpg.format",
+        "commit.gpgSign",
+        "merge.conflictStyle",
+    ] {
+        let value = git.config_get(key)?;
+        result.push_str(&format!(
+            "{}: {}\n",
+            key,
+            value.as_deref().unwrap_or("(unset)")
+        ));
+    }
+    Ok(result)
+}
+
+/// Every config entry with its origin, redacted.
+fn config(git: &GitStore) -> err::Result<String> {
+    let mut result = String::new();
+    for entry in git.config_entries()? {
+        let key = redact_url(&entry.key);
+        match entry.value {
+            None => result.push_str(&format!("{}\t{}\n", entry.origin, key)),
+            Some(ref value) => result.push_str(&format!(
+                "{}\t{}={}\n",
+                entry.origin,
+                key,
+                redact(&entry.key, value)
+            )),
+        }
+    }
+    Ok(result)
+}
+
+/// Redact a config value if its key suggests a credential or an identity, or else any
+/// credentials in URLs within it.
+fn redact(key: &str, value: &str) -> String {
+    let section = key.split('.').next().unwrap_or("").to_ascii_lowercase();
+    let variable = key.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+    if IDENTITY_SECTIONS.contains(&section.as_str())
+        || SECRET_KEY_PARTS.iter().any(|part| variable.contains(part))
+    {
+        return REDACTED.to_string();
+    }
+    redact_url(value)
+}
+
+/// Redact the user info (e.g., `user:token@`) of a URL in `s`, which may also appear in a
+/// config key (e.g., `url.<base>.insteadOf`).
+fn redact_url(s: &str) -> String {
+    let start = match s.find("://") {
+        Some(i) => i + "://".len(),
+        None => return s.to_string(),
+    };
+    let authority_end = s[start..].find('/').map_or(s.len(), |i| start + i);
+    match s[start..authority_end].rfind('@') {
+        Some(at) => format!("{}{}{}", &s[..start], REDACTED, &s[start + at..]),
+        None => s.to_string(),
+    }
+}
+
+/// The most recent entries of the integration journal, with their metadata.
+fn journal(git: &GitStore) -> String {
+    capture(
+        git,
+        &[
+            "for-each-ref",
+            "--sort=-taggerdate",
+            &format!("--count={}", JOURNAL_ENTRIES),
+            "--format=%(refname) %(*objectname)%0a%(contents)",
+            journal::JOURNAL_PREFIX,
+        ],
+    )
+}
+
+/// The transcript of the last git-dx command run in this repository, if any.
+fn transcript(git: &GitStore) -> err::Result<String> {
+    let path: PathBuf = git.git_path(TRANSCRIPT_PATH)?;
+    match std::fs::read_to_string(&path) {
+        Ok(transcript) => Ok(transcript),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Ok("(no git-dx command has run in this repository)\n".to_string())
+        }
+        Err(e) => Err(e).context(|| format!("reading {}", path.display()))?,
+    }
+}
+
+/// The output of a `git` command, or a note of why it failed: a report missing a section is
+/// more useful than no report at all.
+fn capture(git: &GitStore, args: &[&str]) -> String {
+    match git.command().args(args).logged_output() {
+        Ok(ref out) if out.status.success() => String::from_utf8_lossy(&out.stdout).into_owned(),
+        Ok(out) => format!(
+            "(`git {}` failed: {})\n",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        ),
+        Err(e) => format!("(`git {}` failed: {})\n", args.join(" "), e),
+    }
+}
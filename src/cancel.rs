@@ -0,0 +1,212 @@
+//! Cancellation of a run from another thread, for programs that embed git-dx. A run started with
+//! a `Token` checks it before each command that it starts, and kills any command still running
+//! when it is cancelled, then fails with an error whose `root` is `Error::Cancelled`. Cleanup that
+//! puts the repository back as it was (e.g., checking out the original `HEAD`) still runs.
+
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often to check for cancellation while waiting for a command.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A flag that cancels the runs given it once set. Clones share the flag, so one can be kept to
+/// cancel a run on another thread.
+#[derive(Debug, Clone, Default)]
+pub struct Token(Arc<AtomicBool>);
+
+impl Token {
+    pub fn new() -> Token {
+        Token::default()
+    }
+
+    /// Cancel the runs given this token. Commands that they are running are killed.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+thread_local! {
+    /// The token of the run on this thread, if any.
+    static CURRENT: RefCell<Option<Token>> = const { RefCell::new(None) };
+}
+
+/// Marks an I/O error as due to cancellation, so that it becomes `Error::Cancelled`.
+#[derive(Debug)]
+struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Run `f` with `token` as the token of this thread, restoring the previous one after.
+pub(crate) fn with<T>(token: Option<&Token>, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT.with(|current| current.replace(token.cloned()));
+    let result = f();
+    CURRENT.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Run `f`, which undoes the partial work of a failed run, even if the run was cancelled.
+pub(crate) fn shield<T>(f: impl FnOnce() -> T) -> T {
+    with(None, f)
+}
+
+fn current() -> Option<Token> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Fail if the run on this thread has been cancelled.
+pub(crate) fn check() -> io::Result<()> {
+    match current() {
+        Some(token) if token.is_cancelled() => {
+            Err(io::Error::new(io::ErrorKind::Interrupted, Cancelled))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether an I/O error is from `check` or `wait`.
+pub(crate) fn is_cancellation(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<Cancelled>())
+}
+
+/// Wait for `child` to exit, as by `Child::wait`, but kill it if the run on this thread is
+/// cancelled first.
+pub(crate) fn wait(child: &mut Child) -> io::Result<ExitStatus> {
+    let token = match current() {
+        Some(token) => token,
+        None => return child.wait(),
+    };
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if token.is_cancelled() {
+            // The child may have exited just now, which is fine.
+            let _ = child.kill();
+            child.wait()?;
+            return Err(io::Error::new(io::ErrorKind::Interrupted, Cancelled));
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Run `cmd` to completion, as by `Command::output`, but fail without starting it if the run on
+/// this thread has been cancelled, and kill it if the run is cancelled while it runs.
+pub(crate) fn output(cmd: &mut Command) -> io::Result<Output> {
+    check()?;
+    if current().is_none() {
+        return cmd.output();
+    }
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = read_to_end(child.stdout.take().expect("piped stdout"));
+    let stderr = read_to_end(child.stderr.take().expect("piped stderr"));
+    let status = wait(&mut child)?;
+    Ok(Output {
+        status,
+        stdout: stdout.join().expect("stdout reader panicked")?,
+        stderr: stderr.join().expect("stderr reader panicked")?,
+    })
+}
+
+/// Wait for `child` to exit and collect its output, as by `Child::wait_with_output`, but kill it
+/// if the run on this thread is cancelled first.
+pub(crate) fn wait_with_output(mut child: Child) -> io::Result<Output> {
+    if current().is_none() {
+        return child.wait_with_output();
+    }
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+    let status = wait(&mut child)?;
+    let join = |reader: Option<std::thread::JoinHandle<io::Result<Vec<u8>>>>| match reader {
+        Some(reader) => reader.join().expect("output reader panicked"),
+        None => Ok(Vec::new()),
+    };
+    Ok(Output {
+        status,
+        stdout: join(stdout)?,
+        stderr: join(stderr)?,
+    })
+}
+
+/// Read all of `reader` on another thread, so that a child writing to it doesn't block.
+pub(crate) fn read_to_end<R: Read + Send + 'static>(
+    mut reader: R,
+) -> std::thread::JoinHandle<io::Result<Vec<u8>>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).map(|_| buf)
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::time::Instant;
+
+    #[test]
+    fn kills_command_on_cancel() {
+        let token = Token::new();
+        let canceller = token.clone();
+        let start = Instant::now();
+        let result = with(Some(&token), || {
+            let mut child = Command::new("sleep").arg("10").spawn()?;
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                canceller.cancel();
+            });
+            wait(&mut child)
+        });
+        assert!(is_cancellation(&result.unwrap_err()));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // Later commands don't start at all.
+        assert!(with(Some(&token), check).is_err());
+        assert!(with(Some(&token), || shield(check)).is_ok());
+    }
+
+    #[test]
+    fn kills_spawned_command_on_cancel() {
+        use crate::log::CommandExt;
+        let token = Token::new();
+        let canceller = token.clone();
+        let start = Instant::now();
+        let result = with(Some(&token), || {
+            let child = Command::new("sleep")
+                .arg("10")
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .logged_spawn()?;
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                canceller.cancel();
+            });
+            wait_with_output(child)
+        });
+        assert!(is_cancellation(&result.unwrap_err()));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn waits_without_token() {
+        let mut child = Command::new("true").spawn().unwrap();
+        assert!(wait(&mut child).unwrap().success());
+        assert!(check().is_ok());
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+
+use crate::config::Config;
+use crate::err;
+use crate::git::{Commit, GitStore, Oid};
+
+pub const SUBCOMMAND: &str = "check";
+
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_STDIN: &str = "stdin";
+const CLI_ARG_TRUNK: &str = "trunk";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Validates the dx trailers of each change in the current stack")
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Tip of the stack")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help("Remote branch that the stack is based on (default: the remote's HEAD)")
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote whose branches to inspect")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_STDIN)
+                .help("Check the commits read from stdin (one per line or NUL-separated)")
+                .long_help(
+                    "Check the commits read from standard input, one per line or \
+                     NUL-separated, instead of the stack. Branch keys are checked for uniqueness \
+                     among the given commits, the earliest given use of each key winning.",
+                )
+                .long("--stdin"),
+        )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The change cannot be integrated as is.
+    Error,
+    /// The change can be integrated, but probably not as intended.
+    Warning,
+}
+
+/// A problem with the dx trailers of a single commit message.
+#[derive(Debug)]
+pub struct Problem {
+    pub severity: Severity,
+    pub description: String,
+}
+
+impl Problem {
+    fn error(description: String) -> Self {
+        Problem {
+            severity: Severity::Error,
+            description,
+        }
+    }
+
+    fn warning(description: String) -> Self {
+        Problem {
+            severity: Severity::Warning,
+            description,
+        }
+    }
+}
+
+/// A commit message to validate, along with the context needed to validate it.
+pub struct Candidate<'a> {
+    /// Human-readable name for the message, used in error messages (e.g., an object ID).
+    pub context: &'a str,
+    pub message: &'a str,
+    /// The commit that this message's commit has (or will have) as its parent, if that parent is
+    /// part of the same stack (i.e., not yet in trunk).
+    pub parent: Option<&'a Commit>,
+    /// Branch keys used by other changes in the same stack, mapped to the commits that use them.
+    pub taken_keys: &'a HashMap<String, Oid>,
+}
+
+/// Validate the dx trailers of a commit message. Returns all problems found; an `Err` result
+/// indicates a failure to perform the validation itself.
+pub fn validate(
+    git: &mut GitStore,
+    config: &Config,
+    remote: &str,
+    candidate: &Candidate,
+) -> err::Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    let key = match crate::branch_key(
+        config,
+        candidate.context,
+        &crate::trailers(config, candidate.message),
+    ) {
+        Ok(Some(key)) => key,
+        Ok(None) => return Ok(problems), // not a dx change; nothing to check
+        Err(err::Error::DuplicateTrailer { key, .. }) => {
+            problems.push(Problem::error(format!("more than one {:?} trailer", key)));
+            return Ok(problems);
+        }
+        Err(e) => return Err(e),
+    };
+
+    match crate::valid_target_branch(git, config, candidate.context, &key) {
+        Ok(_) => (),
+        Err(err::Error::InvalidBranchName { branch, .. }) => {
+            problems.push(Problem::error(format!(
+                "{:?} is not a valid branch name",
+                branch
+            )));
+        }
+        Err(e) => return Err(e),
+    }
+    if let Some(other) = candidate.taken_keys.get(&key) {
+        problems.push(Problem::error(format!(
+            "branch key {:?} is also used by {}",
+            key, other
+        )));
+    }
+    if let Some(parent) = candidate.parent {
+        // Malformed trailers on the parent are reported when the parent itself is checked.
+        if let Ok(Some(diffbase_branch)) =
+            crate::branch_name(git, config, &parent.oid, &parent.trailers)
+        {
+            if crate::remote_branch_oid(git, remote, &diffbase_branch)?.is_none() {
+                problems.push(Problem::warning(format!(
+                    "diffbase branch {} does not exist on {}; integrate {} first",
+                    diffbase_branch, remote, parent.oid
+                )));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// Map the branch key of each trailer-bearing commit in `base..tip` to that commit's object ID.
+/// If a key is used more than once, the oldest use wins.
+pub fn stack_keys(
+    git: &mut GitStore,
+    config: &Config,
+    base: &str,
+    tip: &str,
+) -> err::Result<HashMap<String, Oid>> {
+    let stack = git.rev_list(base, tip)?;
+    keys_of(git, config, &stack)
+}
+
+/// Map the branch key of each trailer-bearing commit among `oids` to that commit's object ID. If a
+/// key is used more than once, the first use wins.
+fn keys_of(git: &mut GitStore, config: &Config, oids: &[Oid]) -> err::Result<HashMap<String, Oid>> {
+    let mut result = HashMap::new();
+    for oid in oids {
+        let commit = git.commit(oid)?;
+        // Malformed trailers are reported when that commit itself is checked.
+        if let Ok(Some(key)) = crate::branch_key(config, &commit.oid, &commit.trailers) {
+            result.entry(key).or_insert_with(|| oid.clone());
+        }
+    }
+    Ok(result)
+}
+
+/// Print problems to stderr, returning the number of errors among them.
+pub fn report(context: &str, problems: &[Problem]) -> usize {
+    let mut errors = 0;
+    for problem in problems {
+        let label = match problem.severity {
+            Severity::Error => {
+                errors += 1;
+                "error"
+            }
+            Severity::Warning => "warning",
+        };
+        eprintln!("{}: {}: {}", context, label, problem.description);
+    }
+    errors
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let tip = matches.value_of(CLI_ARG_COMMIT).unwrap();
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let stack = if matches.is_present(CLI_ARG_STDIN) {
+        let mut stack = Vec::new();
+        for rev in crate::stdin_revisions()? {
+            stack.push(git.rev_parse_commit_ok(&rev)?);
+        }
+        stack
+    } else {
+        let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+            Some(trunk) => trunk.to_string(),
+            None => crate::default_trunk(git, &remote)?,
+        };
+        let base = crate::trunk_oid(git, &remote, &trunk)?;
+        git.rev_list(&base, tip)?
+    };
+    let all_keys = keys_of(git, config, &stack)?;
+
+    let mut errors = 0;
+    for oid in &stack {
+        let commit = git.commit(oid)?.clone();
+        let parent = match commit.parents.first() {
+            Some(parent) if stack.contains(parent) => Some(git.commit(parent)?.clone()),
+            _ => None,
+        };
+        // A key used more than once maps to its oldest use, so each later use is reported as a
+        // duplicate of that one.
+        let mut taken_keys = all_keys.clone();
+        taken_keys.retain(|_, other| *other != *oid);
+        let problems = validate(
+            git,
+            config,
+            &remote,
+            &Candidate {
+                context: oid,
+                message: &commit.message,
+                parent: parent.as_ref(),
+                taken_keys: &taken_keys,
+            },
+        )?;
+        errors += report(oid, &problems);
+    }
+    if errors > 0 {
+        return Err(err::Error::CheckFailed { errors });
+    }
+    Ok(())
+}
@@ -0,0 +1,37 @@
+//! The current time, and the hidden `--deterministic` mode that fixes it. In deterministic mode,
+//! every commit that git-dx creates gets the same timestamps and committer regardless of when or
+//! by whom it is run, so that tests can compare commits and output byte for byte.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The time in deterministic mode: 2019-01-01T00:00:00Z.
+const DETERMINISTIC_TIME: u64 = 1_546_300_800;
+/// The committer of every commit created in deterministic mode.
+const DETERMINISTIC_COMMITTER: (&str, &str) = ("git-dx", "git-dx@example.com");
+
+static DETERMINISTIC: AtomicBool = AtomicBool::new(false);
+
+/// Enter deterministic mode. Call this before running any `git` commands, since it works by
+/// setting the environment that they inherit.
+pub fn set_deterministic() {
+    DETERMINISTIC.store(true, Ordering::Relaxed);
+    let date = format!("{} +0000", DETERMINISTIC_TIME);
+    // Integration still attributes update commits to the source commit's author, with its own
+    // date; these only fill in for commits (like merges) that have no source author.
+    std::env::set_var("GIT_AUTHOR_DATE", &date);
+    std::env::set_var("GIT_COMMITTER_DATE", &date);
+    std::env::set_var("GIT_COMMITTER_NAME", DETERMINISTIC_COMMITTER.0);
+    std::env::set_var("GIT_COMMITTER_EMAIL", DETERMINISTIC_COMMITTER.1);
+}
+
+/// The current time, in seconds since the epoch.
+pub fn now() -> u64 {
+    if DETERMINISTIC.load(Ordering::Relaxed) {
+        return DETERMINISTIC_TIME;
+    }
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
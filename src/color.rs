@@ -0,0 +1,80 @@
+//! Color for the messages on stderr that matter most: created branches, warnings, conflicts, and
+//! notices that nothing changed. Whether to color is decided once per run by `--color`: `always`,
+//! `never`, or `auto` (the default), which colors only when stderr is a terminal that `terminal`
+//! allows color on; `--plain` turns color off entirely. Results on stdout are never colored,
+//! since scripts read them.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const WHEN_ALWAYS: &str = "always";
+pub const WHEN_NEVER: &str = "never";
+pub const WHEN_AUTO: &str = "auto";
+
+/// The values of `--color`.
+pub const WHENS: &[&str] = &[WHEN_ALWAYS, WHEN_NEVER, WHEN_AUTO];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Decide whether to color, given a value of `--color`.
+pub fn set(when: &str) {
+    let enabled = !crate::terminal::plain()
+        && match when {
+            WHEN_ALWAYS => true,
+            WHEN_NEVER => false,
+            _ => {
+                use std::io::IsTerminal;
+                crate::terminal::color_by_default() && std::io::stderr().is_terminal()
+            }
+        };
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn paint(sgr: &str, text: impl Display) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[m", sgr, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// A branch (or ref) that was created.
+pub fn created(text: impl Display) -> String {
+    paint("1;32", text)
+}
+
+/// The `warning:` prefix.
+pub fn warning(text: impl Display) -> String {
+    paint("1;33", text)
+}
+
+/// The `error:` prefix, and refs that a push failed to update.
+pub fn error(text: impl Display) -> String {
+    paint("1;31", text)
+}
+
+/// A path left conflicted.
+pub fn conflict(text: impl Display) -> String {
+    paint("31", text)
+}
+
+/// A notice that something was left as it was.
+pub fn unchanged(text: impl Display) -> String {
+    paint("36", text)
+}
+
+/// Remove the color from a message, as for the transcript.
+pub fn strip(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("\x1b[") {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match rest.find('m') {
+            Some(end) => rest = &rest[end + 1..],
+            None => break,
+        }
+    }
+    result.push_str(rest);
+    result
+}
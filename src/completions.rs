@@ -0,0 +1,353 @@
+//! Shell completion. `git dx completions <shell>` prints a script that completes subcommands and
+//! flags, as generated by clap, and the values that arguments like `git dx land <commit>` can take
+//! in this repository, which the script gets from `git dx completions-dynamic`. Completion scripts
+//! call that on every keystroke, so it reads only local state (the config snapshot, refs, and
+//! commits) and never contacts a remote.
+
+use std::collections::BTreeSet;
+use std::io::Write;
+
+use crate::config::Config;
+use crate::err;
+use crate::git::GitStore;
+
+pub const SUBCOMMAND: &str = "completions-dynamic";
+pub const SCRIPT_SUBCOMMAND: &str = "completions";
+
+const CLI_ARG_KIND: &str = "kind";
+const CLI_ARG_SHELL: &str = "shell";
+
+const SHELL_BASH: &str = "bash";
+const SHELL_FISH: &str = "fish";
+const SHELL_ZSH: &str = "zsh";
+
+/// Name of the binary that the scripts complete. Git's own completion hands `git dx` to it.
+const BIN_NAME: &str = "git-dx";
+
+const KIND_BRANCHES: &str = "branches";
+const KIND_CHANGES: &str = "changes";
+const KIND_REMOTES: &str = "remotes";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Lists candidate argument values for shell completion")
+        .long_about(
+            "Lists candidate argument values for shell completion, one per line: dx target \
+             branches known from remote-tracking refs, changes in the current stack (each as an \
+             object ID, a tab, and its subject), or remotes.",
+        )
+        .setting(clap::AppSettings::Hidden)
+        .arg(
+            clap::Arg::with_name(CLI_ARG_KIND)
+                .help("What to list")
+                .possible_values(&[KIND_BRANCHES, KIND_CHANGES, KIND_REMOTES])
+                .required(true),
+        )
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let lines = match matches.value_of(CLI_ARG_KIND).unwrap() {
+        KIND_BRANCHES => branches(git, config)?,
+        KIND_CHANGES => changes(git, config)?,
+        KIND_REMOTES => remotes(git)?,
+        kind => unreachable!("unknown kind {:?}", kind),
+    };
+    for line in lines {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Names of the target branches on any remote, deduplicated and sorted.
+fn branches(git: &GitStore, config: &Config) -> err::Result<Vec<String>> {
+    let names: BTreeSet<String> = git
+        .remote_branches()?
+        .into_iter()
+        .filter(|name| config.branch_key_of(name).is_some())
+        .collect();
+    Ok(names.into_iter().collect())
+}
+
+/// The dx changes between the trunk and `HEAD`, oldest first. Empty if the trunk is not known
+/// locally, since finding it would mean asking the remote.
+fn changes(git: &mut GitStore, config: &Config) -> err::Result<Vec<String>> {
+    let remote = crate::remote_name(git, None)?;
+    let trunk = match git.config_get("dx.trunk")? {
+        Some(trunk) => trunk,
+        None => {
+            let prefix = format!("refs/remotes/{}/", remote);
+            match git.symbolic_ref(&format!("{}HEAD", prefix))? {
+                Some(target) => match target.strip_prefix(&prefix) {
+                    Some(branch) => branch.to_string(),
+                    None => return Ok(Vec::new()),
+                },
+                None => return Ok(Vec::new()),
+            }
+        }
+    };
+    let base = match crate::remote_branch_oid(git, &remote, &trunk)? {
+        Some(base) => base,
+        None => return Ok(Vec::new()),
+    };
+    let mut result = Vec::new();
+    for oid in git.rev_list(&base, "HEAD")? {
+        let commit = git.commit(&oid)?;
+        if let Ok(Some(_)) = crate::branch_key(config, &commit.oid, &commit.trailers) {
+            let subject = commit.message.lines().next().unwrap_or("");
+            result.push(format!("{}\t{}", commit.oid, subject));
+        }
+    }
+    Ok(result)
+}
+
+/// Names of the configured remotes, sorted.
+fn remotes(git: &GitStore) -> err::Result<Vec<String>> {
+    let names: BTreeSet<String> = git
+        .config_entries()?
+        .into_iter()
+        .filter_map(|entry| {
+            let name = entry.key.strip_prefix("remote.")?.strip_suffix(".url")?;
+            Some(name.to_string())
+        })
+        .collect();
+    Ok(names.into_iter().collect())
+}
+
+pub fn script_subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SCRIPT_SUBCOMMAND)
+        .about("Prints a shell completion script")
+        .long_about(
+            "Prints a completion script for the given shell, which completes subcommands and \
+             flags as well as dx branches, changes of the current stack, and remotes. It \
+             completes `git dx` under Git's own completion as well as `git-dx`. For example, \
+             add `source <(git dx completions bash)` to `~/.bashrc`, save the zsh script as \
+             `_git-dx` in a directory on `$fpath`, or save the fish script as \
+             `~/.config/fish/completions/git-dx.fish`.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SHELL)
+                .help("Shell to complete for")
+                .possible_values(&[SHELL_BASH, SHELL_FISH, SHELL_ZSH])
+                .required(true),
+        )
+}
+
+/// Print the completion script for the shell given in `matches`, covering all of `app`.
+pub fn script(mut app: clap::App, matches: &clap::ArgMatches) -> err::Result<()> {
+    let (shell, finish): (clap::Shell, fn(&str) -> String) =
+        match matches.value_of(CLI_ARG_SHELL).unwrap() {
+            SHELL_BASH => (clap::Shell::Bash, bash),
+            SHELL_FISH => (clap::Shell::Fish, fish),
+            SHELL_ZSH => (clap::Shell::Zsh, zsh),
+            shell => unreachable!("unknown shell {:?}", shell),
+        };
+    let mut generated = Vec::new();
+    app.gen_completions_to(BIN_NAME, shell, &mut generated);
+    let generated = err::Error::require_utf8(generated, "generated completion script")?;
+    std::io::stdout().write_all(finish(&generated).as_bytes())?;
+    Ok(())
+}
+
+/// The kinds of `completions-dynamic` values that a positional argument takes, by name.
+fn positional_kinds(name: &str) -> Option<&'static [&'static str]> {
+    match name {
+        "commit" => Some(&[KIND_CHANGES]),
+        "branch" => Some(&[KIND_BRANCHES]),
+        "target" => Some(&[KIND_CHANGES, KIND_BRANCHES]),
+        _ => None,
+    }
+}
+
+/// Options that take a remote, in every subcommand that has them.
+const REMOTE_OPTIONS: &[&str] = &["-r", "--fork", "--verify-mirror"];
+
+/// Subcommands whose positional arguments take each kind of value, for fish, whose generated
+/// script omits positional arguments. Integration itself takes changes when no subcommand is
+/// given.
+const FISH_POSITIONALS: &[(&str, &[&str])] = &[
+    (
+        KIND_CHANGES,
+        &[
+            crate::check::SUBCOMMAND,
+            crate::diff::SUBCOMMAND,
+            crate::land::SUBCOMMAND,
+            crate::plan::SUBCOMMAND,
+            crate::pr::SUBCOMMAND,
+            crate::push::SUBCOMMAND,
+            crate::restack::SUBCOMMAND,
+            crate::status::SUBCOMMAND,
+            crate::track::SUBCOMMAND,
+            crate::web::SUBCOMMAND,
+        ],
+    ),
+    (
+        KIND_BRANCHES,
+        &[
+            crate::drop::SUBCOMMAND,
+            crate::history::SUBCOMMAND,
+            crate::rollback::SUBCOMMAND,
+            crate::web::SUBCOMMAND,
+        ],
+    ),
+];
+
+const BASH_PRELUDE: &str = r#"# Values that depend on the repository, one per line.
+_git_dx_values() {
+    local kind
+    for kind in "$@"; do
+        git dx completions-dynamic "${kind}" 2>/dev/null | cut -f1
+    done
+}
+
+"#;
+
+const BASH_POSTLUDE: &str = r#"
+# Git's completion calls this for `git dx`: complete as for `git-dx`, from the word after `dx`.
+_git_dx() {
+    local i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        [ "${COMP_WORDS[i]}" = dx ] && break
+    done
+    local words=("${COMP_WORDS[@]:i+1}") cword=$((COMP_CWORD - i))
+    local COMP_WORDS=(git-dx "${words[@]}") COMP_CWORD="${cword}"
+    _git-dx
+}
+"#;
+
+/// Finish clap's bash script: a positional argument's placeholder among the words to complete
+/// becomes its values, and so do the file names completed for options that take a remote.
+///
+/// clap names the cases for subcommands after the binary name with its hyphen mangled (e.g.,
+/// `git__dx__drop`) but starts from the unmangled name, so that no subcommand case would ever
+/// match; the top-level case is mangled to agree.
+fn bash(generated: &str) -> String {
+    let mut result = String::from(BASH_PRELUDE);
+    let mut remote_option = false;
+    for line in generated.lines() {
+        let trimmed = line.trim();
+        let mangled = BIN_NAME.replace('-', "__");
+        let line = if trimmed == format!("cmd=\"{}\"", BIN_NAME) {
+            line.replace(BIN_NAME, &mangled)
+        } else if line == format!("        {})", BIN_NAME) {
+            format!("        {})", mangled)
+        } else if trimmed.starts_with("opts=") {
+            line.split(' ')
+                .map(|word| {
+                    let name = word.trim_start_matches('<').trim_end_matches("...");
+                    match (word.starts_with('<'), name.strip_suffix('>')) {
+                        (true, Some(name)) => match positional_kinds(name) {
+                            Some(kinds) => format!("$(_git_dx_values {})", kinds.join(" ")),
+                            None => word.to_string(),
+                        },
+                        _ => word.to_string(),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else if remote_option && trimmed.starts_with("COMPREPLY=($(compgen -f") {
+            line.replace("compgen -f", "compgen -W \"$(_git_dx_values remotes)\" --")
+        } else {
+            line.to_string()
+        };
+        remote_option = trimmed
+            .strip_suffix(')')
+            .is_some_and(|option| REMOTE_OPTIONS.contains(&option));
+        result.push_str(&line);
+        result.push('\n');
+    }
+    result.push_str(BASH_POSTLUDE);
+    result
+}
+
+const ZSH_HELPERS: &str = r#"_git-dx_values() {
+    local kind=$1 description=$2
+    local -a values
+    values=(${(f)"$(git dx completions-dynamic $kind 2>/dev/null)"})
+    # Changes come as an object ID and a subject, separated by a tab.
+    values=("${(@)values//$'\t'/:}")
+    _describe -t $kind $description values
+}
+_git-dx_changes() { _git-dx_values changes 'dx change'; }
+_git-dx_branches() { _git-dx_values branches 'dx branch'; }
+_git-dx_targets() {
+    _alternative 'changes:dx change:_git-dx_changes' 'branches:dx branch:_git-dx_branches'
+}
+_git-dx_remotes() { _git-dx_values remotes 'remote'; }
+
+"#;
+
+/// Finish clap's zsh script: positional arguments complete to their values instead of file
+/// names, and options that take a remote complete to remotes. zsh's Git completion calls
+/// `_git-dx` for `git dx` by itself.
+fn zsh(generated: &str) -> String {
+    let mut result = String::new();
+    for line in generated.lines() {
+        let mut line = line.to_string();
+        // Argument specs look like `':commit -- Source commit:_files' \`.
+        let name = line
+            .trim_start_matches('\'')
+            .trim_start_matches('*')
+            .trim_start_matches(':')
+            .split(" -- ")
+            .next()
+            .filter(|_| line.ends_with(":_files' \\"));
+        if let Some(kinds) = name.and_then(positional_kinds) {
+            let action = match kinds {
+                [KIND_CHANGES] => "_git-dx_changes",
+                [KIND_BRANCHES] => "_git-dx_branches",
+                _ => "_git-dx_targets",
+            };
+            line = format!("{}{}' \\", line.trim_end_matches("_files' \\"), action);
+        }
+        // Option specs look like `'-r+[Remote to use]' \` or `'--fork=[Remote ...]' \`.
+        let takes_remote = REMOTE_OPTIONS.iter().any(|option| {
+            line.starts_with(&format!("'{}+[", option))
+                || line.starts_with(&format!("'{}=[", option))
+        });
+        if takes_remote && line.ends_with("]' \\") {
+            line = format!(
+                "{}:remote:_git-dx_remotes' \\",
+                line.trim_end_matches("' \\")
+            );
+        }
+        if line == "_git-dx \"$@\"" {
+            result.push_str(ZSH_HELPERS);
+        }
+        result.push_str(&line);
+        result.push('\n');
+    }
+    result
+}
+
+/// Finish clap's fish script with the values of positional arguments and of options that take a
+/// remote. fish's Git completion uses the `git-dx` completions for `git dx` by itself.
+fn fish(generated: &str) -> String {
+    let mut result = generated.to_string();
+    let values = |kind| format!("(git dx completions-dynamic {} 2>/dev/null)", kind);
+    result.push_str(&format!(
+        "complete -c {} -n \"__fish_use_subcommand\" -f -a \"{}\"\n",
+        BIN_NAME,
+        values(KIND_CHANGES)
+    ));
+    for (kind, subcommands) in FISH_POSITIONALS {
+        result.push_str(&format!(
+            "complete -c {} -n \"__fish_seen_subcommand_from {}\" -f -a \"{}\"\n",
+            BIN_NAME,
+            subcommands.join(" "),
+            values(kind)
+        ));
+    }
+    for option in REMOTE_OPTIONS {
+        let flag = match option.strip_prefix("--") {
+            Some(long) => format!("-l {}", long),
+            None => format!("-s {}", option.trim_start_matches('-')),
+        };
+        result.push_str(&format!(
+            "complete -c {} {} -x -a \"{}\"\n",
+            BIN_NAME,
+            flag,
+            values(KIND_REMOTES)
+        ));
+    }
+    result
+}
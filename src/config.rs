@@ -0,0 +1,255 @@
+use crate::budget::Budget;
+use crate::err;
+use crate::git::GitStore;
+use crate::refname::{self, RefTemplate};
+use crate::resolve::{MergeOptions, PathPolicy};
+use crate::update_message::{self, UpdateTemplate};
+
+pub const SUBCOMMAND: &str = "config";
+
+const CLI_ARG_LIST: &str = "list";
+const CLI_ARG_SHOW_ORIGIN: &str = "show-origin";
+
+/// Branch prefix used when `dx.branchPrefix` is not set.
+const DEFAULT_BRANCH_PREFIX: &str = "wchargin-";
+/// Trailer key of the branch directive when `dx.branchTrailer` is not set.
+const DEFAULT_BRANCH_TRAILER: &str = "wchargin-branch";
+/// Trailer key of the source directive when `dx.sourceTrailer` is not set.
+const DEFAULT_SOURCE_TRAILER: &str = "wchargin-source";
+/// Trailer key listing pull request labels when `dx.labelsTrailer` is not set.
+const DEFAULT_LABELS_TRAILER: &str = "wchargin-labels";
+/// Trailer key listing pull request reviewers when `dx.reviewersTrailer` is not set.
+const DEFAULT_REVIEWERS_TRAILER: &str = "wchargin-reviewers";
+/// Trailer key naming a pull request milestone when `dx.milestoneTrailer` is not set.
+const DEFAULT_MILESTONE_TRAILER: &str = "wchargin-milestone";
+/// Trailer key adjusting a change's size budget when `dx.budgetTrailer` is not set.
+const DEFAULT_BUDGET_TRAILER: &str = "wchargin-budget";
+/// Token that makes CI skip a no-op commit when `dx.ciSkipToken` is not set.
+const DEFAULT_CI_SKIP_TOKEN: &str = "[ci skip]";
+/// Description of a CI bump commit when `dx.ciBumpMessage` is not set.
+const DEFAULT_CI_BUMP_MESSAGE: &str = "bump ci";
+
+/// How changes are sent for review (`dx.mode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Each change is integrated onto its own target branch by merging, for pull requests.
+    Branch,
+    /// Each change is pushed for review as a Gerrit patch set, identified by its `Change-Id`
+    /// trailer (see `crate::gerrit`).
+    Gerrit,
+}
+
+/// Settings read from the `dx.*` section of the Git configuration, loaded once per invocation.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Template that forms target branch names from branch keys (`dx.refTemplate`, else
+    /// `dx.branchPrefix` followed by `{branch}`).
+    pub ref_template: RefTemplate,
+    /// Value of `{user}` in the ref template (`dx.user`, else the local part of `user.email`).
+    pub user: String,
+    /// Trailer key of the branch directive on source commits (`dx.branchTrailer`).
+    pub branch_trailer: String,
+    /// Trailer key that records the source commit on integrated commits (`dx.sourceTrailer`).
+    pub source_trailer: String,
+    /// Trailer key listing comma-separated labels to apply to the pull request
+    /// (`dx.labelsTrailer`).
+    pub labels_trailer: String,
+    /// Trailer key listing comma-separated reviewers to request on the pull request
+    /// (`dx.reviewersTrailer`).
+    pub reviewers_trailer: String,
+    /// Trailer key naming the milestone to apply to the pull request (`dx.milestoneTrailer`).
+    pub milestone_trailer: String,
+    /// Trailer key adjusting the size budget of a change (`dx.budgetTrailer`).
+    pub budget_trailer: String,
+    /// Limits on the size of each change (`dx.maxChangedFiles`, `dx.maxDiffLines`, and
+    /// `dx.maxBinaryBytes`).
+    pub budget: Budget,
+    /// Characters that may separate a trailer's key from its value. This is Git's own
+    /// `trailer.separators` option, so that git-dx finds the same trailers that Git does.
+    pub trailer_separators: String,
+    /// Policies for resolving conflicts in the diffbase merge, in order of precedence
+    /// (`dx.resolve`, multi-valued).
+    pub resolve_policies: Vec<PathPolicy>,
+    /// Fingerprints of keys that may sign the commits of a branch to be landed
+    /// (`dx.allowedSigningKey`, multi-valued). If any are given, every commit must be signed by
+    /// one of them.
+    pub allowed_signing_keys: Vec<String>,
+    /// Push options to send with every push, as by `git push -o` (`dx.pushOption`,
+    /// multi-valued). Options given with `--push-option` are sent after these.
+    pub push_options: Vec<String>,
+    /// Template for the subject of "update patch" commits (`dx.updateMessage`).
+    pub update_template: UpdateTemplate,
+    /// Token appended to the message of a no-op commit so that CI skips it (`dx.ciSkipToken`).
+    /// If empty, no token is added.
+    pub ci_skip_token: String,
+    /// Description in the message of an empty commit made to rerun CI, as with `--bump`
+    /// (`dx.ciBumpMessage`).
+    pub ci_bump_message: String,
+    /// Whether to add a `Signed-off-by` trailer for the committer to each commit that integration
+    /// creates (`dx.signoff`), as projects that require a Developer Certificate of Origin need.
+    pub signoff: bool,
+    /// Whether to start the subject of each commit that integration creates with the change's
+    /// position in its stack (`dx.stackPosition`).
+    pub stack_position: bool,
+    /// Whether to copy the body of each source commit into its pull request's description after
+    /// pushing (`dx.syncDescription`).
+    pub sync_description: bool,
+    /// Merge strategy and strategy options for the diffbase merge (`dx.mergeOptions`).
+    pub merge_options: MergeOptions,
+    /// Whether to run the standard Git hooks (`pre-commit`, `pre-merge-commit`, and `commit-msg`)
+    /// for the commits that integration creates (`dx.runHooks`).
+    pub run_hooks: bool,
+    /// Keys of lines to copy verbatim from each source commit message into the message of the
+    /// commit that integrates it, like Phabricator's `Differential Revision`
+    /// (`dx.passThroughTrailer`, multi-valued).
+    pub pass_through_trailers: Vec<String>,
+    /// How changes are sent for review (`dx.mode`): `branch` (the default) or `gerrit`.
+    pub mode: Mode,
+}
+
+impl Config {
+    pub fn load(git: &GitStore) -> err::Result<Config> {
+        let template = git.config_get(refname::TEMPLATE_CONFIG)?;
+        let ref_template = match template {
+            Some(ref template) => RefTemplate::parse(template)?,
+            None => RefTemplate::with_prefix(
+                &git.config_get("dx.branchPrefix")?
+                    .unwrap_or_else(|| DEFAULT_BRANCH_PREFIX.to_string()),
+            ),
+        };
+        let user = match git.config_get(refname::USER_CONFIG)? {
+            Some(user) => user,
+            None => git
+                .config_get("user.email")?
+                .and_then(|email| email.split('@').next().map(|local| local.to_string()))
+                .unwrap_or_default(),
+        };
+        if ref_template.uses_user() && user.is_empty() {
+            return Err(err::Error::InvalidRefTemplate {
+                template: template.unwrap_or_default(),
+                reason: format!(
+                    "`{{user}}` needs {} or user.email to be set",
+                    refname::USER_CONFIG
+                ),
+            });
+        }
+        Ok(Config {
+            ref_template,
+            user,
+            branch_trailer: git
+                .config_get("dx.branchTrailer")?
+                .unwrap_or_else(|| DEFAULT_BRANCH_TRAILER.to_string()),
+            source_trailer: git
+                .config_get("dx.sourceTrailer")?
+                .unwrap_or_else(|| DEFAULT_SOURCE_TRAILER.to_string()),
+            labels_trailer: git
+                .config_get("dx.labelsTrailer")?
+                .unwrap_or_else(|| DEFAULT_LABELS_TRAILER.to_string()),
+            reviewers_trailer: git
+                .config_get("dx.reviewersTrailer")?
+                .unwrap_or_else(|| DEFAULT_REVIEWERS_TRAILER.to_string()),
+            milestone_trailer: git
+                .config_get("dx.milestoneTrailer")?
+                .unwrap_or_else(|| DEFAULT_MILESTONE_TRAILER.to_string()),
+            budget_trailer: git
+                .config_get("dx.budgetTrailer")?
+                .unwrap_or_else(|| DEFAULT_BUDGET_TRAILER.to_string()),
+            budget: Budget::load(git)?,
+            trailer_separators: git
+                .config_get("trailer.separators")?
+                .unwrap_or_else(|| crate::trailers::DEFAULT_SEPARATORS.to_string()),
+            resolve_policies: git
+                .config_get_all(crate::resolve::POLICY_CONFIG)?
+                .iter()
+                .map(|value| PathPolicy::parse(value))
+                .collect::<err::Result<_>>()?,
+            allowed_signing_keys: git.config_get_all("dx.allowedSigningKey")?,
+            push_options: git.config_get_all("dx.pushOption")?,
+            update_template: match git.config_get(update_message::TEMPLATE_CONFIG)? {
+                Some(template) => UpdateTemplate::parse(&template)?,
+                None => UpdateTemplate::default(),
+            },
+            ci_skip_token: git
+                .config_get("dx.ciSkipToken")?
+                .unwrap_or_else(|| DEFAULT_CI_SKIP_TOKEN.to_string()),
+            ci_bump_message: git
+                .config_get("dx.ciBumpMessage")?
+                .unwrap_or_else(|| DEFAULT_CI_BUMP_MESSAGE.to_string()),
+            signoff: git.config_get_bool("dx.signoff")?.unwrap_or(false),
+            stack_position: git
+                .config_get_bool(crate::stack::POSITION_CONFIG)?
+                .unwrap_or(false),
+            sync_description: git.config_get_bool("dx.syncDescription")?.unwrap_or(false),
+            run_hooks: git.config_get_bool("dx.runHooks")?.unwrap_or(false),
+            merge_options: match git.config_get(crate::resolve::MERGE_OPTIONS_CONFIG)? {
+                Some(value) => MergeOptions::parse(&value)?,
+                None => MergeOptions::default(),
+            },
+            pass_through_trailers: git.config_get_all("dx.passThroughTrailer")?,
+            mode: match git.config_get("dx.mode")?.as_deref() {
+                None | Some("branch") => Mode::Branch,
+                Some("gerrit") => Mode::Gerrit,
+                Some(_) => return Err(err::Error::InvalidConfig("dx.mode".to_string())),
+            },
+        })
+    }
+
+    /// Form a new target branch name for the given branch key. If the ref template has a date,
+    /// this is today's; see `crate::target_branch` to find a change's existing branch instead.
+    pub fn target_branch(&self, branch_key: &str) -> String {
+        self.ref_template.expand(&self.user, branch_key)
+    }
+
+    /// Recover the branch key from a target branch name, or return `None` if the ref template
+    /// could not have formed it.
+    pub fn branch_key_of<'a>(&self, branch: &'a str) -> Option<&'a str> {
+        self.ref_template.match_key(&self.user, branch)
+    }
+
+    /// How trailers are written in this repository.
+    pub fn trailer_syntax(&self) -> crate::trailers::Syntax {
+        crate::trailers::Syntax {
+            separators: self.trailer_separators.clone(),
+            pass_through: self.pass_through_trailers.clone(),
+        }
+    }
+}
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Shows the dx configuration options that are set")
+        .long_about(
+            "Shows the dx configuration options that are set, as `git config --list` does but \
+             only for the `dx` section. Keys are listed as Git lists them, with their section \
+             and variable names lowercased.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_LIST)
+                .help("List all dx options and their values")
+                .long("--list")
+                .short("-l")
+                .required(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SHOW_ORIGIN)
+                .help("Show where each option is set (file, or command line)")
+                .long("--show-origin"),
+        )
+}
+
+pub fn run(git: &mut GitStore, _config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let show_origin = matches.is_present(CLI_ARG_SHOW_ORIGIN);
+    for entry in git.config_entries()? {
+        if !entry.key.starts_with("dx.") {
+            continue;
+        }
+        if show_origin {
+            print!("{}\t", entry.origin);
+        }
+        match entry.value {
+            Some(ref value) => println!("{}={}", entry.key, value),
+            None => println!("{}", entry.key),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use crate::config::Config;
+use crate::err;
+use crate::git::GitStore;
+
+pub const SUBCOMMAND: &str = "diff";
+
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_DIFF_OPTIONS: &str = "diff-options";
+const CLI_ARG_FORK: &str = "fork";
+const CLI_ARG_REMOTE: &str = "remote";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Shows how a source commit differs from its remote target branch")
+        .long_about(
+            "Shows how a source commit differs from the head of its remote target branch, as of \
+             the last fetch: that is, what integrating it would change on the branch. Options \
+             after `--` are passed to `git diff` (e.g., `git dx diff -- --stat`).",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Source commit to compare")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote whose branches to compare against")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_FORK)
+                .help("Remote that holds integration branches, if not the `-r` remote")
+                .value_name("remote")
+                .long("--fork")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DIFF_OPTIONS)
+                .help("Options for `git diff`")
+                .multiple(true)
+                .last(true),
+        )
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let remotes = crate::Remotes::new(&remote, matches.value_of(CLI_ARG_FORK));
+    let commit = git
+        .commit(matches.value_of(CLI_ARG_COMMIT).unwrap())?
+        .clone();
+    let branch =
+        crate::branch_name(git, config, &commit.oid, &commit.trailers)?.ok_or_else(|| {
+            err::Error::MissingTrailer {
+                oid: commit.oid.to_string(),
+                key: config.branch_trailer.clone(),
+            }
+        })?;
+    let tip = remotes
+        .target_oid(git, &branch)?
+        .ok_or_else(|| err::Error::NoSuchCommit(format!("{}/{}", remotes.write, branch)))?;
+    let options: Vec<&str> = matches
+        .values_of(CLI_ARG_DIFF_OPTIONS)
+        .map(|values| values.collect())
+        .unwrap_or_default();
+    git.diff_trees(&tip, &commit.oid, &options)
+}
@@ -0,0 +1,381 @@
+use crate::clock;
+use crate::config::Config;
+use crate::err;
+use crate::git::GitStore;
+use crate::log::CommandExt;
+
+pub const SUBCOMMAND: &str = "doctor";
+
+const CLI_ARG_OPTIMIZE: &str = "optimize";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_REMOTE_BRANCHES: &str = "remote-branches";
+
+/// Age past which an unlanded dx branch whose source commit is not present locally is reported
+/// as possibly abandoned.
+const ABANDONED_DAYS: u64 = 90;
+
+/// Oldest Git version whose `commit-graph write` supports `--changed-paths`.
+const CHANGED_PATHS_GIT_VERSION: (u32, u32) = (2, 27);
+/// Oldest Git version that supports `fetch.writeCommitGraph`.
+const FETCH_WRITE_COMMIT_GRAPH_GIT_VERSION: (u32, u32) = (2, 24);
+/// Oldest Git version with a built-in filesystem monitor (`core.fsmonitor=true`).
+const BUILTIN_FSMONITOR_GIT_VERSION: (u32, u32) = (2, 36);
+
+/// Git features that git-dx uses, each with the oldest Git version that has it and what it is
+/// used for.
+const GIT_FEATURES: &[(&str, (u32, u32), &str)] = &[
+    (
+        "`interpret-trailers --no-divider`",
+        crate::init::MIN_GIT_VERSION,
+        "reading branch directives",
+    ),
+    (
+        "`--end-of-options`",
+        (2, 24),
+        "revisions that start with `-` (not yet required)",
+    ),
+    (
+        "`merge-tree --write-tree`",
+        crate::plan::MERGE_TREE_VERSION,
+        "checking for conflicts in `git dx plan` and sizing merge commits against both parents",
+    ),
+];
+
+/// Config option that, when false, suppresses the performance hint. We set it to false after
+/// showing the hint once.
+const PERFORMANCE_HINT_CONFIG: &str = "dx.performanceHint";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Checks the environment and repository setup for problems and slow configurations")
+        .arg(
+            clap::Arg::with_name(CLI_ARG_OPTIMIZE)
+                .help("Write the commit graph and enable recommended performance settings")
+                .long("--optimize"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE_BRANCHES)
+                .help("Survey the remote's dx branches and suggest which to delete")
+                .long_help(
+                    "Survey the dx branches on the remote, as of the last fetch: how old each \
+                     is, whether its source commit is present locally, and whether it has \
+                     landed on trunk. Branches that have landed are safe to delete, and the \
+                     command to delete them is printed.",
+                )
+                .long("--remote-branches"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote to check and survey (default: `dx.remote`, or `origin`)")
+                .short("-r")
+                .takes_value(true),
+        )
+}
+
+/// Performance-related features of the current repository.
+pub struct Performance {
+    pub version: (u32, u32),
+    /// Whether Git will use a commit-graph file, which speeds up ancestry queries and stack walks.
+    pub commit_graph: bool,
+    /// Whether a filesystem monitor is configured, which speeds up the checkouts that integration
+    /// performs.
+    pub fsmonitor: bool,
+}
+
+impl Performance {
+    pub fn detect(git: &GitStore) -> err::Result<Self> {
+        let version = git.version()?;
+        let commit_graph = git.config_get_bool("core.commitGraph")? != Some(false)
+            && (git.git_path("objects/info/commit-graph")?.exists()
+                || git.git_path("objects/info/commit-graphs")?.exists());
+        // May be a boolean or the path to a hook, so don't parse it as a boolean.
+        let fsmonitor = match git.config_get("core.fsmonitor")? {
+            None => false,
+            Some(value) => !matches!(
+                value.to_ascii_lowercase().as_str(),
+                "" | "false" | "no" | "off" | "0"
+            ),
+        };
+        Ok(Performance {
+            version,
+            commit_graph,
+            fsmonitor,
+        })
+    }
+
+    /// Whether Git has a built-in filesystem monitor on this platform.
+    pub fn fsmonitor_supported(&self) -> bool {
+        cfg!(any(target_os = "macos", windows)) && self.version >= BUILTIN_FSMONITOR_GIT_VERSION
+    }
+
+    fn is_optimal(&self) -> bool {
+        self.commit_graph && (self.fsmonitor || !self.fsmonitor_supported())
+    }
+}
+
+/// Print a hint to run `git dx doctor --optimize` if the repository is missing performance
+/// features, unless this hint has been shown before.
+pub fn performance_hint(git: &GitStore) -> err::Result<()> {
+    if !crate::log::enabled(crate::log::NORMAL)
+        || git.config_get_bool(PERFORMANCE_HINT_CONFIG)? == Some(false)
+    {
+        return Ok(());
+    }
+    if Performance::detect(git)?.is_optimal() {
+        return Ok(());
+    }
+    info!("hint: this repository has no commit-graph or fsmonitor, which may slow git-dx down");
+    info!("hint: run `git dx doctor --optimize` to enable them");
+    // Best effort: failing to record that the hint was shown shouldn't fail the command.
+    let _ = git.config_set(PERFORMANCE_HINT_CONFIG, "false");
+    Ok(())
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let mut perf = Performance::detect(git)?;
+    if matches.is_present(CLI_ARG_OPTIMIZE) {
+        optimize(git, &perf)?;
+        perf = Performance::detect(git)?;
+    }
+
+    let (major, minor) = perf.version;
+    report(
+        perf.version >= crate::init::MIN_GIT_VERSION,
+        format!("git version {}.{}", major, minor),
+        || {
+            format!(
+                "upgrade Git to {}.{} or newer",
+                crate::init::MIN_GIT_VERSION.0,
+                crate::init::MIN_GIT_VERSION.1
+            )
+        },
+    );
+    for &(feature, version, purpose) in GIT_FEATURES {
+        report(
+            perf.version >= version,
+            format!("git supports {}, for {}", feature, purpose),
+            || format!("upgrade Git to {}.{} or newer", version.0, version.1),
+        );
+    }
+    let optimize_hint = || "run `git dx doctor --optimize`".to_string();
+    report(perf.commit_graph, "commit-graph".to_string(), optimize_hint);
+    if perf.fsmonitor_supported() {
+        report(perf.fsmonitor, "fsmonitor".to_string(), optimize_hint);
+    }
+
+    check_committer(git);
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    check_remote(git, &remote)?;
+    check_head(git, config)?;
+
+    if matches.is_present(CLI_ARG_REMOTE_BRANCHES) {
+        survey_remote_branches(git, config, &remote)?;
+    }
+    Ok(())
+}
+
+/// Print whether a check passed, and if not, how to fix it.
+fn report(ok: bool, description: String, fix: impl FnOnce() -> String) {
+    println!("{}: {}", if ok { "ok" } else { "warning" }, description);
+    if !ok {
+        let fix = fix();
+        info!("hint: {}", fix);
+    }
+}
+
+/// Check that Git knows who to commit as, since integration creates commits.
+fn check_committer(git: &GitStore) {
+    match git.committer() {
+        Ok(committer) => report(
+            true,
+            format!("committer {} <{}>", committer.name, committer.email),
+            String::new,
+        ),
+        Err(_) => report(false, "no committer identity".to_string(), || {
+            "set `user.name` and `user.email` with `git config --global`".to_string()
+        }),
+    }
+}
+
+/// Check that `remote` is configured and reachable, and that its trunk is known without asking it.
+fn check_remote(git: &GitStore, remote: &str) -> err::Result<()> {
+    if git.config_get(&format!("remote.{}.url", remote))?.is_none() {
+        report(
+            false,
+            format!("remote {} is not configured", remote),
+            || {
+                format!(
+                    "run `git remote add {} <url>`, or `git dx init -r <remote>` to use another",
+                    remote
+                )
+            },
+        );
+        return Ok(());
+    }
+    match git.ls_remote_head(remote) {
+        Ok(_) => report(true, format!("remote {} is reachable", remote), String::new),
+        Err(e) => report(
+            false,
+            format!("remote {} is unreachable: {}", remote, e),
+            || {
+                format!(
+                    "check the URL with `git remote get-url {}`, and your network and \
+                         credentials",
+                    remote
+                )
+            },
+        ),
+    }
+    let trunk = match git.config_get("dx.trunk")? {
+        Some(trunk) => Some(trunk),
+        None => git
+            .symbolic_ref(&format!("refs/remotes/{}/HEAD", remote))?
+            .and_then(|target| {
+                target
+                    .strip_prefix(&format!("refs/remotes/{}/", remote))
+                    .map(|branch| branch.to_string())
+            }),
+    };
+    match trunk {
+        Some(trunk) => report(true, format!("trunk {}", trunk), String::new),
+        None => report(
+            false,
+            format!(
+                "no trunk configured, and {} has no remote-tracking HEAD",
+                remote
+            ),
+            || {
+                format!(
+                    "run `git dx init` or `git remote set-head {} --auto`",
+                    remote
+                )
+            },
+        ),
+    }
+    Ok(())
+}
+
+/// Check that the branch directive of `HEAD`, if any, parses and names a valid branch.
+fn check_head(git: &mut GitStore, config: &Config) -> err::Result<()> {
+    let head = match git.rev_parse_commit("HEAD")? {
+        Some(head) => head,
+        None => return Ok(()),
+    };
+    let trailers = git.commit(&head)?.trailers.clone();
+    match crate::branch_name(git, config, &head, &trailers) {
+        Ok(Some(branch)) => report(true, format!("HEAD targets {}", branch), String::new),
+        Ok(None) => report(
+            true,
+            format!("HEAD has no `{}:` trailer", config.branch_trailer),
+            String::new,
+        ),
+        Err(e) => report(false, format!("HEAD: {}", e), || {
+            format!(
+                "run `git commit --amend` to give HEAD exactly one `{}:` trailer, with a valid \
+                 branch name",
+                config.branch_trailer
+            )
+        }),
+    }
+    Ok(())
+}
+
+/// Print a line for each dx branch on `remote` giving its age, whether its source commit is
+/// present locally, and whether it has landed on trunk, followed by a summary of how many can be
+/// deleted and the command to delete them.
+fn survey_remote_branches(git: &mut GitStore, config: &Config, remote: &str) -> err::Result<()> {
+    let trunk = crate::default_trunk(git, remote)?;
+    let trunk_tip = crate::trunk_oid(git, remote, &trunk)?;
+    let now = clock::now();
+
+    let mut total = 0;
+    let mut landed = Vec::new();
+    let mut abandoned = 0;
+    for branch in git.remote_branch_tips(remote)? {
+        if branch.name == trunk || config.branch_key_of(&branch.name).is_none() {
+            continue;
+        }
+        total += 1;
+        let days = now.saturating_sub(branch.committed) / 86400;
+        let trailers = git.commit(&branch.oid)?.trailers.clone();
+        let source = crate::recorded_source(config, &branch.oid, &trailers)
+            .ok()
+            .flatten();
+        let source_present = match source {
+            Some(ref source) => git.rev_parse_commit(source)?.is_some(),
+            None => false,
+        };
+        let is_landed = git.is_ancestor(&branch.oid, &trunk_tip)?;
+        let status = if is_landed {
+            "landed"
+        } else if !source_present && days >= ABANDONED_DAYS {
+            abandoned += 1;
+            "not landed, possibly abandoned"
+        } else {
+            "not landed"
+        };
+        println!(
+            "\t{}: {} day(s) old, source {}, {}",
+            branch.name,
+            days,
+            match (source, source_present) {
+                (Some(source), true) => format!("{} present", source),
+                (Some(source), false) => format!("{} missing", source),
+                (None, _) => "unknown".to_string(),
+            },
+            status
+        );
+        if is_landed {
+            landed.push(branch.name);
+        }
+    }
+
+    println!(
+        "{}: {} of {} dx branch(es) on {} have landed on {}",
+        if landed.is_empty() { "ok" } else { "warning" },
+        landed.len(),
+        total,
+        remote,
+        trunk
+    );
+    if abandoned > 0 {
+        info!(
+            "hint: {} more are over {} days old with no local source commit; check with their \
+             authors before deleting them",
+            abandoned, ABANDONED_DAYS
+        );
+    }
+    if !landed.is_empty() {
+        info!(
+            "hint: to delete the landed branches, run `git dx prune -r {}`",
+            remote
+        );
+    }
+    Ok(())
+}
+
+/// Write the commit graph and enable performance settings supported by this version of Git.
+fn optimize(git: &GitStore, perf: &Performance) -> err::Result<()> {
+    let mut cmd = git.command();
+    cmd.args(["commit-graph", "write", "--reachable"]);
+    if perf.version >= CHANGED_PATHS_GIT_VERSION {
+        cmd.arg("--changed-paths");
+    }
+    err::from_git(&cmd.logged_output()?, || {
+        "failed to write commit graph".to_string()
+    })?;
+    info!("wrote commit graph");
+
+    let mut settings = vec![("core.commitGraph", "true")];
+    if perf.version >= FETCH_WRITE_COMMIT_GRAPH_GIT_VERSION {
+        settings.push(("fetch.writeCommitGraph", "true"));
+    }
+    if perf.fsmonitor_supported() {
+        settings.push(("core.fsmonitor", "true"));
+    }
+    for (key, value) in settings {
+        git.config_set(key, value)?;
+        info!("set {} = {}", key, value);
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use crate::config::Config;
+use crate::err;
+use crate::forge;
+use crate::git::GitStore;
+
+pub const SUBCOMMAND: &str = "drop";
+
+const CLI_ARG_BRANCH: &str = "branch";
+const CLI_ARG_CASCADE: &str = "cascade";
+const CLI_ARG_DRY_RUN: &str = "dry_run";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_TRUNK: &str = "trunk";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Deletes remote target branches without landing them")
+        .long_about(
+            "Deletes remote target branches without landing them. A branch that other dx \
+             branches are built on, or that open pull requests target, is not deleted unless \
+             `--cascade` is given, in which case those branches are deleted too.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_BRANCH)
+                .help("Target branches to delete (e.g., wchargin-foo)")
+                .required(true)
+                .multiple(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_CASCADE)
+                .help("Also delete the branches that are built on these ones")
+                .long("--cascade"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DRY_RUN)
+                .help("Use a dry-run push only")
+                .long("--dry-run")
+                .short("-n"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help("Remote branch that the branches are based on (default: the remote's HEAD)")
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote that holds the branches")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(crate::push::push_option_arg())
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+        Some(trunk) => trunk.to_string(),
+        None => crate::default_trunk(git, &remote)?,
+    };
+    let trunk_tip = crate::trunk_oid(git, &remote, &trunk)?;
+    let cascade = matches.is_present(CLI_ARG_CASCADE);
+    let forge = forge::for_remote(git, &remote)?;
+
+    let mut doomed: Vec<(String, crate::git::Oid)> = Vec::new();
+    for branch in matches.values_of(CLI_ARG_BRANCH).unwrap() {
+        let cannot_drop = |reason: String| err::Error::CannotDrop {
+            branch: branch.to_string(),
+            reason,
+        };
+        if config.branch_key_of(branch).is_none() {
+            return Err(cannot_drop("not a dx target branch".to_string()));
+        }
+        let tip = crate::remote_branch_oid(git, &remote, branch)?
+            .ok_or_else(|| cannot_drop(format!("no such branch on {}", remote)))?;
+        doomed.push((branch.to_string(), tip));
+
+        let dependents = dependents(git, config, &remote, &trunk_tip, branch)?;
+        let pulls = match forge {
+            Some(ref forge) => forge.pull_requests_onto(branch)?,
+            None => Vec::new(),
+        };
+        if cascade {
+            doomed.extend(dependents);
+            continue;
+        }
+        let mut blockers: Vec<String> = dependents.into_iter().map(|(name, _)| name).collect();
+        blockers.extend(pulls.into_iter().map(|pull| pull.url));
+        if !blockers.is_empty() {
+            return Err(cannot_drop(format!(
+                "{} depend(s) on it; pass --cascade to delete them too",
+                blockers.join(", ")
+            )));
+        }
+    }
+    doomed.sort();
+    doomed.dedup();
+
+    let mut args = vec!["--atomic".to_string()];
+    if matches.is_present(CLI_ARG_DRY_RUN) {
+        args.push("--dry-run".to_string());
+    }
+    for option in crate::push::push_options(config, matches) {
+        args.push(format!("--push-option={}", option));
+    }
+    // Only delete each branch if it still points to the commit that we checked.
+    for (branch, tip) in &doomed {
+        args.push(format!("--force-with-lease=refs/heads/{}:{}", branch, tip));
+    }
+    args.push(remote.clone());
+    for (branch, _) in &doomed {
+        args.push(format!(":refs/heads/{}", branch));
+    }
+    crate::push::push(git, &args)?.print();
+    git.invalidate_refs();
+    Ok(())
+}
+
+/// Find the dx branches on `remote` that are built on `branch`, directly or through other
+/// branches, with their tips. A branch is built on another if its history since trunk includes
+/// commits with the other's branch key, as its diffbase merges and first commit bring in.
+pub fn dependents(
+    git: &mut GitStore,
+    config: &Config,
+    remote: &str,
+    trunk_tip: &str,
+    branch: &str,
+) -> err::Result<Vec<(String, crate::git::Oid)>> {
+    let key = match config.branch_key_of(branch) {
+        Some(key) => key,
+        None => return Ok(Vec::new()),
+    };
+    let mut result = Vec::new();
+    for other in git.remote_branch_tips(remote)? {
+        if other.name == branch || config.branch_key_of(&other.name).is_none() {
+            continue;
+        }
+        for oid in git.rev_list_no_merges(trunk_tip, &other.oid)? {
+            let commit = git.commit(&oid)?;
+            if let Ok(Some(commit_key)) = crate::branch_key(config, &commit.oid, &commit.trailers) {
+                if commit_key == key {
+                    result.push((other.name.clone(), other.oid.clone()));
+                    break;
+                }
+            }
+        }
+    }
+    Ok(result)
+}
@@ -1,4 +1,5 @@
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     /// A user-provided commit reference does not exist.
     NoSuchCommit(String),
@@ -7,6 +8,176 @@ pub enum Error {
     /// A commit message is expected to have at most one trailer with the given key, but has
     /// more than one.
     DuplicateTrailer { oid: String, key: String },
+    /// The branch directive of the given commit yields a target branch name that is not a valid
+    /// branch name, as judged by `git check-ref-format --branch`.
+    InvalidBranchName { oid: String, branch: String },
+    /// The trunk branch of the given remote was needed but not specified, and could not be
+    /// inferred because the remote has no remote-tracking `HEAD` and did not report its `HEAD`
+    /// when asked directly.
+    UnknownTrunk { remote: String },
+    /// The named remote is not configured.
+    NoSuchRemote(String),
+    /// The named branch does not match `dx.refTemplate`, so it is not one that git-dx integrates
+    /// into.
+    NotTargetBranch(String),
+    /// The installed `git(1)` is older than the oldest version (major, minor) that we support.
+    UnsupportedGitVersion {
+        found: (u32, u32),
+        required: (u32, u32),
+    },
+    /// Another run of git-dx holds the repository lock at the given path. The holder is its
+    /// process ID, if recorded.
+    Locked {
+        path: String,
+        holder: Option<String>,
+    },
+    /// A hook script that was not installed by git-dx already exists at the given path.
+    HookExists(String),
+    /// A dx hook (e.g., `pre-push`), or under `--verify` a Git hook (e.g., `commit-msg`), run for a
+    /// target branch exited unsuccessfully, as described.
+    HookFailed {
+        hook: String,
+        target_branch: String,
+        status: String,
+    },
+    /// Validation of dx trailers (by `git dx check` or a hook) found the given number of errors,
+    /// which have already been reported.
+    CheckFailed { errors: usize },
+    /// A change cannot be landed onto trunk in its current state: e.g., its remote branch is not
+    /// up to date with the source commit, or its diffbase has not yet landed.
+    CannotLand { branch: String, reason: String },
+    /// A remote target branch cannot be dropped: e.g., other branches are built on it.
+    CannotDrop { branch: String, reason: String },
+    /// A change cannot be abandoned: e.g., other branches are built on its target branch.
+    CannotAbandon { branch: String, reason: String },
+    /// Changes pushed to a target branch cannot be pulled into its source commit: e.g., they
+    /// conflict with it, or the worktree has uncommitted changes.
+    CannotPull { branch: String, reason: String },
+    /// A deletion (described, e.g. "deleting 2 branch(es) from origin") was declined at the
+    /// prompt, or there was no terminal to ask on and `--yes` was not given.
+    NotConfirmed(String),
+    /// Uncommitted changes could not be absorbed into the stack.
+    CannotAbsorb(String),
+    /// Work stopped on conflicts that need resolving by hand, as when a rebase onto an amended
+    /// commit stopped: `action` (e.g., "restack wchargin-foo") could not be done, for `reason`.
+    Conflicts { action: String, reason: String },
+    /// The latest round of review on a target branch cannot be rolled back: e.g., the branch's
+    /// head is a diffbase merge, or the branch has only one round.
+    CannotRollBack { branch: String, reason: String },
+    /// The remote target branch shares no history with the remote diffbase that would be merged
+    /// into it (e.g., because upstream history was rewritten), so merging would either fail or
+    /// pull in the whole of both histories.
+    UnrelatedHistories {
+        branch: String,
+        remote_commit: String,
+        diffbase: String,
+    },
+    /// A branch trailer cannot be added to the given commit by `git dx track`: e.g., it already
+    /// has one, or it cannot be rewritten.
+    CannotTrack { oid: String, reason: String },
+    /// A remote branch cannot be adopted by `git dx adopt`: e.g., its name doesn't fit the ref
+    /// template, or the diffbase merge into it would conflict.
+    CannotAdopt { branch: String, reason: String },
+    /// A target branch cannot be renamed by `git dx rename`: e.g., the new name is taken, or no
+    /// commit in the current stack has its branch key.
+    CannotRename { branch: String, reason: String },
+    /// The diffbase merge changed some paths that the source commit also changes, so the merge
+    /// results for those paths were replaced by the source commit's contents (only an error under
+    /// `--strict`).
+    DiscardedMergeChanges { branch: String, paths: Vec<String> },
+    /// No change template with the given name is defined in `.gitdx.toml`.
+    UnknownTemplate {
+        name: String,
+        available: Vec<String>,
+    },
+    /// The change templates file could not be parsed.
+    InvalidTemplates { path: String, reason: String },
+    /// A `regen:` conflict resolution command (from `dx.resolve`) failed.
+    ResolveFailed { command: String, stderr: String },
+    /// A change is bigger than its size budget allows; each violation describes one limit that
+    /// it exceeds.
+    BudgetExceeded {
+        branch: String,
+        violations: Vec<String>,
+        /// Trailer key with which the change may raise its budget.
+        trailer: String,
+    },
+    /// The budget trailer of the given commit is malformed.
+    InvalidBudget { oid: String, reason: String },
+    /// The given commit cannot be pushed to its target branch without discarding the branch's
+    /// remote history, which git-dx never does.
+    NotFastForward { branch: String, oid: String },
+    /// A target branch on the remote is not where its remote-tracking ref says it is (`None`
+    /// meaning that the branch does not exist), so integrating against the remote-tracking ref
+    /// would push over changes that we haven't seen.
+    StaleRemote {
+        remote: String,
+        branch: String,
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+    /// A target branch on the remote no longer contains the commit that git-dx last pushed to it,
+    /// as when someone force-pushes it by hand, so building on it may lose their changes or ours.
+    RemoteRewritten {
+        remote: String,
+        branch: String,
+        pushed: String,
+        actual: String,
+    },
+    /// A target branch on the remote has commits that git-dx did not make since it last wrote to
+    /// it, each given as its object ID and subject, which integration would undo.
+    RemoteEdits {
+        remote: String,
+        branch: String,
+        commits: Vec<String>,
+    },
+    /// The diffbase merge was skipped (by `--no-merge-diffbase`), but the remote target branch at
+    /// `remote_commit` does not contain the remote diffbase, so the merge was needed.
+    DiffbaseNotMerged {
+        branch: String,
+        remote_commit: String,
+        diffbase: String,
+    },
+    /// A line of input to `git dx batch` is not an integration that it can run.
+    InvalidInstruction { instruction: String, reason: String },
+    /// Some instructions given to `git dx batch` failed (and were reported as they did).
+    BatchIncomplete { failed: usize, total: usize },
+    /// The remote rejected some ref updates of a push; each entry names a remote ref and, if
+    /// given, Git's reason for rejecting it.
+    PushRejected { refs: Vec<String> },
+    /// Some changes of a stack failed to integrate (and were reported as they did), and the
+    /// changes that build on them were skipped.
+    StackIncomplete { failed: usize, skipped: usize },
+    /// The named remote is not on the code host that `dx.forge` names, or no API token for it is
+    /// available.
+    NoForge(String),
+    /// The named target branch has no open pull request.
+    NoPullRequest(String),
+    /// A request to a code host's API (e.g., to label a pull request) failed.
+    ForgeRequest { request: String, reason: String },
+    /// The named configuration option has an invalid value.
+    InvalidConfig(String),
+    /// The ref template for target branch names (`dx.refTemplate`) is malformed, or mentions a
+    /// variable that has no value.
+    InvalidRefTemplate { template: String, reason: String },
+    /// The template for update commit messages (`dx.updateMessage`) is malformed.
+    InvalidUpdateTemplate { template: String, reason: String },
+    /// The index or working tree has uncommitted changes, which integration would mix into its
+    /// commits.
+    DirtyWorktree,
+    /// After pushing, the mirror given by `--verify-mirror` did not have the pushed commits on
+    /// the given branches (each described with what the mirror has instead) before the timeout.
+    MirrorMismatch {
+        mirror: String,
+        branches: Vec<String>,
+    },
+    /// Integrated commits were pushed to their target branches' remote, but pushing them to the
+    /// given secondary remotes (from `-r` or `dx.pushRemotes`) failed.
+    SecondaryPushFailed { remotes: Vec<String> },
+    /// `git dx undo` found no integrations in the journal to roll back.
+    NothingToUndo,
+    /// The description of an update to the given target branch was left empty in the editor.
+    EmptyUpdateMessage { branch: String },
     /// User-supplied text (e.g., a commit message) was improperly encoded. Data not in UTF-8 must
     /// be declared as such via the `i18n.commitEncoding` setting at commit time. For details, see
     /// `man git-commit`.
@@ -17,12 +188,38 @@ pub enum Error {
     /// The `git(1)` binary behaved unexpectedly: e.g., `rev-parse --verify REVISION` returned
     /// success but did not write an object ID to standard output.
     GitContract(String),
+    /// The run was cancelled with its `cancel::Token`.
+    Cancelled,
+    /// Arguments given to a library entry point (e.g., `integrate`) don't parse as flags of the
+    /// corresponding command.
+    InvalidArguments(String),
     /// Underlying IO error (e.g., failure to invoke `git`).
     IoError(std::io::Error),
+    /// An error from within a higher-level operation, described by `context` (e.g., "merging
+    /// remote diffbase 1234abcd into wchargin-foo").
+    Context { context: String, source: Box<Error> },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+// Exit codes by class of error, for scripts to branch on. These will not change between releases;
+// see "Exit status" in README.md.
+
+/// Any error not in a class below, including bad command-line usage.
+pub const EXIT_FAILURE: i32 = 1;
+/// A source commit's trailers are missing or invalid.
+pub const EXIT_BAD_TRAILERS: i32 = 2;
+/// A commit or remote named by the user does not exist.
+pub const EXIT_NOT_FOUND: i32 = 3;
+/// Work stopped on conflicts that need resolving by hand.
+pub const EXIT_CONFLICTS: i32 = 4;
+/// A remote branch is not as git-dx expected it: it moved, was rewritten, or was edited by hand.
+pub const EXIT_STALE_REMOTE: i32 = 5;
+/// A check refused the change: a hook, `git dx check`, a size budget, or `--strict`.
+pub const EXIT_REJECTED: i32 = 6;
+/// Some changes of a stack or batch, or some pushes, failed while others succeeded.
+pub const EXIT_INCOMPLETE: i32 = 7;
+
 impl Error {
     /// Parse user-supplied bytes that are expected to represent valid UTF-8, failing with an
     /// `InvalidEncoding` error referring to `context` if the bytes are not valid UTF-8.
@@ -32,14 +229,369 @@ impl Error {
             err: e,
         })
     }
+
+    /// The exit code for this error, by its class: one of the `EXIT_*` constants.
+    pub fn exit_code(&self) -> i32 {
+        match self.root() {
+            Error::MissingTrailer { .. }
+            | Error::DuplicateTrailer { .. }
+            | Error::InvalidBranchName { .. } => EXIT_BAD_TRAILERS,
+            Error::NoSuchCommit(_) | Error::NoSuchRemote(_) => EXIT_NOT_FOUND,
+            Error::ResolveFailed { .. } | Error::Conflicts { .. } => EXIT_CONFLICTS,
+            Error::StaleRemote { .. }
+            | Error::NotFastForward { .. }
+            | Error::RemoteRewritten { .. }
+            | Error::RemoteEdits { .. }
+            | Error::UnrelatedHistories { .. }
+            | Error::DiffbaseNotMerged { .. }
+            | Error::PushRejected { .. } => EXIT_STALE_REMOTE,
+            Error::HookFailed { .. }
+            | Error::CheckFailed { .. }
+            | Error::BudgetExceeded { .. }
+            | Error::DiscardedMergeChanges { .. } => EXIT_REJECTED,
+            Error::StackIncomplete { .. }
+            | Error::BatchIncomplete { .. }
+            | Error::SecondaryPushFailed { .. } => EXIT_INCOMPLETE,
+            _ => EXIT_FAILURE,
+        }
+    }
+
+    /// The innermost error, looking through any `Context` wrappers. Use this to match on the
+    /// kind of an error that may have had context attached.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            other => other,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::NoSuchCommit(rev) => write!(f, "no such commit: {}", rev),
+            Error::MissingTrailer { oid, key } => write!(
+                f,
+                "commit {} has no {:?} trailer; add one (e.g., with `git dx track`)",
+                oid, key
+            ),
+            Error::DuplicateTrailer { oid, key } => write!(
+                f,
+                "commit {} has more than one {:?} trailer; remove all but one",
+                oid, key
+            ),
+            Error::InvalidBranchName { oid, branch } => write!(
+                f,
+                "commit {} names branch {:?}, which is not a valid branch name; \
+                 fix its branch trailer",
+                oid, branch
+            ),
+            Error::UnknownTrunk { remote } => write!(
+                f,
+                "cannot determine the trunk branch of {0}; pass `--trunk`, run \
+                 `git remote set-head {0} --auto`, or run `git dx init`",
+                remote
+            ),
+            Error::NotTargetBranch(branch) => write!(
+                f,
+                "{} is not a dx target branch (see `dx.refTemplate`)",
+                branch
+            ),
+            Error::NoSuchRemote(remote) => write!(
+                f,
+                "no such remote: {}; see `git remote -v` for configured remotes",
+                remote
+            ),
+            Error::UnsupportedGitVersion { found, required } => write!(
+                f,
+                "git {}.{} is too old; git-dx requires git {}.{} or newer",
+                found.0, found.1, required.0, required.1
+            ),
+            Error::Locked { path, holder } => write!(
+                f,
+                "another git-dx{} is running in this repository; if not, remove {}",
+                match holder {
+                    Some(pid) => format!(" (process {})", pid),
+                    None => String::new(),
+                },
+                path
+            ),
+            Error::HookExists(path) => write!(
+                f,
+                "{} already exists and was not installed by git-dx; move it aside first",
+                path
+            ),
+            Error::HookFailed {
+                hook,
+                target_branch,
+                status,
+            } => write!(f, "{} hook for {} failed ({})", hook, target_branch, status),
+            Error::CheckFailed { errors } => {
+                write!(f, "found {} error(s) in dx trailers (see above)", errors)
+            }
+            Error::UnrelatedHistories {
+                branch,
+                remote_commit,
+                diffbase,
+            } => write!(
+                f,
+                "remote branch {} (at {}) shares no history with its diffbase {}, as happens \
+                 when upstream history is rewritten; delete the remote branch to start it over, \
+                 or pass `--allow-unrelated` to merge anyway",
+                branch, remote_commit, diffbase
+            ),
+            Error::CannotLand { branch, reason } => {
+                write!(f, "cannot land {}: {}", branch, reason)
+            }
+            Error::CannotDrop { branch, reason } => {
+                write!(f, "cannot drop {}: {}", branch, reason)
+            }
+            Error::CannotAbandon { branch, reason } => {
+                write!(f, "cannot abandon {}: {}", branch, reason)
+            }
+            Error::CannotPull { branch, reason } => {
+                write!(f, "cannot pull {}: {}", branch, reason)
+            }
+            Error::NotConfirmed(action) => write!(
+                f,
+                "{} was not confirmed; pass --yes to proceed without asking",
+                action
+            ),
+            Error::CannotAbsorb(reason) => write!(f, "cannot absorb changes: {}", reason),
+            Error::Conflicts { action, reason } => write!(f, "cannot {}: {}", action, reason),
+            Error::CannotRollBack { branch, reason } => {
+                write!(f, "cannot roll back {}: {}", branch, reason)
+            }
+            Error::CannotTrack { oid, reason } => {
+                write!(f, "cannot add a branch trailer to {}: {}", oid, reason)
+            }
+            Error::CannotAdopt { branch, reason } => {
+                write!(f, "cannot adopt {}: {}", branch, reason)
+            }
+            Error::CannotRename { branch, reason } => {
+                write!(f, "cannot rename {}: {}", branch, reason)
+            }
+            Error::DiscardedMergeChanges { branch, paths } => write!(
+                f,
+                "source commit for {} overrides diffbase merge changes to: {}; \
+                 resolve them in the source commit or drop `--strict`",
+                branch,
+                paths.join(", ")
+            ),
+            Error::UnknownTemplate { name, available } if available.is_empty() => write!(
+                f,
+                "no change template {:?}; define one under `[templates.{}]` in .gitdx.toml",
+                name, name
+            ),
+            Error::UnknownTemplate { name, available } => write!(
+                f,
+                "no change template {:?}; available templates: {}",
+                name,
+                available.join(", ")
+            ),
+            Error::InvalidTemplates { path, reason } => {
+                write!(f, "cannot read change templates from {}: {}", path, reason)
+            }
+            Error::ResolveFailed { command, stderr } => write!(
+                f,
+                "conflict resolution command `{}` failed: {}; fix it or its `dx.resolve` entry",
+                command, stderr
+            ),
+            Error::BudgetExceeded {
+                branch,
+                violations,
+                trailer,
+            } => write!(
+                f,
+                "change for {} is over its size budget: {}; split it up, or raise the budget \
+                 with a trailer like \"{}: lines=unlimited\"",
+                branch,
+                violations.join(", "),
+                trailer
+            ),
+            Error::InvalidBudget { oid, reason } => {
+                write!(f, "commit {} has a bad budget trailer: {}", oid, reason)
+            }
+            Error::NotFastForward { branch, oid } => write!(
+                f,
+                "{} does not contain the remote head of {}; integrate it instead of pushing it \
+                 directly",
+                oid, branch
+            ),
+            Error::StaleRemote {
+                remote,
+                branch,
+                expected,
+                actual,
+            } => {
+                let describe = |oid: &Option<String>| match oid {
+                    Some(oid) => format!("at {}", oid),
+                    None => "absent".to_string(),
+                };
+                write!(
+                    f,
+                    "branch {} on {} is {}, but was {} when last fetched; run `git fetch {}` and \
+                     integrate again",
+                    branch,
+                    remote,
+                    describe(actual),
+                    describe(expected),
+                    remote
+                )
+            }
+            Error::RemoteRewritten {
+                remote,
+                branch,
+                pushed,
+                actual,
+            } => write!(
+                f,
+                "branch {} on {} (at {}) does not contain {}, which git-dx last pushed to it, so \
+                 it has been rewritten since; pass `--accept-remote-rewrite` to build on it anyway",
+                branch, remote, actual, pushed
+            ),
+            Error::RemoteEdits {
+                remote,
+                branch,
+                commits,
+            } => write!(
+                f,
+                "branch {} on {} has commits that git-dx did not make, which integrating would \
+                 undo:\n\t{}\nrun `git dx pull` to fold them into the source commit, or pass \
+                 `--overwrite-remote-edits` to integrate anyway",
+                branch,
+                remote,
+                commits.join("\n\t")
+            ),
+            Error::DiffbaseNotMerged {
+                branch,
+                remote_commit,
+                diffbase,
+            } => write!(
+                f,
+                "remote branch {} (at {}) does not contain its diffbase {}, which has moved since \
+                 it was last merged; drop `--no-merge-diffbase` to merge it",
+                branch, remote_commit, diffbase
+            ),
+            Error::InvalidInstruction {
+                instruction,
+                reason,
+            } => write!(f, "invalid instruction `{}`: {}", instruction, reason),
+            Error::BatchIncomplete { failed, total } => {
+                write!(f, "{} of {} instructions failed", failed, total)
+            }
+            Error::PushRejected { refs } => write!(
+                f,
+                "remote rejected push of {}; fetch and integrate again",
+                refs.join(", ")
+            ),
+            Error::StackIncomplete { failed, skipped } => write!(
+                f,
+                "failed to integrate {} change(s) of the stack, and skipped {} that build on them",
+                failed, skipped
+            ),
+            Error::NoPullRequest(branch) => write!(
+                f,
+                "no open pull request for {}; open one with `git dx pr`",
+                branch
+            ),
+            Error::NoForge(remote) => write!(
+                f,
+                "remote {} is not on a known code host, or there is no API token for it; see \
+                 dx.forge",
+                remote
+            ),
+            Error::ForgeRequest { request, reason } => {
+                write!(f, "code host request {} failed: {}", request, reason)
+            }
+            Error::InvalidConfig(key) => write!(
+                f,
+                "invalid value for config option {}; see `git config --get-all {}`",
+                key, key
+            ),
+            Error::InvalidRefTemplate { template, reason } => write!(
+                f,
+                "invalid ref template {:?} in dx.refTemplate: {}",
+                template, reason
+            ),
+            Error::InvalidUpdateTemplate { template, reason } => write!(
+                f,
+                "invalid update message template {:?} in dx.updateMessage: {}",
+                template, reason
+            ),
+            Error::DirtyWorktree => write!(
+                f,
+                "you have uncommitted changes, which integration would mix into its commits; \
+                 commit or stash them, or pass --autostash"
+            ),
+            Error::MirrorMismatch { mirror, branches } => write!(
+                f,
+                "mirror {} does not match what was pushed: {}",
+                mirror,
+                branches.join("; ")
+            ),
+            Error::SecondaryPushFailed { remotes } => write!(
+                f,
+                "pushed, but failed to push to {} as well",
+                remotes.join(", ")
+            ),
+            Error::NothingToUndo => write!(f, "nothing to undo: the integration journal is empty"),
+            Error::EmptyUpdateMessage { branch } => write!(
+                f,
+                "aborting integration of {} due to empty update message",
+                branch
+            ),
+            Error::InvalidEncoding { context, err } => write!(
+                f,
+                "{} is not valid UTF-8 ({}); set `i18n.commitEncoding` when committing \
+                 non-UTF-8 messages",
+                context, err
+            ),
+            Error::GitContract(msg) => write!(f, "unexpected git behavior: {}", msg),
+            Error::Cancelled => write!(f, "cancelled"),
+            Error::InvalidArguments(msg) => write!(f, "invalid arguments: {}", msg),
+            Error::IoError(e) => write!(f, "I/O error: {}", e),
+            Error::Context { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidEncoding { err, .. } => Some(err),
+            Error::IoError(e) => Some(e),
+            Error::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
 }
 
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Error {
+        if crate::cancel::is_cancellation(&e) {
+            return Error::Cancelled;
+        }
         Error::IoError(e)
     }
 }
 
+/// Extension methods for attaching context to errors.
+pub trait ResultExt<T> {
+    /// Wrap an error in a `Context` describing the operation that failed. The description is
+    /// only computed on failure.
+    fn context<F: FnOnce() -> String>(self, context: F) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for std::result::Result<T, E> {
+    fn context<F: FnOnce() -> String>(self, context: F) -> Result<T> {
+        self.map_err(|e| Error::Context {
+            context: context(),
+            source: Box::new(e.into()),
+        })
+    }
+}
+
 pub fn from_git<F: FnOnce() -> String>(output: &std::process::Output, fmt: F) -> Result<()> {
     if output.status.success() {
         return Ok(());
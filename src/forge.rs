@@ -0,0 +1,590 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::err;
+use crate::git::{Commit, GitStore};
+use crate::log::CommandExt;
+
+/// Config option naming the code host: `github` (the default) or `gitlab`.
+const FORGE_CONFIG: &str = "dx.forge";
+/// Config option naming the upstream GitHub repository as `owner/name`, for when it can't be
+/// inferred from the remote URL.
+const REPOSITORY_CONFIG: &str = "dx.githubRepository";
+/// Config option giving the base URL of the GitHub REST API (e.g., for GitHub Enterprise).
+const API_URL_CONFIG: &str = "dx.githubApiUrl";
+
+/// A GitHub repository, as reached through its REST API.
+pub struct GitHub {
+    api_url: String,
+    /// The repository's web page, like `https://github.com/owner/name`.
+    web_url: String,
+    token: String,
+    owner: String,
+    name: String,
+}
+
+/// Overall state of the CI checks on a commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckState {
+    /// Some checks haven't finished (or none have been reported yet).
+    Pending,
+    /// All checks passed.
+    Success,
+    /// Some check failed.
+    Failure,
+}
+
+/// The CI checks on a commit, from both the checks and commit statuses APIs.
+#[derive(Debug, Clone)]
+pub struct CheckStatus {
+    pub state: CheckState,
+    /// Names of checks that haven't finished.
+    pub pending: Vec<String>,
+    /// Names of checks that failed, each with how it failed (e.g., `lint (failure)`).
+    pub failed: Vec<String>,
+}
+
+/// An open pull request.
+#[derive(Debug, Clone)]
+pub struct PullRequest {
+    pub number: u64,
+    pub url: String,
+    /// The branch that the pull request would merge into.
+    pub base: String,
+}
+
+impl PullRequest {
+    /// Read a pull request from an API response.
+    fn from_json(pull: &Value) -> Option<PullRequest> {
+        Some(PullRequest {
+            number: pull["number"].as_u64()?,
+            url: pull["html_url"].as_str().unwrap_or_default().to_string(),
+            base: pull["base"]["ref"].as_str().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+/// Operations on a code host's pull requests (or merge requests) and CI, as git-dx needs them.
+/// Pull requests are identified by the number that the host shows for them within the repository.
+pub trait Forge {
+    /// The owner of the upstream repository (e.g., a GitHub user or organization, or a GitLab
+    /// group), from which target branches are pushed unless they go to a fork.
+    fn owner(&self) -> &str;
+
+    /// Find the open pull request whose head is `branch` in the repository owned by `head_owner`
+    /// (which differs from this repository's owner for cross-fork pull requests).
+    fn find_pull_request(&self, head_owner: &str, branch: &str)
+        -> err::Result<Option<PullRequest>>;
+
+    /// Find the open pull requests whose base is `branch`.
+    fn pull_requests_onto(&self, branch: &str) -> err::Result<Vec<PullRequest>>;
+
+    /// Find the head commit of the latest merged pull request whose head is `branch` in the
+    /// repository owned by `head_owner`, if any.
+    fn merged_head(&self, head_owner: &str, branch: &str) -> err::Result<Option<String>>;
+
+    /// Open a pull request to merge `branch` from the repository owned by `head_owner` into
+    /// `base`.
+    fn create_pull_request(
+        &self,
+        head_owner: &str,
+        branch: &str,
+        base: &str,
+        title: &str,
+        body: &str,
+        draft: bool,
+    ) -> err::Result<PullRequest>;
+
+    /// Replace the description of a pull request.
+    fn set_body(&self, number: u64, body: &str) -> err::Result<()>;
+
+    /// Change the branch that a pull request would merge into.
+    fn set_base(&self, number: u64, base: &str) -> err::Result<()>;
+
+    /// Close a pull request without merging it.
+    fn close_pull_request(&self, number: u64) -> err::Result<()>;
+
+    /// Add labels to a pull request, keeping any labels that it already has.
+    fn add_labels(&self, number: u64, labels: &[String]) -> err::Result<()>;
+
+    /// Set the milestone of a pull request to the open milestone with the given title. Returns
+    /// `false` if there is no such milestone.
+    fn set_milestone(&self, number: u64, title: &str) -> err::Result<bool>;
+
+    /// Rename a branch of the upstream repository, taking the open pull requests from it along.
+    /// Returns `false` if the host can't, in which case the branch must be pushed under its new
+    /// name and the old one deleted, which leaves its pull requests behind.
+    fn rename_branch(&self, branch: &str, new_name: &str) -> err::Result<bool>;
+
+    /// Get the state of the CI checks on a commit.
+    fn check_status(&self, sha: &str) -> err::Result<CheckStatus>;
+
+    /// The web page of a pull request, formed without asking the host.
+    fn pull_request_url(&self, number: u64) -> String;
+}
+
+/// Connect to the code host behind `remote`, as chosen by `dx.forge` (`github`, the default, or
+/// `gitlab`). Returns `None` if the remote is not on that host or no API token is available.
+pub fn for_remote(git: &GitStore, remote: &str) -> err::Result<Option<Box<dyn Forge>>> {
+    match git.config_get(FORGE_CONFIG)?.as_deref() {
+        None | Some("github") => {
+            Ok(GitHub::for_remote(git, remote)?.map(|github| Box::new(github) as Box<dyn Forge>))
+        }
+        Some("gitlab") => Ok(crate::gitlab::GitLab::for_remote(git, remote)?
+            .map(|gitlab| Box::new(gitlab) as Box<dyn Forge>)),
+        Some(_) => Err(err::Error::InvalidConfig(FORGE_CONFIG.to_string())),
+    }
+}
+
+impl GitHub {
+    /// Connect to the GitHub repository behind `remote`, if it is one. Returns `None` if the
+    /// remote is not hosted on GitHub or no API token is available.
+    pub fn for_remote(git: &GitStore, remote: &str) -> err::Result<Option<GitHub>> {
+        let url = git.config_get(&format!("remote.{}.url", remote))?;
+        let parsed = url.as_deref().and_then(parse_remote_url);
+        let (owner, name) = match git.config_get(REPOSITORY_CONFIG)? {
+            Some(slug) => match slug.split_once('/') {
+                Some((owner, name)) => (owner.to_string(), name.to_string()),
+                None => return Err(err::Error::InvalidConfig(REPOSITORY_CONFIG.to_string())),
+            },
+            None => match parsed {
+                Some((_, ref owner, ref name)) => (owner.clone(), name.clone()),
+                None => return Ok(None),
+            },
+        };
+        let host = parsed.map_or_else(|| "github.com".to_string(), |(host, _, _)| host);
+        let api_url = match git.config_get(API_URL_CONFIG)? {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None if host == "github.com" => "https://api.github.com".to_string(),
+            None => format!("https://{}/api/v3", host),
+        };
+        let token = match token(git, &host, &["GITHUB_TOKEN", "GH_TOKEN"])? {
+            Some(token) => token,
+            None => {
+                warn!("no GitHub token for {}; set GITHUB_TOKEN", host);
+                return Ok(None);
+            }
+        };
+        Ok(Some(GitHub {
+            api_url,
+            web_url: format!("https://{}/{}/{}", host, owner, name),
+            token,
+            owner,
+            name,
+        }))
+    }
+
+    /// Make a request to the REST API and parse the JSON response.
+    fn request(&self, method: &str, path: &str, body: Option<Value>) -> err::Result<Value> {
+        request(
+            &self.api_url,
+            &[
+                format!("Authorization: token {}", self.token),
+                "Accept: application/vnd.github+json".to_string(),
+            ],
+            method,
+            path,
+            body,
+        )
+    }
+}
+
+impl Forge for GitHub {
+    fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    fn find_pull_request(
+        &self,
+        head_owner: &str,
+        branch: &str,
+    ) -> err::Result<Option<PullRequest>> {
+        let path = format!(
+            "/repos/{}/{}/pulls?state=open&head={}:{}",
+            self.owner, self.name, head_owner, branch
+        );
+        let pulls = self.request("GET", &path, None)?;
+        Ok(pulls
+            .as_array()
+            .and_then(|pulls| pulls.first())
+            .and_then(PullRequest::from_json))
+    }
+
+    fn pull_requests_onto(&self, branch: &str) -> err::Result<Vec<PullRequest>> {
+        let path = format!(
+            "/repos/{}/{}/pulls?state=open&base={}&per_page=100",
+            self.owner, self.name, branch
+        );
+        let pulls = self.request("GET", &path, None)?;
+        Ok(pulls
+            .as_array()
+            .map(|pulls| pulls.iter().filter_map(PullRequest::from_json).collect())
+            .unwrap_or_default())
+    }
+
+    fn merged_head(&self, head_owner: &str, branch: &str) -> err::Result<Option<String>> {
+        // Closed pull requests include those closed without merging, which have no merge time.
+        let path = format!(
+            "/repos/{}/{}/pulls?state=closed&head={}:{}",
+            self.owner, self.name, head_owner, branch
+        );
+        let pulls = self.request("GET", &path, None)?;
+        Ok(pulls.as_array().and_then(|pulls| {
+            pulls
+                .iter()
+                .find(|pull| pull["merged_at"].is_string())
+                .and_then(|pull| pull["head"]["sha"].as_str())
+                .map(str::to_string)
+        }))
+    }
+
+    fn create_pull_request(
+        &self,
+        head_owner: &str,
+        branch: &str,
+        base: &str,
+        title: &str,
+        body: &str,
+        draft: bool,
+    ) -> err::Result<PullRequest> {
+        let path = format!("/repos/{}/{}/pulls", self.owner, self.name);
+        let pull = self.request(
+            "POST",
+            &path,
+            Some(json!({
+                "title": title,
+                "head": format!("{}:{}", head_owner, branch),
+                "base": base,
+                "body": body,
+                "draft": draft,
+            })),
+        )?;
+        PullRequest::from_json(&pull).ok_or_else(|| err::Error::ForgeRequest {
+            request: format!("POST {}", path),
+            reason: "response has no pull request number".to_string(),
+        })
+    }
+
+    fn set_body(&self, number: u64, body: &str) -> err::Result<()> {
+        let path = format!("/repos/{}/{}/pulls/{}", self.owner, self.name, number);
+        self.request("PATCH", &path, Some(json!({ "body": body })))?;
+        Ok(())
+    }
+
+    fn set_base(&self, number: u64, base: &str) -> err::Result<()> {
+        let path = format!("/repos/{}/{}/pulls/{}", self.owner, self.name, number);
+        self.request("PATCH", &path, Some(json!({ "base": base })))?;
+        Ok(())
+    }
+
+    fn close_pull_request(&self, number: u64) -> err::Result<()> {
+        let path = format!("/repos/{}/{}/pulls/{}", self.owner, self.name, number);
+        self.request("PATCH", &path, Some(json!({ "state": "closed" })))?;
+        Ok(())
+    }
+
+    fn add_labels(&self, number: u64, labels: &[String]) -> err::Result<()> {
+        let path = format!(
+            "/repos/{}/{}/issues/{}/labels",
+            self.owner, self.name, number
+        );
+        self.request("POST", &path, Some(json!({ "labels": labels })))?;
+        Ok(())
+    }
+
+    fn set_milestone(&self, number: u64, title: &str) -> err::Result<bool> {
+        let path = format!(
+            "/repos/{}/{}/milestones?state=open&per_page=100",
+            self.owner, self.name
+        );
+        let milestones = self.request("GET", &path, None)?;
+        let milestone = milestones.as_array().and_then(|milestones| {
+            milestones
+                .iter()
+                .find(|milestone| milestone["title"] == title)
+                .and_then(|milestone| milestone["number"].as_u64())
+        });
+        let milestone = match milestone {
+            Some(milestone) => milestone,
+            None => return Ok(false),
+        };
+        let path = format!("/repos/{}/{}/issues/{}", self.owner, self.name, number);
+        self.request("PATCH", &path, Some(json!({ "milestone": milestone })))?;
+        Ok(true)
+    }
+
+    fn rename_branch(&self, branch: &str, new_name: &str) -> err::Result<bool> {
+        let path = format!(
+            "/repos/{}/{}/branches/{}/rename",
+            self.owner, self.name, branch
+        );
+        self.request("POST", &path, Some(json!({ "new_name": new_name })))?;
+        Ok(true)
+    }
+
+    fn check_status(&self, sha: &str) -> err::Result<CheckStatus> {
+        // Checks that were skipped or finished neutral count as passing.
+        let mut pending = Vec::new();
+        let mut failed = Vec::new();
+        let mut total = 0;
+        let path = format!(
+            "/repos/{}/{}/commits/{}/check-runs?per_page=100",
+            self.owner, self.name, sha
+        );
+        let runs = self.request("GET", &path, None)?;
+        for run in runs["check_runs"].as_array().into_iter().flatten() {
+            total += 1;
+            let name = run["name"].as_str().unwrap_or("(unnamed)").to_string();
+            if run["status"] != "completed" {
+                pending.push(name);
+                continue;
+            }
+            match run["conclusion"].as_str().unwrap_or("") {
+                "success" | "neutral" | "skipped" => (),
+                conclusion => failed.push(format!("{} ({})", name, conclusion)),
+            }
+        }
+        let path = format!("/repos/{}/{}/commits/{}/status", self.owner, self.name, sha);
+        let combined = self.request("GET", &path, None)?;
+        for status in combined["statuses"].as_array().into_iter().flatten() {
+            total += 1;
+            let name = status["context"]
+                .as_str()
+                .unwrap_or("(unnamed)")
+                .to_string();
+            match status["state"].as_str().unwrap_or("") {
+                "success" => (),
+                "pending" => pending.push(name),
+                state => failed.push(format!("{} ({})", name, state)),
+            }
+        }
+        let state = if !failed.is_empty() {
+            CheckState::Failure
+        } else if !pending.is_empty() || total == 0 {
+            // With no checks at all, CI may not have picked up the commit yet.
+            CheckState::Pending
+        } else {
+            CheckState::Success
+        };
+        Ok(CheckStatus {
+            state,
+            pending,
+            failed,
+        })
+    }
+
+    fn pull_request_url(&self, number: u64) -> String {
+        format!("{}/pull/{}", self.web_url, number)
+    }
+}
+
+/// Make a request to the REST API at `api_url`, sending `headers` (e.g., for authentication), and
+/// parse the JSON response. The headers and body are passed to `curl` on stdin so that they don't
+/// appear in process listings or verbose logs.
+pub fn request(
+    api_url: &str,
+    headers: &[String],
+    method: &str,
+    path: &str,
+    body: Option<Value>,
+) -> err::Result<Value> {
+    let url = format!("{}{}", api_url, path);
+    let mut config = String::new();
+    let mut option = |key: &str, value: &str| {
+        config.push_str(&format!("{} = \"{}\"\n", key, curl_escape(value)));
+    };
+    option("url", &url);
+    option("request", method);
+    for header in headers {
+        option("header", header);
+    }
+    option("user-agent", "git-dx");
+    option("write-out", "\n%{http_code}");
+    if let Some(body) = body {
+        option("header", "Content-Type: application/json");
+        option("data", &body.to_string());
+    }
+    let mut child = Command::new("curl")
+        .args(["--silent", "--show-error", "--config", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged_spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("curl stdin")
+        .write_all(config.as_bytes())?;
+    let out = crate::cancel::wait_with_output(child)?;
+    let failed = |reason: String| err::Error::ForgeRequest {
+        request: format!("{} {}", method, path),
+        reason,
+    };
+    if !out.status.success() {
+        return Err(failed(
+            String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let (body, status) = stdout.rsplit_once('\n').unwrap_or(("", &stdout));
+    if !status.starts_with('2') {
+        return Err(failed(format!("HTTP {}: {}", status, body.trim())));
+    }
+    if body.trim().is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_json::from_str(body).map_err(|e| failed(format!("bad JSON response: {}", e)))
+}
+
+/// Parse the host, owner, and repository name out of a remote URL, like
+/// `git@github.com:owner/name.git` or `https://github.com/owner/name`. Returns `None` for URLs
+/// without a host (e.g., local paths).
+pub fn parse_remote_url(url: &str) -> Option<(String, String, String)> {
+    let (host, path) = parse_remote_path(url)?;
+    let (owner, name) = path.split_once('/')?;
+    if owner.is_empty() || name.is_empty() || name.contains('/') {
+        return None;
+    }
+    Some((host, owner.to_string(), name.to_string()))
+}
+
+/// Parse the host and repository path out of a remote URL, like `gitlab.com` and
+/// `group/subgroup/name` from `git@gitlab.com:group/subgroup/name.git`. Returns `None` for URLs
+/// without a host (e.g., local paths).
+pub fn parse_remote_path(url: &str) -> Option<(String, String)> {
+    let (host, path) = match url.split_once("://") {
+        Some((_, rest)) => {
+            let (authority, path) = rest.split_once('/')?;
+            let host = authority.rsplit('@').next()?;
+            (host.split(':').next()?, path)
+        }
+        // scp-like syntax: `[user@]host:path`
+        None => {
+            let (authority, path) = url.split_once(':')?;
+            if authority.contains('/') {
+                return None;
+            }
+            (authority.rsplit('@').next()?, path)
+        }
+    };
+    let path = path.trim_end_matches('/').trim_end_matches(".git");
+    if host.is_empty() || path.is_empty() {
+        return None;
+    }
+    Some((host.to_string(), path.to_string()))
+}
+
+/// Find an API token for the given host: the first of the environment variables `vars` that is
+/// set (e.g., `GITHUB_TOKEN`), else a password from the repository's Git credential helper,
+/// without prompting.
+pub fn token(git: &GitStore, host: &str, vars: &[&str]) -> err::Result<Option<String>> {
+    for var in vars {
+        if let Ok(token) = std::env::var(var) {
+            if !token.is_empty() {
+                return Ok(Some(token));
+            }
+        }
+    }
+    let mut child = git
+        .command()
+        .args(["credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .logged_spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("credential stdin")
+        .write_all(format!("protocol=https\nhost={}\n\n", host).as_bytes())?;
+    let out = crate::cancel::wait_with_output(child)?;
+    if !out.status.success() {
+        return Ok(None);
+    }
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("password="))
+        .map(|password| password.to_string()))
+}
+
+/// Escape a value for a double-quoted string in a `curl` config file.
+fn curl_escape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Find the owner of the repository that holds target branches, for the `head` of a pull request
+/// on `forge`: the upstream owner, unless integration branches are pushed to a fork.
+pub fn head_owner(
+    git: &GitStore,
+    forge: &dyn Forge,
+    remotes: &crate::Remotes,
+) -> err::Result<String> {
+    if remotes.write == remotes.read {
+        return Ok(forge.owner().to_string());
+    }
+    let url = git.config_get(&format!("remote.{}.url", remotes.write))?;
+    let owner = url
+        .as_deref()
+        .and_then(parse_remote_path)
+        .and_then(|(_, path)| Some(path.rsplit_once('/')?.0.to_string()));
+    owner.ok_or_else(|| err::Error::NoSuchRemote(remotes.write.to_string()))
+}
+
+/// Apply the labels and milestone named by the trailers of `source_commit` to the open pull
+/// request for `target_branch`, if the upstream remote is on a known code host and such a pull
+/// request exists. Labels are only ever added, so labels applied by hand or by bots are kept.
+pub fn sync_metadata(
+    git: &GitStore,
+    config: &Config,
+    source_commit: &Commit,
+    remotes: &crate::Remotes,
+    target_branch: &str,
+) -> err::Result<()> {
+    let labels = crate::labels(config, &source_commit.message)?;
+    let milestone = crate::milestone(config, &source_commit.oid, &source_commit.message)?;
+    if labels.is_empty() && milestone.is_none() {
+        return Ok(());
+    }
+    let forge = match for_remote(git, remotes.read)? {
+        Some(forge) => forge,
+        None => return Ok(()),
+    };
+    let head_owner = head_owner(git, forge.as_ref(), remotes)?;
+    let pull = match forge.find_pull_request(&head_owner, target_branch)? {
+        Some(pull) => pull,
+        None => {
+            info!(
+                "no open pull request for {}; not applying labels or milestone",
+                target_branch
+            );
+            return Ok(());
+        }
+    };
+    if !labels.is_empty() {
+        forge.add_labels(pull.number, &labels)?;
+        info!("labeled {}: {}", pull.url, labels.join(", "));
+    }
+    if let Some(milestone) = milestone {
+        if forge.set_milestone(pull.number, &milestone)? {
+            info!("set milestone of {}: {}", pull.url, milestone);
+        } else {
+            warn!("no open milestone {:?}", milestone);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! Gerrit mode (`dx.mode = gerrit`). Gerrit tracks each change by the `Change-Id` trailer of its
+//! commit and keeps every revision of it as a patch set, so there are no target branches to merge
+//! into: each source commit is pushed as it is to the magic `refs/for/<branch>` ref, where
+//! `<branch>` is the trunk that the stack is based on.
+
+use crate::config::Config;
+use crate::err;
+use crate::git::{self, GitStore};
+use crate::output;
+use crate::push;
+
+/// Trailer key that identifies a change to Gerrit, as added by Gerrit's `commit-msg` hook.
+pub const CHANGE_ID_TRAILER: &str = "Change-Id";
+
+/// Prefix of the refs that Gerrit accepts new patch sets on.
+const FOR_PREFIX: &str = "refs/for/";
+
+/// Find the Change-Id of a commit, failing if it has none or more than one.
+pub fn change_id(config: &Config, commit: &git::Commit) -> err::Result<String> {
+    let trailers = crate::trailers(config, &commit.message);
+    let id = crate::look_up_trailer(CHANGE_ID_TRAILER, &trailers).unique(&commit.oid)?;
+    Ok(id.to_string())
+}
+
+/// Push each source commit as a new patch set of its change, in order, reporting each as
+/// `integrate` would. Without `--push`, only checks that each has a Change-Id.
+pub fn push_patch_sets(
+    git: &mut GitStore,
+    config: &Config,
+    remotes: &crate::Remotes,
+    source_commits: &[git::Commit],
+    options: &crate::RunOptions,
+) -> err::Result<()> {
+    for commit in source_commits {
+        change_id(config, commit)?;
+    }
+    let trunk = crate::default_trunk(git, remotes.read)?;
+    let target = format!("{}{}", FOR_PREFIX, trunk);
+    for commit in source_commits {
+        let mut report = output::Report {
+            source: &commit.oid,
+            target_branch: &target,
+            remote_commit: &commit.oid,
+            already_integrated: false,
+            new_branch: false,
+            diffbase_merged: false,
+            conflicts: &[],
+            discarded_paths: &[],
+            size: None,
+            pushed: false,
+            push: None,
+            secondary_pushes: &[],
+        };
+        options.output.integrated(&report);
+        if !options.push {
+            options.output.finished(&report);
+            continue;
+        }
+        let mut args = Vec::new();
+        if options.dry_run {
+            args.push("--dry-run".to_string());
+        }
+        for option in &options.push_options {
+            args.push(format!("--push-option={}", option));
+        }
+        args.push(remotes.write.to_string());
+        args.push(format!("{}:{}", commit.oid, target));
+        let outcome = match push::push(git, &args) {
+            Ok(outcome) => outcome,
+            // Gerrit refuses a patch set identical to the current one; that's already done.
+            Err(err::Error::PushRejected { ref refs })
+                if refs.iter().all(|r| r.ends_with("(no new changes)")) =>
+            {
+                info!("{} is already the current patch set", commit.oid);
+                report.already_integrated = true;
+                options.output.finished(&report);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        options.output.pushed(&outcome);
+        report.pushed = !options.dry_run;
+        report.push = Some(&outcome);
+        options.output.finished(&report);
+    }
+    Ok(())
+}
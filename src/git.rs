@@ -1,34 +1,275 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::process::Command;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Output, Stdio};
 
-use crate::err;
+use crate::err::{self, ResultExt};
+use crate::log::CommandExt;
 
 pub struct GitStore {
     directory: PathBuf,
-    commits: HashMap<String, Commit>,
+    commits: HashMap<Oid, Commit>,
+    refs: RefCell<RefCache>,
+    /// Merge bases of pairs of object IDs, which (unlike those of refs) never change.
+    merge_bases: RefCell<HashMap<(Oid, Oid), Option<Oid>>>,
+    /// Results of `check_ref_format`, which is asked about the target branch of every change when
+    /// scanning a stack.
+    branch_names: RefCell<HashMap<String, bool>>,
+    /// Every configuration entry, read once on first use; see `config_snapshot`.
+    config: RefCell<Option<Vec<ConfigEntry>>>,
+    /// Branch heads of each remote asked with `remote_head`, by branch name.
+    remote_heads: RefCell<HashMap<String, HashMap<String, Oid>>>,
+    /// How to find the trailers of the commits read.
+    trailer_syntax: crate::trailers::Syntax,
+    repository: Box<dyn Repository>,
+}
+
+/// Ref lookups that are repeated within a run (e.g., once per change in a stack), cached until
+/// `GitStore::invalidate_refs`. Only fully qualified refs (`refs/...`) are cached: revisions like
+/// `HEAD` move too often to be worth tracking.
+#[derive(Default)]
+struct RefCache {
+    oids: HashMap<String, Option<Oid>>,
+    remote_branches: Option<Vec<String>>,
+}
+
+/// Config option selecting how objects are read: `subprocess` (the default) or `libgit2` (if
+/// compiled with the `libgit2` feature).
+const BACKEND_CONFIG: &str = "dx.backend";
+
+/// Object reads, which may be done by `git` subprocesses or in-process. Everything else (refs,
+/// config, merges, and so on) always goes through `git` itself.
+pub trait Repository {
+    /// Resolve a revision to an object ID, or `None` if it does not resolve, as by
+    /// `git rev-parse --verify`.
+    fn rev_parse(&self, rev: &str) -> err::Result<Option<Oid>>;
+
+    /// Read the commit that a revision refers to. Fails with `NoSuchCommit` if the revision does
+    /// not resolve to a commit.
+    fn read_commit(&self, rev: &str) -> err::Result<Commit>;
+
+    /// List the paths that differ between the trees of the two given commits (or trees), with
+    /// rename detection disabled, in Git's path order.
+    fn diff_names(&self, a: &str, b: &str) -> err::Result<Vec<String>>;
+}
+
+/// A configuration entry, as listed by `git config --list --show-origin`.
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    /// Where the entry is set (e.g., `file:.git/config` or `command line:`).
+    pub origin: String,
+    /// The key, with its section and variable names lowercased as Git lists them (e.g.,
+    /// `dx.branchprefix`, but `remote.Origin.url`).
+    pub key: String,
+    /// The value, or `None` for a key written with no `=` (which Git reads as boolean true).
+    pub value: Option<String>,
+}
+
+/// Normalize a configuration key as Git lists it: the section and variable names are
+/// case-insensitive, but a subsection name (between them) is not.
+fn normalize_config_key(key: &str) -> String {
+    match (key.find('.'), key.rfind('.')) {
+        (Some(first), Some(last)) if first != last => format!(
+            "{}{}{}",
+            key[..first].to_ascii_lowercase(),
+            &key[first..last],
+            key[last..].to_ascii_lowercase()
+        ),
+        _ => key.to_ascii_lowercase(),
+    }
+}
+
+/// A branch of a remote, as recorded by its remote-tracking ref.
+#[derive(Debug, Clone)]
+pub struct RemoteBranch {
+    /// The branch's name on the remote (e.g., `wchargin-foo`).
+    pub name: String,
+    pub oid: Oid,
+    /// Committer date of the tip, in seconds since the epoch.
+    pub committed: u64,
+}
+
+/// A ref that points to a tag object, as listed by `GitStore::tag_refs`.
+#[derive(Debug, Clone)]
+pub struct TagRef {
+    pub refname: String,
+    /// The object that the tag points to.
+    pub target: Oid,
+    /// Tagger date, in seconds since the epoch.
+    pub date: u64,
+    /// The tag message.
+    pub message: String,
+}
+
+/// Reads objects by running `git` subprocesses.
+struct Subprocess {
+    directory: PathBuf,
+    /// Started on first use; reset if a read fails partway, since the stream may be out of sync.
+    cat_file: RefCell<Option<CatFile>>,
+}
+
+/// A long-lived `git cat-file --batch` process, which reads objects over a pipe so that each read
+/// doesn't cost a subprocess.
+struct CatFile {
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+}
+
+/// A full object ID as Git prints it: 40 (SHA-1) or 64 (SHA-256) lowercase hex digits. Unlike a
+/// revision like `HEAD~2` or a branch name, an `Oid` always names the same object.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Oid(String);
+
+impl Oid {
+    /// Parse a full object ID, or return `None` if `s` is anything else: e.g., an abbreviated
+    /// object ID, a ref name, or a revision spec.
+    pub fn parse(s: &str) -> Option<Oid> {
+        let valid =
+            matches!(s.len(), 40 | 64) && s.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'));
+        if valid {
+            Some(Oid(s.to_string()))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for Oid {
+    type Err = err::Error;
+
+    /// Parse a full object ID that Git has given us, failing with `GitContract` if it is not one.
+    fn from_str(s: &str) -> err::Result<Oid> {
+        Oid::parse(s)
+            .ok_or_else(|| err::Error::GitContract(format!("not a full object ID: {:?}", s)))
+    }
+}
+
+impl std::ops::Deref for Oid {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq<str> for Oid {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Commit {
     /// The canonical object ID for this commit.
-    pub oid: String,
+    pub oid: Oid,
     /// The object IDs of this commit's parents, in order.
-    pub parents: Vec<String>,
+    pub parents: Vec<Oid>,
     /// The object ID of this commit's tree.
-    pub tree: String,
+    pub tree: Oid,
     /// The raw commit message.
     pub message: String,
+    /// Who wrote the change, and when.
+    pub author: Signature,
+    /// The trailers of the message, each key and value in order, parsed once when the commit is
+    /// read with the syntax set by `GitStore::set_trailer_syntax`.
+    pub trailers: Vec<(String, String)>,
 }
 
-enum ReadCommit {
-    /// The desired commit has the specified full object ID and already exists in the cache. We
-    /// only return its key rather than a reference to the object (via a `Cow`) because the
-    /// latter would hit an incompleteness in the borrow checker that has not yet been fixed by
-    /// Polonius.
-    Cached(String),
-    /// The desired commit does not exist in cache and has just been read.
-    Read(Commit),
+/// An author or committer identity with a timestamp, as in a commit header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// The date in Git's internal format, `<seconds since epoch> <offset>` (e.g.,
+    /// `1546300800 +0000`), which `GIT_AUTHOR_DATE` accepts as is.
+    pub date: String,
+}
+
+impl Signature {
+    /// Parse the value of an `author` or `committer` commit header, like
+    /// `A U Thor <author@example.com> 1546300800 +0000`.
+    fn parse(header: &str) -> Option<Signature> {
+        let open = header.find('<')?;
+        let close = open + header[open..].find('>')?;
+        Some(Signature {
+            name: header[..open].trim_end().to_string(),
+            email: header[open + 1..close].to_string(),
+            date: header[close + 1..].trim().to_string(),
+        })
+    }
+
+    /// The identity without the date, as in a `Signed-off-by` trailer: `Name <email>`.
+    pub fn ident(&self) -> String {
+        format!("{} <{}>", self.name, self.email)
+    }
+
+    /// Set the `GIT_AUTHOR_*` environment variables of `cmd` so that a commit that it creates is
+    /// attributed to this identity.
+    pub fn set_author_env(&self, cmd: &mut Command) {
+        cmd.env("GIT_AUTHOR_NAME", &self.name)
+            .env("GIT_AUTHOR_EMAIL", &self.email)
+            .env("GIT_AUTHOR_DATE", &self.date);
+    }
+
+    /// Set the `GIT_COMMITTER_*` environment variables of `cmd` likewise.
+    pub fn set_committer_env(&self, cmd: &mut Command) {
+        cmd.env("GIT_COMMITTER_NAME", &self.name)
+            .env("GIT_COMMITTER_EMAIL", &self.email)
+            .env("GIT_COMMITTER_DATE", &self.date);
+    }
+}
+
+/// The result of merging two commits in memory, as by `GitStore::merge_tree`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeTree {
+    /// The merge is clean, with this tree.
+    Clean(Oid),
+    /// The merge has conflicts in these paths.
+    Conflicts(Vec<String>),
+}
+
+/// What `HEAD` points to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Head {
+    /// On a local branch, by its short name (e.g., `feature-x`).
+    Branch(String),
+    Detached(Oid),
+}
+
+impl Head {
+    /// Parse a head as `Display` writes it. For compatibility with journal entries written before
+    /// heads were typed, anything else that is not a full object ID is taken as a branch name.
+    pub fn parse(s: &str) -> Head {
+        if let Some(branch) = s.strip_prefix("refs/heads/") {
+            return Head::Branch(branch.to_string());
+        }
+        match Oid::parse(s) {
+            Some(oid) => Head::Detached(oid),
+            None => Head::Branch(s.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Head {
+    /// Write a branch as its full ref name, so that it can't be mistaken for an object ID.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Head::Branch(branch) => write!(f, "refs/heads/{}", branch),
+            Head::Detached(oid) => write!(f, "{}", oid),
+        }
+    }
 }
 
 impl GitStore {
@@ -37,28 +278,78 @@ impl GitStore {
     /// relative path, then the current directory should not be changed.
     pub fn new(repo: PathBuf) -> GitStore {
         GitStore {
-            directory: repo,
+            directory: repo.clone(),
             commits: HashMap::new(),
+            refs: RefCell::new(RefCache::default()),
+            merge_bases: RefCell::new(HashMap::new()),
+            branch_names: RefCell::new(HashMap::new()),
+            config: RefCell::new(None),
+            remote_heads: RefCell::new(HashMap::new()),
+            trailer_syntax: crate::trailers::Syntax::default(),
+            repository: Box::new(Subprocess {
+                directory: repo,
+                cat_file: RefCell::new(None),
+            }),
         }
     }
 
-    fn git(&self) -> Command {
-        let mut cmd = Command::new("git");
-        cmd.arg("-C");
-        cmd.arg(self.directory.as_os_str());
-        cmd.args(&["-c", "i18n.logOutputEncoding=utf-8"]);
-        cmd
+    /// Construct a store of the repository containing `start` (the current directory if `None`),
+    /// as `git -C <start>` would find it. Commands run at the top of its worktree, so that
+    /// pathspecs like `git add .` cover all of it; for a bare repository, or outside of any, they
+    /// run in `start` itself.
+    pub fn discover(start: Option<&Path>) -> err::Result<GitStore> {
+        let cwd =
+            std::env::current_dir().context(|| "failed to read current directory".to_string())?;
+        let start = match start {
+            Some(path) => cwd.join(path),
+            None => cwd,
+        };
+        let start = strip_verbatim(
+            start
+                .canonicalize()
+                .context(|| format!("cannot change to {}", start.display()))?,
+        );
+        let out = output(&start, &["rev-parse", "--show-toplevel"])?;
+        let directory = match parse_oid(out.stdout) {
+            Ok(toplevel) if out.status.success() && !toplevel.is_empty() => native_path(toplevel),
+            _ => start,
+        };
+        Ok(GitStore::new(directory))
     }
 
-    /// Get the current head, as a symbolic ref (e.g., branch name) if possible, else as an
-    /// unambiguous object ID.
-    ///
-    /// This is intended to use the same algorithm as `git bisect` for saving and restoring the
-    /// head. It can be restored with `git checkout "${orig_ref}" --`, where `"${orig_ref}"` is the
-    /// value returned from this function.
-    pub fn head(&self) -> err::Result<String> {
+    /// A `git` command to run in this repository, for operations that the store doesn't wrap
+    /// (checkouts, merges, pushes, and the like).
+    pub fn command(&self) -> Command {
+        git(&self.directory)
+    }
+
+    /// Switch to the object reading backend selected by `dx.backend`, if any.
+    pub fn use_configured_backend(&mut self) -> err::Result<()> {
+        match self.config_get(BACKEND_CONFIG)?.as_deref() {
+            None | Some("subprocess") => (),
+            #[cfg(feature = "libgit2")]
+            Some("libgit2") => {
+                self.repository = Box::new(crate::libgit2::Libgit2::open(&self.directory)?);
+            }
+            #[cfg(not(feature = "libgit2"))]
+            Some("libgit2") => {
+                warn!("git-dx was built without libgit2 support; using git subprocesses");
+            }
+            Some(_) => return Err(err::Error::InvalidConfig(BACKEND_CONFIG.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Run `git` in this repository with the given arguments, capturing its output.
+    fn output(&self, args: &[&str]) -> err::Result<Output> {
+        output(&self.directory, args)
+    }
+
+    /// Get the current head: the branch that `HEAD` is a symbolic ref to, if any, else the commit
+    /// that it points to. Restore it with `check_out_head`.
+    pub fn head(&self) -> err::Result<Head> {
         // First, check whether we're on a branch.
-        let symbolic_ref_out = self.git().args(&["symbolic-ref", "HEAD"]).output()?;
+        let symbolic_ref_out = self.output(&["symbolic-ref", "HEAD"])?;
         if symbolic_ref_out.status.success() {
             let mut stdout =
                 err::Error::require_utf8(symbolic_ref_out.stdout, "symbolic ref HEAD")?;
@@ -76,62 +367,871 @@ impl GitStore {
             };
             let prefix = "refs/heads/";
             if stdout.starts_with(prefix) {
-                return Ok(stdout.split_off(prefix.len()));
+                return Ok(Head::Branch(stdout.split_off(prefix.len())));
             }
         }
 
         // If we're not, fall back to detached oid.
-        self.rev_parse_commit_ok("HEAD")
+        Ok(Head::Detached(self.rev_parse_commit_ok("HEAD")?))
+    }
+
+    /// Check out a head as returned by `head`: switch to the branch, so that `HEAD` is a symbolic
+    /// ref to it again, or detach at the commit.
+    pub fn check_out_head(&self, head: &Head) -> err::Result<()> {
+        let out = match head {
+            // `git switch` (Git 2.23) never detaches, and never guesses a remote branch.
+            Head::Branch(branch) if self.version()? >= (2, 23) => {
+                self.output(&["switch", "--no-guess", branch])?
+            }
+            Head::Branch(branch) => self.output(&["checkout", branch, "--"])?,
+            Head::Detached(oid) => self.output(&["checkout", "--detach", oid, "--"])?,
+        };
+        err::from_git(&out, || format!("failed to check out {}", head))
+    }
+
+    pub fn rev_parse(&self, rev: &str) -> err::Result<Option<Oid>> {
+        if !rev.starts_with("refs/") {
+            return self.repository.rev_parse(rev);
+        }
+        if let Some(oid) = self.refs.borrow().oids.get(rev) {
+            return Ok(oid.clone());
+        }
+        let oid = self.repository.rev_parse(rev)?;
+        self.refs
+            .borrow_mut()
+            .oids
+            .insert(rev.to_string(), oid.clone());
+        Ok(oid)
     }
 
-    pub fn rev_parse(&self, rev: &str) -> err::Result<Option<String>> {
-        let out = self.git().args(&["rev-parse", "--verify", rev]).output()?;
+    /// Forget cached ref lookups. Call this after anything that may move refs, like a merge,
+    /// commit, fetch, or push.
+    pub fn invalidate_refs(&self) {
+        *self.refs.borrow_mut() = RefCache::default();
+    }
+
+    /// Resolve a symbolic ref (e.g., `refs/remotes/origin/HEAD`) to the full name of the ref
+    /// that it points to, or `None` if the ref does not exist or is not symbolic.
+    pub fn symbolic_ref(&self, name: &str) -> err::Result<Option<String>> {
+        let out = self.output(&["symbolic-ref", "--quiet", name])?;
         if !out.status.success() {
             return Ok(None);
-        };
+        }
         parse_oid(out.stdout).map(Some).map_err(|buf| {
             err::Error::GitContract(format!(
-                "rev-parse returned success but stdout was: {:?}",
+                "symbolic-ref returned success but stdout was: {:?}",
                 String::from_utf8_lossy(&buf)
             ))
         })
     }
 
-    pub fn rev_parse_commit(&self, rev: &str) -> err::Result<Option<String>> {
+    /// Find a best common ancestor of the two given commits, or `None` if their histories are
+    /// disjoint. Results are cached when both commits are given by object ID.
+    pub fn merge_base(&self, a: &str, b: &str) -> err::Result<Option<Oid>> {
+        let key = match (Oid::parse(a), Oid::parse(b)) {
+            (Some(a), Some(b)) => Some((a, b)),
+            _ => None,
+        };
+        if let Some(base) = key
+            .as_ref()
+            .and_then(|k| self.merge_bases.borrow().get(k).cloned())
+        {
+            return Ok(base);
+        }
+        let base = self.merge_base_uncached(a, b)?;
+        if let Some(key) = key {
+            self.merge_bases.borrow_mut().insert(key, base.clone());
+        }
+        Ok(base)
+    }
+
+    fn merge_base_uncached(&self, a: &str, b: &str) -> err::Result<Option<Oid>> {
+        let out = self.output(&["merge-base", a, b])?;
+        match out.status.code() {
+            Some(0) => parse_oid(out.stdout)
+                .map_err(|buf| {
+                    err::Error::GitContract(format!(
+                        "merge-base returned success but stdout was: {:?}",
+                        String::from_utf8_lossy(&buf)
+                    ))
+                })?
+                .parse()
+                .map(Some),
+            Some(1) if out.stderr.is_empty() => Ok(None),
+            _ => Err(err::Error::GitContract(format!(
+                "merge-base {} {} failed: {}",
+                a,
+                b,
+                String::from_utf8_lossy(&out.stderr)
+            ))),
+        }
+    }
+
+    /// Test whether the commit `ancestor` is an ancestor of (or equal to) the commit
+    /// `descendant`. Both should be unambiguous commit references.
+    pub fn is_ancestor(&self, ancestor: &str, descendant: &str) -> err::Result<bool> {
+        let out = self.output(&["merge-base", "--is-ancestor", ancestor, descendant])?;
+        match out.status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => Err(err::Error::GitContract(format!(
+                "merge-base --is-ancestor {} {} failed: {}",
+                ancestor,
+                descendant,
+                String::from_utf8_lossy(&out.stderr)
+            ))),
+        }
+    }
+
+    /// Merge `theirs` into `ours` without touching the index, working tree, or any refs. Needs Git
+    /// 2.38, for `git merge-tree --write-tree`.
+    pub fn merge_tree(&self, ours: &str, theirs: &str) -> err::Result<MergeTree> {
+        let out = self.output(&[
+            "merge-tree",
+            "--write-tree",
+            "--name-only",
+            "--allow-unrelated-histories",
+            ours,
+            theirs,
+        ])?;
+        let clean = match out.status.code() {
+            Some(0) => true,
+            Some(1) => false,
+            _ => {
+                return Err(err::Error::GitContract(format!(
+                    "merge-tree {} {} failed: {}",
+                    ours,
+                    theirs,
+                    String::from_utf8_lossy(&out.stderr)
+                )))
+            }
+        };
+        let stdout = err::Error::require_utf8(out.stdout, "merge-tree output")?;
+        // The tree, then (if conflicted) each conflicted path, then a blank line and messages.
+        let mut lines = stdout.lines();
+        let tree: Oid = lines.next().unwrap_or_default().parse()?;
+        if clean {
+            return Ok(MergeTree::Clean(tree));
+        }
+        let mut paths: Vec<String> = lines
+            .take_while(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect();
+        paths.dedup();
+        Ok(MergeTree::Conflicts(paths))
+    }
+
+    /// List the paths that differ between the trees of the two given commits (or trees), with
+    /// rename detection disabled.
+    pub fn diff_names(&self, a: &str, b: &str) -> err::Result<Vec<String>> {
+        self.repository.diff_names(a, b)
+    }
+
+    /// Show the differences between the trees of `a` and `b` on stdout (through the pager, if
+    /// any), as by `git diff`, passing `options` (e.g., `--stat`) through.
+    pub fn diff_trees(&self, a: &str, b: &str, options: &[&str]) -> err::Result<()> {
+        let status = git(&self.directory)
+            .arg("diff")
+            .args(options)
+            .args([a, b, "--"])
+            .logged_spawn()
+            .and_then(|mut child| crate::cancel::wait(&mut child))?;
+        if !status.success() {
+            return Err(err::Error::GitContract(format!(
+                "failed to diff {} and {} ({})",
+                a, b, status
+            )));
+        }
+        Ok(())
+    }
+
+    /// Count the lines changed (added plus deleted) in each path that differs between `a` and `b`,
+    /// as by `git diff --numstat`. Binary files have no line count.
+    pub fn diff_numstat(&self, a: &str, b: &str) -> err::Result<Vec<(String, Option<u64>)>> {
+        let out = self.output(&["diff-tree", "-r", "-z", "--no-renames", "--numstat", a, b])?;
+        err::from_git(&out, || format!("failed to diff {} and {}", a, b))?;
+        let stdout = err::Error::require_utf8(out.stdout, "diff-tree numstat")?;
+        let mut result = Vec::new();
+        for record in stdout.split_terminator('\0') {
+            let bad = || err::Error::GitContract(format!("bad numstat record: {:?}", record));
+            let mut fields = record.splitn(3, '\t');
+            let lines = match (fields.next(), fields.next()) {
+                (Some("-"), Some("-")) => None,
+                (Some(added), Some(deleted)) => {
+                    let added: u64 = added.parse().map_err(|_| bad())?;
+                    let deleted: u64 = deleted.parse().map_err(|_| bad())?;
+                    Some(added + deleted)
+                }
+                _ => return Err(bad()),
+            };
+            let path = fields.next().ok_or_else(bad)?;
+            result.push((path.to_string(), lines));
+        }
+        Ok(result)
+    }
+
+    /// Get the size in bytes of the given object (e.g., `HEAD:path`), or `None` if it does not
+    /// exist.
+    pub fn object_size(&self, rev: &str) -> err::Result<Option<u64>> {
+        let out = self.output(&["cat-file", "-s", rev])?;
+        if !out.status.success() {
+            return Ok(None);
+        }
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        stdout
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| err::Error::GitContract(format!("bad object size: {:?}", stdout)))
+    }
+
+    /// Summarize the changes between two trees, as `git diff --stat` does.
+    pub fn diff_stat(&self, a: &str, b: &str) -> err::Result<String> {
+        let out = self.output(&["diff", "--stat", a, b])?;
+        err::from_git(&out, || format!("failed to diff {} and {}", a, b))?;
+        err::Error::require_utf8(out.stdout, "diff stat")
+    }
+
+    /// List the commits reachable from `tip` but not from `base`, following only first parents,
+    /// oldest first. This enumerates a linear stack of changes built on top of `base`.
+    ///
+    /// This walk benefits from a commit-graph file (see `git dx doctor`), which Git reads
+    /// automatically. We deliberately don't pass `--use-bitmap-index`: bitmap traversals yield
+    /// commits in pack order rather than honoring `--first-parent --reverse`.
+    pub fn rev_list(&self, base: &str, tip: &str) -> err::Result<Vec<Oid>> {
+        let out = self.output(&[
+            "rev-list",
+            "--first-parent",
+            "--reverse",
+            &format!("{}..{}", base, tip),
+        ])?;
+        err::from_git(&out, || {
+            format!("failed to list commits in {}..{}", base, tip)
+        })?;
+        let stdout = err::Error::require_utf8(out.stdout, "rev-list output")?;
+        stdout.lines().map(str::parse).collect()
+    }
+
+    /// List the commits reachable from any of `tips` but not from `base`, along any parents, in
+    /// topological order with parents before their children.
+    pub fn rev_list_topo(&self, base: &str, tips: &[&str]) -> err::Result<Vec<Oid>> {
+        let exclude = format!("^{}", base);
+        let mut args = vec!["rev-list", "--topo-order", "--reverse", exclude.as_str()];
+        args.extend(tips);
+        args.push("--");
+        let out = self.output(&args)?;
+        err::from_git(&out, || {
+            format!("failed to list commits in {}..{}", base, tips.join(" "))
+        })?;
+        let stdout = err::Error::require_utf8(out.stdout, "rev-list output")?;
+        stdout.lines().map(str::parse).collect()
+    }
+
+    /// Read the commits reachable from `tip` but not from `base`, following only first parents,
+    /// newest first, as `git log --first-parent` would show them.
+    pub fn log(&mut self, base: &str, tip: &str) -> err::Result<Vec<Commit>> {
+        let out = self.output(&["rev-list", "--first-parent", &format!("{}..{}", base, tip)])?;
+        err::from_git(&out, || {
+            format!("failed to list commits in {}..{}", base, tip)
+        })?;
+        let stdout = err::Error::require_utf8(out.stdout, "rev-list output")?;
+        stdout
+            .lines()
+            .map(|oid| self.commit(oid).cloned())
+            .collect()
+    }
+
+    /// List the non-merge commits reachable from `tip` but not from `base`, along any parents,
+    /// oldest first.
+    pub fn rev_list_no_merges(&self, base: &str, tip: &str) -> err::Result<Vec<Oid>> {
+        let out = self.output(&[
+            "rev-list",
+            "--no-merges",
+            "--reverse",
+            &format!("{}..{}", base, tip),
+        ])?;
+        err::from_git(&out, || {
+            format!("failed to list commits in {}..{}", base, tip)
+        })?;
+        let stdout = err::Error::require_utf8(out.stdout, "rev-list output")?;
+        stdout.lines().map(str::parse).collect()
+    }
+
+    /// Find the fingerprint of the key that made a good signature on the given commit, or `None`
+    /// if the commit is unsigned or its signature does not verify. Whether the key is trusted is
+    /// left to the caller.
+    pub fn signing_key(&self, oid: &str) -> err::Result<Option<String>> {
+        let out = self.output(&["log", "-1", "--format=%G?%n%GF", oid])?;
+        err::from_git(&out, || format!("failed to read signature of {}", oid))?;
+        let stdout = err::Error::require_utf8(out.stdout, "log output")?;
+        let mut lines = stdout.lines();
+        match (lines.next(), lines.next()) {
+            // "G" is a good signature from a valid key, and "U" a good signature from a key of
+            // unknown validity.
+            (Some("G"), Some(key)) | (Some("U"), Some(key)) if !key.is_empty() => {
+                Ok(Some(key.to_string()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Format every commit reachable from any ref with the given `git log` pretty format,
+    /// showing notes from `notes_ref` for `%N`. Returns one record per commit, newest first.
+    pub fn log_all(&self, format: &str, notes_ref: &str) -> err::Result<Vec<String>> {
+        let out = self.output(&[
+            "log",
+            "--all",
+            "-z",
+            &format!("--notes={}", notes_ref),
+            &format!("--format={}", format),
+        ])?;
+        err::from_git(&out, || "failed to list commits".to_string())?;
+        let stdout = err::Error::require_utf8(out.stdout, "log output")?;
+        Ok(stdout
+            .split_terminator('\0')
+            .map(|s| s.to_string())
+            .collect())
+    }
+
+    /// Test whether `name` is a valid branch name, as by `git check-ref-format --branch`.
+    pub fn check_ref_format(&self, name: &str) -> err::Result<bool> {
+        if let Some(&valid) = self.branch_names.borrow().get(name) {
+            return Ok(valid);
+        }
+        let out = self.output(&["check-ref-format", "--branch", name])?;
+        let valid = out.status.success();
+        self.branch_names
+            .borrow_mut()
+            .insert(name.to_string(), valid);
+        Ok(valid)
+    }
+
+    /// Read the note attached to the given object under the given notes ref, if any.
+    pub fn note(&self, notes_ref: &str, oid: &str) -> err::Result<Option<String>> {
+        let out = self.output(&["notes", "--ref", notes_ref, "show", oid])?;
+        if !out.status.success() {
+            return Ok(None);
+        }
+        err::Error::require_utf8(out.stdout, &format!("note on {}", oid)).map(Some)
+    }
+
+    /// Attach a note to the given object under the given notes ref, replacing any existing note.
+    pub fn set_note(&self, notes_ref: &str, oid: &str, message: &str) -> err::Result<()> {
+        let out = self.output(&["notes", "--ref", notes_ref, "add", "-f", "-m", message, oid])?;
+        self.invalidate_refs();
+        err::from_git(&out, || format!("failed to add note to {}", oid))
+    }
+
+    /// Point the given ref at the given object, creating it if needed.
+    pub fn update_ref(&self, refname: &str, oid: &str, reason: &str) -> err::Result<()> {
+        let out = self.output(&["update-ref", "-m", reason, refname, oid])?;
+        self.invalidate_refs();
+        err::from_git(&out, || format!("failed to update {}", refname))
+    }
+
+    /// List the refs under `prefix` that point to tag objects, with the tags' targets, dates, and
+    /// messages. Refs that point to other objects are skipped.
+    pub fn tag_refs(&self, prefix: &str) -> err::Result<Vec<TagRef>> {
+        let out = self.output(&[
+            "for-each-ref",
+            concat!(
+                "--format=%(objecttype)%00%(refname)%00%(*objectname)%00",
+                "%(taggerdate:unix)%00%(contents)%00",
+            ),
+            prefix,
+        ])?;
+        err::from_git(&out, || format!("failed to list refs under {}", prefix))?;
+        let stdout = err::Error::require_utf8(out.stdout, "for-each-ref output")?;
+        let mut result = Vec::new();
+        // Each record ends with a NUL, then the newline that `for-each-ref` adds.
+        for record in stdout.split_terminator("\0\n") {
+            let fields: Vec<&str> = record.splitn(5, '\0').collect();
+            match fields.as_slice() {
+                ["tag", refname, target, date, message] => result.push(TagRef {
+                    refname: refname.to_string(),
+                    target: target.parse()?,
+                    date: date.parse().unwrap_or(0),
+                    message: message.to_string(),
+                }),
+                [_, _, _, _, _] => (),
+                _ => {
+                    return Err(err::Error::GitContract(format!(
+                        "bad for-each-ref record: {:?}",
+                        record
+                    )))
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Delete the given ref, if it exists.
+    pub fn delete_ref(&self, refname: &str, reason: &str) -> err::Result<()> {
+        let out = self.output(&["update-ref", "-m", reason, "-d", refname])?;
+        self.invalidate_refs();
+        err::from_git(&out, || format!("failed to delete {}", refname))
+    }
+
+    /// Write a tag object with the given contents, in the form that `git mktag` reads, and return
+    /// its object ID.
+    pub fn mktag(&self, contents: &str) -> err::Result<Oid> {
+        self.write_object(&["mktag"], contents)
+    }
+
+    /// Write a blob with the given contents and return its object ID.
+    pub fn write_blob(&self, contents: &str) -> err::Result<Oid> {
+        self.write_object(&["hash-object", "-w", "--stdin"], contents)
+    }
+
+    /// Write the empty tree and return its object ID.
+    pub fn empty_tree(&self) -> err::Result<Oid> {
+        self.write_object(&["mktree"], "")
+    }
+
+    /// Read the blob that `rev` names as text, or `None` if there is no such object.
+    pub fn read_blob(&self, rev: &str) -> err::Result<Option<String>> {
+        if self.rev_parse(rev)?.is_none() {
+            return Ok(None);
+        }
+        let out = self.output(&["cat-file", "blob", rev])?;
+        err::from_git(&out, || format!("failed to read blob {}", rev))?;
+        err::Error::require_utf8(out.stdout, "blob contents").map(Some)
+    }
+
+    /// Run a command that reads an object's contents on stdin, writes the object, and prints its
+    /// object ID.
+    fn write_object(&self, args: &[&str], contents: &str) -> err::Result<Oid> {
+        let writing = || format!("failed to run `git {}`", args.join(" "));
+        let mut child = git(&self.directory)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .logged_spawn()
+            .context(writing)?;
+        // Dropping stdin once written tells the command that the contents are complete.
+        child
+            .stdin
+            .take()
+            .expect("object writer stdin")
+            .write_all(contents.as_bytes())
+            .context(writing)?;
+        let out = crate::cancel::wait_with_output(child).context(writing)?;
+        err::from_git(&out, || {
+            format!("failed to write object with `git {}`", args[0])
+        })?;
+        parse_oid(out.stdout)
+            .map_err(|buf| {
+                err::Error::GitContract(format!(
+                    "{} gave bad output: {:?}",
+                    args[0],
+                    String::from_utf8_lossy(&buf)
+                ))
+            })?
+            .parse()
+    }
+
+    /// Read a configuration option, or `None` if it is not set. If it is set more than once, the
+    /// last value wins, as with `git config --get`.
+    pub fn config_get(&self, key: &str) -> err::Result<Option<String>> {
+        let key = normalize_config_key(key);
+        Ok(self
+            .config_snapshot()?
+            .iter()
+            .rev()
+            .find(|entry| entry.key == key)
+            .map(|entry| entry.value.clone().unwrap_or_default()))
+    }
+
+    /// Read a boolean configuration option, as interpreted by Git (e.g., `yes`, `off`, `1`).
+    pub fn config_get_bool(&self, key: &str) -> err::Result<Option<bool>> {
+        let normalized = normalize_config_key(key);
+        let snapshot = self.config_snapshot()?;
+        let entry = match snapshot.iter().rev().find(|entry| entry.key == normalized) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        // A key with no `=` at all is true; see `git_parse_maybe_bool` in Git's `parse.c`.
+        let value = match entry.value {
+            Some(ref value) => value.to_ascii_lowercase(),
+            None => return Ok(Some(true)),
+        };
+        match value.as_str() {
+            "true" | "yes" | "on" => Ok(Some(true)),
+            "" | "false" | "no" | "off" => Ok(Some(false)),
+            other => match other.parse::<i64>() {
+                Ok(n) => Ok(Some(n != 0)),
+                Err(_) => Err(err::Error::InvalidConfig(key.to_string())),
+            },
+        }
+    }
+
+    /// Read all values of a multi-valued configuration option, in order.
+    pub fn config_get_all(&self, key: &str) -> err::Result<Vec<String>> {
+        let key = normalize_config_key(key);
+        Ok(self
+            .config_snapshot()?
+            .iter()
+            .filter(|entry| entry.key == key)
+            .map(|entry| entry.value.clone().unwrap_or_default())
+            .collect())
+    }
+
+    /// List every configuration entry that Git sees, in the order that Git reads them, as by
+    /// `git config --list --show-origin`.
+    pub fn config_entries(&self) -> err::Result<Vec<ConfigEntry>> {
+        Ok(self.config_snapshot()?.clone())
+    }
+
+    /// The configuration as of the first read in this run (or the last `config_set`), so that an
+    /// invocation sees one consistent configuration and reads it with a single `git config`.
+    fn config_snapshot(&self) -> err::Result<std::cell::Ref<'_, Vec<ConfigEntry>>> {
+        if self.config.borrow().is_none() {
+            let out = self.output(&["config", "--list", "--show-origin", "-z"])?;
+            err::from_git(&out, || "failed to read config".to_string())?;
+            let stdout = err::Error::require_utf8(out.stdout, "config list")?;
+            let mut entries = Vec::new();
+            let mut fields = stdout.split_terminator('\0');
+            while let Some(origin) = fields.next() {
+                let entry = fields.next().ok_or_else(|| {
+                    err::Error::GitContract(format!("config --list: no entry after {:?}", origin))
+                })?;
+                let (key, value) = match entry.split_once('\n') {
+                    Some((key, value)) => (key, Some(value.to_string())),
+                    None => (entry, None),
+                };
+                entries.push(ConfigEntry {
+                    origin: origin.to_string(),
+                    key: key.to_string(),
+                    value,
+                });
+            }
+            *self.config.borrow_mut() = Some(entries);
+        }
+        Ok(std::cell::Ref::map(self.config.borrow(), |config| {
+            config.as_ref().expect("config snapshot just loaded")
+        }))
+    }
+
+    /// List the branches of all remotes, by their names on the remote, as recorded by
+    /// remote-tracking refs. Assumes that remote names have no slashes.
+    pub fn remote_branches(&self) -> err::Result<Vec<String>> {
+        if let Some(ref branches) = self.refs.borrow().remote_branches {
+            return Ok(branches.clone());
+        }
+        let out = self.output(&[
+            "for-each-ref",
+            "--format=%(refname:lstrip=3)",
+            "refs/remotes/",
+        ])?;
+        err::from_git(&out, || "failed to list remote-tracking refs".to_string())?;
+        let stdout = err::Error::require_utf8(out.stdout, "for-each-ref output")?;
+        let branches: Vec<String> = stdout
+            .lines()
+            .filter(|name| *name != "HEAD")
+            .map(|name| name.to_string())
+            .collect();
+        self.refs.borrow_mut().remote_branches = Some(branches.clone());
+        Ok(branches)
+    }
+
+    /// List the branches of the given remote, as recorded by its remote-tracking refs, with their
+    /// tips and the tips' committer dates.
+    pub fn remote_branch_tips(&self, remote: &str) -> err::Result<Vec<RemoteBranch>> {
+        let out = self.output(&[
+            "for-each-ref",
+            "--format=%(refname:lstrip=3)%00%(objectname)%00%(committerdate:unix)",
+            &format!("refs/remotes/{}/", remote),
+        ])?;
+        err::from_git(&out, || "failed to list remote-tracking refs".to_string())?;
+        let stdout = err::Error::require_utf8(out.stdout, "for-each-ref output")?;
+        let mut result = Vec::new();
+        for line in stdout.lines() {
+            let mut fields = line.split('\0');
+            let (name, oid, committed) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(name), Some(oid), Some(committed)) => (name, oid, committed),
+                _ => {
+                    return Err(err::Error::GitContract(format!(
+                        "bad for-each-ref line: {:?}",
+                        line
+                    )))
+                }
+            };
+            if name == "HEAD" {
+                continue;
+            }
+            result.push(RemoteBranch {
+                name: name.to_string(),
+                oid: oid.parse()?,
+                // Annotated tags and other non-commits have no committer date.
+                committed: committed.parse().unwrap_or(0),
+            });
+        }
+        Ok(result)
+    }
+
+    /// List the local branches, by name, with their tips.
+    pub fn local_branches(&self) -> err::Result<Vec<(String, Oid)>> {
+        let out = self.output(&[
+            "for-each-ref",
+            "--format=%(refname:lstrip=2)%00%(objectname)",
+            "refs/heads/",
+        ])?;
+        err::from_git(&out, || "failed to list branches".to_string())?;
+        let stdout = err::Error::require_utf8(out.stdout, "for-each-ref output")?;
+        let mut result = Vec::new();
+        for line in stdout.lines() {
+            match line.split_once('\0') {
+                Some((name, oid)) => result.push((name.to_string(), oid.parse()?)),
+                None => {
+                    return Err(err::Error::GitContract(format!(
+                        "bad for-each-ref line: {:?}",
+                        line
+                    )))
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Rebase the commits of `branch` after `upstream` onto `onto`, as `git rebase --onto` does,
+    /// leaving `branch` checked out. Returns `false`, with the rebase aborted, if it stopped on
+    /// conflicts.
+    pub fn rebase_onto(&self, onto: &str, upstream: &str, branch: &str) -> err::Result<bool> {
+        let out = self.output(&["rebase", "--onto", onto, upstream, branch])?;
+        self.invalidate_refs();
+        if out.status.success() {
+            return Ok(true);
+        }
+        // If there is a rebase in progress to abort, it stopped partway; else it never started.
+        let abort = self.output(&["rebase", "--abort"])?;
+        if abort.status.success() {
+            return Ok(false);
+        }
+        err::from_git(&out, || format!("failed to rebase {}", branch)).map(|_| false)
+    }
+
+    /// Ask a remote directly for the object ID of one of its refs (e.g., `refs/heads/master`),
+    /// bypassing remote-tracking refs. Requires network access to the remote.
+    pub fn ls_remote(&self, remote: &str, refname: &str) -> err::Result<Option<Oid>> {
+        let out = self.output(&["ls-remote", "--refs", remote, refname])?;
+        err::from_git(&out, || format!("failed to list refs on {}", remote))?;
+        let stdout = err::Error::require_utf8(out.stdout, "ls-remote output")?;
+        for line in stdout.lines() {
+            let mut parts = line.splitn(2, '\t');
+            if let (Some(oid), Some(name)) = (parts.next(), parts.next()) {
+                if name == refname {
+                    return oid.parse().map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Ask a remote directly for the head of one of its branches, as `ls_remote` does, but list all
+    /// of its branches the first time, and answer later lookups on the same remote from that list
+    /// until `forget_remote_heads`.
+    pub fn remote_head(&self, remote: &str, branch: &str) -> err::Result<Option<Oid>> {
+        if let Some(heads) = self.remote_heads.borrow().get(remote) {
+            return Ok(heads.get(branch).cloned());
+        }
+        let out = self.output(&["ls-remote", "--heads", remote])?;
+        err::from_git(&out, || format!("failed to list branches on {}", remote))?;
+        let stdout = err::Error::require_utf8(out.stdout, "ls-remote output")?;
+        let mut heads = HashMap::new();
+        for line in stdout.lines() {
+            if let Some((oid, name)) = line.split_once('\t') {
+                if let Some(name) = name.strip_prefix("refs/heads/") {
+                    heads.insert(name.to_string(), oid.parse()?);
+                }
+            }
+        }
+        let head = heads.get(branch).cloned();
+        self.remote_heads
+            .borrow_mut()
+            .insert(remote.to_string(), heads);
+        Ok(head)
+    }
+
+    /// Forget the branches listed by `remote_head`. Call this after a push.
+    pub fn forget_remote_heads(&self) {
+        self.remote_heads.borrow_mut().clear();
+    }
+
+    /// Ask a remote directly for the target of its `HEAD` symref (e.g., `refs/heads/master`).
+    pub fn ls_remote_head(&self, remote: &str) -> err::Result<Option<String>> {
+        let out = self.output(&["ls-remote", "--symref", remote, "HEAD"])?;
+        err::from_git(&out, || format!("failed to list refs on {}", remote))?;
+        let stdout = err::Error::require_utf8(out.stdout, "ls-remote output")?;
+        // e.g., "ref: refs/heads/master\tHEAD"
+        Ok(stdout.lines().find_map(|line| {
+            let line = line.strip_prefix("ref: ")?;
+            let mut parts = line.splitn(2, '\t');
+            match (parts.next(), parts.next()) {
+                (Some(target), Some("HEAD")) => Some(target.to_string()),
+                _ => None,
+            }
+        }))
+    }
+
+    /// Fetch a single ref from a remote into `FETCH_HEAD` only, so that its objects are available
+    /// locally without creating or updating any other refs.
+    pub fn fetch_ref(&self, remote: &str, refname: &str) -> err::Result<()> {
+        let args = [
+            "fetch",
+            crate::progress::transfer_flag(),
+            "--no-tags",
+            remote,
+            refname,
+        ];
+        let out = crate::progress::output(self.command().args(args))
+            .context(|| format!("failed to run `git {}`", args.join(" ")))?;
+        self.invalidate_refs();
+        err::from_git(&out, || {
+            format!("failed to fetch {} from {}", refname, remote)
+        })
+    }
+
+    /// Set a configuration option in the repository-local config file.
+    pub fn config_set(&self, key: &str, value: &str) -> err::Result<()> {
+        let out = self.output(&["config", "--local", key, value])?;
+        *self.config.borrow_mut() = None;
+        err::from_git(&out, || format!("failed to set config {}", key))
+    }
+
+    /// Remove a configuration option from the repository-local config file, if it is set there.
+    pub fn config_unset(&self, key: &str) -> err::Result<()> {
+        let out = self.output(&["config", "--local", "--unset-all", key])?;
+        *self.config.borrow_mut() = None;
+        // Exit status 5 means that the option was not set.
+        if out.status.code() == Some(5) {
+            return Ok(());
+        }
+        err::from_git(&out, || format!("failed to unset config {}", key))
+    }
+
+    /// Resolve a path inside the Git directory (e.g., `hooks/commit-msg`), as by `git rev-parse
+    /// --git-path`.
+    pub fn git_path(&self, path: &str) -> err::Result<PathBuf> {
+        let out = self.output(&["rev-parse", "--git-path", path])?;
+        err::from_git(&out, || format!("failed to resolve git path {}", path))?;
+        let relative = parse_oid(out.stdout).map_err(|buf| {
+            err::Error::GitContract(format!(
+                "rev-parse --git-path gave bad output: {:?}",
+                String::from_utf8_lossy(&buf)
+            ))
+        })?;
+        Ok(self.directory.join(native_path(relative)))
+    }
+
+    /// Get the absolute path of the top of the worktree.
+    pub fn toplevel(&self) -> err::Result<PathBuf> {
+        let out = self.output(&["rev-parse", "--show-toplevel"])?;
+        err::from_git(&out, || "failed to find top of worktree".to_string())?;
+        let top = parse_oid(out.stdout).map_err(|buf| {
+            err::Error::GitContract(format!(
+                "rev-parse --show-toplevel gave bad output: {:?}",
+                String::from_utf8_lossy(&buf)
+            ))
+        })?;
+        Ok(native_path(top))
+    }
+
+    /// Find the shell that Git runs commands like editors and hooks with: as `git var
+    /// GIT_SHELL_PATH` reports it, where Git is new enough to, else `sh` from the `PATH`. On
+    /// Windows, Git's own shell is usually not on the `PATH`.
+    pub fn shell(&self) -> err::Result<PathBuf> {
+        let out = self.output(&["var", "GIT_SHELL_PATH"])?;
+        match parse_oid(out.stdout) {
+            Ok(path) if out.status.success() && !path.is_empty() => Ok(native_path(path)),
+            _ => Ok(PathBuf::from("sh")),
+        }
+    }
+
+    /// Get the identity that commits made now would be committed by, as `git var` reports it.
+    pub fn committer(&self) -> err::Result<Signature> {
+        let out = self.output(&["var", "GIT_COMMITTER_IDENT"])?;
+        err::from_git(&out, || "failed to get committer identity".to_string())?;
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        Signature::parse(stdout.trim_end()).ok_or_else(|| {
+            err::Error::GitContract(format!("unrecognized committer identity: {:?}", stdout))
+        })
+    }
+
+    /// Get the editor that Git would open for a commit message, as `git var` reports it
+    /// (following `GIT_EDITOR`, `core.editor`, `VISUAL`, and `EDITOR`).
+    pub fn editor(&self) -> err::Result<String> {
+        let out = self.output(&["var", "GIT_EDITOR"])?;
+        err::from_git(&out, || "failed to find an editor".to_string())?;
+        Ok(String::from_utf8_lossy(&out.stdout).trim_end().to_string())
+    }
+
+    /// Open a URL in a web browser, as `git web--browse` does (following `web.browser`).
+    pub fn web_browse(&self, url: &str) -> err::Result<()> {
+        let out = self.output(&["web--browse", url])?;
+        err::from_git(&out, || format!("failed to open {}", url))?;
+        Ok(())
+    }
+
+    /// Get the major and minor version numbers of the `git(1)` binary.
+    pub fn version(&self) -> err::Result<(u32, u32)> {
+        let out = self.output(&["version"])?;
+        err::from_git(&out, || "failed to get git version".to_string())?;
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        // e.g., "git version 2.39.5" or "git version 2.39.5.windows.1"
+        let mut parts = stdout
+            .trim()
+            .trim_start_matches("git version ")
+            .split('.')
+            .map(|part| part.parse::<u32>().ok());
+        match (parts.next(), parts.next()) {
+            (Some(Some(major)), Some(Some(minor))) => Ok((major, minor)),
+            _ => Err(err::Error::GitContract(format!(
+                "unrecognized version string: {:?}",
+                stdout
+            ))),
+        }
+    }
+
+    pub fn rev_parse_commit(&self, rev: &str) -> err::Result<Option<Oid>> {
         match self.rev_parse(rev)? {
             None => Ok(None),
             Some(hash) => self.rev_parse(&format!("{}^{{commit}}", hash)),
         }
     }
 
-    pub fn rev_parse_commit_ok(&self, rev: &str) -> err::Result<String> {
+    pub fn rev_parse_commit_ok(&self, rev: &str) -> err::Result<Oid> {
         self.rev_parse_commit(rev)?
             .ok_or_else(|| err::Error::NoSuchCommit(rev.to_string()))
     }
 
+    /// Set how to find the trailers of commits read from now on, as configured for the repository.
+    /// Commits already read are read again.
+    pub fn set_trailer_syntax(&mut self, syntax: crate::trailers::Syntax) {
+        self.trailer_syntax = syntax;
+        self.commits.clear();
+    }
+
     /// Read details of a commit object (maybe from cache). The `hash` may be any commit reference:
     /// e.g., a literal unambiguous hash, a spec like `HASH~1^2` (where `HASH` is a full hash), or
     /// a context-sensitive reference like `HEAD` or `master`. It should not be misinterpretable as
     /// a flag to `git-show` or friends (e.g., anything starting with a hyphen).
-    // TODO(@wchargin): A recent version of Git (which?) added `--end-of-options` to function like
+    // TODO(@wchargin): Git 2.24 added `--end-of-options` to function like
     // `--` in commands where that is used to disambiguate revisions and paths. Use that to drop
     // the side condition?
     pub fn commit(&mut self, hash: &str) -> err::Result<&Commit> {
-        if self.commits.contains_key(hash) {
-            return Ok(self
-                .commits
-                .get(hash)
-                .expect("hash not in map even after check"));
-        }
-        let commit = match self.read_commit(hash)? {
-            ReadCommit::Cached(oid) => {
+        // Only a full object ID can be looked up in the cache: anything else might resolve
+        // differently now than when it was read.
+        if let Some(oid) = Oid::parse(hash) {
+            if self.commits.contains_key(&oid) {
                 return Ok(self
                     .commits
                     .get(&oid)
-                    .expect("allegedly cached commit not in map"))
+                    .expect("hash not in map even after check"));
             }
-            ReadCommit::Read(commit) => commit,
-        };
+        }
+        let mut commit = self.repository.read_commit(hash)?;
+        commit.trailers = self.trailer_syntax.parse(&commit.message);
         use std::collections::hash_map::Entry::{Occupied, Vacant};
         match self.commits.entry(commit.oid.clone()) {
             Occupied(e) => {
@@ -142,12 +1242,35 @@ impl GitStore {
             Vacant(e) => Ok(e.insert(commit)),
         }
     }
+}
+
+impl Subprocess {
+    fn output(&self, args: &[&str]) -> err::Result<Output> {
+        output(&self.directory, args)
+    }
+
+    /// Read an object from the `cat-file` process, starting it if needed.
+    fn cat_file(&self, rev: &str) -> err::Result<Option<(Oid, String, Vec<u8>)>> {
+        let mut cat_file = self.cat_file.borrow_mut();
+        if cat_file.is_none() {
+            *cat_file = Some(CatFile::spawn(&self.directory)?);
+        }
+        let result = cat_file.as_mut().expect("cat-file just started").read(rev);
+        if result.is_err() {
+            *cat_file = None;
+        }
+        result.context(|| format!("failed to read {} with `git cat-file --batch`", rev))
+    }
 
-    fn read_commit(&self, hash: &str) -> err::Result<ReadCommit> {
-        let show_output = self
-            .git()
-            .args(&["show", "--no-patch", "--pretty=format:%B%n%P%n%T%n%H", hash])
-            .output()?;
+    /// Read a commit with `git show`, which re-encodes its message to UTF-8 if needed.
+    fn show_commit(&self, hash: &str) -> err::Result<Commit> {
+        let show_output = self.output(&[
+            "show",
+            "--no-patch",
+            "--date=raw",
+            "--pretty=format:%B%n%an <%ae> %ad%n%P%n%T%n%H",
+            hash,
+        ])?;
         if !show_output.status.success() {
             return Err(err::Error::NoSuchCommit(hash.to_string()));
         }
@@ -163,31 +1286,30 @@ impl GitStore {
             remainder
         };
         let pre_hash_newline = find_last_newline(&mut stdout)?;
-        let output_hash = split_off_at(&mut stdout, pre_hash_newline);
-        if self.rev_parse_commit_ok(&output_hash)? != output_hash {
+        let output_hash: Oid = split_off_at(&mut stdout, pre_hash_newline).parse()?;
+        if self
+            .rev_parse(&format!("{}^{{commit}}", output_hash))?
+            .as_ref()
+            != Some(&output_hash)
+        {
             // successfully showed a different kind of object, like a tree
             return Err(err::Error::NoSuchCommit(hash.to_string()));
         }
-        if hash != output_hash {
-            if self.commits.contains_key(hash) {
-                return Ok(ReadCommit::Cached(output_hash));
-            }
-        }
         let pre_tree_newline = find_last_newline(&mut stdout)?;
-        let tree = split_off_at(&mut stdout, pre_tree_newline);
+        let tree = split_off_at(&mut stdout, pre_tree_newline).parse()?;
         let pre_parents_newline = find_last_newline(&mut stdout)?;
-        let mut reverse_parents: Vec<String> = Vec::new();
+        let mut reverse_parents: Vec<Oid> = Vec::new();
         loop {
             match stdout.rfind(' ') {
                 Some(i) if i > pre_parents_newline => {
                     let parent = split_off_at(&mut stdout, i);
-                    reverse_parents.push(parent);
+                    reverse_parents.push(parent.parse()?);
                 }
                 _ => {
                     let parent = split_off_at(&mut stdout, pre_parents_newline);
                     if !parent.is_empty() {
                         // will be empty if there are no parents
-                        reverse_parents.push(parent);
+                        reverse_parents.push(parent.parse()?);
                     }
                     break;
                 }
@@ -197,22 +1319,257 @@ impl GitStore {
             reverse_parents.reverse();
             reverse_parents
         };
+        let pre_author_newline = find_last_newline(&mut stdout)?;
+        let author = split_off_at(&mut stdout, pre_author_newline);
+        let author = Signature::parse(&author).ok_or_else(|| {
+            err::Error::GitContract(format!("bad author of {}: {:?}", output_hash, author))
+        })?;
         let message = stdout;
-        Ok(ReadCommit::Read(Commit {
+        Ok(Commit {
             oid: output_hash,
             parents,
             tree,
             message,
-        }))
+            author,
+            trailers: Vec::new(),
+        })
+    }
+}
+
+impl Repository for Subprocess {
+    fn rev_parse(&self, rev: &str) -> err::Result<Option<Oid>> {
+        let out = self.output(&["rev-parse", "--verify", rev])?;
+        if !out.status.success() {
+            return Ok(None);
+        };
+        parse_oid(out.stdout)
+            .map_err(|buf| {
+                err::Error::GitContract(format!(
+                    "rev-parse returned success but stdout was: {:?}",
+                    String::from_utf8_lossy(&buf)
+                ))
+            })?
+            .parse()
+            .map(Some)
+    }
+
+    fn read_commit(&self, hash: &str) -> err::Result<Commit> {
+        let no_such_commit = || err::Error::NoSuchCommit(hash.to_string());
+        if hash.contains('\n') {
+            return Err(no_such_commit());
+        }
+        let (oid, kind, contents) = self
+            .cat_file(&format!("{}^{{commit}}", hash))?
+            .ok_or_else(no_such_commit)?;
+        if kind != "commit" {
+            return Err(no_such_commit());
+        }
+        let (headers, message) = match find_subslice(&contents, b"\n\n") {
+            Some(i) => (&contents[..i], &contents[i + 2..]),
+            None => (&contents[..], &b""[..]),
+        };
+        let headers = String::from_utf8_lossy(headers);
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        for line in headers.lines() {
+            match line.split_once(' ') {
+                Some(("tree", value)) => tree = Some(value.parse()?),
+                Some(("parent", value)) => parents.push(value.parse()?),
+                Some(("author", value)) => author = Signature::parse(value),
+                Some(("encoding", value))
+                    if !value.eq_ignore_ascii_case("utf-8")
+                        && !value.eq_ignore_ascii_case("utf8") =>
+                {
+                    // Let `git show` convert the message to UTF-8.
+                    return self.show_commit(&oid);
+                }
+                _ => (),
+            }
+        }
+        let tree = tree
+            .ok_or_else(|| err::Error::GitContract(format!("commit {} has no tree header", oid)))?;
+        let author = author.ok_or_else(|| {
+            err::Error::GitContract(format!("commit {} has no valid author header", oid))
+        })?;
+        let message = err::Error::require_utf8(message.to_vec(), hash)?;
+        Ok(Commit {
+            oid,
+            parents,
+            tree,
+            message,
+            author,
+            trailers: Vec::new(),
+        })
+    }
+
+    fn diff_names(&self, a: &str, b: &str) -> err::Result<Vec<String>> {
+        let out = self.output(&["diff-tree", "-r", "-z", "--no-renames", "--name-only", a, b])?;
+        err::from_git(&out, || format!("failed to diff {} and {}", a, b))?;
+        let stdout = err::Error::require_utf8(out.stdout, "diff-tree paths")?;
+        Ok(stdout
+            .split_terminator('\0')
+            .map(|path| path.to_string())
+            .collect())
+    }
+}
+
+impl CatFile {
+    fn spawn(directory: &Path) -> err::Result<CatFile> {
+        let mut child = git(directory)
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .logged_spawn()
+            .context(|| "failed to run `git cat-file --batch`".to_string())?;
+        let stdout = BufReader::new(child.stdout.take().expect("cat-file stdout"));
+        Ok(CatFile { child, stdout })
+    }
+
+    /// Read the object named by `rev`, returning its object ID, type, and contents, or `None` if
+    /// there is no such object.
+    fn read(&mut self, rev: &str) -> err::Result<Option<(Oid, String, Vec<u8>)>> {
+        crate::cancel::check()?;
+        let stdin = self.child.stdin.as_mut().expect("cat-file stdin");
+        stdin.write_all(format!("{}\n", rev).as_bytes())?;
+        stdin.flush()?;
+        let mut header = String::new();
+        if self.stdout.read_line(&mut header)? == 0 {
+            return Err(err::Error::GitContract(
+                "`git cat-file --batch` exited unexpectedly".to_string(),
+            ));
+        }
+        // "<oid> <type> <size>", or "<rev> missing" (or "ambiguous") if it doesn't resolve.
+        let fields: Vec<&str> = header.trim_end_matches('\n').rsplitn(3, ' ').collect();
+        let (oid, kind, size) = match fields.as_slice() {
+            [size, kind, oid] => match size.parse::<usize>() {
+                Ok(size) => (oid.parse()?, kind.to_string(), size),
+                Err(_) => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        // The contents are followed by a newline.
+        let mut contents = vec![0; size + 1];
+        self.stdout.read_exact(&mut contents)?;
+        contents.pop();
+        Ok(Some((oid, kind, contents)))
     }
 }
 
+impl Drop for CatFile {
+    fn drop(&mut self) {
+        // Closing stdin tells `cat-file` to exit, but it is killed if the run was cancelled.
+        drop(self.child.stdin.take());
+        let _ = crate::cancel::wait(&mut self.child);
+    }
+}
+
+/// Find the first occurrence of `needle` in `haystack`.
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// A path as Git prints it, with forward slashes even on Windows, in the platform's own form.
+fn native_path(path: String) -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from(path.replace('/', "\\"))
+    } else {
+        PathBuf::from(path)
+    }
+}
+
+/// Drop the `\\?\` prefix that `canonicalize` gives paths on Windows, which Git doesn't accept.
+/// Network paths (`\\?\UNC\...`) are left alone, since they can't simply do without it.
+fn strip_verbatim(path: PathBuf) -> PathBuf {
+    match path.to_str().and_then(|s| s.strip_prefix(r"\\?\")) {
+        Some(rest) if !rest.starts_with(r"UNC\") => PathBuf::from(rest),
+        _ => path,
+    }
+}
+
+/// A `git` command that runs in the repository at `directory`.
+fn git(directory: &Path) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C");
+    cmd.arg(directory.as_os_str());
+    cmd.args(["-c", "i18n.logOutputEncoding=utf-8"]);
+    cmd
+}
+
+/// Run `git` in the repository at `directory` with the given arguments, capturing its output.
+fn output(directory: &Path, args: &[&str]) -> err::Result<Output> {
+    git(directory)
+        .args(args)
+        .logged_output()
+        .context(|| format!("failed to run `git {}`", args.join(" ")))
+}
+
 pub fn parse_oid(stdout: Vec<u8>) -> Result<String, Vec<u8>> {
     let mut raw = String::from_utf8(stdout).map_err(|e| e.into_bytes())?;
     match raw.pop() {
-        Some('\n') => return Ok(raw),
+        Some('\n') => {
+            // Tolerate CRLF line endings, as from some Windows builds of Git.
+            if raw.ends_with('\r') {
+                raw.pop();
+            }
+            return Ok(raw);
+        }
         Some(other) => raw.push(other),
         None => (),
     }
     Err(raw.into_bytes())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_path_keeps_spaces_and_backslashes() {
+        let path = native_path("C:/Users/Jo Doe/my repo".to_string());
+        if cfg!(windows) {
+            assert_eq!(path, PathBuf::from(r"C:\Users\Jo Doe\my repo"));
+        } else {
+            assert_eq!(path, PathBuf::from("C:/Users/Jo Doe/my repo"));
+        }
+        // A backslash is an ordinary character in a Unix file name.
+        let path = native_path(r"/home/jo/odd\dir".to_string());
+        if cfg!(windows) {
+            assert_eq!(path, PathBuf::from(r"\home\jo\odd\dir"));
+        } else {
+            assert_eq!(path, PathBuf::from(r"/home/jo/odd\dir"));
+        }
+    }
+
+    #[test]
+    fn strip_verbatim_drops_local_prefix() {
+        assert_eq!(
+            strip_verbatim(PathBuf::from(r"\\?\C:\Users\Jo Doe\my repo")),
+            PathBuf::from(r"C:\Users\Jo Doe\my repo")
+        );
+    }
+
+    #[test]
+    fn strip_verbatim_keeps_network_and_plain_paths() {
+        for path in [
+            r"\\?\UNC\server\share\my repo",
+            r"C:\Users\Jo Doe\my repo",
+            "/home/jo/my repo",
+            r"/home/jo/odd\dir",
+        ] {
+            assert_eq!(strip_verbatim(PathBuf::from(path)), PathBuf::from(path));
+        }
+    }
+
+    #[test]
+    fn parse_oid_tolerates_crlf() {
+        assert_eq!(
+            parse_oid(b"/home/jo/my repo\r\n".to_vec()),
+            Ok("/home/jo/my repo".to_string())
+        );
+        assert_eq!(parse_oid(b"abc\n".to_vec()), Ok("abc".to_string()));
+        assert!(parse_oid(b"abc".to_vec()).is_err());
+    }
+}
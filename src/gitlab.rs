@@ -0,0 +1,297 @@
+//! The GitLab implementation of `forge::Forge`, selected with `dx.forge = gitlab`. Merge requests
+//! play the part of pull requests, identified by their project-scoped IIDs (e.g., `!7`), and the
+//! latest pipeline on a commit stands in for its checks.
+
+use serde_json::{json, Value};
+
+use crate::err;
+use crate::forge::{self, CheckState, CheckStatus, Forge, PullRequest};
+use crate::git::GitStore;
+
+/// Config option giving the base URL of the GitLab instance (e.g., `https://gitlab.example.com`).
+const FORGE_URL_CONFIG: &str = "dx.forgeUrl";
+/// Config option naming the upstream GitLab project by its full path (e.g., `group/sub/name`), for
+/// when it can't be inferred from the remote URL.
+const PROJECT_CONFIG: &str = "dx.gitlabProject";
+
+/// A GitLab project, as reached through its REST API.
+pub struct GitLab {
+    /// The instance's base URL, like `https://gitlab.com`.
+    base_url: String,
+    api_url: String,
+    token: String,
+    /// The project's full path, like `group/subgroup/name`.
+    project: String,
+}
+
+impl GitLab {
+    /// Connect to the GitLab project behind `remote`, if it is one. Returns `None` if the project
+    /// can't be determined or no API token is available.
+    pub fn for_remote(git: &GitStore, remote: &str) -> err::Result<Option<GitLab>> {
+        let url = git.config_get(&format!("remote.{}.url", remote))?;
+        let parsed = url.as_deref().and_then(forge::parse_remote_path);
+        let project = match git.config_get(PROJECT_CONFIG)? {
+            Some(project) if project.contains('/') => project.trim_matches('/').to_string(),
+            Some(_) => return Err(err::Error::InvalidConfig(PROJECT_CONFIG.to_string())),
+            None => match parsed {
+                Some((_, ref path)) if path.contains('/') => path.clone(),
+                _ => return Ok(None),
+            },
+        };
+        let base_url = match git.config_get(FORGE_URL_CONFIG)? {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => match parsed {
+                Some((ref host, _)) => format!("https://{}", host),
+                None => "https://gitlab.com".to_string(),
+            },
+        };
+        let host = base_url
+            .split_once("://")
+            .map_or(base_url.as_str(), |(_, rest)| rest)
+            .split(['/', ':'])
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let token = match forge::token(git, &host, &["GITLAB_TOKEN"])? {
+            Some(token) => token,
+            None => {
+                warn!("no GitLab token for {}; set GITLAB_TOKEN", host);
+                return Ok(None);
+            }
+        };
+        Ok(Some(GitLab {
+            api_url: format!("{}/api/v4", base_url),
+            base_url,
+            token,
+            project,
+        }))
+    }
+
+    /// The API path of the project, under which all of its resources are.
+    fn project_path(&self) -> String {
+        format!("/projects/{}", encode(&self.project))
+    }
+
+    /// Make a request to the REST API and parse the JSON response.
+    fn request(&self, method: &str, path: &str, body: Option<Value>) -> err::Result<Value> {
+        forge::request(
+            &self.api_url,
+            &[format!("PRIVATE-TOKEN: {}", self.token)],
+            method,
+            path,
+            body,
+        )
+    }
+
+    /// Update fields of a merge request.
+    fn update(&self, number: u64, fields: Value) -> err::Result<()> {
+        let path = format!("{}/merge_requests/{}", self.project_path(), number);
+        self.request("PUT", &path, Some(fields))?;
+        Ok(())
+    }
+}
+
+impl Forge for GitLab {
+    fn owner(&self) -> &str {
+        self.project.rsplit_once('/').map_or("", |(owner, _)| owner)
+    }
+
+    fn find_pull_request(
+        &self,
+        _head_owner: &str,
+        branch: &str,
+    ) -> err::Result<Option<PullRequest>> {
+        // GitLab filters merge requests by source branch, whichever project it is in.
+        let path = format!(
+            "{}/merge_requests?state=opened&source_branch={}",
+            self.project_path(),
+            encode(branch)
+        );
+        let requests = self.request("GET", &path, None)?;
+        Ok(requests
+            .as_array()
+            .and_then(|requests| requests.first())
+            .and_then(merge_request))
+    }
+
+    fn pull_requests_onto(&self, branch: &str) -> err::Result<Vec<PullRequest>> {
+        let path = format!(
+            "{}/merge_requests?state=opened&target_branch={}&per_page=100",
+            self.project_path(),
+            encode(branch)
+        );
+        let requests = self.request("GET", &path, None)?;
+        Ok(requests
+            .as_array()
+            .map(|requests| requests.iter().filter_map(merge_request).collect())
+            .unwrap_or_default())
+    }
+
+    fn merged_head(&self, _head_owner: &str, branch: &str) -> err::Result<Option<String>> {
+        let path = format!(
+            "{}/merge_requests?state=merged&source_branch={}",
+            self.project_path(),
+            encode(branch)
+        );
+        let requests = self.request("GET", &path, None)?;
+        Ok(requests
+            .as_array()
+            .and_then(|requests| requests.first())
+            .and_then(|request| request["sha"].as_str())
+            .map(str::to_string))
+    }
+
+    fn create_pull_request(
+        &self,
+        head_owner: &str,
+        branch: &str,
+        base: &str,
+        title: &str,
+        body: &str,
+        draft: bool,
+    ) -> err::Result<PullRequest> {
+        let path = format!("{}/merge_requests", self.project_path());
+        if head_owner != self.owner() {
+            return Err(err::Error::ForgeRequest {
+                request: format!("POST {}", path),
+                reason: "merge requests from forks are not supported".to_string(),
+            });
+        }
+        let title = if draft {
+            format!("Draft: {}", title)
+        } else {
+            title.to_string()
+        };
+        let request = self.request(
+            "POST",
+            &path,
+            Some(json!({
+                "source_branch": branch,
+                "target_branch": base,
+                "title": title,
+                "description": body,
+            })),
+        )?;
+        merge_request(&request).ok_or_else(|| err::Error::ForgeRequest {
+            request: format!("POST {}", path),
+            reason: "response has no merge request IID".to_string(),
+        })
+    }
+
+    fn set_body(&self, number: u64, body: &str) -> err::Result<()> {
+        self.update(number, json!({ "description": body }))
+    }
+
+    fn set_base(&self, number: u64, base: &str) -> err::Result<()> {
+        self.update(number, json!({ "target_branch": base }))
+    }
+
+    fn close_pull_request(&self, number: u64) -> err::Result<()> {
+        self.update(number, json!({ "state_event": "close" }))
+    }
+
+    fn add_labels(&self, number: u64, labels: &[String]) -> err::Result<()> {
+        self.update(number, json!({ "add_labels": labels.join(",") }))
+    }
+
+    fn set_milestone(&self, number: u64, title: &str) -> err::Result<bool> {
+        let path = format!(
+            "{}/milestones?state=active&title={}",
+            self.project_path(),
+            encode(title)
+        );
+        let milestones = self.request("GET", &path, None)?;
+        let milestone = milestones.as_array().and_then(|milestones| {
+            milestones
+                .iter()
+                .find(|milestone| milestone["title"] == title)
+                .and_then(|milestone| milestone["id"].as_u64())
+        });
+        match milestone {
+            Some(milestone) => {
+                self.update(number, json!({ "milestone_id": milestone }))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn rename_branch(&self, _branch: &str, _new_name: &str) -> err::Result<bool> {
+        // GitLab can't rename branches, nor change the source branch of a merge request.
+        Ok(false)
+    }
+
+    fn check_status(&self, sha: &str) -> err::Result<CheckStatus> {
+        // Only the latest pipeline counts, so that a retried pipeline supersedes a failed one.
+        let path = format!(
+            "{}/pipelines?sha={}&order_by=id&sort=desc&per_page=1",
+            self.project_path(),
+            sha
+        );
+        let pipelines = self.request("GET", &path, None)?;
+        let pipeline = match pipelines.as_array().and_then(|pipelines| pipelines.first()) {
+            Some(pipeline) => pipeline,
+            // With no pipeline at all, CI may not have picked up the commit yet.
+            None => {
+                return Ok(CheckStatus {
+                    state: CheckState::Pending,
+                    pending: Vec::new(),
+                    failed: Vec::new(),
+                })
+            }
+        };
+        let name = format!("pipeline #{}", pipeline["id"].as_u64().unwrap_or_default());
+        let status = pipeline["status"].as_str().unwrap_or("");
+        Ok(match status {
+            "success" | "skipped" => CheckStatus {
+                state: CheckState::Success,
+                pending: Vec::new(),
+                failed: Vec::new(),
+            },
+            "failed" | "canceled" => CheckStatus {
+                state: CheckState::Failure,
+                pending: Vec::new(),
+                failed: vec![format!("{} ({})", name, status)],
+            },
+            _ => CheckStatus {
+                state: CheckState::Pending,
+                pending: vec![name],
+                failed: Vec::new(),
+            },
+        })
+    }
+
+    fn pull_request_url(&self, number: u64) -> String {
+        format!(
+            "{}/{}/-/merge_requests/{}",
+            self.base_url, self.project, number
+        )
+    }
+}
+
+/// Read a merge request from an API response.
+fn merge_request(request: &Value) -> Option<PullRequest> {
+    Some(PullRequest {
+        number: request["iid"].as_u64()?,
+        url: request["web_url"].as_str().unwrap_or_default().to_string(),
+        base: request["target_branch"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
+/// Percent-encode a value for a URL path segment or query parameter, as GitLab needs for project
+/// paths (`group%2Fname`) and for branch names with slashes.
+fn encode(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                result.push(byte as char)
+            }
+            _ => result.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    result
+}
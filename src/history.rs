@@ -0,0 +1,136 @@
+use crate::config::Config;
+use crate::err;
+use crate::git::{Commit, GitStore};
+
+pub const SUBCOMMAND: &str = "log";
+
+const CLI_ARG_BRANCH: &str = "branch";
+const CLI_ARG_FORK: &str = "fork";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_TRUNK: &str = "trunk";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Shows the integration history of a remote target branch")
+        .long_about(
+            "Shows the integration history of a remote target branch, as of the last fetch, \
+             newest first. Each line has the round of review (`-` for diffbase merges), the \
+             commit, what kind of update it was (`initial`, `patch`, `diffbase`, `bump`, or \
+             `no-op`), the source commit that it came from (`-` if none was recorded), and its \
+             subject, separated by tabs.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_BRANCH)
+                .help("Target branch to show (e.g., wchargin-foo)")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help(
+                    "Remote branch that the target branch is based on (default: the remote's HEAD)",
+                )
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote whose branches to inspect")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_FORK)
+                .help("Remote that holds integration branches, if not the `-r` remote")
+                .value_name("remote")
+                .long("--fork")
+                .takes_value(true),
+        )
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let branch = matches.value_of(CLI_ARG_BRANCH).unwrap();
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let remotes = crate::Remotes::new(&remote, matches.value_of(CLI_ARG_FORK));
+    let key = config
+        .branch_key_of(branch)
+        .ok_or_else(|| err::Error::NotTargetBranch(branch.to_string()))?;
+    let tip = remotes
+        .target_oid(git, branch)?
+        .ok_or_else(|| err::Error::NoSuchCommit(format!("{}/{}", remotes.write, branch)))?;
+    let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+        Some(trunk) => trunk.to_string(),
+        None => crate::default_trunk(git, remotes.read)?,
+    };
+    let trunk_ref = crate::trunk_oid(git, remotes.read, &trunk)?;
+
+    // The branch's own commits all carry its key, so stop at the first that doesn't: a diffbase
+    // that the branch was started on (whose own branch may not have landed yet).
+    let mut commits = Vec::new();
+    for commit in git.log(&trunk_ref, &tip)? {
+        match crate::branch_key(config, &commit.oid, &commit.trailers) {
+            Ok(Some(ref commit_key)) if commit_key == key => commits.push(commit),
+            _ => break,
+        }
+    }
+
+    let mut round = commits.iter().filter(|c| c.parents.len() == 1).count();
+    for (i, commit) in commits.iter().enumerate() {
+        let kind = kind(git, config, key, commit, i + 1 == commits.len())?;
+        let round_label = if commit.parents.len() > 1 {
+            "-".to_string()
+        } else {
+            round -= 1;
+            (round + 1).to_string()
+        };
+        let source = crate::recorded_source(config, &commit.oid, &commit.trailers)?;
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            round_label,
+            commit.oid,
+            kind,
+            source.as_deref().unwrap_or("-"),
+            commit.message.lines().next().unwrap_or(""),
+        );
+    }
+    Ok(())
+}
+
+/// Classify a commit on the target branch of `key` by how integration created it. The `oldest`
+/// commit created the branch.
+fn kind(
+    git: &mut GitStore,
+    config: &Config,
+    key: &str,
+    commit: &Commit,
+    oldest: bool,
+) -> err::Result<&'static str> {
+    if commit.parents.len() > 1 {
+        return Ok("diffbase");
+    }
+    if oldest {
+        return Ok("initial");
+    }
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => git.commit(parent)?.tree.clone(),
+        None => return Ok("initial"),
+    };
+    if commit.tree != parent_tree {
+        return Ok("patch");
+    }
+    let bump_subject = format!("[{}: {}]", key, config.ci_bump_message);
+    Ok(
+        if commit
+            .message
+            .lines()
+            .next()
+            .map(crate::stack::strip_position)
+            == Some(bump_subject.as_str())
+        {
+            "bump"
+        } else {
+            "no-op"
+        },
+    )
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::check;
+use crate::config::Config;
+use crate::err;
+use crate::git::GitStore;
+
+pub const SUBCOMMAND: &str = "hook";
+
+const COMMIT_MSG: &str = "commit-msg";
+const CLI_ARG_FILE: &str = "file";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Entry points for Git hooks installed by `git dx init`")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            clap::SubCommand::with_name(COMMIT_MSG)
+                .about("Validates the dx trailers of a commit message")
+                .arg(
+                    clap::Arg::with_name(CLI_ARG_FILE)
+                        .help("File containing the proposed commit message")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    match matches.subcommand() {
+        (COMMIT_MSG, Some(sub)) => {
+            commit_msg(git, config, Path::new(sub.value_of(CLI_ARG_FILE).unwrap()))
+        }
+        _ => unreachable!("clap requires a hook subcommand"),
+    }
+}
+
+/// Reject a commit message whose dx trailers are invalid, using the same validation as `git dx
+/// check`. The new commit is assumed to be created atop `HEAD`, unless `HEAD` has the same branch
+/// key, in which case we assume that `HEAD` is being amended or reworded (the hook cannot tell).
+fn commit_msg(git: &mut GitStore, config: &Config, file: &Path) -> err::Result<()> {
+    let context = file.to_string_lossy();
+    let message = err::Error::require_utf8(std::fs::read(file)?, &context)?;
+    let key = match crate::branch_key(config, &context, &crate::trailers(config, &message)) {
+        Ok(Some(key)) => Some(key),
+        Ok(None) => return Ok(()),
+        Err(err::Error::DuplicateTrailer { .. }) => None, // reported by `validate`
+        Err(e) => return Err(e),
+    };
+
+    let mut parent = match git.rev_parse_commit("HEAD")? {
+        Some(head) => Some(git.commit(&head)?.clone()),
+        None => None, // unborn branch
+    };
+    if let Some(head) = parent.clone() {
+        if key.is_some() && crate::branch_key(config, &head.oid, &head.trailers)? == key {
+            parent = match head.parents.first() {
+                Some(oid) => Some(git.commit(oid)?.clone()),
+                None => None,
+            };
+        }
+    }
+
+    let remote = crate::remote_name(git, None)?;
+    let base = match crate::default_trunk(git, &remote) {
+        Ok(trunk) => crate::remote_branch_oid(git, &remote, &trunk)?,
+        // Don't block commits just because the remote can't be reached.
+        Err(e) => match e.root() {
+            err::Error::UnknownTrunk { .. } | err::Error::GitContract(_) => None,
+            _ => return Err(e),
+        },
+    };
+    let mut taken_keys = HashMap::new();
+    match (&base, parent.clone()) {
+        (_, None) => (),
+        (None, Some(_)) => {
+            warn!("cannot determine trunk; not checking branch key uniqueness");
+        }
+        (Some(base), Some(parent_commit)) => {
+            if git.is_ancestor(&parent_commit.oid, base)? {
+                parent = None; // already in trunk, so not part of the stack
+            } else {
+                taken_keys = check::stack_keys(git, config, base, &parent_commit.oid)?;
+            }
+        }
+    }
+
+    let problems = check::validate(
+        git,
+        config,
+        &remote,
+        &check::Candidate {
+            context: &context,
+            message: &message,
+            parent: parent.as_ref(),
+            taken_keys: &taken_keys,
+        },
+    )?;
+    let errors = check::report(&context, &problems);
+    if errors > 0 {
+        return Err(err::Error::CheckFailed { errors });
+    }
+    Ok(())
+}
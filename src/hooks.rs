@@ -0,0 +1,122 @@
+//! Hooks of git-dx's own let a repository wire in validation and notifications around integration
+//! and pushing: an executable at `dx/hooks/<name>` under the Git directory (e.g.,
+//! `.git/dx/hooks/pre-push`) is run for each change at that point, with environment variables
+//! describing it. A failing `pre-*` hook stops that step; a failing `post-*` hook is only warned
+//! about. Under `--verify`, integration also runs the standard Git hooks for the commits that it
+//! creates from here. (Entry points for the standard Git hooks are in `hook`.)
+
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::err;
+use crate::git::GitStore;
+use crate::log::CommandExt;
+
+/// Runs before a source commit is integrated; failing skips the integration.
+pub const PRE_INTEGRATE: &str = "pre-integrate";
+/// Runs after a source commit is integrated, with the resulting commit.
+pub const POST_INTEGRATE: &str = "post-integrate";
+/// Runs before an integrated commit is pushed; failing stops the push of every branch. Not run
+/// under `--dry-run`, which pushes nothing.
+pub const PRE_PUSH: &str = "pre-push";
+/// Runs after an integrated commit is pushed.
+pub const POST_PUSH: &str = "post-push";
+
+/// What a hook is run for, given to it in environment variables.
+pub struct Context<'a> {
+    /// The source commit (`DX_SOURCE`).
+    pub source: &'a str,
+    /// The target branch (`DX_TARGET_BRANCH`).
+    pub target_branch: &'a str,
+    /// The remote that holds the target branch (`DX_REMOTE`).
+    pub remote: &'a str,
+    /// The integrated commit, once there is one (`DX_COMMIT`).
+    pub commit: Option<&'a str>,
+}
+
+/// Run the hook `name` for `context`, if it exists and is executable, in the top directory of the
+/// worktree. Its standard output goes to standard error, keeping git-dx's own output parseable.
+/// Fails with `HookFailed` if the hook exits unsuccessfully.
+pub fn run(git: &GitStore, name: &str, context: &Context) -> err::Result<()> {
+    let path = git.git_path(&format!("dx/hooks/{}", name))?;
+    run_at(git, &path, name, context.target_branch, |cmd| {
+        cmd.env("DX_HOOK", name)
+            .env("DX_SOURCE", context.source)
+            .env("DX_TARGET_BRANCH", context.target_branch)
+            .env("DX_REMOTE", context.remote);
+        if let Some(commit) = context.commit {
+            cmd.env("DX_COMMIT", commit);
+        }
+    })
+}
+
+/// Run the standard Git hook `name` (e.g., `commit-msg`) with `args`, as Git would for a commit
+/// on `target_branch`, if it exists and is executable. Hooks are found as Git finds them, so
+/// `core.hooksPath` applies. Fails with `HookFailed` if the hook rejects the commit.
+pub fn run_git_hook(
+    git: &GitStore,
+    name: &str,
+    args: &[&OsStr],
+    target_branch: &str,
+) -> err::Result<()> {
+    let path = git.git_path(&format!("hooks/{}", name))?;
+    run_at(git, &path, name, target_branch, |cmd| {
+        cmd.args(args);
+    })
+}
+
+/// Run the hook `name` at `path`, set up by `configure`, if it is executable.
+fn run_at<F: FnOnce(&mut Command)>(
+    git: &GitStore,
+    path: &Path,
+    name: &str,
+    target_branch: &str,
+    configure: F,
+) -> err::Result<()> {
+    if !is_executable(path) {
+        return Ok(());
+    }
+    let mut cmd = if cfg!(windows) {
+        // Windows can't run a script by itself, so have Git's shell run it, which follows its
+        // `#!` line as Git does.
+        let mut cmd = Command::new(git.shell()?);
+        cmd.args(["-c", "\"$0\" \"$@\""]).arg(path);
+        cmd
+    } else {
+        Command::new(path)
+    };
+    cmd.current_dir(git.toplevel()?)
+        .stdin(Stdio::null())
+        .stdout(std::io::stderr());
+    configure(&mut cmd);
+    let status = crate::cancel::wait(&mut cmd.logged_spawn()?)?;
+    if !status.success() {
+        return Err(err::Error::HookFailed {
+            hook: name.to_string(),
+            target_branch: target_branch.to_string(),
+            status: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Run a `post-*` hook, whose failure comes too late to stop anything, so is only warned about.
+pub fn run_after(git: &GitStore, name: &str, context: &Context) {
+    if let Err(e) = run(git, name, context) {
+        warn!("{}", e);
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
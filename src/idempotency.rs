@@ -0,0 +1,62 @@
+//! Idempotency keys let automated callers (e.g., CI bots that retry failed jobs) run the same
+//! integration more than once without creating duplicate commits. The key and source commit of
+//! each keyed integration are recorded in a note on the resulting commit.
+
+use crate::config::Config;
+use crate::err;
+use crate::git::{Commit, GitStore, Oid};
+
+/// Notes ref under which idempotency records are stored. This is pushed along with the target
+/// branch, so that callers in other clones can see it after fetching it.
+pub const NOTES_REF: &str = "refs/notes/dx";
+
+pub const KEY_FIELD: &str = "idempotency-key";
+const SOURCE_FIELD: &str = "source";
+
+/// If the head of the remote target branch for `source_commit` was produced by an integration
+/// with the given key and the same source commit, return that head.
+pub fn already_integrated(
+    git: &mut GitStore,
+    config: &Config,
+    source_commit: &Commit,
+    remotes: &crate::Remotes,
+    key: &str,
+) -> err::Result<Option<Oid>> {
+    let target_branch =
+        match crate::branch_name(git, config, &source_commit.oid, &source_commit.trailers)? {
+            Some(name) => name,
+            None => return Ok(None), // `integrate` will report the missing trailer
+        };
+    let tip = match remotes.target_oid(git, &target_branch)? {
+        Some(tip) => tip,
+        None => return Ok(None),
+    };
+    let note = match git.note(NOTES_REF, &tip)? {
+        Some(note) => note,
+        None => return Ok(None),
+    };
+    let mut recorded_key = None;
+    let mut recorded_source = None;
+    for line in note.lines() {
+        let mut parts = line.splitn(2, ": ");
+        match (parts.next(), parts.next()) {
+            (Some(KEY_FIELD), Some(v)) => recorded_key = Some(v),
+            (Some(SOURCE_FIELD), Some(v)) => recorded_source = Some(v),
+            _ => (),
+        }
+    }
+    if recorded_key == Some(key) && recorded_source == Some(source_commit.oid.as_str()) {
+        Ok(Some(tip))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Record that `remote_commit` was produced by integrating `source_oid` with the given key.
+pub fn record(git: &GitStore, remote_commit: &str, source_oid: &str, key: &str) -> err::Result<()> {
+    git.set_note(
+        NOTES_REF,
+        remote_commit,
+        &format!("{}: {}\n{}: {}\n", KEY_FIELD, key, SOURCE_FIELD, source_oid),
+    )
+}
@@ -0,0 +1,97 @@
+use std::io::Write;
+
+use crate::config::Config;
+use crate::err;
+use crate::git::GitStore;
+
+pub const SUBCOMMAND: &str = "init";
+
+const CLI_ARG_INSTALL_HOOK: &str = "install_hook";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_TRUNK: &str = "trunk";
+
+/// Oldest Git version that supports everything we use (notably `interpret-trailers
+/// --no-divider`, added in Git 2.19).
+pub const MIN_GIT_VERSION: (u32, u32) = (2, 19);
+
+/// Line identifying a hook script as installed by `git dx init`, so that it may safely be
+/// overwritten by a later `init`.
+const HOOK_MARKER: &str = "# Installed by `git dx init`.";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Configures the current repository for use with git-dx")
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote to record as the default (default: `origin`)")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help("Trunk branch to record as the default (default: the remote's HEAD)")
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_INSTALL_HOOK)
+                .help("Install a commit-msg hook that validates dx trailers")
+                .long("--install-hook"),
+        )
+}
+
+pub fn run(git: &mut GitStore, _config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let version = git.version()?;
+    if version < MIN_GIT_VERSION {
+        return Err(err::Error::UnsupportedGitVersion {
+            found: version,
+            required: MIN_GIT_VERSION,
+        });
+    }
+
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    if git.config_get(&format!("remote.{}.url", remote))?.is_none() {
+        return Err(err::Error::NoSuchRemote(remote));
+    }
+    let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+        Some(trunk) => trunk.to_string(),
+        None => crate::remote_head_branch(git, &remote)?,
+    };
+    git.config_set("dx.remote", &remote)?;
+    git.config_set("dx.trunk", &trunk)?;
+    info!("set dx.remote = {}", remote);
+    info!("set dx.trunk = {}", trunk);
+
+    if matches.is_present(CLI_ARG_INSTALL_HOOK) {
+        let path = git.git_path("hooks/commit-msg")?;
+        install_hook(&path)?;
+        info!("installed {}", path.display());
+    }
+    Ok(())
+}
+
+/// Write a `commit-msg` hook that delegates to `git dx hook commit-msg`, refusing to clobber a
+/// hook that we did not install.
+fn install_hook(path: &std::path::Path) -> err::Result<()> {
+    if let Ok(existing) = std::fs::read_to_string(path) {
+        if !existing.contains(HOOK_MARKER) {
+            return Err(err::Error::HookExists(path.to_string_lossy().into_owned()));
+        }
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::File::create(path)?;
+    write!(
+        file,
+        "#!/bin/sh\n{}\nexec git dx hook commit-msg \"$@\"\n",
+        HOOK_MARKER
+    )?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! The integration journal records each successful integration as a ref,
+//! `refs/dx/journal/<branch>/<timestamp>`, so that there is a durable, local audit trail of what
+//! git-dx did to each target branch even after the branch has moved on or been deleted. Each ref
+//! points at a tag object whose target is the integrated commit and whose message holds the
+//! entry's metadata, one `field: value` per line.
+
+use crate::clock;
+use crate::err;
+use crate::git::{GitStore, Oid};
+
+pub const JOURNAL_PREFIX: &str = "refs/dx/journal/";
+
+pub const SOURCE_FIELD: &str = "source";
+pub const PREVIOUS_FIELD: &str = "previous";
+pub const FLAGS_FIELD: &str = "flags";
+pub const RUN_FIELD: &str = "run";
+pub const HEAD_FIELD: &str = "head";
+
+/// One successful integration.
+pub struct Entry<'a> {
+    pub target_branch: &'a str,
+    /// The integrated commit.
+    pub remote_commit: &'a str,
+    /// The source commit.
+    pub source: &'a str,
+    /// The head of the remote target branch before integration, if it existed.
+    pub previous: Option<&'a str>,
+    /// The command-line flags that affected integration (e.g., `--bump`), without values.
+    pub flags: Vec<&'static str>,
+    /// Identifies the run of git-dx that made this integration, as from `run_id`.
+    pub run: &'a str,
+    /// What was checked out before the run, as `git::Head` writes it.
+    pub head: &'a str,
+}
+
+/// An entry as read back from the journal.
+pub struct Recorded {
+    pub refname: String,
+    /// The integrated commit.
+    pub remote_commit: Oid,
+    pub run: Option<String>,
+    pub head: Option<String>,
+}
+
+/// A new identifier for the current run, unique among runs in this repository.
+pub fn run_id() -> String {
+    format!("{}.{}", clock::now(), std::process::id())
+}
+
+/// Record a journal entry, returning the name of its ref. Entries recorded within the same
+/// second are told apart by a sequence number after the timestamp.
+pub fn record(git: &GitStore, entry: &Entry) -> err::Result<String> {
+    let tagger = git.committer()?;
+    let timestamp = tagger.date.split(' ').next().unwrap_or("0");
+    let base = format!("{}{}/{}", JOURNAL_PREFIX, entry.target_branch, timestamp);
+    let mut refname = base.clone();
+    let mut sequence = 0;
+    while git.rev_parse(&refname)?.is_some() {
+        sequence += 1;
+        refname = format!("{}.{}", base, sequence);
+    }
+
+    let mut metadata = format!("{}: {}\n", SOURCE_FIELD, entry.source);
+    if let Some(previous) = entry.previous {
+        metadata.push_str(&format!("{}: {}\n", PREVIOUS_FIELD, previous));
+    }
+    if !entry.flags.is_empty() {
+        metadata.push_str(&format!("{}: {}\n", FLAGS_FIELD, entry.flags.join(" ")));
+    }
+    metadata.push_str(&format!("{}: {}\n", RUN_FIELD, entry.run));
+    metadata.push_str(&format!("{}: {}\n", HEAD_FIELD, entry.head));
+    let tag: Oid = git.mktag(&format!(
+        "object {}\ntype commit\ntag {}\ntagger {} {}\n\n{}",
+        entry.remote_commit,
+        entry.target_branch,
+        tagger.ident(),
+        tagger.date,
+        metadata
+    ))?;
+    git.update_ref(
+        &refname,
+        &tag,
+        &format!("git-dx: integrated {}", entry.source),
+    )?;
+    Ok(refname)
+}
+
+/// Read the entries of the most recent run that has any left in the journal, newest first.
+pub fn last_run(git: &GitStore) -> err::Result<Vec<Recorded>> {
+    let mut tags = git.tag_refs(JOURNAL_PREFIX)?;
+    // Entries within a second are told apart by sequence number, which sorts after the timestamp.
+    tags.sort_by(|a, b| (b.date, &b.refname).cmp(&(a.date, &a.refname)));
+    let mut entries = tags.into_iter().map(|tag| {
+        let field = |name: &str| {
+            tag.message.lines().find_map(|line| {
+                let (key, value) = line.split_once(": ")?;
+                Some(value.to_string()).filter(|_| key == name)
+            })
+        };
+        Recorded {
+            run: field(RUN_FIELD),
+            head: field(HEAD_FIELD),
+            refname: tag.refname,
+            remote_commit: tag.target,
+        }
+    });
+    let first = match entries.next() {
+        Some(first) => first,
+        None => return Ok(Vec::new()),
+    };
+    let run = first.run.clone();
+    let mut result = vec![first];
+    // Entries from before runs were recorded stand alone.
+    if run.is_some() {
+        result.extend(entries.filter(|entry| entry.run == run));
+    }
+    Ok(result)
+}
+
+/// Delete the journal entries for `target_branch`, as once it is gone for good, returning how many
+/// there were.
+pub fn remove(git: &GitStore, target_branch: &str) -> err::Result<usize> {
+    // A prefix matches whole path components, which leaves out branches like `foo-bar` for `foo`
+    // but not those nested under it, like `foo/bar`, whose entries have more components after it.
+    let prefix = format!("{}{}/", JOURNAL_PREFIX, target_branch);
+    let tags: Vec<_> = git
+        .tag_refs(&prefix)?
+        .into_iter()
+        .filter(|tag| {
+            tag.refname
+                .strip_prefix(&prefix)
+                .is_some_and(|timestamp| !timestamp.contains('/'))
+        })
+        .collect();
+    for tag in &tags {
+        git.delete_ref(&tag.refname, "git-dx: prune journal")?;
+    }
+    Ok(tags.len())
+}
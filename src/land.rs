@@ -0,0 +1,393 @@
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::err;
+use crate::forge::{self, CheckState, Forge};
+use crate::git::{Commit, GitStore, Oid};
+use crate::log::CommandExt;
+
+pub const SUBCOMMAND: &str = "land";
+
+const CLI_ARG_ALLOW_FOREIGN_COMMITS: &str = "allow-foreign-commits";
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_DRY_RUN: &str = "dry_run";
+const CLI_ARG_POLL_INTERVAL: &str = "poll-interval";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_SQUASH: &str = "squash";
+const CLI_ARG_TIMEOUT: &str = "timeout";
+const CLI_ARG_TRUNK: &str = "trunk";
+const CLI_ARG_WHEN_GREEN: &str = "when-green";
+
+/// Longest time to wait between polls of check status; the interval backs off to this so that
+/// long CI runs don't eat into the API rate limit.
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(300);
+
+fn parse_u64(value: String) -> Result<(), String> {
+    value.parse::<u64>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Merges an integrated branch into trunk and deletes the remote branch")
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Source commit whose change should be landed")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SQUASH)
+                .help("Land a single squashed commit instead of fast-forwarding trunk")
+                .long("--squash"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help("Remote branch to land onto (default: the remote's HEAD)")
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DRY_RUN)
+                .help("Use dry-run pushes only")
+                .long("--dry-run")
+                .short("-n"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote to land onto")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_ALLOW_FOREIGN_COMMITS)
+                .help("Land even if the branch has commits that git-dx did not make")
+                .long("--allow-foreign-commits"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_WHEN_GREEN)
+                .help("Wait for the branch's checks on GitHub or GitLab to pass, then land")
+                .long("--when-green"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_POLL_INTERVAL)
+                .help("Seconds between initial polls of check status (backs off over time)")
+                .value_name("seconds")
+                .long("--poll-interval")
+                .takes_value(true)
+                .default_value("30")
+                .validator(parse_u64),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TIMEOUT)
+                .help("Minutes to wait for checks before giving up")
+                .value_name("minutes")
+                .long("--timeout")
+                .takes_value(true)
+                .default_value("60")
+                .validator(parse_u64),
+        )
+        .arg(crate::push::push_option_arg())
+}
+
+/// Choices about how to land a change, from the command line.
+struct LandOptions {
+    /// Land a single squashed commit instead of fast-forwarding trunk.
+    squash: bool,
+    /// Use dry-run pushes only.
+    dry_run: bool,
+    /// Options to send with the push, as by `git push -o`.
+    push_options: Vec<String>,
+    /// Skip checking that every commit on the branch was made by git-dx.
+    allow_foreign_commits: bool,
+    /// If given, wait for checks to pass before landing.
+    when_green: Option<Polling>,
+}
+
+/// How to wait for checks with `--when-green`.
+struct Polling {
+    /// Time between the first polls, which grows up to `MAX_POLL_INTERVAL`.
+    interval: Duration,
+    /// Time after which to give up if checks are still pending.
+    timeout: Duration,
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let source_rev = matches.value_of(CLI_ARG_COMMIT).unwrap();
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let remote = remote.as_str();
+    let seconds = |arg| matches.value_of(arg).unwrap().parse::<u64>().unwrap();
+    let options = LandOptions {
+        squash: matches.is_present(CLI_ARG_SQUASH),
+        dry_run: matches.is_present(CLI_ARG_DRY_RUN),
+        push_options: crate::push::push_options(config, matches),
+        allow_foreign_commits: matches.is_present(CLI_ARG_ALLOW_FOREIGN_COMMITS),
+        when_green: if matches.is_present(CLI_ARG_WHEN_GREEN) {
+            Some(Polling {
+                interval: Duration::from_secs(seconds(CLI_ARG_POLL_INTERVAL)),
+                timeout: Duration::from_secs(seconds(CLI_ARG_TIMEOUT) * 60),
+            })
+        } else {
+            None
+        },
+    };
+    let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+        Some(trunk) => trunk.to_string(),
+        None => crate::default_trunk(git, remote)?,
+    };
+
+    let source_commit = git.commit(source_rev)?.clone();
+    let landed = land(git, config, &source_commit, remote, &trunk, &options)?;
+    info!("successfully landed onto {}", trunk);
+    println!("{}", landed);
+    Ok(())
+}
+
+/// Land the change whose source is `source_commit`, returning the OID of the commit that was (or,
+/// on a dry run, would have been) pushed to `trunk`.
+///
+/// The target branch must already be integrated: its tree must equal the source commit's tree,
+/// and it must contain the current trunk. If the source commit's diffbase is itself a change with
+/// a live remote branch, that change must be landed first. Unless `--allow-foreign-commits` is
+/// given, every commit on the branch must have been made by git-dx (see `foreign_commits`). With
+/// `--when-green`, its checks must also pass, and the remote branch must not change while waiting
+/// for them.
+fn land(
+    git: &mut GitStore,
+    config: &Config,
+    source_commit: &crate::git::Commit,
+    remote: &str,
+    trunk: &str,
+    options: &LandOptions,
+) -> err::Result<Oid> {
+    let source_oid = &source_commit.oid;
+    let target_branch = crate::branch_name(git, config, source_oid, &source_commit.trailers)?
+        .ok_or_else(|| err::Error::MissingTrailer {
+            oid: source_oid.to_string(),
+            key: config.branch_trailer.clone(),
+        })?;
+    let cannot_land = |reason: String| err::Error::CannotLand {
+        branch: target_branch.clone(),
+        reason,
+    };
+
+    let branch_tip = crate::remote_branch_oid(git, remote, &target_branch)?
+        .ok_or_else(|| cannot_land(format!("no such branch on {}", remote)))?;
+    let branch_tree = git.commit(&branch_tip)?.tree.clone();
+    if branch_tree != source_commit.tree {
+        return Err(cannot_land(format!(
+            "tree of {} differs from source commit {}; integrate and push first",
+            branch_tip, source_oid
+        )));
+    }
+    let trunk_tip = crate::remote_branch_oid(git, remote, trunk)?
+        .ok_or_else(|| cannot_land(format!("no trunk branch {} on {}", trunk, remote)))?;
+    if !git.is_ancestor(&trunk_tip, &branch_tip)? {
+        return Err(cannot_land(format!(
+            "branch does not contain {}/{}; rebase onto it and integrate again",
+            remote, trunk
+        )));
+    }
+
+    // A root commit has no diffbase to wait for.
+    if let Some(parent) = source_commit.parents.first() {
+        let local_diffbase = git.commit(parent)?.clone();
+        if let Some(diffbase_branch) =
+            crate::branch_name(git, config, &local_diffbase.oid, &local_diffbase.trailers)?
+        {
+            if let Some(diffbase_tip) = crate::remote_branch_oid(git, remote, &diffbase_branch)? {
+                if !git.is_ancestor(&diffbase_tip, &trunk_tip)? {
+                    return Err(cannot_land(format!(
+                        "diffbase {} has not landed yet",
+                        diffbase_branch
+                    )));
+                }
+            }
+        }
+    }
+
+    if !options.allow_foreign_commits {
+        let foreign = foreign_commits(git, config, &trunk_tip, &branch_tip)?;
+        if !foreign.is_empty() {
+            let list: Vec<String> = foreign
+                .iter()
+                .map(|(oid, reason)| format!("{} ({})", oid, reason))
+                .collect();
+            return Err(cannot_land(format!(
+                "branch has commits not made by git-dx: {}; pass --allow-foreign-commits to land \
+                 anyway",
+                list.join(", ")
+            )));
+        }
+    }
+
+    if let Some(ref polling) = options.when_green {
+        let forge = forge::for_remote(git, remote)?.ok_or_else(|| {
+            cannot_land(format!(
+                "`--when-green` needs {} to be a GitHub or GitLab repository with an API token",
+                remote
+            ))
+        })?;
+        wait_for_checks(forge.as_ref(), &target_branch, &branch_tip, polling)
+            .map_err(cannot_land)?;
+        let refname = format!("refs/heads/{}", target_branch);
+        if git.ls_remote(remote, &refname)?.as_deref() != Some(branch_tip.as_str()) {
+            return Err(cannot_land(format!(
+                "branch changed on {} while waiting for checks on {}",
+                remote, branch_tip
+            )));
+        }
+    }
+
+    let landed = if options.squash {
+        let sign = crate::sign_commits(git, None)?;
+        squash_commit(git, &branch_tree, &trunk_tip, source_commit, sign)?
+    } else {
+        branch_tip.clone()
+    };
+
+    let mut args = vec!["--atomic".to_string()];
+    if options.dry_run {
+        args.push("--dry-run".to_string());
+    }
+    for option in &options.push_options {
+        args.push(format!("--push-option={}", option));
+    }
+    // Only delete the branch if it still points to the commit that we verified.
+    args.push(format!(
+        "--force-with-lease=refs/heads/{}:{}",
+        target_branch, branch_tip
+    ));
+    args.push(remote.to_string());
+    args.push(format!("{}:refs/heads/{}", landed, trunk));
+    args.push(format!(":refs/heads/{}", target_branch));
+    crate::push::push(git, &args)?.print();
+    git.invalidate_refs();
+    Ok(landed)
+}
+
+/// Find the non-merge commits in `base..tip` that git-dx did not make, each with the reason why
+/// not. A commit made by git-dx has exactly one branch directive and one source directive naming
+/// an object ID; if `dx.allowedSigningKey` is set, it must also have a good signature from one of
+/// those keys. Merge commits are not checked, since each merge brings in only commits that are
+/// checked themselves.
+fn foreign_commits(
+    git: &mut GitStore,
+    config: &Config,
+    base: &str,
+    tip: &str,
+) -> err::Result<Vec<(Oid, &'static str)>> {
+    let mut result = Vec::new();
+    for oid in git.rev_list_no_merges(base, tip)? {
+        let message = git.commit(&oid)?.message.clone();
+        let all_trailers = crate::trailers(config, &message);
+        let has_trailer = |key: &str, valid: fn(&str) -> bool| {
+            let mut values = all_trailers.iter().filter(|(k, _)| k == key);
+            match (values.next(), values.next()) {
+                (Some((_, value)), None) => valid(value),
+                _ => false,
+            }
+        };
+        if !has_trailer(&config.branch_trailer, |_| true)
+            || !has_trailer(&config.source_trailer, |v| Oid::parse(v).is_some())
+        {
+            result.push((oid, "missing dx trailers"));
+            continue;
+        }
+        if !config.allowed_signing_keys.is_empty() {
+            match git.signing_key(&oid)? {
+                Some(key) if config.allowed_signing_keys.contains(&key) => (),
+                Some(_) => result.push((oid, "signed by a key not in dx.allowedSigningKey")),
+                None => result.push((oid, "no good signature")),
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Poll the checks on `sha` until they all pass, backing off over time. Returns a description of
+/// the problem if any check fails or the timeout expires.
+fn wait_for_checks(
+    forge: &dyn Forge,
+    branch: &str,
+    sha: &str,
+    polling: &Polling,
+) -> Result<(), String> {
+    let start = Instant::now();
+    let mut interval = polling.interval;
+    let mut last_pending = None;
+    loop {
+        let status = forge.check_status(sha).map_err(|e| e.to_string())?;
+        match status.state {
+            CheckState::Success => {
+                info!("all checks passed on {}", branch);
+                return Ok(());
+            }
+            CheckState::Failure => {
+                return Err(format!("checks failed: {}", status.failed.join(", ")));
+            }
+            CheckState::Pending => (),
+        }
+        if start.elapsed() + interval >= polling.timeout {
+            return Err(format!(
+                "checks still pending after {} minute(s): {}",
+                polling.timeout.as_secs() / 60,
+                describe_pending(&status.pending)
+            ));
+        }
+        if last_pending.as_ref() != Some(&status.pending) {
+            info!(
+                "waiting for checks on {}: {}",
+                branch,
+                describe_pending(&status.pending)
+            );
+            last_pending = Some(status.pending);
+        }
+        std::thread::sleep(interval);
+        interval = std::cmp::min(interval * 3 / 2, MAX_POLL_INTERVAL).max(polling.interval);
+    }
+}
+
+fn describe_pending(pending: &[String]) -> String {
+    if pending.is_empty() {
+        "none reported yet".to_string()
+    } else {
+        pending.join(", ")
+    }
+}
+
+/// Create a commit with the given tree whose only parent is `parent`, with the message and
+/// author of `source_commit`, signed if `sign` is set.
+fn squash_commit(
+    git: &GitStore,
+    tree: &str,
+    parent: &str,
+    source_commit: &Commit,
+    sign: bool,
+) -> err::Result<Oid> {
+    let mut cmd = git.command();
+    source_commit.author.set_author_env(&mut cmd);
+    let mut child = cmd
+        .args(["commit-tree", crate::gpg_sign_arg(sign), tree, "-p", parent])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged_spawn()?;
+    use std::io::Write;
+    child
+        .stdin
+        .take()
+        .expect("commit-tree stdin")
+        .write_all(source_commit.message.as_bytes())?;
+    let out = crate::cancel::wait_with_output(child)?;
+    err::from_git(&out, || "failed to create squash commit".to_string())?;
+    crate::git::parse_oid(out.stdout)
+        .map_err(|buf| {
+            err::Error::GitContract(format!(
+                "commit-tree gave bad output: {:?}",
+                String::from_utf8_lossy(&buf),
+            ))
+        })?
+        .parse()
+}
@@ -0,0 +1,2604 @@
+//! git-dx integrates source commits into branches of a remote: see README.md. Besides the
+//! `git-dx` command, this library offers its main operations to programs that embed it, like
+//! editors and GUIs, in [`api`]. Each takes the command-line flags of its command and a
+//! [`cancel::Token`] with which to stop it from another thread.
+
+extern crate clap;
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::Stdio;
+
+#[macro_use]
+mod log;
+
+mod abandon;
+mod absorb;
+mod adopt;
+pub mod api;
+mod autostash;
+mod batch;
+mod budget;
+mod bugreport;
+pub mod cancel;
+mod check;
+mod clock;
+mod color;
+mod completions;
+mod config;
+mod diff;
+mod doctor;
+mod drop;
+pub mod err;
+mod forge;
+mod gerrit;
+mod git;
+mod gitlab;
+mod history;
+mod hook;
+mod hooks;
+mod idempotency;
+mod init;
+mod journal;
+mod land;
+#[cfg(feature = "libgit2")]
+mod libgit2;
+mod lock;
+mod mapping;
+mod mirror;
+mod new;
+mod output;
+mod plan;
+mod pr;
+mod progress;
+mod prune;
+mod pull;
+mod push;
+mod refname;
+mod rename;
+mod resolve;
+mod restack;
+mod rollback;
+mod stack;
+mod status;
+mod terminal;
+mod track;
+mod trailers;
+mod undo;
+mod update_message;
+mod web;
+
+use config::Config;
+use err::ResultExt;
+use git::GitStore;
+use log::CommandExt;
+
+/// Run the `git-dx` command with the arguments of this process, exiting with its status.
+pub fn main() {
+    let result = run();
+    if let Err(ref e) = result {
+        eprintln!("{} {}", color::error("error:"), e);
+        log::record(format!("error: {}", e));
+    }
+    log::save_transcript();
+    if let Err(e) = result {
+        std::process::exit(e.exit_code());
+    }
+}
+
+const CLI_ARG_ACCEPT_REMOTE_REWRITE: &str = "accept-remote-rewrite";
+const CLI_ARG_ALLOW_EMPTY: &str = "allow_empty";
+const CLI_ARG_ALLOW_UNRELATED: &str = "allow-unrelated";
+const CLI_ARG_AUTOSTASH: &str = "autostash";
+const CLI_ARG_BUMP: &str = "bump";
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_COLOR: &str = "color";
+const CLI_ARG_DETERMINISTIC: &str = "deterministic";
+const CLI_ARG_DIRECTORY: &str = "directory";
+const CLI_ARG_DRY_RUN: &str = "dry_run";
+const CLI_ARG_EDIT: &str = "edit";
+const CLI_ARG_EXPLAIN: &str = "explain";
+const CLI_ARG_FORK: &str = "fork";
+const CLI_ARG_FORMAT: &str = "format";
+const CLI_ARG_IDEMPOTENCY_KEY: &str = "idempotency_key";
+const CLI_ARG_MESSAGE: &str = "message";
+const CLI_ARG_NO_PROGRESS: &str = "no-progress";
+const CLI_ARG_MIRROR_TIMEOUT: &str = "mirror-timeout";
+const CLI_ARG_NO_EDIT: &str = "no-edit";
+const CLI_ARG_NO_MERGE_DIFFBASE: &str = "no-merge-diffbase";
+const CLI_ARG_NO_SIGN: &str = "no-sign";
+const CLI_ARG_NO_SIGNOFF: &str = "no-signoff";
+const CLI_ARG_NO_STACK_POSITION: &str = "no-stack-position";
+const CLI_ARG_NO_SYNC_DESCRIPTION: &str = "no-sync-description";
+const CLI_ARG_NO_VERIFY: &str = "no-verify";
+const CLI_ARG_OVERWRITE_REMOTE_EDITS: &str = "overwrite-remote-edits";
+const CLI_ARG_PLAIN: &str = "plain";
+const CLI_ARG_PORCELAIN: &str = "porcelain";
+const CLI_ARG_PROGRESS: &str = "progress";
+const CLI_ARG_PUSH: &str = "push";
+const CLI_ARG_QUIET: &str = "quiet";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_SIGN: &str = "sign";
+const CLI_ARG_SIGNOFF: &str = "signoff";
+const CLI_ARG_STACK: &str = "stack";
+const CLI_ARG_STRATEGY: &str = "strategy";
+const CLI_ARG_STRATEGY_OPTION: &str = "strategy-option";
+const CLI_ARG_STACK_POSITION: &str = "stack-position";
+const CLI_ARG_STDIN: &str = "stdin";
+const CLI_ARG_STRICT: &str = "strict";
+const CLI_ARG_SYNC_DESCRIPTION: &str = "sync-description";
+const CLI_ARG_VERIFY: &str = "verify";
+const CLI_ARG_VERBOSE: &str = "verbose";
+const CLI_ARG_VERIFY_MIRROR: &str = "verify-mirror";
+
+fn run() -> err::Result<()> {
+    terminal::set(std::env::args_os());
+    let app = app();
+    let matches = app.clone().get_matches();
+    if !matches.is_present(CLI_ARG_STACK) && matches.occurrences_of(CLI_ARG_COMMIT) > 1 {
+        clap::Error::with_description(
+            "more than one source commit given; use --stack to integrate several tips",
+            clap::ErrorKind::WrongNumberOfValues,
+        )
+        .exit();
+    }
+    run_matches(app, &matches, None, true)
+}
+
+/// The command line of git-dx.
+fn app() -> clap::App<'static, 'static> {
+    clap::App::new("git-dx")
+        .version("0.1.0")
+        .setting(clap::AppSettings::ArgsNegateSubcommands)
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
+        .setting(if terminal::color_by_default() {
+            clap::AppSettings::ColorAuto
+        } else {
+            clap::AppSettings::ColorNever
+        })
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Source commit (with `--stack`, tips of the stack)")
+                .required(true)
+                .default_value("HEAD")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_STACK)
+                .help("Integrate every change between trunk and the given tips, in stack order")
+                .long_help(
+                    "Integrate every change between trunk and the given tips, each after the \
+                     change that it builds on. Tips may share changes, so a stack that branches \
+                     into independent changes on the same diffbase is integrated whole. If a \
+                     change fails to integrate, the changes that build on it are skipped, but \
+                     the rest still are, and each outcome is reported.",
+                )
+                .long("--stack")
+                .conflicts_with(CLI_ARG_STDIN),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_STDIN)
+                .help("Read source commits from stdin, one per line or NUL-separated")
+                .long_help(
+                    "Read source commits from standard input, one per line or NUL-separated, \
+                     instead of taking one as an argument. They are integrated in the order \
+                     given, so list a stack oldest first (e.g., `git rev-list --reverse`).",
+                )
+                .long("--stdin"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_PUSH)
+                .help("Pushes integrated commit to remote")
+                .long_help(
+                    "Pushes integrated commit to remote. In Gerrit mode (`dx.mode = gerrit`), \
+                     the source commit itself is pushed to `refs/for/<trunk>` as a new patch \
+                     set of the change named by its `Change-Id` trailer.",
+                )
+                .long("--push"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DRY_RUN)
+                .help("Use dry-run pushes only")
+                .long("--dry-run")
+                .short("-n"),
+        )
+        .arg(push::push_option_arg())
+        .arg(
+            clap::Arg::with_name(CLI_ARG_VERIFY_MIRROR)
+                .help("After pushing, check that the pushed branches appear on this remote")
+                .long_help(
+                    "After pushing, check that the pushed branches appear on this remote, which \
+                     mirrors the one pushed to, polling until they do or `--mirror-timeout` \
+                     expires. Fails if the mirror lags behind or has diverged.",
+                )
+                .value_name("remote")
+                .long("--verify-mirror")
+                .takes_value(true)
+                .requires(CLI_ARG_PUSH),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_MIRROR_TIMEOUT)
+                .help("Seconds to wait for the `--verify-mirror` remote to catch up")
+                .value_name("seconds")
+                .long("--mirror-timeout")
+                .takes_value(true)
+                .default_value("60")
+                .validator(|value| value.parse::<u64>().map(|_| ()).map_err(|e| e.to_string())),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_MESSAGE)
+                .help("Short description of updates")
+                .value_name("msg")
+                .long("--message")
+                .short("-m")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_EDIT)
+                .help(
+                    "Edit the description of updates (default: if -m is not given and interactive)",
+                )
+                .long_help(
+                    "Open an editor for the description of each \"update patch\" commit, \
+                     pre-filled with the `-m` message (if any) and a summary of the changes. By \
+                     default, the editor is opened when no `-m` is given and git-dx is run from \
+                     a terminal.",
+                )
+                .long("--edit")
+                .short("-e")
+                .overrides_with(CLI_ARG_NO_EDIT),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_NO_EDIT)
+                .help("Don't edit the description of updates; use -m or \"update patch\"")
+                .long("--no-edit")
+                .overrides_with(CLI_ARG_EDIT),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_ALLOW_EMPTY)
+                .help("Create integration commit even when there is no change")
+                .long("--allow-empty"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_AUTOSTASH)
+                .help("Stash uncommitted changes for the run, and re-apply them afterward")
+                .long("--autostash"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_ALLOW_UNRELATED)
+                .help("Merge the diffbase even if it shares no history with the remote branch")
+                .long("--allow-unrelated"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_NO_MERGE_DIFFBASE)
+                .help("Skip the diffbase merge, failing if the diffbase has moved")
+                .long_help(
+                    "Skip merging the remote diffbase into the remote target branch, and commit \
+                     the source tree directly on top of the branch. Fails if the branch does not \
+                     already contain the remote diffbase, since then the merge was needed.",
+                )
+                .long("--no-merge-diffbase")
+                .conflicts_with_all(&[CLI_ARG_ALLOW_UNRELATED, CLI_ARG_STRATEGY]),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_STRATEGY)
+                .help("Merge strategy for the diffbase merge, as by `git merge --strategy`")
+                .long_help(
+                    "Merge strategy for the diffbase merge, as by `git merge --strategy`, in \
+                     place of any given by the `dx.mergeOptions` config option.",
+                )
+                .value_name("strategy")
+                .long("--strategy")
+                .short("-s")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_STRATEGY_OPTION)
+                .help("Strategy option for the diffbase merge, as by `git merge -X` (repeatable)")
+                .long_help(
+                    "Strategy option for the diffbase merge, as by `git merge -X` (e.g., \
+                     `renormalize` or `ignore-space-change`), after any given by the \
+                     `dx.mergeOptions` config option. May be given more than once.",
+                )
+                .value_name("option")
+                .long("--strategy-option")
+                .short("-X")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_ACCEPT_REMOTE_REWRITE)
+                .help("Build on a remote branch even if it no longer contains our last push to it")
+                .long_help(
+                    "Build on a remote target branch even if it no longer contains the commit \
+                     that git-dx last pushed to it, as when someone has force-pushed it by hand. \
+                     Without this flag, such an integration fails.",
+                )
+                .long("--accept-remote-rewrite"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_OVERWRITE_REMOTE_EDITS)
+                .help("Integrate even if others have pushed commits to the remote branch")
+                .long_help(
+                    "Integrate even if the remote target branch has commits that git-dx did not \
+                     make since it last wrote to the branch, as when someone pushes a fix to it \
+                     by hand. Integration commits the source tree as is, so their changes are \
+                     undone; without this flag, such an integration fails. To keep the changes \
+                     instead, fold them into the source commit with `git dx pull`.",
+                )
+                .long("--overwrite-remote-edits"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_BUMP)
+                .help("Don't skip CI on an empty commit (implies `--allow-empty`)")
+                .long("--bump"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SIGN)
+                .help("Sign the commits that integration creates (default: `commit.gpgSign`)")
+                .long("--sign")
+                .overrides_with(CLI_ARG_NO_SIGN),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_NO_SIGN)
+                .help("Don't sign the commits that integration creates")
+                .long("--no-sign")
+                .overrides_with(CLI_ARG_SIGN),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SIGNOFF)
+                .help("Add a Signed-off-by trailer to the commits that integration creates")
+                .long_help(
+                    "Add a `Signed-off-by` trailer for the committer to the commits that \
+                     integration creates, as `git commit --signoff` does (default: \
+                     `dx.signoff`).",
+                )
+                .long("--signoff")
+                .overrides_with(CLI_ARG_NO_SIGNOFF),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_NO_SIGNOFF)
+                .help("Don't add a Signed-off-by trailer to the commits that integration creates")
+                .long("--no-signoff")
+                .overrides_with(CLI_ARG_SIGNOFF),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_VERIFY)
+                .help("Run the Git hooks for the commits that integration creates")
+                .long_help(
+                    "Run the standard Git hooks for the commits that integration creates, as \
+                     `git commit` and `git merge` would: `pre-merge-commit` for a clean diffbase \
+                     merge, `pre-commit` for a conflicted one and for the update commit, and \
+                     `commit-msg` for both (default: `dx.runHooks`). A hook that rejects a \
+                     commit fails the integration.",
+                )
+                .long("--verify")
+                .overrides_with(CLI_ARG_NO_VERIFY),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_NO_VERIFY)
+                .help("Don't run the Git hooks for the commits that integration creates")
+                .long("--no-verify")
+                .overrides_with(CLI_ARG_VERIFY),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_STACK_POSITION)
+                .help("Start commit subjects with the change's position in its stack, like [3/7]")
+                .long_help(
+                    "Start the subject of each commit that integration creates with the \
+                     change's position in the local stack, like `[3/7]` for the third of seven \
+                     changes above trunk (default: `dx.stackPosition`).",
+                )
+                .long("--stack-position")
+                .overrides_with(CLI_ARG_NO_STACK_POSITION),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_NO_STACK_POSITION)
+                .help("Don't start commit subjects with the change's position in its stack")
+                .long("--no-stack-position")
+                .overrides_with(CLI_ARG_STACK_POSITION),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_SYNC_DESCRIPTION)
+                .help("After pushing, copy the source commit's body into its pull request")
+                .long_help(
+                    "After pushing, replace the description of each target branch's open pull \
+                     request with the body of the source commit's message, followed by \
+                     links to the pull requests of the rest of its stack (default: \
+                     `dx.syncDescription`).",
+                )
+                .long("--sync-description")
+                .overrides_with(CLI_ARG_NO_SYNC_DESCRIPTION),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_NO_SYNC_DESCRIPTION)
+                .help("Don't copy the source commit's body into its pull request")
+                .long("--no-sync-description")
+                .overrides_with(CLI_ARG_SYNC_DESCRIPTION),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_EXPLAIN)
+                .help("Describe each integration step that was taken")
+                .long("--explain"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_FORMAT)
+                .help(
+                    "Output format: a bare commit ID (`human`), a JSON object (`json`), or a \
+                     stable tab-separated line (`porcelain`)",
+                )
+                .long("--format")
+                .takes_value(true)
+                .possible_values(output::FORMATS)
+                .default_value(output::FORMAT_HUMAN),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_PORCELAIN)
+                .help("Print results in a stable, versioned format (overrides `--format`)")
+                .long_help(
+                    "Print the results of each integration on stdout as lines tagged `source`, \
+                     `branch`, `remote-commit`, `already-integrated`, `merge-performed`, and \
+                     `pushed`, in the given version of the format (default: `v1`), which will \
+                     not change between releases. Overrides `--format`.",
+                )
+                .value_name("version")
+                .long("--porcelain")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .possible_values(output::PORCELAIN_VERSIONS),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_STRICT)
+                .help("Fail if the source commit discards changes from the diffbase merge")
+                .long("--strict"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote to use for integration and pushing (if `--push` is given)")
+                .long_help(
+                    "Remote to use for integration and pushing (if `--push` is given). Defaults \
+                     to the `dx.remote` config option, or `origin` if that is not set. Without \
+                     this option, a target branch whose `branch.<name>.dxRemote` option is set \
+                     is read from and pushed to that remote instead, as is a diffbase branch. \
+                     May be given more than once: integration is against the first remote, and \
+                     the integrated commits are then pushed to each of the others as well. \
+                     Without this option, the others are those listed in `dx.pushRemotes`.",
+                )
+                .short("-r")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_FORK)
+                .help("Remote to hold integration branches, if not the `-r` remote")
+                .value_name("remote")
+                .long("--fork")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_IDEMPOTENCY_KEY)
+                .help("Do nothing if already integrated with this key from the same source")
+                .long_help(
+                    "Do nothing if the target branch was already integrated with this key from \
+                     the same source commit. Keys are recorded in notes under `refs/notes/dx`, \
+                     which are pushed along with the target branch.",
+                )
+                .value_name("key")
+                .long("--idempotency-key")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DIRECTORY)
+                .help("Run as if started in <path> instead of the current directory")
+                .long_help(
+                    "Run as if started in <path> instead of the current directory, as with \
+                     `git -C`. Every git command that git-dx runs targets the repository found \
+                     there.",
+                )
+                .value_name("path")
+                .short("-C")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_VERBOSE)
+                .help("Echo each git command (repeat to also show durations)")
+                .long("--verbose")
+                .short("-v")
+                .multiple(true)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_QUIET)
+                .help("Print only results, with no progress messages or warnings")
+                .long("--quiet")
+                .short("-q")
+                .conflicts_with(CLI_ARG_VERBOSE)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_PLAIN)
+                .help("Keep output plain, for screen readers and dumb terminals")
+                .long_help(
+                    "Keep output to plain lines of text, for screen readers and dumb terminals: \
+                     no color and nothing redrawn in place. Setting `TERM=dumb` does the same, \
+                     and setting `NO_COLOR` turns off color alone.",
+                )
+                .long(terminal::PLAIN_FLAG)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COLOR)
+                .help("Color messages on stderr: `always`, `never`, or `auto`")
+                .long_help(
+                    "Whether to color messages on stderr, like created branches, warnings, and \
+                     conflicts: `always`, `never`, or `auto` (the default), which colors when \
+                     stderr is a terminal, the `NO_COLOR` environment variable is not set, and \
+                     `TERM` is not `dumb`. Under `--plain`, nothing is colored.",
+                )
+                .value_name("when")
+                .long("--color")
+                .takes_value(true)
+                .possible_values(color::WHENS)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_PROGRESS)
+                .help("Show progress even if stderr is not a terminal, or with `--quiet`")
+                .long_help(
+                    "Show the steps of integration as they run, with how long each took, and \
+                     the transfer progress of fetches and pushes. This is the default when \
+                     stderr is a terminal and `--quiet` is not given.",
+                )
+                .long("--progress")
+                .overrides_with(CLI_ARG_NO_PROGRESS)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_NO_PROGRESS)
+                .help("Don't show progress")
+                .long("--no-progress")
+                .overrides_with(CLI_ARG_PROGRESS)
+                .global(true),
+        )
+        .arg(
+            // For tests only: see `clock::set_deterministic`.
+            clap::Arg::with_name(CLI_ARG_DETERMINISTIC)
+                .long("--deterministic")
+                .hidden(true)
+                .global(true),
+        )
+        .subcommand(abandon::subcommand())
+        .subcommand(absorb::subcommand())
+        .subcommand(adopt::subcommand())
+        .subcommand(batch::subcommand())
+        .subcommand(bugreport::subcommand())
+        .subcommand(check::subcommand())
+        .subcommand(completions::subcommand())
+        .subcommand(completions::script_subcommand())
+        .subcommand(config::subcommand())
+        .subcommand(diff::subcommand())
+        .subcommand(doctor::subcommand())
+        .subcommand(drop::subcommand())
+        .subcommand(history::subcommand())
+        .subcommand(hook::subcommand())
+        .subcommand(init::subcommand())
+        .subcommand(land::subcommand())
+        .subcommand(mapping::subcommand())
+        .subcommand(new::subcommand())
+        .subcommand(plan::subcommand())
+        .subcommand(pr::subcommand())
+        .subcommand(prune::subcommand())
+        .subcommand(pull::subcommand())
+        .subcommand(push::subcommand())
+        .subcommand(rename::subcommand())
+        .subcommand(restack::subcommand())
+        .subcommand(rollback::subcommand())
+        .subcommand(status::subcommand())
+        .subcommand(track::subcommand())
+        .subcommand(undo::subcommand())
+        .subcommand(web::subcommand())
+}
+
+/// Run the command given by `matches`, parsed from the command line of `app`. Results of
+/// integration go to `output` if given, else as `--porcelain` or `--format` says. Only the run of
+/// the `git-dx` command itself (`process`) sets process-wide state from its flags (verbosity,
+/// color, progress, and the clock) and keeps a transcript for bug reports.
+fn run_matches(
+    app: clap::App<'static, 'static>,
+    matches: &clap::ArgMatches,
+    output: Option<Box<dyn output::OutputSink>>,
+    process: bool,
+) -> err::Result<()> {
+    if process {
+        // Global flags may appear either before or after the subcommand name.
+        let sub = matches.subcommand().1;
+        let count = |name| {
+            std::cmp::max(
+                matches.occurrences_of(name),
+                sub.map_or(0, |m| m.occurrences_of(name)),
+            )
+        };
+        log::set_verbosity(if count(CLI_ARG_QUIET) > 0 {
+            log::QUIET
+        } else {
+            std::cmp::min(count(CLI_ARG_VERBOSE), log::VERY_VERBOSE as u64) as i32
+        });
+        color::set(
+            sub.and_then(|m| m.value_of(CLI_ARG_COLOR))
+                .or_else(|| matches.value_of(CLI_ARG_COLOR))
+                .unwrap_or(color::WHEN_AUTO),
+        );
+        progress::set(if count(CLI_ARG_PROGRESS) > 0 {
+            Some(true)
+        } else if count(CLI_ARG_NO_PROGRESS) > 0 {
+            Some(false)
+        } else {
+            None
+        });
+        if count(CLI_ARG_DETERMINISTIC) > 0 {
+            clock::set_deterministic();
+        }
+    }
+    if let (completions::SCRIPT_SUBCOMMAND, Some(sub)) = matches.subcommand() {
+        // Needs no repository.
+        return completions::script(app, sub);
+    }
+    let directory = matches
+        .subcommand()
+        .1
+        .and_then(|sub| sub.value_of(CLI_ARG_DIRECTORY))
+        .or_else(|| matches.value_of(CLI_ARG_DIRECTORY));
+    let mut git = GitStore::discover(directory.map(Path::new))?;
+    // Keep the previous run's transcript for the bug report to include. Outside a repository,
+    // there's nowhere to keep one.
+    if process && matches.subcommand_name() != Some(bugreport::SUBCOMMAND) {
+        if let Ok(path) = git.git_path(bugreport::TRANSCRIPT_PATH) {
+            log::start_transcript(path);
+        }
+    }
+    git.use_configured_backend()?;
+    let config = Config::load(&git)?;
+    git.set_trailer_syntax(config.trailer_syntax());
+    // Commands that check out, commit, or move refs mustn't run at the same time in one
+    // repository. The lock is released when this function returns, however it returns.
+    let _lock = match matches.subcommand_name() {
+        None
+        | Some(abandon::SUBCOMMAND)
+        | Some(absorb::SUBCOMMAND)
+        | Some(adopt::SUBCOMMAND)
+        | Some(batch::SUBCOMMAND)
+        | Some(drop::SUBCOMMAND)
+        | Some(land::SUBCOMMAND)
+        | Some(new::SUBCOMMAND)
+        | Some(prune::SUBCOMMAND)
+        | Some(pull::SUBCOMMAND)
+        | Some(push::SUBCOMMAND)
+        | Some(rename::SUBCOMMAND)
+        | Some(restack::SUBCOMMAND)
+        | Some(rollback::SUBCOMMAND)
+        | Some(track::SUBCOMMAND)
+        | Some(undo::SUBCOMMAND) => Some(lock::RepoLock::acquire(&git)?),
+        _ => None,
+    };
+    match matches.subcommand_name() {
+        // Setup, hooks, config listing, bug reports, and shell completion shouldn't nag; the rest
+        // walk history and so benefit from the hint.
+        Some(bugreport::SUBCOMMAND)
+        | Some(completions::SUBCOMMAND)
+        | Some(config::SUBCOMMAND)
+        | Some(doctor::SUBCOMMAND)
+        | Some(hook::SUBCOMMAND)
+        | Some(init::SUBCOMMAND) => (),
+        _ => doctor::performance_hint(&git)?,
+    }
+    match matches.subcommand() {
+        (abandon::SUBCOMMAND, Some(sub)) => return abandon::run(&mut git, &config, sub),
+        (absorb::SUBCOMMAND, Some(sub)) => return absorb::run(&mut git, &config, sub),
+        (adopt::SUBCOMMAND, Some(sub)) => return adopt::run(&mut git, &config, sub),
+        (batch::SUBCOMMAND, Some(sub)) => {
+            return batch::run(sub, |args, output| {
+                let invalid = |reason: &str| err::Error::InvalidInstruction {
+                    instruction: args.join(" "),
+                    reason: reason.to_string(),
+                };
+                let argv = std::iter::once("git-dx").chain(args.iter().map(String::as_str));
+                let matches = app
+                    .clone()
+                    .setting(clap::AppSettings::ColorNever)
+                    .get_matches_from_safe(argv)
+                    .map_err(|e| {
+                        let message = e.message.lines().next().unwrap_or_default();
+                        invalid(message.trim_start_matches("error: "))
+                    })?;
+                if matches.subcommand_name().is_some() {
+                    return Err(invalid("not an integration"));
+                }
+                if matches.is_present(CLI_ARG_STACK)
+                    || matches.is_present(CLI_ARG_STDIN)
+                    || matches.occurrences_of(CLI_ARG_COMMIT) > 1
+                {
+                    return Err(invalid("integrates more than one commit"));
+                }
+                integrate_matches(&mut git, &config, &matches, output)
+            })
+        }
+        (bugreport::SUBCOMMAND, Some(sub)) => return bugreport::run(&mut git, &config, sub),
+        (check::SUBCOMMAND, Some(sub)) => return check::run(&mut git, &config, sub),
+        (completions::SUBCOMMAND, Some(sub)) => return completions::run(&mut git, &config, sub),
+        (config::SUBCOMMAND, Some(sub)) => return config::run(&mut git, &config, sub),
+        (diff::SUBCOMMAND, Some(sub)) => return diff::run(&mut git, &config, sub),
+        (doctor::SUBCOMMAND, Some(sub)) => return doctor::run(&mut git, &config, sub),
+        (drop::SUBCOMMAND, Some(sub)) => return drop::run(&mut git, &config, sub),
+        (history::SUBCOMMAND, Some(sub)) => return history::run(&mut git, &config, sub),
+        (hook::SUBCOMMAND, Some(sub)) => return hook::run(&mut git, &config, sub),
+        (init::SUBCOMMAND, Some(sub)) => return init::run(&mut git, &config, sub),
+        (land::SUBCOMMAND, Some(sub)) => return land::run(&mut git, &config, sub),
+        (mapping::SUBCOMMAND, Some(sub)) => return mapping::run(&mut git, &config, sub),
+        (new::SUBCOMMAND, Some(sub)) => return new::run(&mut git, &config, sub),
+        (plan::SUBCOMMAND, Some(sub)) => return plan::run(&mut git, &config, sub),
+        (pr::SUBCOMMAND, Some(sub)) => return pr::run(&mut git, &config, sub),
+        (prune::SUBCOMMAND, Some(sub)) => return prune::run(&mut git, &config, sub),
+        (pull::SUBCOMMAND, Some(sub)) => return pull::run(&mut git, &config, sub),
+        (push::SUBCOMMAND, Some(sub)) => return push::run(&mut git, &config, sub),
+        (rename::SUBCOMMAND, Some(sub)) => return rename::run(&mut git, &config, sub),
+        (restack::SUBCOMMAND, Some(sub)) => return restack::run(&mut git, &config, sub),
+        (rollback::SUBCOMMAND, Some(sub)) => return rollback::run(&mut git, &config, sub),
+        (status::SUBCOMMAND, Some(sub)) => return status::run(&mut git, &config, sub),
+        (track::SUBCOMMAND, Some(sub)) => return track::run(&mut git, &config, sub),
+        (undo::SUBCOMMAND, Some(sub)) => return undo::run(&mut git, &config, sub),
+        (web::SUBCOMMAND, Some(sub)) => return web::run(&mut git, &config, sub),
+        _ => (),
+    }
+
+    let output = if let Some(output) = output {
+        output
+    } else if matches.is_present(CLI_ARG_PORCELAIN) {
+        output::porcelain(
+            matches
+                .value_of(CLI_ARG_PORCELAIN)
+                .unwrap_or(output::PORCELAIN_V1),
+        )
+    } else {
+        output::sink(matches.value_of(CLI_ARG_FORMAT).unwrap())
+    };
+    integrate_matches(&mut git, &config, matches, output)
+}
+
+/// Integrate the source commits named on the command line by `matches`, pushing them if so
+/// directed, and report the results to `output`.
+fn integrate_matches(
+    git: &mut GitStore,
+    config: &Config,
+    matches: &clap::ArgMatches,
+    output: Box<dyn output::OutputSink>,
+) -> err::Result<()> {
+    // Save the original head to re-check-out at the end: the branch, if on one, else the commit.
+    // (Ideally, all this work should be in a separate worktree.)
+    let original_head = git.head()?;
+    let _stash = if matches.is_present(CLI_ARG_AUTOSTASH) {
+        autostash::AutoStash::stash(git)?
+    } else if autostash::is_dirty(git)? {
+        return Err(err::Error::DirtyWorktree);
+    } else {
+        None
+    };
+
+    let tips: Vec<&str> = matches.values_of(CLI_ARG_COMMIT).unwrap().collect();
+    let stack = matches.is_present(CLI_ARG_STACK);
+    let push = matches.is_present(CLI_ARG_PUSH);
+    let dry_run = matches.is_present(CLI_ARG_DRY_RUN);
+    let mut allow_empty = matches.is_present(CLI_ARG_ALLOW_EMPTY);
+    let bump = matches.is_present(CLI_ARG_BUMP);
+    let remote = remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let secondary_remotes = secondary_remotes(git, &remote, matches.values_of(CLI_ARG_REMOTE))?;
+    let remotes = Remotes::new(&remote, matches.value_of(CLI_ARG_FORK))
+        .per_branch(matches.is_present(CLI_ARG_REMOTE) || matches.is_present(CLI_ARG_FORK));
+    let message = matches.value_of(CLI_ARG_MESSAGE);
+    let explain = matches.is_present(CLI_ARG_EXPLAIN);
+    let strict = matches.is_present(CLI_ARG_STRICT);
+    let idempotency_key = matches.value_of(CLI_ARG_IDEMPOTENCY_KEY);
+    let verify_mirror = matches.value_of(CLI_ARG_VERIFY_MIRROR);
+    let mirror_timeout = std::time::Duration::from_secs(
+        matches
+            .value_of(CLI_ARG_MIRROR_TIMEOUT)
+            .unwrap()
+            .parse()
+            .unwrap(),
+    );
+
+    if bump {
+        allow_empty = true;
+    }
+    let signoff = if matches.is_present(CLI_ARG_SIGNOFF) {
+        true
+    } else if matches.is_present(CLI_ARG_NO_SIGNOFF) {
+        false
+    } else {
+        config.signoff
+    };
+    let signoff = if signoff {
+        Some(git.committer()?.ident())
+    } else {
+        None
+    };
+    let verify = if matches.is_present(CLI_ARG_VERIFY) {
+        true
+    } else if matches.is_present(CLI_ARG_NO_VERIFY) {
+        false
+    } else {
+        config.run_hooks
+    };
+    let merge_options = config.merge_options.clone().with(
+        matches.value_of(CLI_ARG_STRATEGY),
+        matches
+            .values_of(CLI_ARG_STRATEGY_OPTION)
+            .into_iter()
+            .flatten(),
+    );
+    let stack_position = if matches.is_present(CLI_ARG_STACK_POSITION) {
+        true
+    } else if matches.is_present(CLI_ARG_NO_STACK_POSITION) {
+        false
+    } else {
+        config.stack_position
+    };
+    let sync_description = if matches.is_present(CLI_ARG_SYNC_DESCRIPTION) {
+        true
+    } else if matches.is_present(CLI_ARG_NO_SYNC_DESCRIPTION) {
+        false
+    } else {
+        config.sync_description
+    };
+    let trunk = if stack || stack_position || sync_description {
+        let trunk = default_trunk(git, remotes.read)?;
+        Some(trunk_oid(git, remotes.read, &trunk)?)
+    } else {
+        None
+    };
+    let edit = if matches.is_present(CLI_ARG_EDIT) {
+        true
+    } else if matches.is_present(CLI_ARG_NO_EDIT) {
+        false
+    } else {
+        use std::io::IsTerminal;
+        message.is_none() && std::io::stdin().is_terminal() && std::io::stderr().is_terminal()
+    };
+
+    let options = RunOptions {
+        integrate: IntegrateOptions {
+            allow_empty,
+            bump,
+            message,
+            edit,
+            allow_unrelated: matches.is_present(CLI_ARG_ALLOW_UNRELATED),
+            skip_diffbase_merge: matches.is_present(CLI_ARG_NO_MERGE_DIFFBASE),
+            accept_remote_rewrite: matches.is_present(CLI_ARG_ACCEPT_REMOTE_REWRITE),
+            overwrite_remote_edits: matches.is_present(CLI_ARG_OVERWRITE_REMOTE_EDITS),
+            verify,
+            merge_options: &merge_options,
+            sign: sign_commits(
+                git,
+                if matches.is_present(CLI_ARG_SIGN) {
+                    Some(true)
+                } else if matches.is_present(CLI_ARG_NO_SIGN) {
+                    Some(false)
+                } else {
+                    None
+                },
+            )?,
+            signoff: signoff.as_deref(),
+        },
+        push,
+        dry_run,
+        push_options: push::push_options(config, matches),
+        secondary_remotes,
+        explain,
+        strict,
+        output,
+        idempotency_key,
+        run_id: journal::run_id(),
+        trunk,
+        stack_position,
+        sync_description,
+    };
+    let revisions = if matches.is_present(CLI_ARG_STDIN) {
+        stdin_revisions()?
+    } else {
+        tips.iter().map(|tip| tip.to_string()).collect()
+    };
+    // Resolve every revision before integrating any, so that a typo doesn't leave a batch half
+    // done. In stack mode, each change is integrated after the change that it builds on (its
+    // dependency); otherwise, changes are integrated in the order given.
+    let mut source_commits = Vec::new();
+    let mut dependencies = Vec::new();
+    if let (true, Some(trunk)) = (stack, &options.trunk) {
+        for rev in &revisions {
+            git.commit(rev)?;
+        }
+        let revisions: Vec<&str> = revisions.iter().map(|rev| rev.as_str()).collect();
+        for node in stack::tree(git, config, trunk, &revisions)? {
+            source_commits.push(node.commit);
+            dependencies.push(node.parent);
+        }
+    } else {
+        for rev in &revisions {
+            source_commits.push(git.commit(rev)?.clone());
+            dependencies.push(None);
+        }
+    }
+    if config.mode == config::Mode::Gerrit {
+        return gerrit::push_patch_sets(git, config, &remotes, &source_commits, &options);
+    }
+    let mut integrated = Vec::new();
+    // Indices of the changes that failed to integrate, or were skipped for building on one that
+    // did (stack mode only).
+    let mut failed = HashSet::new();
+    let mut skipped = 0;
+    for (i, source_commit) in source_commits.iter().enumerate() {
+        if let Some(dependency) = dependencies[i].filter(|d| failed.contains(d)) {
+            warn!(
+                "skipping {}, which builds on {}",
+                source_commit.oid, source_commits[dependency].oid
+            );
+            failed.insert(i);
+            skipped += 1;
+            continue;
+        }
+        let result = match integrate_source(
+            git,
+            config,
+            &remotes,
+            source_commit,
+            &original_head,
+            &options,
+        ) {
+            Ok(result) => result,
+            Err(e) if stack => {
+                warn!("failed to integrate {}: {}", source_commit.oid, e);
+                failed.insert(i);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(result) = result {
+            // Later changes in the stack build on this one as it will be once pushed.
+            remotes.set_pending(&result.target_branch, result.remote_commit.clone());
+            integrated.push((source_commit, result));
+        }
+    }
+
+    // Push every target branch on each remote at once, so that a rejected update leaves none of
+    // them changed.
+    let mut push_outcomes: Vec<(&str, push::PushOutcome)> = Vec::new();
+    if options.push && !integrated.is_empty() {
+        // A dry run pushes nothing, so there's nothing for a pre-push hook to vet, and a hook
+        // with side effects (like starting CI) mustn't fire for it.
+        if !options.dry_run {
+            for (source_commit, result) in &integrated {
+                hooks::run(
+                    git,
+                    hooks::PRE_PUSH,
+                    &result.hook_context(&source_commit.oid),
+                )?;
+            }
+        }
+        let mut by_remote: Vec<(&str, Vec<push::BranchUpdate>)> = Vec::new();
+        for (_, result) in &integrated {
+            let update = push::BranchUpdate {
+                branch: &result.target_branch,
+                commit: &result.remote_commit,
+                // Lease the branch at the commit that we merged from, so that anything pushed
+                // since then is not overwritten.
+                expected: if result.new_branch {
+                    None
+                } else {
+                    Some(&result.merge_head)
+                },
+            };
+            match by_remote
+                .iter_mut()
+                .find(|(remote, _)| *remote == result.remote)
+            {
+                Some((_, updates)) => updates.push(update),
+                None => by_remote.push((&result.remote, vec![update])),
+            }
+        }
+        let mut extra_refspecs = Vec::new();
+        if options.idempotency_key.is_some() {
+            extra_refspecs.push(format!("{0}:{0}", idempotency::NOTES_REF));
+        }
+        for (remote, updates) in by_remote {
+            let step = progress::Step::start(format!("push to {}", remote));
+            let outcome = push::push_branches(
+                git,
+                remote,
+                &updates,
+                options.dry_run,
+                &options.push_options,
+                &extra_refspecs,
+            )?;
+            git.invalidate_refs();
+            step.finish();
+            options.output.pushed(&outcome);
+            push_outcomes.push((remote, outcome));
+        }
+    }
+    // The target branches are now safely pushed, so a failure to push them elsewhere too is
+    // reported for each remote but does not stop the run.
+    let mut secondary_pushes = Vec::new();
+    if options.push && !integrated.is_empty() {
+        for remote in &options.secondary_remotes {
+            let branches: Vec<(&str, &str)> = integrated
+                .iter()
+                .filter(|(_, result)| result.remote != *remote)
+                .map(|(_, result)| (result.target_branch.as_str(), result.remote_commit.as_str()))
+                .collect();
+            if branches.is_empty() {
+                continue;
+            }
+            let step = progress::Step::start(format!("push to {}", remote));
+            let pushed = push::push_secondary(
+                git,
+                remote,
+                &branches,
+                options.dry_run,
+                &options.push_options,
+            );
+            git.invalidate_refs();
+            secondary_pushes.push(match pushed {
+                Ok(outcome) => {
+                    step.finish();
+                    info!("pushed to {}", remote);
+                    options.output.pushed(&outcome);
+                    output::SecondaryPush {
+                        remote,
+                        error: None,
+                    }
+                }
+                Err(e) => {
+                    warn!("failed to push to {}: {}", remote, e);
+                    output::SecondaryPush {
+                        remote,
+                        error: Some(e.to_string()),
+                    }
+                }
+            });
+        }
+    }
+
+    for (source_commit, result) in &integrated {
+        if options.push && !options.dry_run {
+            hooks::run_after(
+                git,
+                hooks::POST_PUSH,
+                &result.hook_context(&source_commit.oid),
+            );
+            // The push succeeded, so don't fail the whole command over review metadata.
+            if let Err(e) =
+                forge::sync_metadata(git, config, source_commit, &remotes, &result.target_branch)
+            {
+                warn!("failed to update pull request: {}", e);
+            }
+            if let (true, Some(trunk)) = (options.sync_description, &options.trunk) {
+                let head = original_head.to_string();
+                let synced =
+                    stack::find(git, config, trunk, &head, source_commit).and_then(|stack| {
+                        pr::sync_description(
+                            git,
+                            config,
+                            source_commit,
+                            &stack.unwrap_or_default(),
+                            &remotes,
+                            &result.target_branch,
+                        )
+                    });
+                if let Err(e) = synced {
+                    warn!("failed to update pull request description: {}", e);
+                }
+            }
+        }
+        let push = push_outcomes
+            .iter()
+            .find(|(remote, _)| *remote == result.remote)
+            .map(|(_, outcome)| outcome.for_branch(&result.target_branch));
+        options.output.finished(&result.report(
+            &source_commit.oid,
+            options.push && !options.dry_run,
+            push.as_ref(),
+            &secondary_pushes,
+        ));
+    }
+
+    if let Some(mirror) = verify_mirror {
+        if options.push && !options.dry_run && !integrated.is_empty() {
+            let branches: Vec<(&str, &str)> = integrated
+                .iter()
+                .map(|(_, result)| (result.target_branch.as_str(), result.remote_commit.as_str()))
+                .collect();
+            mirror::verify(git, mirror, &branches, mirror_timeout)?;
+        }
+    }
+    let secondary_failed: Vec<String> = secondary_pushes
+        .iter()
+        .filter(|pushed| pushed.error.is_some())
+        .map(|pushed| pushed.remote.to_string())
+        .collect();
+    if !secondary_failed.is_empty() {
+        return Err(err::Error::SecondaryPushFailed {
+            remotes: secondary_failed,
+        });
+    }
+    if !failed.is_empty() {
+        return Err(err::Error::StackIncomplete {
+            failed: failed.len() - skipped,
+            skipped,
+        });
+    }
+    Ok(())
+}
+
+/// Choices about how to integrate and push each source commit, from the command line.
+struct RunOptions<'a> {
+    integrate: IntegrateOptions<'a>,
+    push: bool,
+    dry_run: bool,
+    /// Options to send with the push, as by `git push -o`.
+    push_options: Vec<String>,
+    /// Remotes to push integrated commits to after the remote that holds their target branches.
+    secondary_remotes: Vec<String>,
+    explain: bool,
+    strict: bool,
+    output: Box<dyn output::OutputSink>,
+    idempotency_key: Option<&'a str>,
+    /// Identifies this run's entries in the journal, so that `git dx undo` can find them.
+    run_id: String,
+    /// Trunk that stacks are built on, if stack positions or pull request descriptions need it.
+    trunk: Option<git::Oid>,
+    /// Whether to start commit subjects with stack positions.
+    stack_position: bool,
+    /// Whether to copy source commit bodies into pull request descriptions after pushing.
+    sync_description: bool,
+}
+
+impl RunOptions<'_> {
+    /// The flags given that affect integration, as recorded in the journal.
+    fn journal_flags(&self) -> Vec<&'static str> {
+        let integrate = &self.integrate;
+        [
+            (integrate.allow_empty, "--allow-empty"),
+            (integrate.bump, "--bump"),
+            (integrate.message.is_some(), "--message"),
+            (integrate.edit, "--edit"),
+            (integrate.allow_unrelated, "--allow-unrelated"),
+            (integrate.skip_diffbase_merge, "--no-merge-diffbase"),
+            (integrate.accept_remote_rewrite, "--accept-remote-rewrite"),
+            (integrate.overwrite_remote_edits, "--overwrite-remote-edits"),
+            (integrate.verify, "--verify"),
+            (integrate.merge_options.strategy.is_some(), "--strategy"),
+            (
+                !integrate.merge_options.strategy_options.is_empty(),
+                "--strategy-option",
+            ),
+            (integrate.sign, "--sign"),
+            (integrate.signoff.is_some(), "--signoff"),
+            (self.strict, "--strict"),
+            (self.idempotency_key.is_some(), "--idempotency-key"),
+            (self.stack_position, "--stack-position"),
+        ]
+        .iter()
+        .filter(|(given, _)| *given)
+        .map(|(_, flag)| *flag)
+        .collect()
+    }
+}
+
+/// Integrate a single source commit, returning the integration to be pushed and reported, or
+/// `None` if the commit was already integrated (which is reported here). On failure,
+/// `original_head` is checked out again.
+fn integrate_source(
+    git: &mut GitStore,
+    config: &Config,
+    remotes: &Remotes,
+    source_commit: &git::Commit,
+    original_head: &git::Head,
+    options: &RunOptions,
+) -> err::Result<Option<Integration>> {
+    if let Some(key) = options.idempotency_key {
+        let existing = idempotency::already_integrated(git, config, source_commit, remotes, key)?;
+        if let Some(existing) = existing {
+            let target_branch =
+                branch_name(git, config, &source_commit.oid, &source_commit.trailers)?
+                    .unwrap_or_default();
+            options.output.integrated(&output::Report {
+                source: &source_commit.oid,
+                target_branch: &target_branch,
+                remote_commit: &existing,
+                already_integrated: true,
+                new_branch: false,
+                diffbase_merged: false,
+                conflicts: &[],
+                discarded_paths: &[],
+                size: None,
+                pushed: false,
+                push: None,
+                secondary_pushes: &[],
+            });
+            return Ok(None);
+        }
+    }
+    // Without a branch directive, integration fails on its own, and says why.
+    if let Some(target_branch) =
+        branch_name(git, config, &source_commit.oid, &source_commit.trailers)?
+    {
+        let remote = remotes.target_remote(git, &target_branch)?;
+        hooks::run(
+            git,
+            hooks::PRE_INTEGRATE,
+            &hooks::Context {
+                source: &source_commit.oid,
+                target_branch: &target_branch,
+                remote: &remote,
+                commit: None,
+            },
+        )?;
+    }
+    let position = match options.trunk {
+        Some(ref trunk) if options.stack_position => {
+            let position = stack::position(
+                git,
+                config,
+                trunk,
+                &original_head.to_string(),
+                source_commit,
+            )?;
+            if position.is_none() {
+                warn!("{} is not in a stack above trunk", source_commit.oid);
+            }
+            position
+        }
+        _ => None,
+    };
+    let result = match integrate(
+        git,
+        config,
+        source_commit,
+        remotes,
+        &options.integrate,
+        position,
+    ) {
+        Ok(result) => result,
+        Err(e) => {
+            // Best effort: return to where we started so that the user can fix and retry. A
+            // cancelled run may have stopped partway through a merge, so abort that first.
+            cancel::shield(|| {
+                if let err::Error::Cancelled = e.root() {
+                    let _ = git.command().args(["merge", "--abort"]).logged_output();
+                }
+                let _ = git.check_out_head(original_head);
+            });
+            return Err(e);
+        }
+    };
+    if options.explain {
+        result.explain();
+    }
+    if !result.discarded_paths.is_empty() {
+        warn!(
+            "source commit overrides diffbase merge changes to {} path(s):",
+            result.discarded_paths.len()
+        );
+        for path in &result.discarded_paths {
+            info!("\t{}", path);
+        }
+    }
+    git.check_out_head(original_head)?;
+    if options.strict && !result.discarded_paths.is_empty() {
+        return Err(err::Error::DiscardedMergeChanges {
+            branch: result.target_branch,
+            paths: result.discarded_paths,
+        });
+    }
+    if let Some(key) = options.idempotency_key {
+        idempotency::record(git, &result.remote_commit, &source_commit.oid, key)
+            .context(|| format!("recording idempotency key {:?}", key))?;
+    }
+    journal::record(
+        git,
+        &journal::Entry {
+            target_branch: &result.target_branch,
+            remote_commit: &result.remote_commit,
+            source: &source_commit.oid,
+            previous: if result.new_branch {
+                None
+            } else {
+                Some(&result.merge_head)
+            },
+            flags: options.journal_flags(),
+            run: &options.run_id,
+            head: &original_head.to_string(),
+        },
+    )
+    .context(|| {
+        format!(
+            "recording integration of {} in the journal",
+            source_commit.oid
+        )
+    })?;
+    options
+        .output
+        .integrated(&result.report(&source_commit.oid, false, None, &[]));
+    hooks::run_after(
+        git,
+        hooks::POST_INTEGRATE,
+        &result.hook_context(&source_commit.oid),
+    );
+    Ok(Some(result))
+}
+
+/// Read revisions given with `--stdin` (or instructions for `git dx batch`): NUL-separated if the
+/// input contains a NUL (as from the `-z` output of many Git commands), else one per line. Blank
+/// entries are skipped.
+fn stdin_revisions() -> err::Result<Vec<String>> {
+    use std::io::Read;
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input)?;
+    let input = err::Error::require_utf8(input, "standard input")?;
+    let separator = if input.contains('\0') { '\0' } else { '\n' };
+    Ok(input
+        .split(separator)
+        .map(|rev| rev.trim())
+        .filter(|rev| !rev.is_empty())
+        .map(|rev| rev.to_string())
+        .collect())
+}
+
+/// The remotes involved in integrating a change. These are usually the same remote, but differ in
+/// fork mode, where a contributor without push access to the upstream repository keeps their
+/// integration branches on a fork.
+struct Remotes<'a> {
+    /// The upstream remote, against which diffbases are resolved first.
+    read: &'a str,
+    /// The remote that holds target branches: their current heads are read from here, and
+    /// integrated commits are pushed here.
+    write: &'a str,
+    /// Target branches integrated in this run but not yet pushed, mapped to their new heads,
+    /// which take precedence over what the remote has.
+    pending: RefCell<HashMap<String, git::Oid>>,
+    /// Whether a branch's `branch.<name>.dxRemote` option, if set, names the remote that holds
+    /// it, in place of `read` and `write`. Off when remotes are given on the command line.
+    per_branch: bool,
+}
+
+impl<'a> Remotes<'a> {
+    fn new(upstream: &'a str, fork: Option<&'a str>) -> Self {
+        Remotes {
+            read: upstream,
+            write: fork.unwrap_or(upstream),
+            pending: RefCell::new(HashMap::new()),
+            per_branch: false,
+        }
+    }
+
+    /// Consult `branch.<name>.dxRemote` for the remote of each branch, unless `explicit`.
+    fn per_branch(mut self, explicit: bool) -> Self {
+        self.per_branch = !explicit;
+        self
+    }
+
+    /// The remote configured for `branch` with `branch.<name>.dxRemote`, if consulted and set.
+    fn branch_remote(&self, git: &GitStore, branch: &str) -> err::Result<Option<String>> {
+        if !self.per_branch {
+            return Ok(None);
+        }
+        git.config_get(&format!("branch.{}.{}", branch, BRANCH_REMOTE_CONFIG))
+    }
+
+    /// The remote that holds the given target branch, and that it is pushed to.
+    fn target_remote(&self, git: &GitStore, branch: &str) -> err::Result<String> {
+        Ok(self
+            .branch_remote(git, branch)?
+            .unwrap_or_else(|| self.write.to_string()))
+    }
+
+    /// Record the new head of a target branch that will be pushed later in this run.
+    fn set_pending(&self, branch: &str, oid: git::Oid) {
+        self.pending.borrow_mut().insert(branch.to_string(), oid);
+    }
+
+    fn pending_oid(&self, branch: &str) -> Option<git::Oid> {
+        self.pending.borrow().get(branch).cloned()
+    }
+
+    /// Find the remote head of the given diffbase branch. The branch is resolved against its own
+    /// remote, if configured, or else the upstream remote, falling back to the fork so that
+    /// stacked changes that exist only on the fork still chain correctly.
+    fn diffbase_oid(&self, git: &mut GitStore, branch: &str) -> err::Result<Option<git::Oid>> {
+        if let Some(oid) = self.pending_oid(branch) {
+            return Ok(Some(oid));
+        }
+        if let Some(remote) = self.branch_remote(git, branch)? {
+            return remote_branch_oid(git, &remote, branch);
+        }
+        match remote_branch_oid(git, self.read, branch)? {
+            Some(oid) => Ok(Some(oid)),
+            None if self.write != self.read => remote_branch_oid(git, self.write, branch),
+            None => Ok(None),
+        }
+    }
+
+    /// Find the remote head of the given target branch.
+    fn target_oid(&self, git: &mut GitStore, branch: &str) -> err::Result<Option<git::Oid>> {
+        if let Some(oid) = self.pending_oid(branch) {
+            return Ok(Some(oid));
+        }
+        let remote = self.target_remote(git, branch)?;
+        remote_branch_oid(git, &remote, branch)
+    }
+}
+
+struct Integration {
+    remote_commit: git::Oid,
+    target_branch: String,
+    /// The remote that holds the target branch.
+    remote: String,
+    /// Whether the target branch did not yet exist on the remote.
+    new_branch: bool,
+    /// The commit checked out in step (1).
+    merge_head: git::Oid,
+    /// The commits merged in step (2): one per parent of the source commit, in order.
+    remote_diffbases: Vec<git::Oid>,
+    /// The "update diffbase" commit created in step (2), if the merge incurred any changes.
+    diffbase_merge: Option<git::Oid>,
+    /// Paths that conflicted in the diffbase merge of step (2), and how each was resolved.
+    conflicts: Vec<resolve::Conflict>,
+    /// Paths changed by the diffbase merge whose merged contents were replaced by the source
+    /// commit's contents in step (3). For these paths, any resolution that the merge performed
+    /// (automatic or conflicted) is not reflected in the remote commit.
+    discarded_paths: Vec<String>,
+    /// How big the change is, relative to its local diffbase.
+    size: budget::Size,
+}
+
+impl Integration {
+    /// Describe this integration of `source` for an `OutputSink`.
+    fn report<'a>(
+        &'a self,
+        source: &'a str,
+        pushed: bool,
+        push: Option<&'a push::PushOutcome>,
+        secondary_pushes: &'a [output::SecondaryPush<'a>],
+    ) -> output::Report<'a> {
+        output::Report {
+            source,
+            target_branch: &self.target_branch,
+            remote_commit: &self.remote_commit,
+            already_integrated: false,
+            new_branch: self.new_branch,
+            diffbase_merged: self.diffbase_merge.is_some(),
+            conflicts: &self.conflicts,
+            discarded_paths: &self.discarded_paths,
+            size: Some(&self.size),
+            pushed,
+            push,
+            secondary_pushes,
+        }
+    }
+
+    /// Describe this integration of `source` for a dx hook.
+    fn hook_context<'a>(&'a self, source: &'a str) -> hooks::Context<'a> {
+        hooks::Context {
+            source,
+            target_branch: &self.target_branch,
+            remote: &self.remote,
+            commit: Some(&self.remote_commit),
+        }
+    }
+
+    /// Print a description of the steps taken to produce this integration to stderr.
+    fn explain(&self) {
+        eprintln!("target branch: {}", self.target_branch);
+        if self.new_branch {
+            eprintln!(
+                "(1) no remote target branch; started from remote diffbase {}",
+                self.merge_head
+            );
+        } else {
+            eprintln!(
+                "(1) started from remote target branch at {}",
+                self.merge_head
+            );
+        }
+        let diffbases: Vec<&str> = self
+            .remote_diffbases
+            .iter()
+            .map(|oid| oid.as_str())
+            .collect();
+        let noun = if diffbases.len() == 1 {
+            "remote diffbase"
+        } else {
+            "remote diffbases"
+        };
+        match self.diffbase_merge {
+            Some(ref oid) => eprintln!("(2) merged {} {} as {}", noun, diffbases.join(", "), oid),
+            None => eprintln!("(2) {} {} already merged", noun, diffbases.join(", ")),
+        }
+        for conflict in &self.conflicts {
+            match conflict.resolution {
+                Some(ref policy) => {
+                    eprintln!("\tconflict in {} (resolved: {})", conflict.path, policy)
+                }
+                None if conflict.kind == resolve::ConflictKind::Content => eprintln!(
+                    "\tconflict in {} (committed with markers)",
+                    color::conflict(&conflict.path)
+                ),
+                None => eprintln!(
+                    "\tconflict in {} ({}; committed as it stands)",
+                    color::conflict(&conflict.path),
+                    conflict.kind
+                ),
+            }
+        }
+        if self.discarded_paths.is_empty() {
+            eprintln!("(3) committed source tree as {}", self.remote_commit);
+        } else {
+            eprintln!(
+                "(3) committed source tree as {}, overriding merge results for:",
+                self.remote_commit
+            );
+            for path in &self.discarded_paths {
+                eprintln!("\t{}", path);
+            }
+        }
+    }
+}
+
+/// Choices about how to integrate a change, from the command line.
+#[derive(Debug, Clone, Copy)]
+struct IntegrateOptions<'a> {
+    /// Create an integration commit even if there is no change.
+    allow_empty: bool,
+    /// Don't skip CI on an empty integration commit.
+    bump: bool,
+    /// Message for the "update patch" commit.
+    message: Option<&'a str>,
+    /// Open an editor for the message of the "update patch" commit.
+    edit: bool,
+    /// Merge the remote diffbase even if it shares no history with the remote target branch.
+    allow_unrelated: bool,
+    /// Skip the diffbase merge, requiring the remote target branch to contain the remote
+    /// diffbase already.
+    skip_diffbase_merge: bool,
+    /// Build on the remote target branch even if it no longer contains our last push to it.
+    accept_remote_rewrite: bool,
+    /// Integrate even if the remote target branch has commits that git-dx didn't make.
+    overwrite_remote_edits: bool,
+    /// Run the standard Git hooks for the commits that integration creates.
+    verify: bool,
+    /// Merge strategy and strategy options for the diffbase merge.
+    merge_options: &'a resolve::MergeOptions,
+    /// Sign the merge and update commits.
+    sign: bool,
+    /// Identity to sign off the merge and update commits as, if any.
+    signoff: Option<&'a str>,
+}
+
+/// Process the change at `oid` to create a remote-friendly commit, returning the new commit's OID.
+/// The new commit will be treequal to the input commit, and may be cleanly pushed to its remote
+/// branch.
+///
+/// The diff of the commit at `oid` should represent the full contents of the change, and its
+/// parent commit should be the desired diffbase. A merge commit has a diffbase for each parent,
+/// and the change is its diff against the merge of them all. A root commit builds on the trunk.
+///
+/// The resulting commit will also be checked out on success. On failure, the state of the work
+/// tree and index are not defined.
+fn integrate(
+    git: &mut git::GitStore,
+    config: &Config,
+    source_commit: &git::Commit,
+    remotes: &Remotes,
+    options: &IntegrateOptions,
+    position: Option<stack::Position>,
+) -> err::Result<Integration> {
+    // Steps (see Terminology section of README.md):
+    //
+    //  1. Check out the remote target branch, or (if none exists) the remote diffbase, or (if none
+    //     exists) the local diffbase.
+    //  2. Merge in the remote diffbase, or (if none exists) the local diffbase, of each parent of
+    //     the source commit in turn. Commit conflicts as they stand. Create an "update diffbase"
+    //     commit for each merge that incurs any changes.
+    //  3. Commit the tree of the source commit. Create an "update patch" commit if this incurs any
+    //     changes.
+    //
+    // Future enhancements:
+    //
+    //  4. If neither (2) nor (3) incurs changes, create a "CI bump" commit if so directed.
+    //  5. If neither (2) nor (3) nor (4) incurs changes, create a "CI skip" commit, purely for
+    //     updating the dx-source trailer reference.
+    let IntegrateOptions {
+        allow_empty,
+        bump,
+        message,
+        edit,
+        allow_unrelated,
+        skip_diffbase_merge,
+        accept_remote_rewrite,
+        overwrite_remote_edits,
+        verify,
+        merge_options,
+        sign,
+        signoff,
+    } = *options;
+    let source_oid = &source_commit.oid;
+
+    let branch_key = branch_key(config, source_oid, &source_commit.trailers)?.ok_or_else(|| {
+        err::Error::MissingTrailer {
+            oid: source_oid.to_string(),
+            key: config.branch_trailer.clone(),
+        }
+    })?;
+    let target_branch = valid_target_branch(git, config, source_oid, &branch_key)?;
+
+    let mut local_diffbases = Vec::new();
+    for parent in &source_commit.parents {
+        let local_diffbase = git
+            .commit(parent)
+            .context(|| format!("reading local diffbase of {}", source_oid))?
+            .clone();
+        local_diffbases.push(local_diffbase);
+    }
+    let size_base = size_base(git, &source_commit.parents)?;
+    let size = budget::check(git, config, source_commit, &size_base, &target_branch)?;
+    let mut remote_diffbases: Vec<git::Oid> = Vec::new();
+    if source_commit.parents.is_empty() {
+        remote_diffbases.push(root_diffbase(git, remotes, source_commit, &branch_key)?);
+    }
+    for local_diffbase in &local_diffbases {
+        let oid = remote_diffbase(git, config, remotes, local_diffbase)?;
+        // Two parents may share a remote diffbase, as when both have landed.
+        if !remote_diffbases.contains(&oid) {
+            remote_diffbases.push(oid);
+        }
+    }
+    let remote = remotes.target_remote(git, &target_branch)?;
+    let merge_head = remotes
+        .target_oid(git, &target_branch)
+        .context(|| format!("finding remote target branch {}", target_branch))?;
+    let new_branch = merge_head.is_none();
+    let merge_head = merge_head.unwrap_or_else(|| remote_diffbases[0].clone());
+    for remote_diffbase in &remote_diffbases {
+        if skip_diffbase_merge && !git.is_ancestor(remote_diffbase, &merge_head)? {
+            return Err(err::Error::DiffbaseNotMerged {
+                branch: target_branch,
+                remote_commit: merge_head.to_string(),
+                diffbase: remote_diffbase.to_string(),
+            });
+        }
+        if !new_branch
+            && !allow_unrelated
+            && git.merge_base(&merge_head, remote_diffbase)?.is_none()
+        {
+            return Err(err::Error::UnrelatedHistories {
+                branch: target_branch,
+                remote_commit: merge_head.to_string(),
+                diffbase: remote_diffbase.to_string(),
+            });
+        }
+    }
+    if !new_branch {
+        let pushed_ref = push::pushed_ref(&remote, &target_branch);
+        let pushed = git.rev_parse(&pushed_ref)?;
+        if let Some(ref pushed) = pushed {
+            if !git.is_ancestor(pushed, &merge_head)? {
+                if !accept_remote_rewrite {
+                    return Err(err::Error::RemoteRewritten {
+                        remote: remote.clone(),
+                        branch: target_branch,
+                        pushed: pushed.to_string(),
+                        actual: merge_head.to_string(),
+                    });
+                }
+                warn!(
+                    "remote branch {} no longer contains {}, which git-dx last pushed to it; \
+                     building on its new head {}",
+                    target_branch, pushed, merge_head
+                );
+            }
+        }
+        let pulled = git.rev_parse(&pull::pulled_ref(&remote, &target_branch))?;
+        let known: Vec<&str> = pushed.iter().chain(&pulled).map(git::Oid::as_str).collect();
+        let foreign = foreign_commits(git, config, &merge_head, &known, &remote_diffbases)?;
+        if !foreign.is_empty() {
+            let commits: Vec<String> = foreign
+                .iter()
+                .map(|c| format!("{} {}", c.oid, c.message.lines().next().unwrap_or("")))
+                .collect();
+            if !overwrite_remote_edits {
+                return Err(err::Error::RemoteEdits {
+                    remote: remote.clone(),
+                    branch: target_branch,
+                    commits,
+                });
+            }
+            warn!(
+                "overwriting {} commit(s) on {} that git-dx did not make:\n\t{}",
+                commits.len(),
+                target_branch,
+                commits.join("\n\t")
+            );
+        }
+    }
+
+    // (1)
+    let step = progress::Step::start(format!("checkout {}", target_branch));
+    let checking_out = || format!("checking out {} at {}", target_branch, merge_head);
+    let out = git
+        .command()
+        .args(["checkout", "--detach", &merge_head])
+        .logged_output()
+        .context(checking_out)?;
+    err::from_git(&out, || format!("failed to check out {}", merge_head)).context(checking_out)?;
+    std::mem::drop(out);
+    step.finish();
+
+    // (2)
+    let step = progress::Step::start("diffbase merge");
+    let mut merge_trailers = format!(
+        "{}: {}\n{}: {}",
+        config.branch_trailer, branch_key, config.source_trailer, source_oid
+    );
+    if let Some(ident) = signoff {
+        merge_trailers.push_str(&format!("\n{}: {}", SIGNOFF_TRAILER, ident));
+    }
+    let merge_subject = format!("[{}: update diffbase]", branch_key);
+    let mut conflicts = Vec::new();
+    // With the merge skipped, the branch is known to contain every remote diffbase already.
+    let to_merge: &[git::Oid] = if skip_diffbase_merge {
+        &[]
+    } else {
+        &remote_diffbases
+    };
+    for remote_diffbase in to_merge {
+        let merging = || {
+            format!(
+                "merging remote diffbase {} into {}",
+                remote_diffbase, target_branch
+            )
+        };
+        let mut merge = git.command();
+        merge.args([
+            "-c",
+            "rerere.enabled=false",
+            "merge",
+            "--no-verify",
+            "--no-edit",
+            gpg_sign_arg(sign),
+        ]);
+        if allow_unrelated {
+            merge.arg("--allow-unrelated-histories");
+        }
+        merge.args(merge_options.args());
+        if verify {
+            // Stop short of committing, to run the hooks first.
+            merge.arg("--no-commit");
+        }
+        let out = merge
+            .args([
+                remote_diffbase.as_str(),
+                "-m",
+                &merge_subject,
+                "-m",
+                &merge_trailers,
+            ])
+            .logged_output()
+            .context(merging)?;
+        let mut found = Vec::new();
+        if !out.status.success() && git.rev_parse("MERGE_HEAD")?.is_none() {
+            // The merge never started, as with an unknown strategy.
+            err::from_git(&out, || "failed to merge".to_string()).context(merging)?;
+        }
+        if !out.status.success() {
+            // Assume that this is due to conflicts. Resolve what we can by policy, and commit the
+            // rest as they stand.
+            let resolved = resolve::resolve_conflicts(git, &config.resolve_policies);
+            found = match resolved.context(merging) {
+                Ok(conflicts) => conflicts,
+                Err(e) => {
+                    // Best effort: don't leave a half-resolved merge behind.
+                    let _ =
+                        cancel::shield(|| git.command().args(["merge", "--abort"]).logged_output());
+                    return Err(e);
+                }
+            };
+            let out = &git
+                .command()
+                .args(["add", "."])
+                .logged_output()
+                .context(merging)?;
+            err::from_git(out, || "failed to stage".to_string()).context(merging)?;
+            // A resolution policy that left conflict markers behind didn't resolve its path.
+            for path in resolve::conflict_markers(git, "HEAD").context(merging)? {
+                let conflict = found.iter_mut().find(|c| c.path == path);
+                if let Some(policy) = conflict.and_then(|c| c.resolution.take()) {
+                    warn!("resolving {} with {} left conflict markers", path, policy);
+                }
+            }
+            if !found.is_empty() {
+                // List the conflicts in the merge commit's message, between its subject and
+                // trailers.
+                let listing: Vec<String> = found.iter().map(|c| format!("\t{}", c)).collect();
+                let msg = format!(
+                    "{}\n\nConflicts:\n{}\n\n{}\n",
+                    merge_subject,
+                    listing.join("\n"),
+                    merge_trailers
+                );
+                std::fs::write(git.git_path("MERGE_MSG")?, msg).context(merging)?;
+            }
+            if !verify {
+                commit_merge(git, sign).context(merging)?;
+            }
+        }
+        if verify && git.rev_parse("MERGE_HEAD")?.is_some() {
+            // As Git would: `pre-merge-commit` if the merge went cleanly, else `pre-commit`.
+            let pre_hook = if out.status.success() {
+                "pre-merge-commit"
+            } else {
+                "pre-commit"
+            };
+            let msg_path = git.git_path("MERGE_MSG")?;
+            let verified = hooks::run_git_hook(git, pre_hook, &[], &target_branch).and_then(|()| {
+                hooks::run_git_hook(git, "commit-msg", &[msg_path.as_os_str()], &target_branch)
+            });
+            if let Err(e) = verified {
+                // Best effort: don't leave a half-done merge behind.
+                let _ = cancel::shield(|| git.command().args(["merge", "--abort"]).logged_output());
+                return Err(e);
+            }
+            commit_merge(git, sign).context(merging)?;
+        }
+        if !out.status.success() {
+            for conflict in found.iter().filter(|c| c.resolution.is_none()) {
+                warn!("{} from merging diffbase", conflict.unresolved());
+            }
+        }
+        std::mem::drop(out);
+        conflicts.extend(found);
+    }
+    git.invalidate_refs();
+
+    let base_commit = git.commit("HEAD")?.clone();
+    let diffbase_merge = if base_commit.oid != merge_head {
+        Some(base_commit.oid.clone())
+    } else {
+        None
+    };
+
+    // Paths that the merge changed and that the source commit also changed, but to different
+    // contents, will have their merge results silently replaced by step (3).
+    let discarded_paths = if diffbase_merge.is_some() {
+        let comparing = || "comparing diffbase merge with source commit".to_string();
+        let merge_changed = git
+            .diff_names(&merge_head, &base_commit.oid)
+            .context(comparing)?;
+        // For a merge commit, only what it changed relative to every parent is its own change. A
+        // root commit changed everything.
+        let (first, rest): (git::Oid, &[git::Oid]) = match source_commit.parents.split_first() {
+            Some((first, rest)) => (first.clone(), rest),
+            None => (git.empty_tree()?, &[]),
+        };
+        let mut source_changed: HashSet<_> = git
+            .diff_names(&first, source_oid)
+            .context(comparing)?
+            .into_iter()
+            .collect();
+        for parent in rest {
+            let changed: HashSet<_> = git
+                .diff_names(parent, source_oid)
+                .context(comparing)?
+                .into_iter()
+                .collect();
+            source_changed.retain(|path| changed.contains(path));
+        }
+        let replaced: HashSet<_> = git
+            .diff_names(&base_commit.oid, source_oid)
+            .context(comparing)?
+            .into_iter()
+            .collect();
+        merge_changed
+            .into_iter()
+            .filter(|path| source_changed.contains(path) && replaced.contains(path))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    step.finish();
+
+    // (3)
+    let step = progress::Step::start("commit");
+    let same_tree = source_commit.tree == base_commit.tree;
+    let remote_commit = if same_tree && !allow_empty {
+        info!(
+            "{}",
+            color::unchanged(format!(
+                "tree unchanged; {} already has the source tree",
+                target_branch
+            ))
+        );
+        base_commit.oid
+    } else {
+        let committing = || format!("committing source tree to {}", target_branch);
+        let msg: Cow<'_, str> = if new_branch {
+            source_commit.message.as_str().into()
+        } else if same_tree && bump {
+            format!("[{}: {}]\n", branch_key, config.ci_bump_message).into()
+        } else if same_tree && config.ci_skip_token.is_empty() {
+            format!("[{}: no-op]\n", branch_key).into()
+        } else if same_tree {
+            format!("[{}: no-op] {}\n", branch_key, config.ci_skip_token).into()
+        } else {
+            let round = if config.update_template.uses_round() {
+                review_round(git, config, &branch_key, &remote_diffbases[0], &merge_head)?
+            } else {
+                0
+            };
+            let message: Cow<'_, str> = if edit {
+                update_message::edit(
+                    git,
+                    &target_branch,
+                    message.unwrap_or(update_message::DEFAULT_MESSAGE),
+                    &base_commit.oid,
+                    source_oid,
+                )?
+                .into()
+            } else {
+                message.unwrap_or(update_message::DEFAULT_MESSAGE).into()
+            };
+            let subject = config.update_template.expand(&update_message::Vars {
+                branch: &branch_key,
+                message: &message,
+                source: source_oid,
+                round,
+            });
+            format!("{}\n", subject).into()
+        };
+        let msg: Cow<'_, str> = match position {
+            Some(position) => format!("{} {}", position, msg).into(),
+            None => msg,
+        };
+        let msg = trailers::carry(
+            &source_commit.message,
+            &msg,
+            &config.pass_through_trailers,
+            &config.trailer_separators,
+        );
+        let mut interpret_trailers = git.command();
+        interpret_trailers.args([
+            "interpret-trailers",
+            "--no-divider",
+            "--where",
+            "end",
+            "--if-exists",
+            "replace",
+            "--trailer",
+            &format!("{}: {}", config.branch_trailer, branch_key),
+            "--trailer",
+            &format!("{}: {}", config.source_trailer, source_oid),
+        ]);
+        if let Some(ident) = signoff {
+            // Keep any sign-offs already in the source message, as `git commit --signoff` does.
+            interpret_trailers.args([
+                "--if-exists",
+                "addIfDifferent",
+                "--trailer",
+                &format!("{}: {}", SIGNOFF_TRAILER, ident),
+            ]);
+        }
+        let mut interpret_trailers_child = interpret_trailers
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .logged_spawn()
+            .context(committing)?;
+        use std::io::Write;
+        interpret_trailers_child
+            .stdin
+            .take()
+            .expect("interpret-trailers stdin")
+            .write_all(msg.as_bytes())
+            .context(committing)?;
+        let out = cancel::wait_with_output(interpret_trailers_child).context(committing)?;
+        err::from_git(&out, || "failed to add trailers".to_string()).context(committing)?;
+        let mut msg = out.stdout;
+        if verify {
+            msg = verify_update(git, &source_commit.tree, msg, &target_branch)?;
+        }
+        // Attribute the change to the source commit's author, not whoever runs the integration.
+        let mut commit_tree = git.command();
+        source_commit.author.set_author_env(&mut commit_tree);
+        let mut commit_tree_child = commit_tree
+            .args([
+                "commit-tree",
+                gpg_sign_arg(sign),
+                &source_commit.tree,
+                "-p",
+                "HEAD",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .logged_spawn()
+            .context(committing)?;
+        commit_tree_child
+            .stdin
+            .take()
+            .expect("commit-tree stdin")
+            .write_all(&msg)
+            .context(committing)?;
+        let out = cancel::wait_with_output(commit_tree_child).context(committing)?;
+        let result: git::Oid = git::parse_oid(out.stdout)
+            .map_err(|buf| {
+                err::Error::GitContract(format!(
+                    "commit-tree gave bad output: {:?}",
+                    String::from_utf8_lossy(&buf),
+                ))
+            })
+            .and_then(|oid| oid.parse())
+            .context(committing)?;
+        let out = git
+            .command()
+            .args(["checkout", "--detach", &result])
+            .logged_output()
+            .context(committing)?;
+        err::from_git(&out, || "failed to commit merge".to_string()).context(committing)?;
+        git.invalidate_refs();
+        result
+    };
+    step.finish();
+
+    Ok(Integration {
+        remote_commit,
+        target_branch,
+        remote,
+        new_branch,
+        merge_head,
+        remote_diffbases,
+        diffbase_merge,
+        conflicts,
+        discarded_paths,
+        size,
+    })
+}
+
+/// Commit the merge in progress, with the message that Git prepared for it.
+fn commit_merge(git: &GitStore, sign: bool) -> err::Result<()> {
+    let out = git
+        .command()
+        .args(["commit", "--no-edit", "--no-verify", gpg_sign_arg(sign)])
+        .logged_output()?;
+    err::from_git(&out, || "failed to commit merge".to_string())
+}
+
+/// Run the `pre-commit` and `commit-msg` hooks for an update commit to `target_branch` with the
+/// given tree and message, as `git commit` would, returning the message as the hooks leave it.
+/// The index and worktree are set to the tree for the hooks to inspect.
+fn verify_update(
+    git: &GitStore,
+    tree: &str,
+    msg: Vec<u8>,
+    target_branch: &str,
+) -> err::Result<Vec<u8>> {
+    let out = git
+        .command()
+        .args(["read-tree", "--reset", "-u", tree])
+        .logged_output()?;
+    err::from_git(&out, || format!("failed to check out tree {}", tree))?;
+    hooks::run_git_hook(git, "pre-commit", &[], target_branch)?;
+    let msg_path = git.git_path("DX_COMMIT_EDITMSG")?;
+    std::fs::write(&msg_path, msg)?;
+    let verified = hooks::run_git_hook(git, "commit-msg", &[msg_path.as_os_str()], target_branch)
+        .and_then(|()| Ok(std::fs::read(&msg_path)?));
+    let _ = std::fs::remove_file(&msg_path);
+    verified
+}
+
+/// Find the review round that an update to the target branch of `key` would start: one
+/// more than the number of commits already pushed to the branch for this change (its first commit
+/// and each update), not counting merges or commits that came in from `remote_diffbase`.
+fn review_round(
+    git: &mut GitStore,
+    config: &Config,
+    key: &str,
+    remote_diffbase: &str,
+    merge_head: &str,
+) -> err::Result<usize> {
+    let mut rounds = 1;
+    for oid in git.rev_list_no_merges(remote_diffbase, merge_head)? {
+        let commit = git.commit(&oid)?;
+        // Commits of landed diffbases may remain, under their own keys.
+        if let Ok(Some(commit_key)) = branch_key(config, &commit.oid, &commit.trailers) {
+            if commit_key == key {
+                rounds += 1;
+            }
+        }
+    }
+    Ok(rounds)
+}
+
+/// Find what to measure a source commit with the given parents against: its parent or, for a merge
+/// of two parents that merge cleanly, the tree of that merge, so that changes brought in from the
+/// other parent don't count toward the change. Other merges, and all merges with a Git too old for
+/// `merge-tree --write-tree`, are measured against their first parent, and a root commit against
+/// the empty tree.
+fn size_base(git: &GitStore, parents: &[git::Oid]) -> err::Result<git::Oid> {
+    match parents {
+        [] => git.empty_tree(),
+        [ours, theirs] if git.version()? >= plan::MERGE_TREE_VERSION => {
+            match git.merge_tree(ours, theirs)? {
+                git::MergeTree::Clean(tree) => Ok(tree),
+                git::MergeTree::Conflicts(_) => Ok(ours.clone()),
+            }
+        }
+        [first, ..] => Ok(first.clone()),
+    }
+}
+
+/// Find the remote diffbase for a source commit with no parents: the head of the remote trunk, if
+/// there is one yet, else an empty root commit. The empty commit is made from the source commit's
+/// author and the branch key alone, so that each integration of the change finds the same one.
+fn root_diffbase(
+    git: &mut GitStore,
+    remotes: &Remotes,
+    source_commit: &git::Commit,
+    key: &str,
+) -> err::Result<git::Oid> {
+    if let Ok(trunk) = default_trunk(git, remotes.read) {
+        if let Some(oid) = remote_branch_oid(git, remotes.read, &trunk)? {
+            return Ok(oid);
+        }
+    }
+    let tree = git.empty_tree()?;
+    let mut cmd = git.command();
+    source_commit.author.set_author_env(&mut cmd);
+    source_commit.author.set_committer_env(&mut cmd);
+    let out = cmd
+        .args(["commit-tree", "--no-gpg-sign", &tree, "-m"])
+        .arg(format!("[{}: empty diffbase]", key))
+        .logged_output()?;
+    err::from_git(&out, || "failed to create empty diffbase".to_string())?;
+    git::parse_oid(out.stdout)
+        .map_err(|buf| {
+            err::Error::GitContract(format!(
+                "commit-tree gave bad output: {:?}",
+                String::from_utf8_lossy(&buf),
+            ))
+        })?
+        .parse()
+}
+
+/// Find the remote diffbase for a source commit whose parent is `local_diffbase`: the head of the
+/// local diffbase's remote target branch, if it has one, else the local diffbase itself.
+///
+/// If that branch is already contained in trunk, then its change has landed (e.g., by `git dx
+/// land` in another clone) and the local diffbase is a stale copy of it. Such changes are skipped
+/// with a warning, following the parent chain to the nearest change that has not landed. If there
+/// is none, the trunk stands in for the landed changes.
+fn remote_diffbase(
+    git: &mut GitStore,
+    config: &Config,
+    remotes: &Remotes,
+    local_diffbase: &git::Commit,
+) -> err::Result<git::Oid> {
+    // Looked up only once a diffbase has a remote branch; `None` if the trunk can't be found.
+    let mut trunk: Option<Option<(String, git::Oid)>> = None;
+    let mut landed = None;
+    let mut commit = local_diffbase.clone();
+    while let Some(name) = branch_name(git, config, &commit.oid, &commit.trailers)? {
+        let tip = match remotes
+            .diffbase_oid(git, &name)
+            .context(|| format!("finding remote diffbase {}", name))?
+        {
+            Some(tip) => tip,
+            None => break,
+        };
+        if trunk.is_none() {
+            trunk = Some(match default_trunk(git, remotes.read) {
+                Ok(name) => remote_branch_oid(git, remotes.read, &name)?.map(|oid| (name, oid)),
+                Err(_) => None, // best effort: just don't skip anything
+            });
+        }
+        let (trunk_name, trunk_tip) = match trunk {
+            Some(Some(ref trunk)) if git.is_ancestor(&tip, &trunk.1)? => trunk,
+            _ => return Ok(tip),
+        };
+        warn!(
+            "diffbase {} has already landed on {}/{}; skipping its local copy {}",
+            name, remotes.read, trunk_name, commit.oid
+        );
+        info!(
+            "hint: to drop the local copy, run `git rebase --onto {0}~ {0}`",
+            commit.oid
+        );
+        landed = Some(trunk_tip.clone());
+        match commit.parents.first() {
+            Some(parent) => commit = git.commit(parent)?.clone(),
+            None => break,
+        }
+    }
+    Ok(landed.unwrap_or_else(|| local_diffbase.oid.clone()))
+}
+
+/// Whether to sign the commits that git-dx creates: as given explicitly (e.g., by `--sign`), or
+/// else as by `commit.gpgSign`. The plumbing that git-dx uses to create commits doesn't read
+/// `commit.gpgSign` itself.
+fn sign_commits(git: &GitStore, explicit: Option<bool>) -> err::Result<bool> {
+    match explicit {
+        Some(sign) => Ok(sign),
+        None => Ok(git.config_get_bool("commit.gpgSign")?.unwrap_or(false)),
+    }
+}
+
+/// Variable of a target branch's `branch.<name>` config section naming the remote that holds it.
+const BRANCH_REMOTE_CONFIG: &str = "dxRemote";
+
+/// Trailer key of the sign-off that `--signoff` adds.
+const SIGNOFF_TRAILER: &str = "Signed-off-by";
+
+/// The option that makes `git commit`, `git merge`, or `git commit-tree` sign (or not sign) the
+/// commit that it creates, with the key from `user.signingKey` in the format from `gpg.format`.
+fn gpg_sign_arg(sign: bool) -> &'static str {
+    if sign {
+        "--gpg-sign"
+    } else {
+        "--no-gpg-sign"
+    }
+}
+
+/// Parse the trailers of a commit message, in order, ignoring pass-through lines (see
+/// `trailers::carry`), which would otherwise hide the trailer block that they are in.
+fn trailers(config: &Config, message: &str) -> Vec<(String, String)> {
+    config.trailer_syntax().parse(message)
+}
+
+#[derive(Debug)]
+enum TrailerMatch<'a> {
+    Missing { key: &'a str },
+    Duplicate { key: &'a str },
+    Unique { key: &'a str, value: &'a str },
+}
+
+impl<'a> TrailerMatch<'a> {
+    fn plus(self, value: &'a str) -> Self {
+        use TrailerMatch::{Duplicate, Missing, Unique};
+        match self {
+            Missing { key } => Unique { key, value },
+            Unique { key, .. } => Duplicate { key },
+            Duplicate { .. } => self,
+        }
+    }
+    fn unique(self, oid: &str) -> err::Result<&'a str> {
+        match self {
+            TrailerMatch::Unique { value, .. } => Ok(value),
+            TrailerMatch::Missing { key } => Err(err::Error::MissingTrailer {
+                oid: oid.to_string(),
+                key: key.to_string(),
+            }),
+            TrailerMatch::Duplicate { key } => Err(err::Error::DuplicateTrailer {
+                oid: oid.to_string(),
+                key: key.to_string(),
+            }),
+        }
+    }
+    fn is_duplicate(&self) -> bool {
+        matches!(self, TrailerMatch::Duplicate { .. })
+    }
+}
+
+fn look_up_trailer<'a>(key: &'a str, trailers: &'a [(String, String)]) -> TrailerMatch<'a> {
+    let mut found = TrailerMatch::Missing { key };
+    for (k, v) in trailers {
+        if k == key {
+            found = found.plus(v);
+            if found.is_duplicate() {
+                return found;
+            }
+        }
+    }
+    found
+}
+
+/// Find the branch key given by the branch directive among the trailers of a commit message (as
+/// by `trailers`), if any.
+fn branch_key(
+    config: &Config,
+    oid: &str,
+    trailers: &[(String, String)],
+) -> err::Result<Option<String>> {
+    match look_up_trailer(&config.branch_trailer, trailers).unique(oid) {
+        Ok(v) => Ok(Some(v.to_string())),
+        Err(err::Error::MissingTrailer { .. }) => Ok(None),
+        Err(other) => Err(other), // duplicate trailer
+    }
+}
+
+/// Find the target branch name given by the branch directive among the trailers of a commit
+/// message, if any. Fails with `InvalidBranchName` if the directive does not yield a valid branch
+/// name.
+fn branch_name(
+    git: &GitStore,
+    config: &Config,
+    oid: &str,
+    trailers: &[(String, String)],
+) -> err::Result<Option<String>> {
+    match branch_key(config, oid, trailers)? {
+        Some(key) => Ok(Some(valid_target_branch(git, config, oid, &key)?)),
+        None => Ok(None),
+    }
+}
+
+/// Form the target branch name for the branch key of the commit `oid`, failing with
+/// `InvalidBranchName` if it is not a valid branch name (e.g., it contains spaces or `..`).
+fn valid_target_branch(
+    git: &GitStore,
+    config: &Config,
+    oid: &str,
+    key: &str,
+) -> err::Result<String> {
+    let branch = target_branch(git, config, key)?;
+    if !git.check_ref_format(&branch)? {
+        return Err(err::Error::InvalidBranchName {
+            oid: oid.to_string(),
+            branch,
+        });
+    }
+    Ok(branch)
+}
+
+/// Form the target branch name for a branch key. Under a ref template with `{date}`, a change keeps
+/// the branch that it was first integrated to: the latest remote branch whose name matches the key
+/// is reused, and only a change with no such branch gets today's date.
+fn target_branch(git: &GitStore, config: &Config, key: &str) -> err::Result<String> {
+    if config.ref_template.is_dated() {
+        let existing = git
+            .remote_branches()?
+            .into_iter()
+            .filter(|branch| config.branch_key_of(branch) == Some(key))
+            .max();
+        if let Some(branch) = existing {
+            return Ok(branch);
+        }
+    }
+    Ok(config.target_branch(key))
+}
+
+/// Find the source commit recorded by the source directive among the trailers of a commit created
+/// by integration, if any.
+fn recorded_source(
+    config: &Config,
+    oid: &str,
+    trailers: &[(String, String)],
+) -> err::Result<Option<String>> {
+    match look_up_trailer(&config.source_trailer, trailers).unique(oid) {
+        Ok(v) => Ok(Some(v.to_string())),
+        Err(err::Error::MissingTrailer { .. }) => Ok(None),
+        Err(other) => Err(other), // duplicate trailer
+    }
+}
+
+/// Find the commits on a remote target branch at `tip` that git-dx did not make, newest first: the
+/// first-parent ancestors of `tip` back to the newest one that git-dx made, that is one of `known`
+/// (as the commit that git-dx last pushed or pulled), or that is already in one of `bases`.
+/// Commits with a source or branch directive count as made by git-dx, since a source commit pushed
+/// as is has only the latter. Empty commits are left out.
+fn foreign_commits(
+    git: &mut git::GitStore,
+    config: &Config,
+    tip: &str,
+    known: &[&str],
+    bases: &[git::Oid],
+) -> err::Result<Vec<git::Commit>> {
+    let mut foreign = Vec::new();
+    let mut commit = git.commit(tip)?.clone();
+    loop {
+        let mut found = known.contains(&commit.oid.as_str())
+            || recorded_source(config, &commit.oid, &commit.trailers)?.is_some()
+            || branch_key(config, &commit.oid, &commit.trailers)?.is_some();
+        for base in bases {
+            found = found || git.is_ancestor(&commit.oid, base)?;
+        }
+        if found {
+            return Ok(foreign);
+        }
+        let parent = match commit.parents.first() {
+            Some(parent) => Some(git.commit(parent)?.clone()),
+            None => None,
+        };
+        // An empty commit has no changes for integration to undo.
+        if parent
+            .as_ref()
+            .is_none_or(|parent| parent.tree != commit.tree)
+        {
+            foreign.push(commit);
+        }
+        match parent {
+            Some(parent) => commit = parent,
+            None => return Ok(foreign),
+        }
+    }
+}
+
+/// Find the pull request labels listed by the labels trailers of a commit message. Labels are
+/// comma-separated, and the trailer may be repeated.
+fn labels(config: &Config, msg: &str) -> err::Result<Vec<String>> {
+    let mut result = Vec::new();
+    for (key, value) in trailers(config, msg) {
+        if key == config.labels_trailer {
+            result.extend(
+                value
+                    .split(',')
+                    .map(|label| label.trim())
+                    .filter(|label| !label.is_empty())
+                    .map(|label| label.to_string()),
+            );
+        }
+    }
+    Ok(result)
+}
+
+/// Find the pull request milestone named by the milestone trailer of a commit message, if any.
+fn milestone(config: &Config, oid: &str, msg: &str) -> err::Result<Option<String>> {
+    let all_trailers = trailers(config, msg);
+    match look_up_trailer(&config.milestone_trailer, &all_trailers).unique(oid) {
+        Ok(v) => Ok(Some(v.to_string())),
+        Err(err::Error::MissingTrailer { .. }) => Ok(None),
+        Err(other) => Err(other), // duplicate trailer
+    }
+}
+
+/// Determine the remote to use: the one given on the command line, else the `dx.remote` config
+/// option (as set by `git dx init`), else `origin`.
+fn remote_name(git: &GitStore, explicit: Option<&str>) -> err::Result<String> {
+    if let Some(remote) = explicit {
+        return Ok(remote.to_string());
+    }
+    Ok(git
+        .config_get("dx.remote")?
+        .unwrap_or_else(|| "origin".to_string()))
+}
+
+/// The remotes to push integrated commits to besides `primary`: the rest of those given with
+/// `-r`, if any, or else those listed in `dx.pushRemotes` (multi-valued, or separated by spaces).
+fn secondary_remotes<'a>(
+    git: &GitStore,
+    primary: &str,
+    explicit: Option<impl Iterator<Item = &'a str>>,
+) -> err::Result<Vec<String>> {
+    let listed: Vec<String> = match explicit {
+        Some(values) => values.skip(1).map(|remote| remote.to_string()).collect(),
+        None => git
+            .config_get_all("dx.pushRemotes")?
+            .iter()
+            .flat_map(|value| value.split_whitespace())
+            .map(|remote| remote.to_string())
+            .collect(),
+    };
+    let mut result: Vec<String> = Vec::new();
+    for remote in listed {
+        if remote != primary && !result.contains(&remote) {
+            result.push(remote);
+        }
+    }
+    Ok(result)
+}
+
+/// Determine the trunk branch: the `dx.trunk` config option (as set by `git dx init`) if set,
+/// else the branch pointed to by the remote-tracking `HEAD` of `remote`.
+fn default_trunk(git: &GitStore, remote: &str) -> err::Result<String> {
+    if let Some(trunk) = git.config_get("dx.trunk")? {
+        return Ok(trunk);
+    }
+    remote_head_branch(git, remote)
+}
+
+/// Determine the trunk branch of `remote` from its remote-tracking `HEAD`, as set by `git clone`
+/// or `git remote set-head`. If there is no remote-tracking `HEAD`, ask the remote directly and
+/// suggest setting one.
+fn remote_head_branch(git: &GitStore, remote: &str) -> err::Result<String> {
+    let prefix = format!("refs/remotes/{}/", remote);
+    if let Some(target) = git.symbolic_ref(&format!("{}HEAD", prefix))? {
+        if let Some(branch) = target.strip_prefix(&prefix) {
+            return Ok(branch.to_string());
+        }
+    }
+    let unknown = || err::Error::UnknownTrunk {
+        remote: remote.to_string(),
+    };
+    let target = git.ls_remote_head(remote)?.ok_or_else(unknown)?;
+    let branch = target.strip_prefix("refs/heads/").ok_or_else(unknown)?;
+    static HINTED: std::sync::Once = std::sync::Once::new();
+    HINTED.call_once(|| {
+        info!(
+            "hint: {} has no remote-tracking HEAD, so git-dx asked the remote directly; \
+             to avoid this, run `git remote set-head {} --auto`",
+            remote, remote
+        );
+    });
+    Ok(branch.to_string())
+}
+
+/// Find the head of `branch` on `remote`. This reads the remote-tracking ref if the remote's
+/// fetch refspecs keep it up to date. If they don't (e.g., in a clone with `--single-branch`, or
+/// with a refspec that fetches the branch somewhere else), the remote is queried directly, once
+/// per run, and the branch is fetched into `FETCH_HEAD` so that its objects are available.
+fn remote_branch_oid(
+    git: &mut git::GitStore,
+    remote: &str,
+    branch: &str,
+) -> err::Result<Option<git::Oid>> {
+    let refname = format!("refs/heads/{}", branch);
+    let tracking = format!("refs/remotes/{}/{}", remote, branch);
+    if is_fetched(git, remote, &refname, &tracking)? {
+        // A missing remote-tracking ref means that the branch doesn't exist (as of the last fetch).
+        return git.rev_parse(&tracking);
+    }
+    let oid = match git.remote_head(remote, branch)? {
+        Some(oid) => oid,
+        None => return Ok(None),
+    };
+    if git.rev_parse_commit(&oid)?.is_none() {
+        git.fetch_ref(remote, &refname)?;
+    }
+    Ok(Some(oid))
+}
+
+/// Check whether `git fetch <remote>` would update the remote-tracking ref `tracking` from
+/// `refname`, according to the `remote.<remote>.fetch` refspecs.
+fn is_fetched(git: &GitStore, remote: &str, refname: &str, tracking: &str) -> err::Result<bool> {
+    let mut fetched = false;
+    for refspec in git.config_get_all(&format!("remote.{}.fetch", remote))? {
+        if let Some(refspec) = refspec.strip_prefix('^') {
+            if refspec_match(refspec, refname).is_some() {
+                return Ok(false);
+            }
+            continue;
+        }
+        let mut parts = refspec.trim_start_matches('+').splitn(2, ':');
+        let src = parts.next().unwrap_or("");
+        let dst = parts.next().unwrap_or("");
+        fetched |= refspec_match(src, refname).map(|matched| dst.replacen('*', matched, 1))
+            == Some(tracking.to_string());
+    }
+    Ok(fetched)
+}
+
+/// Match `refname` against the source side `src` of a refspec, returning the part that its `*`
+/// matched (or the empty string if it has no `*`), or `None` if it doesn't match.
+fn refspec_match<'a>(src: &str, refname: &'a str) -> Option<&'a str> {
+    match src.find('*') {
+        Some(i) => {
+            let (prefix, suffix) = (&src[..i], &src[i + 1..]);
+            if refname.len() > prefix.len() + suffix.len()
+                && refname.starts_with(prefix)
+                && refname.ends_with(suffix)
+            {
+                Some(&refname[prefix.len()..refname.len() - suffix.len()])
+            } else {
+                None
+            }
+        }
+        None if src == refname => Some(""),
+        None => None,
+    }
+}
+
+/// Find the head of the trunk branch on `remote`, failing if it does not exist.
+fn trunk_oid(git: &mut git::GitStore, remote: &str, trunk: &str) -> err::Result<git::Oid> {
+    remote_branch_oid(git, remote, trunk)?
+        .ok_or_else(|| err::Error::NoSuchCommit(format!("{}/{}", remote, trunk)))
+}
@@ -0,0 +1,104 @@
+//! Object reads done in-process with libgit2, avoiding a `git` subprocess per read. This matters
+//! most where process creation is slow, like on Windows or network filesystems.
+
+use std::path::Path;
+
+use crate::err;
+use crate::git::{Commit, Oid, Repository, Signature};
+
+pub struct Libgit2 {
+    repo: git2::Repository,
+}
+
+impl Libgit2 {
+    /// Open the repository containing `directory`, as `git -C directory` would find it.
+    pub fn open(directory: &Path) -> err::Result<Libgit2> {
+        let repo = git2::Repository::open_ext(
+            directory,
+            git2::RepositoryOpenFlags::FROM_ENV,
+            &[] as &[&std::ffi::OsStr],
+        )
+        .map_err(|e| contract("failed to open repository", e))?;
+        Ok(Libgit2 { repo })
+    }
+
+    fn tree(&self, rev: &str) -> err::Result<git2::Tree<'_>> {
+        self.repo
+            .revparse_single(rev)
+            .and_then(|object| object.peel_to_tree())
+            .map_err(|e| contract(&format!("failed to read tree of {}", rev), e))
+    }
+}
+
+fn oid(id: git2::Oid) -> err::Result<Oid> {
+    id.to_string().parse()
+}
+
+fn signature(sig: &git2::Signature) -> Signature {
+    let when = sig.when();
+    let offset = when.offset_minutes();
+    Signature {
+        name: String::from_utf8_lossy(sig.name_bytes()).into_owned(),
+        email: String::from_utf8_lossy(sig.email_bytes()).into_owned(),
+        date: format!(
+            "{} {}{:02}{:02}",
+            when.seconds(),
+            if offset < 0 { '-' } else { '+' },
+            offset.abs() / 60,
+            offset.abs() % 60
+        ),
+    }
+}
+
+fn contract(what: &str, e: git2::Error) -> err::Error {
+    err::Error::GitContract(format!("{}: {}", what, e.message()))
+}
+
+impl Repository for Libgit2 {
+    fn rev_parse(&self, rev: &str) -> err::Result<Option<Oid>> {
+        match self.repo.revparse_single(rev) {
+            Ok(object) => oid(object.id()).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn read_commit(&self, rev: &str) -> err::Result<Commit> {
+        let no_such_commit = || err::Error::NoSuchCommit(rev.to_string());
+        let object = self
+            .repo
+            .revparse_single(rev)
+            .map_err(|_| no_such_commit())?;
+        // Accept tags pointing at commits, but not trees or blobs.
+        let commit = object.peel_to_commit().map_err(|_| no_such_commit())?;
+        // libgit2 doesn't re-encode messages, so non-UTF-8 messages are rejected rather than
+        // converted as with `i18n.logOutputEncoding`.
+        let message = err::Error::require_utf8(commit.message_raw_bytes().to_vec(), rev)?;
+        let author = signature(&commit.author());
+        Ok(Commit {
+            oid: oid(commit.id())?,
+            parents: commit.parent_ids().map(oid).collect::<err::Result<_>>()?,
+            tree: oid(commit.tree_id())?,
+            message,
+            author,
+            trailers: Vec::new(),
+        })
+    }
+
+    fn diff_names(&self, a: &str, b: &str) -> err::Result<Vec<String>> {
+        let (a_tree, b_tree) = (self.tree(a)?, self.tree(b)?);
+        let diff = self
+            .repo
+            .diff_tree_to_tree(Some(&a_tree), Some(&b_tree), None)
+            .map_err(|e| contract(&format!("failed to diff {} and {}", a, b), e))?;
+        diff.deltas()
+            .map(|delta| {
+                let path = delta
+                    .new_file()
+                    .path_bytes()
+                    .or_else(|| delta.old_file().path_bytes())
+                    .unwrap_or_default();
+                err::Error::require_utf8(path.to_vec(), "diff-tree paths")
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,51 @@
+//! An advisory lock that keeps two runs of git-dx from checking out, merging, and committing in
+//! the same repository at once (e.g., one from a hook and one by hand), which would interleave
+//! disastrously. The lock is a file holding the process ID of its owner, created exclusively and
+//! removed when the `RepoLock` is dropped, so that it is released on every exit path that unwinds.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::err;
+use crate::git::GitStore;
+
+/// Lock file path, relative to the Git directory.
+const LOCK_PATH: &str = "dx.lock";
+
+pub struct RepoLock {
+    path: PathBuf,
+}
+
+impl RepoLock {
+    /// Take the lock, failing with `Error::Locked` if another run holds it.
+    pub fn acquire(git: &GitStore) -> err::Result<RepoLock> {
+        let path = git.git_path(LOCK_PATH)?;
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                let lock = RepoLock { path };
+                // Only informational, for the error message of a run that finds the lock held.
+                let _ = writeln!(file, "{}", std::process::id());
+                Ok(lock)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                let holder = std::fs::read_to_string(&path)
+                    .ok()
+                    .map(|pid| pid.trim().to_string())
+                    .filter(|pid| !pid.is_empty());
+                Err(err::Error::Locked {
+                    path: path.display().to_string(),
+                    holder,
+                })
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        // Best effort: nothing more can be done about a lock that can't be removed.
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,130 @@
+use std::path::PathBuf;
+use std::process::{Child, Command, Output};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Only results are printed (`-q`).
+pub const QUIET: i32 = -1;
+/// Progress messages, warnings, and hints are printed.
+pub const NORMAL: i32 = 0;
+/// Each `git` subcommand is echoed as it is run (`-v`).
+pub const VERBOSE: i32 = 1;
+/// Each `git` subcommand is echoed along with its duration and exit status (`-vv`).
+pub const VERY_VERBOSE: i32 = 2;
+
+static VERBOSITY: AtomicI32 = AtomicI32::new(NORMAL);
+
+pub fn set_verbosity(level: i32) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+pub fn enabled(level: i32) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= level
+}
+
+/// Where to save the transcript of this run, if anywhere, and what it holds so far.
+static TRANSCRIPT: Mutex<Option<(PathBuf, Vec<String>)>> = Mutex::new(None);
+
+/// Start keeping a transcript of this run, to be written to `path` by `save_transcript`. The
+/// transcript holds the command line, every message and `git` command regardless of verbosity,
+/// and any error, so that `git dx bugreport` can include it.
+pub fn start_transcript(path: PathBuf) {
+    let args: Vec<String> = std::env::args().collect();
+    let lines = vec![format!("$ {}", args.join(" "))];
+    *TRANSCRIPT.lock().unwrap() = Some((path, lines));
+}
+
+/// Add a line to the transcript, if one is being kept, without any color.
+pub fn record(line: String) {
+    if let Some((_, ref mut lines)) = *TRANSCRIPT.lock().unwrap() {
+        lines.push(crate::color::strip(&line));
+    }
+}
+
+/// Write the transcript, if one is being kept, replacing that of the previous run. This is best
+/// effort: failing to save it shouldn't fail the command.
+pub fn save_transcript() {
+    if let Some((ref path, ref lines)) = *TRANSCRIPT.lock().unwrap() {
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let _ = std::fs::write(path, lines.join("\n") + "\n");
+    }
+}
+
+/// Print a progress message to stderr, unless quiet. (Results, like integrated commit IDs, go to
+/// stdout via `println!` and are never filtered.)
+macro_rules! info {
+    ($($arg:tt)*) => {{
+        $crate::log::record(format!($($arg)*));
+        if $crate::log::enabled($crate::log::NORMAL) {
+            eprintln!($($arg)*);
+        }
+    }};
+}
+
+/// Print a warning to stderr, unless quiet.
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        $crate::log::record(format!("warning: {}", format_args!($($arg)*)));
+        if $crate::log::enabled($crate::log::NORMAL) {
+            eprintln!("{} {}", $crate::color::warning("warning:"), format_args!($($arg)*));
+        }
+    }};
+}
+
+/// Extension methods for running commands such that they are echoed when verbose.
+pub trait CommandExt {
+    /// Run a command to completion, as by `Command::output`, unless the run is cancelled.
+    fn logged_output(&mut self) -> std::io::Result<Output>;
+    /// Spawn a command, as by `Command::spawn`, unless the run is cancelled.
+    fn logged_spawn(&mut self) -> std::io::Result<Child>;
+}
+
+impl CommandExt for Command {
+    fn logged_output(&mut self) -> std::io::Result<Output> {
+        let command = format!("+ {}", describe(self));
+        if enabled(VERBOSE) {
+            eprintln!("{}", command);
+        }
+        let start = Instant::now();
+        let result = crate::cancel::output(self);
+        let status = match result {
+            Ok(ref out) => out.status.to_string(),
+            Err(ref e) => e.to_string(),
+        };
+        let outcome = format!("  ({:.1?}, {})", start.elapsed(), status);
+        if enabled(VERY_VERBOSE) {
+            eprintln!("{}", outcome);
+        }
+        record(command);
+        record(outcome);
+        result
+    }
+
+    fn logged_spawn(&mut self) -> std::io::Result<Child> {
+        let command = format!("+ {} &", describe(self));
+        if enabled(VERBOSE) {
+            eprintln!("{}", command);
+        }
+        record(command);
+        crate::cancel::check()?;
+        self.spawn()
+    }
+}
+
+/// Render a command line for display, quoting arguments that need it.
+fn describe(cmd: &Command) -> String {
+    let mut result = cmd.get_program().to_string_lossy().into_owned();
+    for arg in cmd.get_args() {
+        let arg = arg.to_string_lossy();
+        result.push(' ');
+        if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '\'') {
+            result.push_str(&format!("'{}'", arg.replace('\'', r"'\''")));
+        } else {
+            result.push_str(&arg);
+        }
+    }
+    result
+}
@@ -0,0 +1,148 @@
+//! A mapping between source commits, the integration commits made from them, target branches,
+//! and code review URLs, exported for tools that migrate review metadata between systems.
+
+use std::collections::BTreeMap;
+
+use crate::config::Config;
+use crate::err;
+use crate::git::GitStore;
+use crate::idempotency;
+
+pub const SUBCOMMAND: &str = "export-mapping";
+
+const CLI_ARG_OUTPUT: &str = "output";
+
+/// Version of the mapping file format. Bump this on incompatible changes.
+const FORMAT_VERSION: u32 = 1;
+
+/// Config option giving the review URL of a target branch, with `{branch}` replaced by the
+/// branch name (e.g., `https://github.com/owner/name/pulls?q=head:{branch}`).
+const REVIEW_URL_CONFIG: &str = "dx.reviewUrl";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Exports a JSON mapping between source commits, integration commits, and branches")
+        .arg(
+            clap::Arg::with_name(CLI_ARG_OUTPUT)
+                .help("File to write the mapping to (default: standard output)")
+                .value_name("file")
+                .long("--output")
+                .short("-o")
+                .takes_value(true),
+        )
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct Mapping {
+    version: u32,
+    /// Target branches, by name.
+    branches: BTreeMap<String, Branch>,
+    /// Source commits, by object ID.
+    sources: BTreeMap<String, Source>,
+    /// Integration commits, by object ID.
+    integrations: BTreeMap<String, Integration>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct Branch {
+    review_url: Option<String>,
+    /// Source commits integrated into this branch, oldest first.
+    sources: Vec<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct Source {
+    branch: String,
+    /// Integration commits made from this source commit, oldest first.
+    integrations: Vec<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Integration {
+    source: String,
+    branch: String,
+    /// Idempotency key of the integration that made this commit, from its note, if any.
+    idempotency_key: Option<String>,
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let mapping = build(git, config)?;
+    let json = serde_json::to_string_pretty(&mapping).expect("serialize to JSON");
+    match matches.value_of(CLI_ARG_OUTPUT) {
+        Some(path) => std::fs::write(path, format!("{}\n", json))?,
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+fn build(git: &GitStore, config: &Config) -> err::Result<Mapping> {
+    let review_url = git.config_get(REVIEW_URL_CONFIG)?;
+    let mut mapping = Mapping {
+        version: FORMAT_VERSION,
+        ..Mapping::default()
+    };
+    // Newest first, so reverse to list integrations and sources oldest first.
+    let mut records = git.log_all(
+        "%H%x01%(trailers:only,unfold)%x01%N",
+        idempotency::NOTES_REF,
+    )?;
+    records.reverse();
+    for record in records {
+        let mut fields = record.splitn(3, '\x01');
+        let (oid, trailers, note) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(oid), Some(trailers), Some(note)) => (oid, trailers, note),
+            _ => {
+                return Err(err::Error::GitContract(format!(
+                    "unexpected log record: {:?}",
+                    record
+                )))
+            }
+        };
+        let trailer = |key: &str| {
+            trailers
+                .lines()
+                .filter_map(|line| line.split_once(": "))
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.trim().to_string())
+        };
+        // Integration commits carry both a branch directive and a source directive.
+        let (key, source) = match (
+            trailer(&config.branch_trailer),
+            trailer(&config.source_trailer),
+        ) {
+            (Some(key), Some(source)) => (key, source),
+            _ => continue,
+        };
+        let branch = crate::target_branch(git, config, &key)?;
+        let idempotency_key = note
+            .lines()
+            .filter_map(|line| line.strip_prefix(idempotency::KEY_FIELD))
+            .find_map(|rest| rest.strip_prefix(": "))
+            .map(|key| key.to_string());
+
+        let branch_entry = mapping
+            .branches
+            .entry(branch.clone())
+            .or_insert_with(|| Branch {
+                review_url: review_url
+                    .as_ref()
+                    .map(|template| template.replace("{branch}", &branch)),
+                sources: Vec::new(),
+            });
+        if !branch_entry.sources.contains(&source) {
+            branch_entry.sources.push(source.clone());
+        }
+        let source_entry = mapping.sources.entry(source.clone()).or_default();
+        source_entry.branch = branch.clone();
+        source_entry.integrations.push(oid.to_string());
+        mapping.integrations.insert(
+            oid.to_string(),
+            Integration {
+                source,
+                branch,
+                idempotency_key,
+            },
+        );
+    }
+    Ok(mapping)
+}
@@ -0,0 +1,61 @@
+//! Some setups push to one remote and review on another that mirrors it. Replication can lag or
+//! fail silently, leaving reviewers looking at an old round, so `--verify-mirror` checks after
+//! pushing that the mirror has caught up, polling until it has or a timeout expires.
+
+use std::time::{Duration, Instant};
+
+use crate::err;
+use crate::git::GitStore;
+
+/// Time between the first polls of the mirror, which grows up to `MAX_POLL_INTERVAL`.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Wait until each of `branches`, as `(branch, commit)` pairs, points to its commit on `mirror`.
+/// Fails with `Error::MirrorMismatch` naming the branches that still don't after `timeout`.
+pub fn verify(
+    git: &GitStore,
+    mirror: &str,
+    branches: &[(&str, &str)],
+    timeout: Duration,
+) -> err::Result<()> {
+    let start = Instant::now();
+    let mut interval = POLL_INTERVAL;
+    let mut pending: Vec<(&str, &str)> = branches.to_vec();
+    loop {
+        let mut mismatched = Vec::new();
+        for &(branch, commit) in &pending {
+            let actual = git.ls_remote(mirror, &format!("refs/heads/{}", branch))?;
+            if actual.as_deref() != Some(commit) {
+                mismatched.push((branch, commit, actual));
+            }
+        }
+        if mismatched.is_empty() {
+            info!("{} is up to date", mirror);
+            return Ok(());
+        }
+        if start.elapsed() + interval >= timeout {
+            return Err(err::Error::MirrorMismatch {
+                mirror: mirror.to_string(),
+                branches: mismatched
+                    .into_iter()
+                    .map(|(branch, commit, actual)| match actual {
+                        Some(actual) => format!("{} is at {}, not {}", branch, actual, commit),
+                        None => format!("{} is missing", branch),
+                    })
+                    .collect(),
+            });
+        }
+        pending = mismatched
+            .into_iter()
+            .map(|(branch, commit, _)| (branch, commit))
+            .collect();
+        info!(
+            "waiting for {} to replicate {} branch(es)",
+            mirror,
+            pending.len()
+        );
+        std::thread::sleep(interval);
+        interval = std::cmp::min(interval * 3 / 2, MAX_POLL_INTERVAL);
+    }
+}
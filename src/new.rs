@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+
+use crate::config::Config;
+use crate::err;
+use crate::git::GitStore;
+use crate::log::CommandExt;
+
+pub const SUBCOMMAND: &str = "new";
+
+const CLI_ARG_TEMPLATE: &str = "template";
+const CLI_ARG_KEY: &str = "key";
+const CLI_ARG_NO_EDIT: &str = "no-edit";
+
+/// File at the top of the worktree that defines change templates, checked in so that a team can
+/// share them.
+const TEMPLATES_FILE: &str = ".gitdx.toml";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Starts a new change as an empty source commit from a template in .gitdx.toml")
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TEMPLATE)
+                .help("Name of the template, as in `[templates.<name>]`")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_KEY)
+                .help("Branch key of the new change")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_NO_EDIT)
+                .help("Commit the template's message without opening an editor")
+                .long("--no-edit"),
+        )
+}
+
+/// Contents of `.gitdx.toml`.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplatesFile {
+    #[serde(default)]
+    templates: BTreeMap<String, Template>,
+}
+
+/// A change template, like:
+///
+/// ```toml
+/// [templates.bugfix]
+/// subject = "fix: "
+/// body = "Describe the bug and how this fixes it."
+/// labels = ["bug"]
+/// reviewers = ["alice", "bob"]
+/// diffbase = "origin/master"
+/// trailers = { "Bug" = "", "Test-plan" = "" }
+/// ```
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Template {
+    /// Subject line skeleton.
+    #[serde(default)]
+    subject: String,
+    /// Message body skeleton.
+    #[serde(default)]
+    body: Option<String>,
+    /// Additional trailers required of changes made from this template, by key.
+    #[serde(default)]
+    trailers: BTreeMap<String, String>,
+    /// Pull request labels, written to the labels trailer.
+    #[serde(default)]
+    labels: Vec<String>,
+    /// Pull request reviewers, written to the reviewers trailer.
+    #[serde(default)]
+    reviewers: Vec<String>,
+    /// Commit to start the change on (default: `HEAD`).
+    #[serde(default)]
+    diffbase: Option<String>,
+}
+
+impl Template {
+    /// Render the commit message for a new change with the given branch key.
+    fn message(&self, config: &Config, key: &str) -> String {
+        let mut msg = format!("{}\n", self.subject);
+        if let Some(ref body) = self.body {
+            msg.push_str(&format!("\n{}\n", body.trim_end()));
+        }
+        let mut trailers: Vec<(&str, String)> = self
+            .trailers
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.clone()))
+            .collect();
+        if !self.labels.is_empty() {
+            trailers.push((&config.labels_trailer, self.labels.join(", ")));
+        }
+        if !self.reviewers.is_empty() {
+            trailers.push((&config.reviewers_trailer, self.reviewers.join(", ")));
+        }
+        trailers.push((&config.branch_trailer, key.to_string()));
+        msg.push('\n');
+        for (key, value) in trailers {
+            msg.push_str(&format!("{}: {}\n", key, value));
+        }
+        msg
+    }
+}
+
+/// Read the change templates of the worktree, if it defines any.
+fn load_templates(git: &GitStore) -> err::Result<TemplatesFile> {
+    let path = git.toplevel()?.join(TEMPLATES_FILE);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(TemplatesFile::default()),
+        Err(e) => return Err(e.into()),
+    };
+    toml::from_str(&contents).map_err(|e| err::Error::InvalidTemplates {
+        path: path.display().to_string(),
+        reason: e.to_string(),
+    })
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let name = matches.value_of(CLI_ARG_TEMPLATE).unwrap();
+    let key = matches.value_of(CLI_ARG_KEY).unwrap();
+    let mut file = load_templates(git)?;
+    let template = match file.templates.remove(name) {
+        Some(template) => template,
+        None => {
+            return Err(err::Error::UnknownTemplate {
+                name: name.to_string(),
+                available: file.templates.into_keys().collect(),
+            })
+        }
+    };
+    let head = git.rev_parse_commit_ok("HEAD")?;
+    crate::valid_target_branch(git, config, &head, key)?;
+
+    if let Some(ref diffbase) = template.diffbase {
+        let diffbase = git.rev_parse_commit_ok(diffbase)?;
+        if diffbase != head {
+            let out = git
+                .command()
+                .args(["checkout", "--detach", &diffbase])
+                .logged_output()?;
+            err::from_git(&out, || format!("failed to check out {}", diffbase))?;
+        }
+    }
+
+    let msg_path = git.git_path("DX_NEW_MSG")?;
+    std::fs::write(&msg_path, template.message(config, key))?;
+    let mut cmd = git.command();
+    cmd.args(["commit", "--allow-empty", "--cleanup=strip", "-F"])
+        .arg(&msg_path);
+    if !matches.is_present(CLI_ARG_NO_EDIT) {
+        cmd.arg("--edit");
+    }
+    let status = crate::cancel::wait(&mut cmd.logged_spawn()?);
+    let _ = std::fs::remove_file(&msg_path);
+    git.invalidate_refs();
+    if !status?.success() {
+        return Err(err::Error::GitContract(
+            "failed to commit new change".to_string(),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+//! Results of integration, in the format chosen with `--format` (or `--porcelain`). Each format
+//! is an `OutputSink`, picked once per invocation, so that call sites report what happened without
+//! caring how it is shown. Diagnostics go to stderr through `info!` and `warn!` regardless of
+//! format, so that `--quiet` silences them uniformly.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::budget;
+use crate::push::PushOutcome;
+use crate::resolve;
+
+pub const FORMAT_HUMAN: &str = "human";
+pub const FORMAT_JSON: &str = "json";
+pub const FORMAT_PORCELAIN: &str = "porcelain";
+
+/// The values of `--format`.
+pub const FORMATS: &[&str] = &[FORMAT_HUMAN, FORMAT_JSON, FORMAT_PORCELAIN];
+
+pub const PORCELAIN_V1: &str = "v1";
+
+/// The versions of `--porcelain`.
+pub const PORCELAIN_VERSIONS: &[&str] = &[PORCELAIN_V1];
+
+/// Result of an integration, as printed to stdout under `--format=json`.
+#[derive(serde::Serialize)]
+pub struct Report<'a> {
+    /// The source commit.
+    pub source: &'a str,
+    pub target_branch: &'a str,
+    /// The integrated commit, which is (or would be) the new head of the target branch.
+    pub remote_commit: &'a str,
+    /// Whether integration was skipped because the target branch was already integrated with the
+    /// given idempotency key.
+    pub already_integrated: bool,
+    /// Whether the target branch did not yet exist on the remote.
+    pub new_branch: bool,
+    /// Whether the diffbase merge incurred changes, creating an "update diffbase" commit.
+    pub diffbase_merged: bool,
+    /// Paths that conflicted in the diffbase merge, and how each was resolved.
+    pub conflicts: &'a [resolve::Conflict],
+    pub discarded_paths: &'a [String],
+    /// How big the change is, relative to its local diffbase (absent if integration was skipped).
+    pub size: Option<&'a budget::Size>,
+    /// Whether the remote commit was pushed (not just dry-run pushed).
+    pub pushed: bool,
+    /// Per-ref results of the push, if one was attempted.
+    pub push: Option<&'a PushOutcome>,
+    /// Results of pushing to each remote given after the first with `-r` (or in
+    /// `dx.pushRemotes`), once the target branch's own remote had been pushed to.
+    pub secondary_pushes: &'a [SecondaryPush<'a>],
+}
+
+/// Result of pushing the integrated commits to a secondary remote.
+#[derive(serde::Serialize)]
+pub struct SecondaryPush<'a> {
+    pub remote: &'a str,
+    /// Why the push failed, or `None` if it succeeded.
+    pub error: Option<String>,
+}
+
+/// Receives the results of a run of integration, in order: `integrated` once per source commit
+/// as soon as it is integrated (or found to be already integrated), then `pushed` if the changes
+/// were pushed, then `finished` once per integrated source commit with the full report.
+pub trait OutputSink {
+    fn integrated(&self, report: &Report);
+    fn pushed(&self, outcome: &PushOutcome);
+    fn finished(&self, report: &Report);
+}
+
+/// Pick the sink for a `--format` value.
+pub fn sink(format: &str) -> Box<dyn OutputSink> {
+    match format {
+        FORMAT_JSON => Box::new(Json),
+        FORMAT_PORCELAIN => Box::new(Porcelain),
+        _ => Box::new(Human),
+    }
+}
+
+/// Pick the sink for a `--porcelain` version, one of `PORCELAIN_VERSIONS`.
+pub fn porcelain(version: &str) -> Box<dyn OutputSink> {
+    // The only version so far.
+    debug_assert_eq!(version, PORCELAIN_V1);
+    Box::new(PorcelainV1)
+}
+
+/// Each integrated commit's ID on stdout as soon as it is made, for scripts like
+/// `git push origin "$(git dx)":wchargin-foo`, with progress on stderr.
+pub struct Human;
+
+impl OutputSink for Human {
+    fn integrated(&self, report: &Report) {
+        if report.already_integrated {
+            info!("already integrated with this idempotency key");
+        } else {
+            info!("successfully integrated");
+        }
+        println!("{}", report.remote_commit);
+    }
+
+    fn pushed(&self, outcome: &PushOutcome) {
+        outcome.print();
+    }
+
+    fn finished(&self, _report: &Report) {}
+}
+
+/// One JSON object per source commit on stdout, once all is done.
+pub struct Json;
+
+impl OutputSink for Json {
+    fn integrated(&self, report: &Report) {
+        // Skipped commits get no other report.
+        if report.already_integrated {
+            print_json(report);
+        }
+    }
+
+    fn pushed(&self, _outcome: &PushOutcome) {}
+
+    fn finished(&self, report: &Report) {
+        print_json(report);
+    }
+}
+
+/// One line per source commit on stdout, once all is done: the outcome (`already-integrated`,
+/// `integrated`, or `pushed`), source commit, target branch, and integrated commit, separated by
+/// tabs. Unlike the human format, this will not change between versions.
+pub struct Porcelain;
+
+impl Porcelain {
+    fn line(outcome: &str, report: &Report) -> String {
+        format!(
+            "{}\t{}\t{}\t{}",
+            outcome, report.source, report.target_branch, report.remote_commit
+        )
+    }
+
+    fn finished_line(report: &Report) -> String {
+        Porcelain::line(
+            if report.pushed {
+                "pushed"
+            } else {
+                "integrated"
+            },
+            report,
+        )
+    }
+}
+
+impl OutputSink for Porcelain {
+    fn integrated(&self, report: &Report) {
+        if report.already_integrated {
+            println!("{}", Porcelain::line("already-integrated", report));
+        }
+    }
+
+    fn pushed(&self, _outcome: &PushOutcome) {}
+
+    fn finished(&self, report: &Report) {
+        println!("{}", Porcelain::finished_line(report));
+    }
+}
+
+/// Under `--porcelain=v1`, a record of tagged lines per source commit on stdout, once all is done:
+/// `source`, `branch`, `remote-commit`, `already-integrated`, `merge-performed`, and `pushed`, in
+/// that order, each followed by a space and its value (`true` or `false` for the last three).
+/// Later versions may add tags, but will never change or remove these.
+pub struct PorcelainV1;
+
+impl PorcelainV1 {
+    fn print(report: &Report) {
+        println!("source {}", report.source);
+        println!("branch {}", report.target_branch);
+        println!("remote-commit {}", report.remote_commit);
+        println!("already-integrated {}", report.already_integrated);
+        println!("merge-performed {}", report.diffbase_merged);
+        println!("pushed {}", report.pushed);
+    }
+}
+
+impl OutputSink for PorcelainV1 {
+    fn integrated(&self, report: &Report) {
+        if report.already_integrated {
+            PorcelainV1::print(report);
+        }
+    }
+
+    fn pushed(&self, _outcome: &PushOutcome) {}
+
+    fn finished(&self, report: &Report) {
+        PorcelainV1::print(report);
+    }
+}
+
+/// The porcelain line of each source commit, kept rather than printed, for a caller that prints
+/// its own results once the run is done (as `git dx batch` does). Clones share their lines.
+#[derive(Clone, Default)]
+pub struct Collect(Rc<RefCell<Vec<String>>>);
+
+impl Collect {
+    /// Take the lines collected so far.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+impl OutputSink for Collect {
+    fn integrated(&self, report: &Report) {
+        if report.already_integrated {
+            let line = Porcelain::line("already-integrated", report);
+            self.0.borrow_mut().push(line);
+        }
+    }
+
+    fn pushed(&self, _outcome: &PushOutcome) {}
+
+    fn finished(&self, report: &Report) {
+        self.0.borrow_mut().push(Porcelain::finished_line(report));
+    }
+}
+
+fn print_json<T: serde::Serialize>(value: &T) {
+    // Only fails for non-string map keys and failing `Serialize` impls, neither of which we use.
+    println!(
+        "{}",
+        serde_json::to_string(value).expect("serialize to JSON")
+    );
+}
@@ -0,0 +1,169 @@
+use crate::config::Config;
+use crate::err::{self, ResultExt};
+use crate::git::{GitStore, MergeTree, Oid};
+
+pub const SUBCOMMAND: &str = "plan";
+
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_FORK: &str = "fork";
+const CLI_ARG_REMOTE: &str = "remote";
+
+/// `git merge-tree --write-tree`, which checks a merge for conflicts without a working tree.
+pub const MERGE_TREE_VERSION: (u32, u32) = (2, 38);
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Shows what integrating a source commit would do, without doing it")
+        .long_about(
+            "Shows what integrating a source commit would do, as of the last fetch, without \
+             creating any commits or changing what is checked out: the target branch, the \
+             commit that integration would build on, the remote diffbase and whether it would \
+             need to be merged in (and with what conflicts, with Git 2.38 or newer), and what \
+             commit integration would create.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Source commit to plan for")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote to plan integration with")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_FORK)
+                .help("Remote that holds integration branches, if not the `-r` remote")
+                .value_name("remote")
+                .long("--fork")
+                .takes_value(true),
+        )
+}
+
+/// How the remote diffbase would be brought into the target branch.
+enum DiffbaseMerge {
+    /// The target branch already contains the remote diffbase.
+    NotNeeded,
+    /// The target branch would fast-forward to the remote diffbase.
+    FastForward,
+    Clean(Oid),
+    Conflicts(Vec<String>),
+    /// A merge is needed, but this Git is too old to check it for conflicts.
+    Unchecked,
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let remotes = crate::Remotes::new(&remote, matches.value_of(CLI_ARG_FORK))
+        .per_branch(matches.is_present(CLI_ARG_REMOTE) || matches.is_present(CLI_ARG_FORK));
+    let source = git
+        .commit(matches.value_of(CLI_ARG_COMMIT).unwrap())?
+        .clone();
+    let key = crate::branch_key(config, &source.oid, &source.trailers)?.ok_or_else(|| {
+        err::Error::MissingTrailer {
+            oid: source.oid.to_string(),
+            key: config.branch_trailer.clone(),
+        }
+    })?;
+    let target_branch = crate::valid_target_branch(git, config, &source.oid, &key)?;
+    let remote_diffbase = match source.parents.first() {
+        Some(parent) => {
+            let local_diffbase = git
+                .commit(parent)
+                .context(|| format!("reading local diffbase of {}", source.oid))?
+                .clone();
+            crate::remote_diffbase(git, config, &remotes, &local_diffbase)?
+        }
+        // A root commit is integrated onto the trunk, or onto an empty commit if there is none.
+        None => crate::root_diffbase(git, &remotes, &source, &key)?,
+    };
+    let remote_tip = remotes.target_oid(git, &target_branch)?;
+    let merge_head = remote_tip
+        .clone()
+        .unwrap_or_else(|| remote_diffbase.clone());
+
+    println!("source: {}", source.oid);
+    match remote_tip {
+        Some(_) => println!("target branch: {}", target_branch),
+        None => println!("target branch: {} (new)", target_branch),
+    }
+    println!("merge head: {}", merge_head);
+    println!("remote diffbase: {}", remote_diffbase);
+
+    // The checks that would make integration fail before it changed anything.
+    let mut blocker = None;
+    if remote_tip.is_some() {
+        if git.merge_base(&merge_head, &remote_diffbase)?.is_none() {
+            blocker = Some(err::Error::UnrelatedHistories {
+                branch: target_branch.clone(),
+                remote_commit: merge_head.to_string(),
+                diffbase: remote_diffbase.to_string(),
+            });
+        }
+        let remote = remotes.target_remote(git, &target_branch)?;
+        let pushed_ref = crate::push::pushed_ref(&remote, &target_branch);
+        if let Some(pushed) = git.rev_parse(&pushed_ref)? {
+            if !git.is_ancestor(&pushed, &merge_head)? {
+                blocker = Some(err::Error::RemoteRewritten {
+                    remote,
+                    branch: target_branch.clone(),
+                    pushed: pushed.to_string(),
+                    actual: merge_head.to_string(),
+                });
+            }
+        }
+    }
+
+    let merge = if git.is_ancestor(&remote_diffbase, &merge_head)? {
+        DiffbaseMerge::NotNeeded
+    } else if git.is_ancestor(&merge_head, &remote_diffbase)? {
+        DiffbaseMerge::FastForward
+    } else if git.version()? >= MERGE_TREE_VERSION {
+        match git.merge_tree(&merge_head, &remote_diffbase)? {
+            MergeTree::Clean(tree) => DiffbaseMerge::Clean(tree),
+            MergeTree::Conflicts(paths) => DiffbaseMerge::Conflicts(paths),
+        }
+    } else {
+        DiffbaseMerge::Unchecked
+    };
+    // The tree that the source tree would be committed onto, if known.
+    let base_tree = match merge {
+        DiffbaseMerge::NotNeeded => {
+            println!("diffbase merge: not needed");
+            Some(git.commit(&merge_head)?.tree.clone())
+        }
+        DiffbaseMerge::FastForward => {
+            println!("diffbase merge: fast-forward");
+            Some(git.commit(&remote_diffbase)?.tree.clone())
+        }
+        DiffbaseMerge::Clean(tree) => {
+            println!("diffbase merge: clean");
+            Some(tree)
+        }
+        DiffbaseMerge::Conflicts(ref paths) => {
+            println!("diffbase merge: conflicts in {}", paths.join(", "));
+            None
+        }
+        DiffbaseMerge::Unchecked => {
+            println!(
+                "diffbase merge: needed (Git {}.{} is needed to check it for conflicts)",
+                MERGE_TREE_VERSION.0, MERGE_TREE_VERSION.1
+            );
+            None
+        }
+    };
+
+    let action = if let Some(e) = blocker {
+        format!("none: integration would fail: {}", e)
+    } else if remote_tip.is_none() {
+        "create the branch with the source commit".to_string()
+    } else if base_tree.as_ref() == Some(&source.tree) {
+        "none: the trees already match".to_string()
+    } else {
+        "commit an update patch".to_string()
+    };
+    println!("action: {}", action);
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use crate::config::Config;
+use crate::err;
+use crate::forge::{self, PullRequest};
+use crate::git::{Commit, GitStore};
+
+pub const SUBCOMMAND: &str = "pr";
+
+/// Prefix of the refs that record the pull request for each target branch. Each points to a blob
+/// holding the pull request's number.
+pub const PULL_PREFIX: &str = "refs/dx/pull/";
+
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_DRAFT: &str = "draft";
+const CLI_ARG_FORK: &str = "fork";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_TRUNK: &str = "trunk";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Opens or updates the pull request for a source commit's target branch")
+        .long_about(
+            "Opens a pull request (on GitLab, a merge request) for a source commit's remote \
+             target branch, or updates \
+             the open one, so that it merges into the change's diffbase branch (or trunk, if \
+             the diffbase has no branch of its own). The target branch must already be pushed. \
+             The pull request's number is recorded under `refs/dx/pull/`, and its URL is \
+             printed.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Source commit whose target branch to open a pull request for")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DRAFT)
+                .help("Open a new pull request as a draft")
+                .long("--draft"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help("Remote branch that the stack is based on (default: the remote's HEAD)")
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote to open the pull request on")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_FORK)
+                .help("Remote that holds integration branches, if not the `-r` remote")
+                .value_name("remote")
+                .long("--fork")
+                .takes_value(true),
+        )
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let remotes = crate::Remotes::new(&remote, matches.value_of(CLI_ARG_FORK));
+    let source = git
+        .commit(matches.value_of(CLI_ARG_COMMIT).unwrap())?
+        .clone();
+    let branch =
+        crate::branch_name(git, config, &source.oid, &source.trailers)?.ok_or_else(|| {
+            err::Error::MissingTrailer {
+                oid: source.oid.to_string(),
+                key: config.branch_trailer.clone(),
+            }
+        })?;
+    if remotes.target_oid(git, &branch)?.is_none() {
+        return Err(err::Error::NoSuchCommit(format!(
+            "{}/{}",
+            remotes.write, branch
+        )));
+    }
+    let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+        Some(trunk) => trunk.to_string(),
+        None => crate::default_trunk(git, remotes.read)?,
+    };
+    let base = base_branch(git, config, remotes.read, &trunk, &source)?;
+    let trunk_tip = crate::trunk_oid(git, remotes.read, &trunk)?;
+    let head = git.head()?.to_string();
+    let stack = crate::stack::find(git, config, &trunk_tip, &head, &source)?.unwrap_or_default();
+    let forge = forge::for_remote(git, remotes.read)?
+        .ok_or_else(|| err::Error::NoForge(remotes.read.to_string()))?;
+    let head_owner = forge::head_owner(git, forge.as_ref(), &remotes)?;
+
+    let pull = match forge.find_pull_request(&head_owner, &branch)? {
+        Some(pull) => {
+            if pull.base != base {
+                forge.set_base(pull.number, &base)?;
+                info!("retargeted {} from {} to {}", pull.url, pull.base, base);
+            }
+            pull
+        }
+        None => {
+            let body = description(git, config, &source, &stack, None)?;
+            let pull = forge.create_pull_request(
+                &head_owner,
+                &branch,
+                &base,
+                title(&source),
+                &body,
+                matches.is_present(CLI_ARG_DRAFT),
+            )?;
+            info!("opened {} onto {}", pull.url, base);
+            pull
+        }
+    };
+    record(git, &branch, &pull)?;
+    println!("{}", pull.url);
+    Ok(())
+}
+
+/// Find the branch that the pull request for `source` should merge into: its diffbase's target
+/// branch, if that is on the upstream remote and has not landed, else the trunk. A pull request
+/// can't merge into a branch that exists only on a fork.
+fn base_branch(
+    git: &mut GitStore,
+    config: &Config,
+    remote: &str,
+    trunk: &str,
+    source: &Commit,
+) -> err::Result<String> {
+    let parent = match source.parents.first() {
+        Some(parent) => git.commit(parent)?.clone(),
+        None => return Ok(trunk.to_string()),
+    };
+    let diffbase = match crate::branch_name(git, config, &parent.oid, &parent.trailers)? {
+        Some(diffbase) => diffbase,
+        None => return Ok(trunk.to_string()),
+    };
+    let tip = match crate::remote_branch_oid(git, remote, &diffbase)? {
+        Some(tip) => tip,
+        None => return Ok(trunk.to_string()),
+    };
+    if let Some(trunk_tip) = crate::remote_branch_oid(git, remote, trunk)? {
+        if git.is_ancestor(&tip, &trunk_tip)? {
+            return Ok(trunk.to_string());
+        }
+    }
+    Ok(diffbase)
+}
+
+/// The title of the pull request for a source commit: its subject.
+fn title(commit: &Commit) -> &str {
+    commit.message.lines().next().unwrap_or_default().trim()
+}
+
+/// Form the description of the pull request for `source`: the body of its commit message, without
+/// trailers, then a footer that links the other pull requests of its `stack` (oldest first), if
+/// it has any. Changes without a recorded pull request are listed by target branch. The pull
+/// request itself is `this`, if it already exists.
+fn description(
+    git: &mut GitStore,
+    config: &Config,
+    source: &Commit,
+    stack: &[Commit],
+    this: Option<u64>,
+) -> err::Result<String> {
+    let message = crate::trailers::remove(
+        &source.message,
+        &config.pass_through_trailers,
+        &config.trailer_separators,
+    );
+    let message = crate::trailers::strip(&message, &config.trailer_separators);
+    let mut result = match message.split_once('\n') {
+        Some((_, body)) => body.trim().to_string(),
+        None => String::new(),
+    };
+    if stack.len() < 2 {
+        return Ok(result);
+    }
+    if !result.is_empty() {
+        result.push_str("\n\n");
+    }
+    result.push_str("---\n\nStack (oldest at bottom):\n");
+    for change in stack.iter().rev() {
+        let branch =
+            crate::branch_name(git, config, &change.oid, &change.trailers)?.unwrap_or_default();
+        let pull = if change.oid == source.oid {
+            this
+        } else {
+            recorded(git, &branch)?
+        };
+        let link = match pull {
+            Some(number) => format!("#{}", number),
+            None => format!("`{}`", branch),
+        };
+        if change.oid == source.oid {
+            result.push_str(&format!("- {} (this pull request)\n", link));
+        } else {
+            result.push_str(&format!("- {}\n", link));
+        }
+    }
+    Ok(result)
+}
+
+/// Replace the description of the open pull request for `target_branch` with one formed from
+/// `source` and its `stack`, as by `description`, if the upstream remote is on a known code host
+/// and such a pull request exists.
+pub fn sync_description(
+    git: &mut GitStore,
+    config: &Config,
+    source: &Commit,
+    stack: &[Commit],
+    remotes: &crate::Remotes,
+    target_branch: &str,
+) -> err::Result<()> {
+    let forge = match forge::for_remote(git, remotes.read)? {
+        Some(forge) => forge,
+        None => return Ok(()),
+    };
+    let head_owner = forge::head_owner(git, forge.as_ref(), remotes)?;
+    let pull = match forge.find_pull_request(&head_owner, target_branch)? {
+        Some(pull) => pull,
+        None => {
+            info!(
+                "no open pull request for {}; not updating its description",
+                target_branch
+            );
+            return Ok(());
+        }
+    };
+    record(git, target_branch, &pull)?;
+    let body = description(git, config, source, stack, Some(pull.number))?;
+    forge.set_body(pull.number, &body)?;
+    info!("updated description of {}", pull.url);
+    Ok(())
+}
+
+/// Record the pull request for a target branch under `PULL_PREFIX`.
+fn record(git: &GitStore, branch: &str, pull: &PullRequest) -> err::Result<()> {
+    let blob = git.write_blob(&format!("{}\n", pull.number))?;
+    git.update_ref(
+        &format!("{}{}", PULL_PREFIX, branch),
+        &blob,
+        &format!("git-dx: pull request {}", pull.url),
+    )
+}
+
+/// Read the number of the pull request recorded for a target branch, if any.
+pub fn recorded(git: &GitStore, branch: &str) -> err::Result<Option<u64>> {
+    let contents = git.read_blob(&format!("{}{}", PULL_PREFIX, branch))?;
+    Ok(contents.and_then(|contents| contents.trim().parse().ok()))
+}
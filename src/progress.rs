@@ -0,0 +1,113 @@
+//! Progress for runs that can take a while on large repositories: the name of each step of
+//! integration as it starts, and its duration when it finishes, along with the transfer progress
+//! of `git fetch` and `git push`. Shown when stderr is a terminal, unless quiet, or as
+//! `--progress` and `--no-progress` say. Transfer progress redraws its line with carriage
+//! returns, so it is left out when `terminal` says that output must be plain; the steps still
+//! show.
+
+use std::io::{Read, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crate::log::CommandExt;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TRANSFER: AtomicBool = AtomicBool::new(false);
+
+/// Decide whether to show progress: as `explicit` says, if given, or else only when stderr is a
+/// terminal and messages are not silenced with `--quiet`.
+pub fn set(explicit: Option<bool>) {
+    let enabled = explicit.unwrap_or_else(|| {
+        use std::io::IsTerminal;
+        crate::log::enabled(crate::log::NORMAL) && std::io::stderr().is_terminal()
+    });
+    ENABLED.store(enabled, Ordering::Relaxed);
+    TRANSFER.store(enabled && !crate::terminal::dumb(), Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Whether to show the transfer progress of `git fetch` and `git push` as it arrives.
+pub fn transfer_enabled() -> bool {
+    TRANSFER.load(Ordering::Relaxed)
+}
+
+/// A step of a run, reported when it starts and, with how long it took, when it finishes. A step
+/// that fails is dropped without finishing, since the error says enough.
+pub struct Step {
+    name: String,
+    start: Instant,
+}
+
+impl Step {
+    pub fn start(name: impl Into<String>) -> Step {
+        let name = name.into();
+        report(format!("{}...", name));
+        Step {
+            name,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn finish(self) {
+        report(format!(
+            "{}: done ({:.1}s)",
+            self.name,
+            self.start.elapsed().as_secs_f64()
+        ));
+    }
+}
+
+fn report(line: String) {
+    crate::log::record(line.clone());
+    if enabled() {
+        eprintln!("{}", line);
+    }
+}
+
+/// The flag to pass to `git fetch` or `git push`: `--progress` to force transfer progress even
+/// though its stderr is not a terminal (since we capture it), or else `--quiet`.
+pub fn transfer_flag() -> &'static str {
+    if transfer_enabled() {
+        "--progress"
+    } else {
+        "--quiet"
+    }
+}
+
+/// Run a command to completion, capturing its output as `logged_output` does. When transfer
+/// progress is shown, its stderr is also copied to ours as it arrives, so that it is live.
+pub fn output(cmd: &mut Command) -> std::io::Result<Output> {
+    if !transfer_enabled() {
+        return cmd.logged_output();
+    }
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged_spawn()?;
+    let stdout = crate::cancel::read_to_end(child.stdout.take().expect("piped stdout"));
+    let mut child_stderr = child.stderr.take().expect("piped stderr");
+    let stderr = std::thread::spawn(move || {
+        let mut stderr = Vec::new();
+        let mut chunk = [0; 4096];
+        loop {
+            let n = child_stderr.read(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            // Best effort: losing progress output shouldn't fail the command.
+            let _ = std::io::stderr().write_all(&chunk[..n]);
+            stderr.extend_from_slice(&chunk[..n]);
+        }
+        Ok::<_, std::io::Error>(stderr)
+    });
+    let status = crate::cancel::wait(&mut child)?;
+    Ok(Output {
+        status,
+        stdout: stdout.join().expect("stdout reader panicked")?,
+        stderr: stderr.join().expect("stderr reader panicked")?,
+    })
+}
@@ -0,0 +1,173 @@
+use std::io::Write;
+
+use crate::config::Config;
+use crate::err;
+use crate::forge;
+use crate::git::{GitStore, MergeTree, Oid};
+use crate::journal;
+
+pub const SUBCOMMAND: &str = "prune";
+
+const CLI_ARG_DRY_RUN: &str = "dry_run";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_TRUNK: &str = "trunk";
+const CLI_ARG_YES: &str = "yes";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Deletes remote target branches whose changes have landed")
+        .long_about(
+            "Deletes the dx branches on the remote, as of the last fetch, whose changes have \
+             landed: those contained in trunk, those whose tree trunk already has (as after a \
+             squash merge, with Git 2.38 or newer), and those whose pull request was merged at \
+             their current tip. Branches that unlanded branches are built on, or that open pull \
+             requests target, are kept. The branches to delete are listed and confirmed first, \
+             and their journal entries are deleted with them.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DRY_RUN)
+                .help("List the branches to delete and use a dry-run push only")
+                .long("--dry-run")
+                .short("-n"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_YES)
+                .help("Delete without asking for confirmation")
+                .long("--yes")
+                .short("-y"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help("Remote branch that changes land on (default: the remote's HEAD)")
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote that holds the branches")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(crate::push::push_option_arg())
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+        Some(trunk) => trunk.to_string(),
+        None => crate::default_trunk(git, &remote)?,
+    };
+    let trunk_tip = crate::trunk_oid(git, &remote, &trunk)?;
+    let trunk_tree = git.commit(&trunk_tip)?.tree.clone();
+    let merge_tree = git.version()? >= crate::plan::MERGE_TREE_VERSION;
+    let forge = forge::for_remote(git, &remote)?;
+    let dry_run = matches.is_present(CLI_ARG_DRY_RUN);
+
+    let mut landed: Vec<(String, Oid, String)> = Vec::new();
+    for branch in git.remote_branch_tips(&remote)? {
+        if branch.name == trunk || config.branch_key_of(&branch.name).is_none() {
+            continue;
+        }
+        let how = if git.is_ancestor(&branch.oid, &trunk_tip)? {
+            Some(format!("contained in {}", trunk))
+        } else if merge_tree
+            && git.merge_tree(&trunk_tip, &branch.oid)? == MergeTree::Clean(trunk_tree.clone())
+        {
+            Some(format!("tree already in {}", trunk))
+        } else {
+            match forge {
+                Some(ref forge) => forge
+                    .merged_head(forge.owner(), &branch.name)?
+                    .filter(|head| *head == branch.oid.as_str())
+                    .map(|_| "pull request merged".to_string()),
+                None => None,
+            }
+        };
+        if let Some(how) = how {
+            landed.push((branch.name, branch.oid, how));
+        }
+    }
+
+    // A landed branch is kept while something still builds on it: deleting it would leave the
+    // dependents' diffbases unresolvable.
+    let mut doomed: Vec<(String, Oid)> = Vec::new();
+    for (branch, tip, how) in &landed {
+        let mut blockers: Vec<String> =
+            crate::drop::dependents(git, config, &remote, &trunk_tip, branch)?
+                .into_iter()
+                .map(|(name, _)| name)
+                .filter(|name| !landed.iter().any(|(other, _, _)| other == name))
+                .collect();
+        if let Some(ref forge) = forge {
+            blockers.extend(
+                forge
+                    .pull_requests_onto(branch)?
+                    .into_iter()
+                    .map(|pull| pull.url),
+            );
+        }
+        if blockers.is_empty() {
+            println!("\t{}: {}", branch, how);
+            doomed.push((branch.clone(), tip.clone()));
+        } else {
+            info!(
+                "keeping {} ({}), since {} depend(s) on it",
+                branch,
+                how,
+                blockers.join(", ")
+            );
+        }
+    }
+    if doomed.is_empty() {
+        info!("nothing to prune on {}", remote);
+        return Ok(());
+    }
+
+    let action = format!("deleting {} branch(es) from {}", doomed.len(), remote);
+    if !dry_run && !matches.is_present(CLI_ARG_YES) && !confirm(&action)? {
+        return Err(err::Error::NotConfirmed(action));
+    }
+
+    let mut args = vec!["--atomic".to_string()];
+    if dry_run {
+        args.push("--dry-run".to_string());
+    }
+    for option in crate::push::push_options(config, matches) {
+        args.push(format!("--push-option={}", option));
+    }
+    // Only delete each branch if it still points to the commit that we checked.
+    for (branch, tip) in &doomed {
+        args.push(format!("--force-with-lease=refs/heads/{}:{}", branch, tip));
+    }
+    args.push(remote.clone());
+    for (branch, _) in &doomed {
+        args.push(format!(":refs/heads/{}", branch));
+    }
+    crate::push::push(git, &args)?.print();
+    git.invalidate_refs();
+    if dry_run {
+        return Ok(());
+    }
+    for (branch, _) in &doomed {
+        let entries = journal::remove(git, branch)?;
+        if entries > 0 {
+            info!("deleted {} journal entr(ies) for {}", entries, branch);
+        }
+    }
+    Ok(())
+}
+
+/// Ask on the terminal whether to go ahead with `action`. Without a terminal to ask on, the answer
+/// is no.
+fn confirm(action: &str) -> err::Result<bool> {
+    use std::io::IsTerminal;
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Ok(false);
+    }
+    eprint!("{}; continue? [y/N] ", action);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
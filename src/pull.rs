@@ -0,0 +1,256 @@
+use std::process::Stdio;
+
+use crate::config::Config;
+use crate::err;
+use crate::git::{Commit, GitStore, Head, Oid};
+use crate::log::CommandExt;
+
+pub const SUBCOMMAND: &str = "pull";
+
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_REMOTE: &str = "remote";
+
+/// Prefix of the local refs recording, for each remote target branch, the last commit on it whose
+/// changes `git dx pull` folded into its source commit: `refs/dx/pulled/<remote>/<branch>`.
+const PULLED_REFS_PREFIX: &str = "refs/dx/pulled/";
+
+/// The local ref recording the last commit on the given branch that `git dx pull` pulled from.
+pub fn pulled_ref(remote: &str, branch: &str) -> String {
+    format!("{}{}/{}", PULLED_REFS_PREFIX, remote, branch)
+}
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Folds changes pushed to a remote target branch back into the source commit")
+        .long_about(
+            "Folds changes that others pushed to a source commit's remote target branch, as of \
+             the last fetch, back into the source commit: the changes since the last commit \
+             that git-dx made on the branch are applied to the source commit's tree, the source \
+             commit is amended with the result, and the commits after it in the current stack \
+             are rebased onto it.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Source commit to amend; must be HEAD or one of its ancestors")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote that holds the target branch")
+                .short("-r")
+                .takes_value(true),
+        )
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let source = git
+        .commit(matches.value_of(CLI_ARG_COMMIT).unwrap())?
+        .clone();
+    let branch =
+        crate::branch_name(git, config, &source.oid, &source.trailers)?.ok_or_else(|| {
+            err::Error::MissingTrailer {
+                oid: source.oid.to_string(),
+                key: config.branch_trailer.clone(),
+            }
+        })?;
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let remotes = crate::Remotes::new(&remote, None).per_branch(matches.is_present(CLI_ARG_REMOTE));
+    let cannot_pull = |reason: String| err::Error::CannotPull {
+        branch: branch.clone(),
+        reason,
+    };
+    let tip = remotes
+        .target_oid(git, &branch)?
+        .ok_or_else(|| cannot_pull(format!("no such branch on {}", remote)))?;
+    if !git.is_ancestor(&source.oid, "HEAD")? {
+        return Err(cannot_pull(format!(
+            "source commit {} is not an ancestor of HEAD",
+            source.oid
+        )));
+    }
+    if crate::autostash::is_dirty(git)? {
+        return Err(cannot_pull(
+            "you have uncommitted changes; commit or stash them first".to_string(),
+        ));
+    }
+
+    // The commits on the branch since git-dx last integrated into it, newest first.
+    let mut pushed = Vec::new();
+    let mut commit = git.commit(&tip)?.clone();
+    let integrated = loop {
+        if crate::recorded_source(config, &commit.oid, &commit.trailers)?.is_some() {
+            break commit;
+        }
+        let parent = commit.parents.first().cloned();
+        pushed.push(commit);
+        match parent {
+            Some(parent) => commit = git.commit(&parent)?.clone(),
+            None => {
+                return Err(cannot_pull(
+                    "no commit on it was made by git-dx".to_string(),
+                ))
+            }
+        }
+    };
+    if pushed.is_empty() {
+        info!(
+            "nothing to pull: {} has no commits after {}, which git-dx made",
+            branch, integrated.oid
+        );
+        return Ok(());
+    }
+    for commit in &pushed {
+        info!(
+            "pulling {} {}",
+            commit.oid,
+            commit.message.lines().next().unwrap_or("")
+        );
+    }
+
+    let tree = apply(git, &source.tree, &integrated.oid, &tip).map_err(|e| match e {
+        ApplyError::Conflicts(paths) => cannot_pull(format!(
+            "the changes since {} conflict with {} in {}",
+            integrated.oid,
+            source.oid,
+            paths.join(", ")
+        )),
+        ApplyError::Other(e) => e,
+    })?;
+    // Either way, the changes are accounted for now, so integration may build on them.
+    let pulled_ref = pulled_ref(&remotes.target_remote(git, &branch)?, &branch);
+    let reason = format!("dx pull: {}", branch);
+    if tree == source.tree {
+        git.update_ref(&pulled_ref, &tip, &reason)?;
+        info!("{} already has the changes on {}", source.oid, branch);
+        return Ok(());
+    }
+    let sign = crate::sign_commits(git, None)?;
+    let amended =
+        crate::track::recommit(git, &source, &tree, &source.parents, &source.message, sign)?;
+    restack(git, &source, &amended)?;
+    git.update_ref(&pulled_ref, &tip, &reason)?;
+    info!(
+        "amended {} with {} commit(s) from {}",
+        source.oid,
+        pushed.len(),
+        branch
+    );
+    println!("{}", amended);
+    Ok(())
+}
+
+enum ApplyError {
+    /// The changes didn't apply cleanly, leaving these paths conflicted.
+    Conflicts(Vec<String>),
+    Other(err::Error),
+}
+
+impl From<err::Error> for ApplyError {
+    fn from(e: err::Error) -> Self {
+        ApplyError::Other(e)
+    }
+}
+
+impl From<std::io::Error> for ApplyError {
+    fn from(e: std::io::Error) -> Self {
+        ApplyError::Other(e.into())
+    }
+}
+
+/// Apply the changes from `from` to `to` onto `tree`, with a three-way merge for any that don't
+/// apply directly, and return the resulting tree. This uses an index of its own, so the working
+/// tree and the real index are left alone.
+fn apply(git: &GitStore, tree: &str, from: &str, to: &str) -> Result<Oid, ApplyError> {
+    let index = git.git_path("dx-pull-index")?;
+    let result = apply_in(git, &index, tree, from, to);
+    // Best effort: a stale index is rewritten on the next pull anyway.
+    let _ = std::fs::remove_file(&index);
+    result
+}
+
+fn apply_in(
+    git: &GitStore,
+    index: &std::path::Path,
+    tree: &str,
+    from: &str,
+    to: &str,
+) -> Result<Oid, ApplyError> {
+    let indexed = || {
+        let mut cmd = git.command();
+        cmd.env("GIT_INDEX_FILE", index);
+        cmd
+    };
+    err::from_git(
+        &indexed().args(["read-tree", tree]).logged_output()?,
+        || format!("failed to read tree {}", tree),
+    )?;
+    let diff = git
+        .command()
+        .args(["diff", "--binary", "--full-index", "--no-renames", from, to])
+        .logged_output()?;
+    err::from_git(&diff, || format!("failed to diff {} and {}", from, to))?;
+    let mut child = indexed()
+        .args(["apply", "--cached", "--3way"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged_spawn()?;
+    use std::io::Write;
+    child
+        .stdin
+        .take()
+        .expect("apply stdin")
+        .write_all(&diff.stdout)?;
+    let applied = crate::cancel::wait_with_output(child)?;
+    if !applied.status.success() {
+        let unmerged = indexed()
+            .args([
+                "diff-index",
+                "--cached",
+                "--name-only",
+                "--diff-filter=U",
+                tree,
+            ])
+            .logged_output()?;
+        err::from_git(&unmerged, || "failed to list conflicts".to_string())?;
+        let mut paths: Vec<String> = String::from_utf8_lossy(&unmerged.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect();
+        if paths.is_empty() {
+            err::from_git(&applied, || format!("failed to apply {}..{}", from, to))?;
+        }
+        paths.dedup();
+        return Err(ApplyError::Conflicts(paths));
+    }
+    let out = indexed().arg("write-tree").logged_output()?;
+    err::from_git(&out, || "failed to write tree".to_string())?;
+    Ok(crate::git::parse_oid(out.stdout)
+        .map_err(|buf| {
+            err::Error::GitContract(format!(
+                "write-tree gave bad output: {:?}",
+                String::from_utf8_lossy(&buf),
+            ))
+        })?
+        .parse()?)
+}
+
+/// Replace `source` with `amended` in the current stack, rebasing the commits after it.
+fn restack(git: &GitStore, source: &Commit, amended: &Oid) -> err::Result<()> {
+    let head = match git.head()? {
+        Head::Branch(name) => name,
+        Head::Detached(_) => "HEAD".to_string(),
+    };
+    if !git.rebase_onto(amended, &source.oid, &head)? {
+        return Err(err::Error::Conflicts {
+            action: format!("pull {}", head),
+            reason: format!(
+                "rebasing onto the amended commit {} stopped on conflicts; to resolve them by \
+                 hand, run `git rebase --onto {} {}`",
+                amended, amended, source.oid
+            ),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,412 @@
+use std::ffi::OsStr;
+
+use crate::config::Config;
+use crate::err;
+use crate::git::GitStore;
+
+pub const SUBCOMMAND: &str = "push";
+
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_DRY_RUN: &str = "dry_run";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_PUSH_OPTION: &str = "push-option";
+
+/// Prefix of the local refs that record, for each target branch, the last commit that git-dx
+/// pushed to it: `refs/dx/pushed/<remote>/<branch>`.
+const PUSHED_REFS_PREFIX: &str = "refs/dx/pushed/";
+
+/// The local ref recording the last commit that git-dx pushed to the given branch.
+pub fn pushed_ref(remote: &str, branch: &str) -> String {
+    format!("{}{}/{}", PUSHED_REFS_PREFIX, remote, branch)
+}
+
+/// The `--push-option` argument, shared by every subcommand that pushes.
+pub fn push_option_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name(CLI_ARG_PUSH_OPTION)
+        .help("Send a push option to the server, as by `git push -o` (repeatable)")
+        .long_help(
+            "Send a push option to the server, as by `git push -o`, after any given by the \
+             `dx.pushOption` config option. May be given more than once.",
+        )
+        .value_name("option")
+        .long("--push-option")
+        .short("-o")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+}
+
+/// Collect the push options to send: those from `dx.pushOption`, then those from
+/// `--push-option`.
+pub fn push_options(config: &Config, matches: &clap::ArgMatches) -> Vec<String> {
+    let mut result = config.push_options.clone();
+    if let Some(values) = matches.values_of(CLI_ARG_PUSH_OPTION) {
+        result.extend(values.map(|value| value.to_string()));
+    }
+    result
+}
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Pushes an integrated commit to its target branch")
+        .long_about(
+            "Pushes an integrated commit to its target branch, as `git dx --push` does. The push \
+             only succeeds if the remote branch is still where its remote-tracking ref says it \
+             is (or, for a new branch, still does not exist), so that a concurrent push by \
+             someone else is never overwritten.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Integrated commit to push")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DRY_RUN)
+                .help("Use a dry-run push only")
+                .long("--dry-run")
+                .short("-n"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote to push to")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(push_option_arg())
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let rev = matches.value_of(CLI_ARG_COMMIT).unwrap();
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let commit = git.commit(rev)?.clone();
+    let branch =
+        crate::branch_name(git, config, &commit.oid, &commit.trailers)?.ok_or_else(|| {
+            err::Error::MissingTrailer {
+                oid: commit.oid.to_string(),
+                key: config.branch_trailer.clone(),
+            }
+        })?;
+    let expected = crate::remote_branch_oid(git, &remote, &branch)?;
+    if let Some(ref expected) = expected {
+        if !git.is_ancestor(expected, &commit.oid)? {
+            return Err(err::Error::NotFastForward {
+                branch,
+                oid: commit.oid.to_string(),
+            });
+        }
+    }
+    let update = BranchUpdate {
+        branch: &branch,
+        commit: &commit.oid,
+        expected: expected.as_deref(),
+    };
+    let outcome = push_branches(
+        git,
+        &remote,
+        &[update],
+        matches.is_present(CLI_ARG_DRY_RUN),
+        &push_options(config, matches),
+        &[],
+    )?;
+    git.invalidate_refs();
+    outcome.print();
+    Ok(())
+}
+
+/// Outcome of updating a single remote ref, as reported by `git push --porcelain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefStatus {
+    FastForward,
+    Forced,
+    Deleted,
+    New,
+    Rejected,
+    UpToDate,
+}
+
+impl RefStatus {
+    fn from_flag(flag: char) -> Option<Self> {
+        match flag {
+            ' ' => Some(RefStatus::FastForward),
+            '+' => Some(RefStatus::Forced),
+            '-' => Some(RefStatus::Deleted),
+            '*' => Some(RefStatus::New),
+            '!' => Some(RefStatus::Rejected),
+            '=' => Some(RefStatus::UpToDate),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RefUpdate {
+    pub status: RefStatus,
+    /// The local side of the refspec (empty for deletions).
+    pub from: String,
+    /// The remote ref being updated (e.g., `refs/heads/wchargin-foo`).
+    pub to: String,
+    /// Git's short description of the update (e.g., `[new branch]`, `abc123..def456`).
+    pub summary: String,
+    /// Git's explanation for a rejection or other unusual outcome (e.g., `fetch first`).
+    pub reason: Option<String>,
+}
+
+/// Result of a successful `git push`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PushOutcome {
+    pub updates: Vec<RefUpdate>,
+    /// Messages printed by the remote (lines of `remote: ...`, without the prefix).
+    pub remote_messages: Vec<String>,
+    /// URL suggested by the remote for opening a pull (or merge) request, as GitHub and GitLab
+    /// print when a new branch is pushed.
+    pub pull_request_url: Option<String>,
+}
+
+impl PushOutcome {
+    /// The part of this outcome that concerns the given target branch: its ref update, and the
+    /// pull request URL that the remote suggested for it, if any (recognized by ending in the
+    /// branch name). Remote messages are kept whole.
+    pub fn for_branch(&self, branch: &str) -> PushOutcome {
+        let to = format!("refs/heads/{}", branch);
+        PushOutcome {
+            updates: self
+                .updates
+                .iter()
+                .filter(|update| update.to == to)
+                .cloned()
+                .collect(),
+            remote_messages: self.remote_messages.clone(),
+            pull_request_url: find_pull_request_urls(&self.remote_messages)
+                .into_iter()
+                .find(|url| {
+                    url.strip_suffix(branch)
+                        .is_some_and(|rest| rest.ends_with('/') || rest.ends_with('='))
+                }),
+        }
+    }
+
+    /// Print the remote's messages and a line per updated ref to stderr.
+    pub fn print(&self) {
+        for message in &self.remote_messages {
+            info!("remote: {}", message);
+        }
+        for update in &self.updates {
+            let to = match update.status {
+                RefStatus::New => crate::color::created(&update.to),
+                RefStatus::Rejected => crate::color::error(&update.to),
+                _ => update.to.clone(),
+            };
+            match update.reason {
+                Some(ref reason) => info!("{}: {} ({})", to, update.summary, reason),
+                None => info!("{}: {}", to, update.summary),
+            }
+        }
+    }
+}
+
+/// Run `git push --porcelain` with the given arguments, parsing the per-ref results. Fails with
+/// `PushRejected` if the remote rejected any ref update.
+pub fn push<I, S>(git: &GitStore, args: I) -> err::Result<PushOutcome>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut cmd = git.command();
+    cmd.args(["push", "--porcelain"]);
+    if crate::progress::transfer_enabled() {
+        cmd.arg("--progress");
+    }
+    let output = crate::progress::output(cmd.args(args))?;
+    git.forget_remote_heads();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let updates: Vec<RefUpdate> = stdout.lines().filter_map(parse_ref_line).collect();
+    let rejected: Vec<String> = updates
+        .iter()
+        .filter(|update| update.status == RefStatus::Rejected)
+        .map(|update| match update.reason {
+            Some(ref reason) => format!("{} ({})", update.to, reason),
+            None => update.to.clone(),
+        })
+        .collect();
+    if !rejected.is_empty() {
+        return Err(err::Error::PushRejected { refs: rejected });
+    }
+    err::from_git(&output, || "failed to push".to_string())?;
+    let remote_messages: Vec<String> = stderr
+        .lines()
+        .filter_map(|line| line.strip_prefix("remote:"))
+        .map(|line| line.trim().to_string())
+        .collect();
+    let pull_request_url = find_pull_request_urls(&remote_messages).into_iter().next();
+    Ok(PushOutcome {
+        updates,
+        remote_messages,
+        pull_request_url,
+    })
+}
+
+/// An update of a target branch, to be pushed with a lease.
+pub struct BranchUpdate<'a> {
+    pub branch: &'a str,
+    /// The commit to push.
+    pub commit: &'a str,
+    /// Where the remote branch must still be for the update to go through, or `None` if the
+    /// branch must not exist yet.
+    pub expected: Option<&'a str>,
+}
+
+/// Push each update, along with any `extra_refspecs`, in a single atomic push: either every ref
+/// is updated or none is. Each of `push_options` is sent to the server with `-o`.
+///
+/// Before pushing, each branch's live tip is read from the remote and compared with its expected
+/// tip, failing with `StaleRemote` if our remote-tracking refs are out of date. Each branch is
+/// leased as with `git push --force-with-lease`, so if someone else has pushed to it since we last
+/// fetched it, the push is rejected rather than clobbering their work.
+///
+/// After a successful push, each branch's new tip is recorded under its `pushed_ref`, so that a
+/// later integration can tell whether someone has since rewritten the branch.
+pub fn push_branches(
+    git: &GitStore,
+    remote: &str,
+    updates: &[BranchUpdate],
+    dry_run: bool,
+    push_options: &[String],
+    extra_refspecs: &[String],
+) -> err::Result<PushOutcome> {
+    for update in updates {
+        let live = git.ls_remote(remote, &format!("refs/heads/{}", update.branch))?;
+        if live.as_deref() != update.expected {
+            return Err(err::Error::StaleRemote {
+                remote: remote.to_string(),
+                branch: update.branch.to_string(),
+                expected: update.expected.map(|oid| oid.to_string()),
+                actual: live.map(|oid| oid.to_string()),
+            });
+        }
+    }
+    let mut args = vec!["--atomic".to_string()];
+    if dry_run {
+        args.push("--dry-run".to_string());
+    }
+    for option in push_options {
+        args.push(format!("--push-option={}", option));
+    }
+    for update in updates {
+        args.push(format!(
+            "--force-with-lease=refs/heads/{}:{}",
+            update.branch,
+            update.expected.unwrap_or("")
+        ));
+    }
+    args.push(remote.to_string());
+    for update in updates {
+        args.push(format!("{}:refs/heads/{}", update.commit, update.branch));
+    }
+    args.extend(extra_refspecs.iter().cloned());
+    let outcome = push(git, &args)?;
+    if !dry_run {
+        for update in updates {
+            git.update_ref(
+                &pushed_ref(remote, update.branch),
+                update.commit,
+                &format!("dx push: {}", update.branch),
+            )?;
+        }
+    }
+    Ok(outcome)
+}
+
+/// Push each of `branches`, as `(branch, commit)` pairs, to a secondary remote in a single atomic
+/// push, as `push_branches` does. Each branch is leased at wherever it is on that remote now,
+/// which must be an ancestor of its new commit, so that the remote follows the primary one without
+/// dropping commits that only it has.
+pub fn push_secondary(
+    git: &GitStore,
+    remote: &str,
+    branches: &[(&str, &str)],
+    dry_run: bool,
+    push_options: &[String],
+) -> err::Result<PushOutcome> {
+    let mut live = Vec::new();
+    for &(branch, commit) in branches {
+        let oid = git.ls_remote(remote, &format!("refs/heads/{}", branch))?;
+        if let Some(ref oid) = oid {
+            let known = git.rev_parse_commit(oid)?.is_some();
+            if !known || !git.is_ancestor(oid, commit)? {
+                return Err(err::Error::NotFastForward {
+                    branch: format!("{}/{}", remote, branch),
+                    oid: commit.to_string(),
+                });
+            }
+        }
+        live.push(oid);
+    }
+    let updates: Vec<BranchUpdate> = branches
+        .iter()
+        .zip(&live)
+        .map(|(&(branch, commit), expected)| BranchUpdate {
+            branch,
+            commit,
+            expected: expected.as_deref(),
+        })
+        .collect();
+    push_branches(git, remote, &updates, dry_run, push_options, &[])
+}
+
+/// Parse a ref line of `git push --porcelain` output, e.g.:
+///
+/// ```text
+/// *\trefs/heads/x:refs/heads/wchargin-foo\t[new branch]
+/// !\trefs/heads/x:refs/heads/wchargin-foo\t[rejected] (fetch first)
+/// ```
+///
+/// Returns `None` for other lines (`To <url>`, `Done`).
+fn parse_ref_line(line: &str) -> Option<RefUpdate> {
+    let mut fields = line.splitn(3, '\t');
+    let mut flag = fields.next()?.chars();
+    let status = RefStatus::from_flag(flag.next()?)?;
+    if flag.next().is_some() {
+        return None;
+    }
+    let refspec = fields.next()?;
+    let (from, to) = match refspec.find(':') {
+        Some(i) => (&refspec[..i], &refspec[i + 1..]),
+        None => ("", refspec),
+    };
+    let summary = fields.next()?;
+    let (summary, reason) = match summary.find(" (") {
+        Some(i) if summary.ends_with(')') => (
+            &summary[..i],
+            Some(summary[i + 2..summary.len() - 1].to_string()),
+        ),
+        _ => (summary, None),
+    };
+    Some(RefUpdate {
+        status,
+        from: from.to_string(),
+        to: to.to_string(),
+        summary: summary.to_string(),
+        reason,
+    })
+}
+
+/// Find the URLs that follow a remote's "create a pull request" (GitHub) or "create a merge
+/// request" (GitLab) messages, one per new branch.
+fn find_pull_request_urls(messages: &[String]) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut after_hint = false;
+    for message in messages {
+        let lower = message.to_ascii_lowercase();
+        if lower.contains("pull request") || lower.contains("merge request") {
+            after_hint = true;
+        } else if after_hint && (message.starts_with("https://") || message.starts_with("http://"))
+        {
+            result.push(message.clone());
+            after_hint = false;
+        }
+    }
+    result
+}
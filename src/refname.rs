@@ -0,0 +1,179 @@
+//! Templates for target branch names, like `users/{user}/{branch}` or `wip/{date}/{branch}`, set
+//! with `dx.refTemplate`. A template is expanded to name the target branch of a branch key, and
+//! matched in reverse to recover the branch key from an existing branch name.
+
+use crate::clock;
+use crate::err;
+
+/// Config option giving the ref template. If unset, the template is `dx.branchPrefix` followed by
+/// `{branch}`.
+pub const TEMPLATE_CONFIG: &str = "dx.refTemplate";
+
+/// Config option giving the value of `{user}`, which otherwise defaults to the local part of
+/// `user.email`.
+pub const USER_CONFIG: &str = "dx.user";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    /// The branch key. Every template has exactly one.
+    Branch,
+    /// The user's name, from `dx.user` or `user.email`.
+    User,
+    /// The UTC date when the branch is first integrated, as `YYYY-MM-DD`.
+    Date,
+}
+
+/// Length of a `{date}` expansion.
+const DATE_LEN: usize = "YYYY-MM-DD".len();
+
+impl RefTemplate {
+    /// The template that just prepends `prefix` to the branch key.
+    pub fn with_prefix(prefix: &str) -> RefTemplate {
+        let mut parts = Vec::new();
+        if !prefix.is_empty() {
+            parts.push(Part::Literal(prefix.to_string()));
+        }
+        parts.push(Part::Branch);
+        RefTemplate { parts }
+    }
+
+    /// Parse a template, which must mention `{branch}` exactly once and may also mention `{user}`
+    /// and `{date}`.
+    pub fn parse(template: &str) -> err::Result<RefTemplate> {
+        let invalid = |reason: &str| err::Error::InvalidRefTemplate {
+            template: template.to_string(),
+            reason: reason.to_string(),
+        };
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            let (literal, after) = match rest.find('{') {
+                Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+                None => (rest, None),
+            };
+            if literal.contains('}') {
+                return Err(invalid("unmatched `}`"));
+            }
+            if !literal.is_empty() {
+                parts.push(Part::Literal(literal.to_string()));
+            }
+            rest = match after {
+                None => "",
+                Some(after) => {
+                    let (name, after) = after
+                        .split_once('}')
+                        .ok_or_else(|| invalid("unmatched `{`"))?;
+                    parts.push(match name {
+                        "branch" => Part::Branch,
+                        "user" => Part::User,
+                        "date" => Part::Date,
+                        _ => {
+                            return Err(invalid(&format!(
+                                "unknown variable `{{{}}}`; use `{{branch}}`, `{{user}}`, or \
+                                 `{{date}}`",
+                                name
+                            )))
+                        }
+                    });
+                    after
+                }
+            };
+        }
+        match parts.iter().filter(|part| **part == Part::Branch).count() {
+            0 => Err(invalid("must contain `{branch}`")),
+            1 => Ok(RefTemplate { parts }),
+            _ => Err(invalid("must contain `{branch}` only once")),
+        }
+    }
+
+    /// Whether the template mentions `{user}`.
+    pub fn uses_user(&self) -> bool {
+        self.parts.contains(&Part::User)
+    }
+
+    /// Whether the template mentions `{date}`, in which case the target branch of a branch key
+    /// depends on when it was first integrated, not just on the key.
+    pub fn is_dated(&self) -> bool {
+        self.parts.contains(&Part::Date)
+    }
+
+    /// Form a branch name for the given branch key, dated today if the template has a date.
+    pub fn expand(&self, user: &str, branch_key: &str) -> String {
+        let mut result = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => result.push_str(s),
+                Part::Branch => result.push_str(branch_key),
+                Part::User => result.push_str(user),
+                Part::Date => result.push_str(&today()),
+            }
+        }
+        result
+    }
+
+    /// Recover the branch key from a branch name formed by this template for the given user, or
+    /// return `None` if the name doesn't match. A `{date}` matches any date.
+    pub fn match_key<'a>(&self, user: &str, name: &'a str) -> Option<&'a str> {
+        let branch = self.parts.iter().position(|part| *part == Part::Branch)?;
+        let mut rest = name;
+        for part in &self.parts[..branch] {
+            rest = match part {
+                Part::Literal(s) => rest.strip_prefix(s.as_str())?,
+                Part::User => rest.strip_prefix(user)?,
+                Part::Date => {
+                    rest.get(..DATE_LEN).filter(|date| is_date(date))?;
+                    &rest[DATE_LEN..]
+                }
+                Part::Branch => unreachable!("branch key appears only once"),
+            };
+        }
+        for part in self.parts[branch + 1..].iter().rev() {
+            rest = match part {
+                Part::Literal(s) => rest.strip_suffix(s.as_str())?,
+                Part::User => rest.strip_suffix(user)?,
+                Part::Date => {
+                    let start = rest.len().checked_sub(DATE_LEN)?;
+                    rest.get(start..).filter(|date| is_date(date))?;
+                    &rest[..start]
+                }
+                Part::Branch => unreachable!("branch key appears only once"),
+            };
+        }
+        if rest.is_empty() {
+            None
+        } else {
+            Some(rest)
+        }
+    }
+}
+
+/// Whether `s` looks like a `{date}` expansion.
+fn is_date(s: &str) -> bool {
+    s.bytes().enumerate().all(|(i, c)| match i {
+        4 | 7 => c == b'-',
+        _ => c.is_ascii_digit(),
+    })
+}
+
+/// Today's date in UTC, as `YYYY-MM-DD`.
+fn today() -> String {
+    let secs = clock::now();
+    // Convert days since the epoch to a proleptic Gregorian date, per Howard Hinnant's
+    // `civil_from_days` algorithm.
+    let z = (secs / 86400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
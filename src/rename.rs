@@ -0,0 +1,190 @@
+use crate::config::Config;
+use crate::err;
+use crate::forge;
+use crate::git::GitStore;
+
+pub const SUBCOMMAND: &str = "rename";
+
+const CLI_ARG_NEW: &str = "new";
+const CLI_ARG_OLD: &str = "old";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_TRUNK: &str = "trunk";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Renames a change's target branch")
+        .long_about(
+            "Renames a change's target branch: moves the remote branch to its new name, and \
+             rewrites the branch trailer of the change's commit in the current stack to match. \
+             On GitHub, the branch is renamed through the API, so that its open pull requests \
+             follow it; elsewhere, it is pushed under the new name and the old one is deleted.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_OLD)
+                .help("Current target branch (e.g., wchargin-foo)")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_NEW)
+                .help("New target branch (e.g., wchargin-bar)")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help("Remote branch that the stack is based on (default: the remote's HEAD)")
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote that holds the branch")
+                .short("-r")
+                .takes_value(true),
+        )
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let old = matches.value_of(CLI_ARG_OLD).unwrap();
+    let new = matches.value_of(CLI_ARG_NEW).unwrap();
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let remotes = crate::Remotes::new(&remote, None).per_branch(matches.is_present(CLI_ARG_REMOTE));
+    let cannot_rename = |reason: String| err::Error::CannotRename {
+        branch: old.to_string(),
+        reason,
+    };
+    let old_key = config
+        .branch_key_of(old)
+        .ok_or_else(|| cannot_rename("not a dx target branch".to_string()))?;
+    let new_key = config
+        .branch_key_of(new)
+        .ok_or_else(|| cannot_rename(format!("{} is not a dx target branch name", new)))?;
+    if !git.check_ref_format(new)? {
+        return Err(cannot_rename(format!("{} is not a valid branch name", new)));
+    }
+
+    // The change's commit in the current stack.
+    let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+        Some(trunk) => trunk.to_string(),
+        None => crate::default_trunk(git, &remote)?,
+    };
+    let trunk_tip = crate::trunk_oid(git, &remote, &trunk)?;
+    let mut sources = crate::stack::commits_with_key(git, config, &trunk_tip, old_key)?;
+    let source = match sources.len() {
+        1 => sources.pop().unwrap(),
+        0 => {
+            return Err(cannot_rename(format!(
+                "no commit between {}/{} and HEAD has trailer \"{}: {}\"",
+                remote, trunk, config.branch_trailer, old_key
+            )))
+        }
+        n => {
+            return Err(cannot_rename(format!(
+                "{} commits between {}/{} and HEAD have trailer \"{}: {}\"",
+                n, remote, trunk, config.branch_trailer, old_key
+            )))
+        }
+    };
+
+    let target_remote = remotes.target_remote(git, old)?;
+    if remotes.target_oid(git, new)?.is_some() {
+        return Err(cannot_rename(format!(
+            "{} already exists on {}",
+            new, target_remote
+        )));
+    }
+    match remotes.target_oid(git, old)? {
+        Some(tip) => {
+            move_remote_branch(git, &remote, &target_remote, old, new, &tip)?;
+            info!("renamed {} to {} on {}", old, new, target_remote);
+        }
+        None => info!(
+            "{} is not on {}; only renaming it locally",
+            old, target_remote
+        ),
+    }
+
+    // Local records of the branch follow it to its new name.
+    let pushed = crate::push::pushed_ref(&target_remote, old);
+    if let Some(oid) = git.rev_parse(&pushed)? {
+        let reason = format!("dx rename: {} to {}", old, new);
+        git.update_ref(&crate::push::pushed_ref(&target_remote, new), &oid, &reason)?;
+        git.delete_ref(&pushed, &reason)?;
+    }
+    let branch_remote = format!("branch.{}.{}", old, crate::BRANCH_REMOTE_CONFIG);
+    if let Some(value) = git.config_get(&branch_remote)? {
+        git.config_set(
+            &format!("branch.{}.{}", new, crate::BRANCH_REMOTE_CONFIG),
+            &value,
+        )?;
+        git.config_unset(&branch_remote)?;
+    }
+
+    let renamed = crate::track::set_branch_trailer(git, config, &source, new_key)?;
+    info!(
+        "changed trailer of {} to \"{}: {}\"",
+        source.oid, config.branch_trailer, new_key
+    );
+    println!("{}", renamed);
+    Ok(())
+}
+
+/// Move `old` on `target_remote`, whose head is `tip`, to `new`. The code host renames it if it
+/// can, moving the pull request along; else it is pushed under the new name and the old name is
+/// deleted, all at once, and the pull request recorded for it (which the host will close) is
+/// forgotten.
+fn move_remote_branch(
+    git: &GitStore,
+    upstream: &str,
+    target_remote: &str,
+    old: &str,
+    new: &str,
+    tip: &str,
+) -> err::Result<()> {
+    let pull_ref = format!("{}{}", crate::pr::PULL_PREFIX, old);
+    let reason = format!("dx rename: {} to {}", old, new);
+    let forge = if target_remote == upstream {
+        forge::for_remote(git, upstream)?
+    } else {
+        None
+    };
+    if let Some(forge) = forge {
+        if forge.rename_branch(old, new)? {
+            // Catch the remote-tracking refs up with the rename, as a fetch would.
+            let tracking = |branch: &str| format!("refs/remotes/{}/{}", target_remote, branch);
+            git.update_ref(&tracking(new), tip, &reason)?;
+            git.delete_ref(&tracking(old), &reason)?;
+            if let Some(pull) = git.rev_parse(&pull_ref)? {
+                git.update_ref(
+                    &format!("{}{}", crate::pr::PULL_PREFIX, new),
+                    &pull,
+                    &reason,
+                )?;
+                git.delete_ref(&pull_ref, &reason)?;
+            }
+            return Ok(());
+        }
+    }
+
+    let args = vec![
+        "--atomic".to_string(),
+        format!("--force-with-lease=refs/heads/{}:{}", old, tip),
+        format!("--force-with-lease=refs/heads/{}:", new),
+        target_remote.to_string(),
+        format!("{}:refs/heads/{}", tip, new),
+        format!(":refs/heads/{}", old),
+    ];
+    crate::push::push(git, &args)?.print();
+    git.invalidate_refs();
+    if let Some(number) = crate::pr::recorded(git, old)? {
+        warn!(
+            "pull request #{} for {} will be closed now that the branch is gone; run `git dx \
+             pr` to open one for {}",
+            number, old, new
+        );
+        git.delete_ref(&pull_ref, &reason)?;
+    }
+    Ok(())
+}
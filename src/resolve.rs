@@ -0,0 +1,391 @@
+use std::process::Command;
+
+use crate::err;
+use crate::git::GitStore;
+use crate::log::CommandExt;
+
+/// Config option listing conflict resolution policies, one `<glob>=<policy>` entry per value.
+pub const POLICY_CONFIG: &str = "dx.resolve";
+
+/// Config option giving default `git merge` arguments for the diffbase merge, like
+/// `-X renormalize`.
+pub const MERGE_OPTIONS_CONFIG: &str = "dx.mergeOptions";
+
+/// The merge strategy and strategy options for the diffbase merge, as `git merge -s` and `-X`
+/// take them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeOptions {
+    pub strategy: Option<String>,
+    pub strategy_options: Vec<String>,
+}
+
+impl MergeOptions {
+    /// Parse a value of `dx.mergeOptions`: whitespace-separated `git merge` arguments, each of
+    /// which is `-s <strategy>` or `-X <option>`, in any of the forms that `git merge` accepts
+    /// (e.g., `-Xrenormalize` or `--strategy-option=renormalize`).
+    pub fn parse(value: &str) -> err::Result<Self> {
+        let invalid = || err::Error::InvalidConfig(MERGE_OPTIONS_CONFIG.to_string());
+        let mut result = MergeOptions::default();
+        let mut words = value.split_whitespace();
+        while let Some(word) = words.next() {
+            let (is_strategy, attached) = if let Some(rest) = word.strip_prefix("--strategy=") {
+                (true, Some(rest))
+            } else if let Some(rest) = word.strip_prefix("--strategy-option=") {
+                (false, Some(rest))
+            } else if word == "--strategy" || word == "-s" {
+                (true, None)
+            } else if word == "--strategy-option" || word == "-X" {
+                (false, None)
+            } else if let Some(rest) = word.strip_prefix("-s") {
+                (true, Some(rest))
+            } else if let Some(rest) = word.strip_prefix("-X") {
+                (false, Some(rest))
+            } else {
+                return Err(invalid());
+            };
+            let value = match attached {
+                Some(value) => value,
+                None => words.next().ok_or_else(invalid)?,
+            };
+            if value.is_empty() {
+                return Err(invalid());
+            }
+            if is_strategy {
+                result.strategy = Some(value.to_string());
+            } else {
+                result.strategy_options.push(value.to_string());
+            }
+        }
+        Ok(result)
+    }
+
+    /// Apply options from the command line: a strategy replaces this one, and strategy options
+    /// come after these, so that they win where the two disagree.
+    pub fn with<'a, I: IntoIterator<Item = &'a str>>(
+        mut self,
+        strategy: Option<&str>,
+        strategy_options: I,
+    ) -> Self {
+        if let Some(strategy) = strategy {
+            self.strategy = Some(strategy.to_string());
+        }
+        self.strategy_options
+            .extend(strategy_options.into_iter().map(str::to_string));
+        self
+    }
+
+    /// The arguments that give these options to `git merge`.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(ref strategy) = self.strategy {
+            args.push(format!("--strategy={}", strategy));
+        }
+        for option in &self.strategy_options {
+            args.push(format!("--strategy-option={}", option));
+        }
+        args
+    }
+}
+
+/// How to resolve a conflicted path in the diffbase merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Policy {
+    /// Take the version from the remote target branch (the checked-out side).
+    Ours,
+    /// Take the version from the remote diffbase (the merged-in side).
+    Theirs,
+    /// Run a shell command at the top of the worktree that regenerates the path.
+    Regen(String),
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Policy::Ours => write!(f, "ours"),
+            Policy::Theirs => write!(f, "theirs"),
+            Policy::Regen(command) => write!(f, "regen:{}", command),
+        }
+    }
+}
+
+/// A resolution policy for paths matching a glob pattern (as a `:(glob)` pathspec, relative to
+/// the top of the worktree).
+#[derive(Debug, Clone)]
+pub struct PathPolicy {
+    pub pattern: String,
+    pub policy: Policy,
+}
+
+impl PathPolicy {
+    /// Parse a value of `dx.resolve`, like `package-lock.json=ours` or `gen/**=regen:make gen`.
+    pub fn parse(value: &str) -> err::Result<Self> {
+        let invalid = || err::Error::InvalidConfig(POLICY_CONFIG.to_string());
+        // A regen command may itself contain `=`, so split before it if there is one.
+        let (pattern, policy) = match value.find("=regen:") {
+            Some(i) => (&value[..i], &value[i + 1..]),
+            None => value.rsplit_once('=').ok_or_else(invalid)?,
+        };
+        let policy = match policy {
+            "ours" => Policy::Ours,
+            "theirs" => Policy::Theirs,
+            _ => match policy.strip_prefix("regen:") {
+                Some(command) if !command.is_empty() => Policy::Regen(command.to_string()),
+                _ => return Err(invalid()),
+            },
+        };
+        if pattern.is_empty() {
+            return Err(invalid());
+        }
+        Ok(PathPolicy {
+            pattern: pattern.to_string(),
+            policy,
+        })
+    }
+}
+
+/// A path left conflicted by the diffbase merge, and how it was resolved.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Conflict {
+    /// Path relative to the top of the worktree.
+    pub path: String,
+    pub kind: ConflictKind,
+    /// The policy that resolved this path, or `None` if it was committed as it stood (for a
+    /// content conflict, with conflict markers).
+    pub resolution: Option<String>,
+}
+
+impl Conflict {
+    /// Describe, for a warning, how this path was committed if no policy resolved it.
+    pub fn unresolved(&self) -> String {
+        let path = crate::color::conflict(&self.path);
+        match self.kind {
+            ConflictKind::Content => format!("committed conflict markers in {}", path),
+            ConflictKind::DeletedByUs => format!(
+                "committed {} as the diffbase modified it, though the target branch deleted it",
+                path
+            ),
+            ConflictKind::DeletedByThem => format!(
+                "kept {} as the target branch modified it, though the diffbase deleted it",
+                path
+            ),
+            ConflictKind::AddedByUs | ConflictKind::AddedByThem => format!(
+                "committed {} as it stands, after conflicting renames or directory changes",
+                path
+            ),
+            ConflictKind::DeletedByBoth => format!("deleted {}, which both sides renamed", path),
+        }
+    }
+}
+
+/// Which sides of the diffbase merge changed a conflicted path, as Git reports it: "us" is the
+/// remote target branch, and "them" is the remote diffbase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictKind {
+    /// Both sides changed (or added) the path, differently.
+    Content,
+    DeletedByUs,
+    DeletedByThem,
+    AddedByUs,
+    AddedByThem,
+    DeletedByBoth,
+}
+
+impl ConflictKind {
+    /// Classify an unmerged path by which of the stages 1 (base), 2 (ours), and 3 (theirs) it
+    /// has in the index.
+    fn from_stages(base: bool, ours: bool, theirs: bool) -> Self {
+        match (base, ours, theirs) {
+            (_, true, true) => ConflictKind::Content,
+            (true, false, true) => ConflictKind::DeletedByUs,
+            (true, true, false) => ConflictKind::DeletedByThem,
+            (false, true, false) => ConflictKind::AddedByUs,
+            (false, false, true) => ConflictKind::AddedByThem,
+            (_, false, false) => ConflictKind::DeletedByBoth,
+        }
+    }
+}
+
+impl std::fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            ConflictKind::Content => "content",
+            ConflictKind::DeletedByUs => "deleted by target branch",
+            ConflictKind::DeletedByThem => "deleted by diffbase",
+            ConflictKind::AddedByUs => "added by target branch",
+            ConflictKind::AddedByThem => "added by diffbase",
+            ConflictKind::DeletedByBoth => "deleted by both",
+        })
+    }
+}
+
+impl std::fmt::Display for Conflict {
+    /// Like `a (content; resolved: ours)`, as listed in the message of the merge commit.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.resolution {
+            Some(ref policy) => write!(f, "{} ({}; resolved: {})", self.path, self.kind, policy),
+            None => write!(f, "{} ({})", self.path, self.kind),
+        }
+    }
+}
+
+/// List the conflicted paths in the index, optionally limited to those matching a glob.
+fn unmerged_paths(git: &GitStore, glob: Option<&str>) -> err::Result<Vec<String>> {
+    Ok(unmerged_entries(git, glob)?
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect())
+}
+
+/// List the conflicted paths in the index, optionally limited to those matching a glob, each
+/// with the stages (1 to 3) that it has.
+fn unmerged_entries(git: &GitStore, glob: Option<&str>) -> err::Result<Vec<(String, [bool; 3])>> {
+    let mut cmd = git.command();
+    cmd.args(["ls-files", "--unmerged", "-z"]);
+    if let Some(glob) = glob {
+        cmd.arg("--").arg(format!(":(glob){}", glob));
+    }
+    let out = cmd.logged_output()?;
+    err::from_git(&out, || "failed to list conflicted paths".to_string())?;
+    let stdout = err::Error::require_utf8(out.stdout, "ls-files output")?;
+    let mut entries: Vec<(String, [bool; 3])> = Vec::new();
+    // Each entry is "<mode> <oid> <stage>\t<path>", with one entry per stage.
+    for entry in stdout.split_terminator('\0') {
+        let (info, path) = match entry.split_once('\t') {
+            Some(split) => split,
+            None => continue,
+        };
+        let stage = match info.rsplit(' ').next() {
+            Some("1") => 0,
+            Some("2") => 1,
+            Some("3") => 2,
+            _ => {
+                return Err(err::Error::GitContract(format!(
+                    "bad ls-files entry: {:?}",
+                    entry
+                )))
+            }
+        };
+        match entries.last_mut() {
+            Some((last, stages)) if last == path => stages[stage] = true,
+            _ => {
+                let mut stages = [false; 3];
+                stages[stage] = true;
+                entries.push((path.to_string(), stages));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// List the paths whose staged contents have leftover conflict markers that `base` does not,
+/// as `git diff --check` finds them.
+pub fn conflict_markers(git: &GitStore, base: &str) -> err::Result<Vec<String>> {
+    const MARKER: &str = ": leftover conflict marker";
+    let out = git
+        .command()
+        .args([
+            "-c",
+            "core.quotePath=false",
+            "diff",
+            "--cached",
+            "--check",
+            base,
+        ])
+        .logged_output()?;
+    // Exits 2 if it finds problems, which may also be whitespace errors.
+    if !out.status.success() && out.status.code() != Some(2) {
+        err::from_git(&out, || "failed to check for conflict markers".to_string())?;
+    }
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let mut paths: Vec<String> = Vec::new();
+    // Each problem is reported as "<path>:<line>: <problem>", followed by the offending line.
+    for line in stdout.lines() {
+        let location = match line.strip_suffix(MARKER) {
+            Some(location) => location,
+            None => continue,
+        };
+        if let Some((path, _)) = location.rsplit_once(':') {
+            if !paths.iter().any(|p| p == path) {
+                paths.push(path.to_string());
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// Apply resolution policies to the conflicts of an in-progress merge in the worktree, staging
+/// each resolved path. The first policy whose pattern matches a path wins. Returns the
+/// manifest of all conflicts, resolved or not; unresolved paths are left for the caller.
+pub fn resolve_conflicts(git: &GitStore, policies: &[PathPolicy]) -> err::Result<Vec<Conflict>> {
+    let top = git.toplevel()?;
+    let mut conflicts: Vec<Conflict> = unmerged_entries(git, None)?
+        .into_iter()
+        .map(|(path, [base, ours, theirs])| Conflict {
+            path,
+            kind: ConflictKind::from_stages(base, ours, theirs),
+            resolution: None,
+        })
+        .collect();
+    for path_policy in policies {
+        let matched = unmerged_paths(git, Some(&path_policy.pattern))?;
+        if matched.is_empty() {
+            continue;
+        }
+        let stage = |args: &[&str], paths: &[String]| -> err::Result<()> {
+            let out = git
+                .command()
+                .arg("--literal-pathspecs")
+                .args(args)
+                .arg("--")
+                .args(paths)
+                .logged_output()?;
+            err::from_git(&out, || format!("failed to resolve {}", paths.join(", ")))
+        };
+        match path_policy.policy {
+            Policy::Ours | Policy::Theirs => {
+                let side = if path_policy.policy == Policy::Ours {
+                    "--ours"
+                } else {
+                    "--theirs"
+                };
+                for path in &matched {
+                    // Fails if this side deleted the path, in which case resolve by deleting.
+                    let out = git
+                        .command()
+                        .arg("--literal-pathspecs")
+                        .args(["checkout", side, "--", path])
+                        .logged_output()?;
+                    if out.status.success() {
+                        stage(&["add"], std::slice::from_ref(path))?;
+                    } else {
+                        stage(
+                            &["rm", "--quiet", "--cached", "--ignore-unmatch"],
+                            std::slice::from_ref(path),
+                        )?;
+                        let _ = std::fs::remove_file(top.join(path));
+                    }
+                }
+            }
+            Policy::Regen(ref command) => {
+                let out = Command::new(git.shell()?)
+                    .args(["-c", command])
+                    .current_dir(&top)
+                    .logged_output()?;
+                if !out.status.success() {
+                    return Err(err::Error::ResolveFailed {
+                        command: command.clone(),
+                        stderr: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+                    });
+                }
+                stage(&["add", "--all"], &matched)?;
+            }
+        }
+        for conflict in conflicts.iter_mut() {
+            if conflict.resolution.is_none() && matched.contains(&conflict.path) {
+                conflict.resolution = Some(path_policy.policy.to_string());
+            }
+        }
+    }
+    Ok(conflicts)
+}
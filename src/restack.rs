@@ -0,0 +1,121 @@
+use crate::config::Config;
+use crate::err;
+use crate::git::{GitStore, Oid};
+
+pub const SUBCOMMAND: &str = "restack";
+
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_TRUNK: &str = "trunk";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Rebases the changes above an amended commit onto it")
+        .long_about(
+            "Rebases the changes above an amended source commit onto it. The commit that it \
+             replaced is found on each local branch as the one with the same branch directive, \
+             or the one that the remote target branch was last integrated from. Each branch \
+             with commits above the replaced commit is rebased onto the amended one, and its \
+             new tip is printed. A branch whose rebase stops on conflicts is left as it was.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Amended source commit")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help("Remote branch that the stack is based on (default: the remote's HEAD)")
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote that holds the trunk and target branches")
+                .short("-r")
+                .takes_value(true),
+        )
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    if crate::autostash::is_dirty(git)? {
+        return Err(err::Error::DirtyWorktree);
+    }
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+        Some(trunk) => trunk.to_string(),
+        None => crate::default_trunk(git, &remote)?,
+    };
+    let trunk_tip = crate::trunk_oid(git, &remote, &trunk)?;
+    let amended = git
+        .commit(matches.value_of(CLI_ARG_COMMIT).unwrap())?
+        .clone();
+    let key = crate::branch_key(config, &amended.oid, &amended.trailers)?.ok_or_else(|| {
+        err::Error::MissingTrailer {
+            oid: amended.oid.to_string(),
+            key: config.branch_trailer.clone(),
+        }
+    })?;
+    // The source commit of the last integration, which the amended commit may have replaced.
+    let target_branch = crate::valid_target_branch(git, config, &amended.oid, &key)?;
+    let integrated = match crate::remote_branch_oid(git, &remote, &target_branch)? {
+        Some(tip) => {
+            let tip = git.commit(&tip)?.clone();
+            crate::recorded_source(config, &tip.oid, &tip.trailers)?
+        }
+        None => None,
+    };
+
+    let original_head = git.head()?;
+    let mut restacked = 0;
+    let mut stuck = Vec::new();
+    for (branch, tip) in git.local_branches()? {
+        if git.is_ancestor(&amended.oid, &tip)? {
+            continue;
+        }
+        let commits: Vec<Oid> = git.rev_list(&trunk_tip, &tip)?;
+        let mut replaced = None;
+        for (i, oid) in commits.iter().enumerate() {
+            let commit = git.commit(oid)?.clone();
+            let same_change = integrated.as_deref() == Some(oid.as_str())
+                || crate::branch_key(config, &commit.oid, &commit.trailers)?.as_ref() == Some(&key);
+            if same_change {
+                replaced = Some(i);
+            }
+        }
+        // Nothing to do unless some commits build on the replaced one.
+        let replaced = match replaced {
+            Some(i) if i + 1 < commits.len() => &commits[i],
+            _ => continue,
+        };
+        if git.rebase_onto(&amended.oid, replaced, &branch)? {
+            let new_tip = git.rev_parse_commit_ok(&format!("refs/heads/{}", branch))?;
+            info!("restacked {} onto {}", branch, amended.oid);
+            println!("{}\t{}", branch, new_tip);
+            restacked += 1;
+        } else {
+            warn!(
+                "rebasing {} onto {} stopped on conflicts",
+                branch, amended.oid
+            );
+            stuck.push((branch, replaced.clone()));
+        }
+    }
+    git.check_out_head(&original_head)?;
+    if restacked == 0 && stuck.is_empty() {
+        info!("nothing builds on an earlier version of {}", amended.oid);
+    }
+    match stuck.first() {
+        Some((branch, replaced)) => Err(err::Error::Conflicts {
+            action: format!("restack {}", branch),
+            reason: format!(
+                "rebase stopped on conflicts; rebase it by hand with \
+                 `git rebase --onto {} {} {}`",
+                amended.oid, replaced, branch
+            ),
+        }),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,134 @@
+use crate::config::Config;
+use crate::err::{self, ResultExt};
+use crate::git::{Commit, GitStore};
+use crate::log::CommandExt;
+use crate::push;
+
+pub const SUBCOMMAND: &str = "rollback-round";
+
+const CLI_ARG_BRANCH: &str = "branch";
+const CLI_ARG_DRY_RUN: &str = "dry_run";
+const CLI_ARG_REMOTE: &str = "remote";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Reverts the latest round of review on a target branch")
+        .long_about(
+            "Reverts the latest round of review on a target branch by pushing a new commit that \
+             restores the branch's tree from before that round's update. History is not \
+             rewritten, so reviewers see the rollback as one more update. The latest round must \
+             be the head of the branch, and cannot be the branch's first commit.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_BRANCH)
+                .help("Target branch to roll back (e.g., wchargin-foo)")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DRY_RUN)
+                .help("Use a dry-run push only")
+                .long("--dry-run")
+                .short("-n"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote that holds the branch")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(push::push_option_arg())
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let branch = matches.value_of(CLI_ARG_BRANCH).unwrap();
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let cannot_roll_back = |reason: String| err::Error::CannotRollBack {
+        branch: branch.to_string(),
+        reason,
+    };
+    let key = config
+        .branch_key_of(branch)
+        .ok_or_else(|| cannot_roll_back("not a dx target branch".to_string()))?;
+
+    let tip = crate::remote_branch_oid(git, &remote, branch)?
+        .ok_or_else(|| cannot_roll_back(format!("no such branch on {}", remote)))?;
+    let latest = git.commit(&tip)?.clone();
+    let is_round = |commit: &Commit| match crate::branch_key(config, &commit.oid, &commit.trailers)
+    {
+        Ok(Some(commit_key)) => commit_key == key,
+        _ => false,
+    };
+    // Each round is a single commit carrying the branch's trailer: the commit that created the
+    // branch, or an "update patch" (or no-op, or CI bump) commit. Diffbase merges are not rounds.
+    if latest.parents.len() != 1 || !is_round(&latest) {
+        return Err(cannot_roll_back(format!(
+            "head {} is not a round of review (e.g., it is a diffbase merge)",
+            tip
+        )));
+    }
+    let previous = git.commit(&latest.parents[0])?.clone();
+    if !is_round(&previous) {
+        return Err(cannot_roll_back(
+            "the branch has only one round; delete it instead".to_string(),
+        ));
+    }
+
+    let mut message = format!(
+        "[{}: roll back round]\n\nThis reverts commit {}.\n\n{}: {}",
+        key, latest.oid, config.branch_trailer, key
+    );
+    // The restored tree is the previous round's, so credit that round's source commit.
+    if let Some(source) = crate::recorded_source(config, &previous.oid, &previous.trailers)? {
+        message.push_str(&format!("\n{}: {}", config.source_trailer, source));
+    }
+    if config.signoff {
+        message.push_str(&format!(
+            "\n{}: {}",
+            crate::SIGNOFF_TRAILER,
+            git.committer()?.ident()
+        ));
+    }
+    let sign = crate::sign_commits(git, None)?;
+    let committing = || format!("committing rollback of {}", latest.oid);
+    let out = git
+        .command()
+        .args([
+            "commit-tree",
+            crate::gpg_sign_arg(sign),
+            &previous.tree,
+            "-p",
+            &tip,
+            "-m",
+            &message,
+        ])
+        .logged_output()
+        .context(committing)?;
+    err::from_git(&out, || "failed to create rollback commit".to_string()).context(committing)?;
+    let rollback = crate::git::parse_oid(out.stdout)
+        .map_err(|buf| {
+            err::Error::GitContract(format!(
+                "commit-tree gave bad output: {:?}",
+                String::from_utf8_lossy(&buf),
+            ))
+        })?
+        .parse::<crate::git::Oid>()?;
+
+    let outcome = push::push_branches(
+        git,
+        &remote,
+        &[push::BranchUpdate {
+            branch,
+            commit: &rollback,
+            expected: Some(&tip),
+        }],
+        matches.is_present(CLI_ARG_DRY_RUN),
+        &push::push_options(config, matches),
+        &[],
+    )?;
+    git.invalidate_refs();
+    outcome.print();
+    info!("rolled back {} to the tree of {}", branch, previous.oid);
+    println!("{}", rollback);
+    Ok(())
+}
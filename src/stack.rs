@@ -0,0 +1,157 @@
+//! Stack coordinates like `[3/7]` at the start of the subject of each integration commit
+//! (`--stack-position`, or `dx.stackPosition`), so that reviewers scanning a list of remote
+//! branches or CI logs can see at a glance where each change sits in its stack. Positions are
+//! computed from the local stack on each run, so they follow the stack as it is reordered.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::config::Config;
+use crate::err;
+use crate::git::{Commit, GitStore, Oid};
+
+/// Config option to add stack positions by default.
+pub const POSITION_CONFIG: &str = "dx.stackPosition";
+
+/// Where a change sits in its stack: the `index`th of `len` changes, counting from 1 at the
+/// bottom (nearest trunk).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub index: usize,
+    pub len: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}/{}]", self.index, self.len)
+    }
+}
+
+/// Find the stack that `source` is in: the changes (commits with branch directives) between
+/// `trunk` and `head`, oldest first. If `source` isn't in that stack, as when integrating a commit
+/// that isn't checked out, it is taken as the top of the stack between `trunk` and itself.
+/// Returns `None` if `source` isn't above `trunk` at all.
+pub fn find(
+    git: &mut GitStore,
+    config: &Config,
+    trunk: &str,
+    head: &str,
+    source: &Commit,
+) -> err::Result<Option<Vec<Commit>>> {
+    for tip in [head, source.oid.as_str()] {
+        let mut changes = Vec::new();
+        for oid in git.rev_list(trunk, tip)? {
+            let commit = git.commit(&oid)?.clone();
+            if crate::branch_key(config, &commit.oid, &commit.trailers)?.is_some() {
+                changes.push(commit);
+            }
+        }
+        if changes.iter().any(|change| change.oid == source.oid) {
+            return Ok(Some(changes));
+        }
+    }
+    Ok(None)
+}
+
+/// Find the commits between `trunk` and `HEAD` whose branch key is `key`, newest first. In a
+/// well-formed stack there is exactly one.
+pub fn commits_with_key(
+    git: &mut GitStore,
+    config: &Config,
+    trunk: &str,
+    key: &str,
+) -> err::Result<Vec<Commit>> {
+    let mut result = Vec::new();
+    for oid in git.rev_list(trunk, "HEAD")? {
+        let commit = git.commit(&oid)?.clone();
+        if crate::branch_key(config, &commit.oid, &commit.trailers)?.as_deref() == Some(key) {
+            result.push(commit);
+        }
+    }
+    Ok(result)
+}
+
+/// A change in a stack that may branch, as found by `tree`.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub commit: Commit,
+    /// Index (in the tree) of the change that this one builds on, or `None` if it builds directly
+    /// on trunk.
+    pub parent: Option<usize>,
+}
+
+/// Find the changes between `trunk` and any of `tips`, as a tree: each builds on the nearest
+/// change among its first-parent ancestors. The changes are in topological order, so each comes
+/// after the change that it builds on, and siblings come in the order that Git lists them.
+pub fn tree(
+    git: &mut GitStore,
+    config: &Config,
+    trunk: &str,
+    tips: &[&str],
+) -> err::Result<Vec<Node>> {
+    let mut nodes: Vec<Node> = Vec::new();
+    // For each commit above trunk, the nearest change at or below it.
+    let mut nearest: HashMap<Oid, Option<usize>> = HashMap::new();
+    for oid in git.rev_list_topo(trunk, tips)? {
+        let commit = git.commit(&oid)?.clone();
+        let below = commit
+            .parents
+            .first()
+            .and_then(|parent| nearest.get(parent).copied())
+            .flatten();
+        let here = if crate::branch_key(config, &commit.oid, &commit.trailers)?.is_some() {
+            nodes.push(Node {
+                commit,
+                parent: below,
+            });
+            Some(nodes.len() - 1)
+        } else {
+            below
+        };
+        nearest.insert(oid, here);
+    }
+    Ok(nodes)
+}
+
+/// Find the position of `source` in its stack, as found by `find`.
+pub fn position(
+    git: &mut GitStore,
+    config: &Config,
+    trunk: &str,
+    head: &str,
+    source: &Commit,
+) -> err::Result<Option<Position>> {
+    let changes = match find(git, config, trunk, head, source)? {
+        Some(changes) => changes,
+        None => return Ok(None),
+    };
+    Ok(changes
+        .iter()
+        .position(|change| change.oid == source.oid)
+        .map(|i| Position {
+            index: i + 1,
+            len: changes.len(),
+        }))
+}
+
+/// Strip a stack position from the start of a commit subject, if it has one.
+pub fn strip_position(subject: &str) -> &str {
+    let rest = match subject.strip_prefix('[') {
+        Some(rest) => rest,
+        None => return subject,
+    };
+    let (coordinates, rest) = match rest.split_once("] ") {
+        Some(split) => split,
+        None => return subject,
+    };
+    let is_position = coordinates.split_once('/').is_some_and(|(index, len)| {
+        [index, len]
+            .iter()
+            .all(|n| !n.is_empty() && n.bytes().all(|c| c.is_ascii_digit()))
+    });
+    if is_position {
+        rest
+    } else {
+        subject
+    }
+}
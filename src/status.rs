@@ -0,0 +1,121 @@
+use crate::config::Config;
+use crate::err;
+use crate::git::{GitStore, Oid};
+
+pub const SUBCOMMAND: &str = "status";
+
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_FORK: &str = "fork";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_TRUNK: &str = "trunk";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Shows the integration state of each change in the current stack")
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Tip of the stack")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TRUNK)
+                .help("Remote branch that the stack is based on (default: the remote's HEAD)")
+                .value_name("branch")
+                .long("--trunk")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote whose branches to inspect")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_FORK)
+                .help("Remote that holds integration branches, if not the `-r` remote")
+                .value_name("remote")
+                .long("--fork")
+                .takes_value(true),
+        )
+}
+
+/// Integration state of a single change in the stack.
+struct ChangeStatus {
+    /// The local source commit.
+    oid: Oid,
+    target_branch: String,
+    /// The head of the remote target branch, if it exists.
+    remote_tip: Option<Oid>,
+    /// Whether the remote target branch is treequal to the source commit.
+    up_to_date: bool,
+    /// The source commit recorded in the source directive of the remote target branch's head, if
+    /// any.
+    remote_source: Option<String>,
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let tip = matches.value_of(CLI_ARG_COMMIT).unwrap();
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let remotes = crate::Remotes::new(&remote, matches.value_of(CLI_ARG_FORK));
+    let trunk = match matches.value_of(CLI_ARG_TRUNK) {
+        Some(trunk) => trunk.to_string(),
+        None => crate::default_trunk(git, remotes.read)?,
+    };
+    let trunk_ref = crate::trunk_oid(git, remotes.read, &trunk)?;
+    for change in stack_status(git, config, &remotes, &trunk_ref, tip)? {
+        let state = match change.remote_tip {
+            None => "not on remote",
+            Some(_) if change.up_to_date => "up to date",
+            Some(_) => "out of date",
+        };
+        println!("{} {}: {}", change.oid, change.target_branch, state);
+        if let Some(ref source) = change.remote_source {
+            let note = if change.oid == **source {
+                " (this commit)"
+            } else {
+                ""
+            };
+            println!("\tremote source: {}{}", source, note);
+        }
+    }
+    Ok(())
+}
+
+/// Compute the status of each trailer-bearing commit between `base` and `tip`, oldest first.
+/// Commits without a branch directive are skipped.
+fn stack_status(
+    git: &mut GitStore,
+    config: &Config,
+    remotes: &crate::Remotes,
+    base: &str,
+    tip: &str,
+) -> err::Result<Vec<ChangeStatus>> {
+    let mut result = Vec::new();
+    for oid in git.rev_list(base, tip)? {
+        let commit = git.commit(&oid)?.clone();
+        let target_branch = match crate::branch_name(git, config, &commit.oid, &commit.trailers)? {
+            Some(name) => name,
+            None => continue,
+        };
+        let remote_tip = remotes.target_oid(git, &target_branch)?;
+        let (up_to_date, remote_source) = match remote_tip {
+            Some(ref remote_tip) => {
+                let remote_commit = git.commit(remote_tip)?.clone();
+                (
+                    remote_commit.tree == commit.tree,
+                    crate::recorded_source(config, &remote_commit.oid, &remote_commit.trailers)?,
+                )
+            }
+            None => (false, None),
+        };
+        result.push(ChangeStatus {
+            oid: commit.oid,
+            target_branch,
+            remote_tip,
+            up_to_date,
+            remote_source,
+        });
+    }
+    Ok(result)
+}
@@ -0,0 +1,36 @@
+//! What the terminal on stderr can take, decided once per run and consulted by everything that
+//! styles its output. Under `--plain`, for screen readers, or when `TERM` is `dumb`, output is
+//! kept to plain lines of text: no color, and nothing redrawn in place with carriage returns.
+//! Color alone is also off by default when `NO_COLOR` is set (see <https://no-color.org>).
+
+use std::ffi::OsStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The global flag that asks for plain output.
+pub const PLAIN_FLAG: &str = "--plain";
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Decide whether output must be plain, given the command-line arguments. This is read before
+/// they are parsed, since the parser styles its own errors and help.
+pub fn set<I: IntoIterator<Item = S>, S: AsRef<OsStr>>(args: I) {
+    let flag = args.into_iter().any(|arg| arg.as_ref() == PLAIN_FLAG);
+    PLAIN.store(flag, Ordering::Relaxed);
+}
+
+/// Whether `--plain` was given.
+pub fn plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// Whether output must be plain lines of text, with no escape sequences or redrawn lines: under
+/// `--plain`, or when `TERM` says that the terminal can't handle them, as for Emacs shell buffers.
+pub fn dumb() -> bool {
+    plain() || std::env::var_os("TERM").is_some_and(|term| term == "dumb")
+}
+
+/// Whether to color output that isn't told otherwise: unless it must be plain or `NO_COLOR` is
+/// set to a non-empty value.
+pub fn color_by_default() -> bool {
+    !dumb() && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
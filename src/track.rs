@@ -0,0 +1,258 @@
+use std::process::Stdio;
+
+use crate::config::Config;
+use crate::err;
+use crate::git::{Commit, GitStore, Oid};
+use crate::log::CommandExt;
+
+pub const SUBCOMMAND: &str = "track";
+
+const CLI_ARG_COMMIT: &str = "commit";
+const CLI_ARG_KEY: &str = "key";
+
+/// Longest branch key that we will generate from a subject line.
+const MAX_SLUG_LEN: usize = 40;
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Adds a branch trailer to a commit in the current stack")
+        .arg(
+            clap::Arg::with_name(CLI_ARG_COMMIT)
+                .help("Commit to amend; must be HEAD or one of its first-parent ancestors")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_KEY)
+                .help("Branch key to use (default: derived from the subject line)")
+                .value_name("key")
+                .long("--key")
+                .short("-k")
+                .takes_value(true),
+        )
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let target = git
+        .commit(matches.value_of(CLI_ARG_COMMIT).unwrap())?
+        .clone();
+    let cannot_track = |reason: String| err::Error::CannotTrack {
+        oid: target.oid.to_string(),
+        reason,
+    };
+    match crate::branch_key(config, &target.oid, &target.trailers) {
+        Ok(None) => (),
+        Ok(Some(key)) => {
+            return Err(cannot_track(format!(
+                "already has trailer \"{}: {}\"",
+                config.branch_trailer, key
+            )))
+        }
+        Err(err::Error::DuplicateTrailer { key, .. }) => {
+            return Err(cannot_track(format!("already has {:?} trailers", key)))
+        }
+        Err(e) => return Err(e),
+    }
+    let key = match matches.value_of(CLI_ARG_KEY) {
+        Some(key) => key.to_string(),
+        None => slug(subject(&target.message)),
+    };
+    if key.is_empty() {
+        return Err(cannot_track(
+            "cannot derive a branch key from the subject line; pass `--key`".to_string(),
+        ));
+    }
+    crate::valid_target_branch(git, config, &target.oid, &key)?;
+
+    let tracked = set_branch_trailer(git, config, &target, &key)?;
+    info!(
+        "added trailer \"{}: {}\" (target branch {})",
+        config.branch_trailer,
+        key,
+        crate::target_branch(git, config, &key)?
+    );
+    println!("{}", tracked);
+    Ok(())
+}
+
+/// Amend `target`, which must be `HEAD` or one of its first-parent ancestors, to set its branch
+/// trailer to the given key, rewriting the commits after it onto the amended commit and moving
+/// `HEAD` to match. Returns the amended commit.
+pub fn set_branch_trailer(
+    git: &mut GitStore,
+    config: &Config,
+    target: &Commit,
+    key: &str,
+) -> err::Result<Oid> {
+    let cannot_track = |reason: String| err::Error::CannotTrack {
+        oid: target.oid.to_string(),
+        reason,
+    };
+    // Commits after the target, oldest first, which must be rewritten onto the amended commit.
+    let head = git.rev_parse_commit_ok("HEAD")?;
+    if !git.is_ancestor(&target.oid, &head)? {
+        return Err(cannot_track("not an ancestor of HEAD".to_string()));
+    }
+    let mut descendants = Vec::new();
+    let mut expected_parent = target.oid.clone();
+    for oid in git.rev_list(&target.oid, &head)? {
+        let commit = git.commit(&oid)?.clone();
+        if commit.parents != [expected_parent] {
+            return Err(cannot_track(format!(
+                "{} is a merge or is not a first-parent descendant; cannot rewrite it",
+                oid
+            )));
+        }
+        expected_parent = commit.oid.clone();
+        descendants.push(commit);
+    }
+
+    let message = set_trailer(git, &target.message, &config.branch_trailer, key)?;
+    let sign = crate::sign_commits(git, None)?;
+    let tracked = recommit(git, target, &target.tree, &target.parents, &message, sign)?;
+    let mut new_head = tracked.clone();
+    for commit in &descendants {
+        new_head = recommit(
+            git,
+            commit,
+            &commit.tree,
+            &[new_head],
+            &commit.message,
+            sign,
+        )?;
+    }
+    err::from_git(
+        &git.command()
+            .args([
+                "update-ref",
+                "-m",
+                &format!("dx track: {}", key),
+                "HEAD",
+                &new_head,
+                &head,
+            ])
+            .logged_output()?,
+        || "failed to update HEAD".to_string(),
+    )?;
+    git.invalidate_refs();
+    Ok(tracked)
+}
+
+/// Extract the subject line (first paragraph, joined onto one line) of a commit message.
+fn subject(message: &str) -> String {
+    message
+        .lines()
+        .take_while(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Derive a branch key from a subject line: lowercase ASCII alphanumerics separated by single
+/// hyphens, truncated at a word boundary. May be empty if the subject has no such characters.
+fn slug(subject: String) -> String {
+    let mut result = String::new();
+    for word in subject
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        let extra = if result.is_empty() { 0 } else { 1 };
+        if !result.is_empty() && result.len() + extra + word.len() > MAX_SLUG_LEN {
+            break;
+        }
+        if extra > 0 {
+            result.push('-');
+        }
+        result.extend(
+            word.chars()
+                .take(MAX_SLUG_LEN)
+                .map(|c| c.to_ascii_lowercase()),
+        );
+    }
+    result
+}
+
+/// Set a trailer in a commit message, replacing any with the same key, or else appending it and
+/// starting a new trailer block if there is none.
+fn set_trailer(git: &GitStore, message: &str, key: &str, value: &str) -> err::Result<String> {
+    let mut child = git
+        .command()
+        .args([
+            "-c",
+            "trailer.separators=:",
+            "interpret-trailers",
+            "--no-divider",
+            "--if-exists",
+            "replace",
+            "--trailer",
+            &format!("{}: {}", key, value),
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged_spawn()?;
+    use std::io::Write;
+    child
+        .stdin
+        .take()
+        .expect("interpret-trailers stdin")
+        .write_all(message.as_bytes())?;
+    let out = crate::cancel::wait_with_output(child)?;
+    err::from_git(&out, || "failed to set trailer".to_string())?;
+    err::Error::require_utf8(out.stdout, "interpret-trailers output")
+}
+
+/// Create a copy of `commit` with the given tree, parents, and message, preserving its author
+/// identity and date, and signing it if `sign` is set.
+pub fn recommit(
+    git: &GitStore,
+    commit: &Commit,
+    tree: &str,
+    parents: &[Oid],
+    message: &str,
+    sign: bool,
+) -> err::Result<Oid> {
+    let author_output = git
+        .command()
+        .args([
+            "show",
+            "--no-patch",
+            "--format=%an%x00%ae%x00%ad",
+            "--date=raw",
+        ])
+        .arg(commit.oid.as_str())
+        .logged_output()?;
+    err::from_git(&author_output, || {
+        format!("failed to read author of {}", commit.oid)
+    })?;
+    let author = err::Error::require_utf8(author_output.stdout, &commit.oid)?;
+    let mut author = author.trim_end_matches('\n').splitn(3, '\0');
+    let mut cmd = git.command();
+    cmd.args(["commit-tree", crate::gpg_sign_arg(sign), tree]);
+    for parent in parents {
+        cmd.args(["-p", parent]);
+    }
+    for var in &["GIT_AUTHOR_NAME", "GIT_AUTHOR_EMAIL", "GIT_AUTHOR_DATE"] {
+        cmd.env(var, author.next().unwrap_or(""));
+    }
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged_spawn()?;
+    use std::io::Write;
+    child
+        .stdin
+        .take()
+        .expect("commit-tree stdin")
+        .write_all(message.as_bytes())?;
+    let out = crate::cancel::wait_with_output(child)?;
+    err::from_git(&out, || format!("failed to rewrite {}", commit.oid))?;
+    crate::git::parse_oid(out.stdout)
+        .map_err(|buf| {
+            err::Error::GitContract(format!(
+                "commit-tree gave bad output: {:?}",
+                String::from_utf8_lossy(&buf),
+            ))
+        })?
+        .parse()
+}
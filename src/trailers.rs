@@ -0,0 +1,472 @@
+//! Parsing of commit message trailers, following the rules that `git interpret-trailers` uses to
+//! find the trailer block. In brief:
+//!
+//!   - Trailing blank lines and comment lines, and anything after a scissors line, are ignored.
+//!   - The trailer block is the last paragraph, and never the first (the title).
+//!   - A line like `Key: value` is a trailer line, with any of the configured separator characters
+//!     in place of `:`. Whitespace-indented lines continue the line before them.
+//!   - The paragraph is a trailer block if all of its lines are trailer lines, or if at least one
+//!     of them has a prefix that Git itself generates (e.g., `Signed-off-by: `) and at least a
+//!     quarter of them are trailer lines.
+//!
+//! See `find_trailer_block_start` in Git's `trailer.c` for the reference implementation.
+
+/// Prefixes of lines that Git writes into commit messages itself, whose presence marks a
+/// paragraph as a trailer block even if it also contains other lines.
+const GIT_GENERATED_PREFIXES: &[&str] = &["Signed-off-by: ", "(cherry picked from commit "];
+
+/// Git's default `core.commentChar`.
+const COMMENT_CHAR: char = '#';
+
+/// Line below which `git commit --verbose` puts the diff, which is not part of the message.
+const SCISSORS_LINE: &str = "# ------------------------ >8 ------------------------";
+
+/// Trailer separators when `trailer.separators` is not set, as in Git.
+pub const DEFAULT_SEPARATORS: &str = ":";
+
+/// How trailers are written in a repository: the characters that may separate a key from its
+/// value (`trailer.separators`), and the keys of lines that are copied verbatim into messages
+/// (`dx.passThroughTrailer`), which are left out before looking for the trailer block.
+#[derive(Debug, Clone)]
+pub struct Syntax {
+    pub separators: String,
+    pub pass_through: Vec<String>,
+}
+
+impl Default for Syntax {
+    fn default() -> Syntax {
+        Syntax {
+            separators: DEFAULT_SEPARATORS.to_string(),
+            pass_through: Vec::new(),
+        }
+    }
+}
+
+impl Syntax {
+    /// Parse the trailers of a commit message, as `parse` does, leaving out pass-through lines.
+    pub fn parse(&self, message: &str) -> Vec<(String, String)> {
+        let message = remove(message, &self.pass_through, &self.separators);
+        parse(&message, &self.separators)
+    }
+}
+
+/// Parse the trailers of a commit message, as `git interpret-trailers --parse` does, returning
+/// each key and value in order. Values of folded trailers are unfolded. The `separators` are the
+/// characters that may separate a key from its value, as in Git's `trailer.separators` option.
+pub fn parse(message: &str, separators: &str) -> Vec<(String, String)> {
+    let (lines, start) = split(message, separators);
+    let start = match start {
+        Some(start) => start,
+        None => return Vec::new(),
+    };
+
+    // Join continuation lines onto the trailers that they continue, then split each trailer into
+    // its key and value.
+    let mut trailers: Vec<(String, String)> = Vec::new();
+    let mut continuing = false;
+    for line in &lines[start..] {
+        if is_comment(line) {
+            continuing = false;
+        } else if continuing && starts_with_whitespace(line) {
+            let value = &mut trailers.last_mut().expect("continuing a trailer").1;
+            value.push('\n');
+            value.push_str(line);
+        } else {
+            continuing = match find_separator(line, separators) {
+                Some(i) => {
+                    let (key, value) = (&line[..i], &line[i + 1..]);
+                    trailers.push((key.trim().to_string(), value.to_string()));
+                    true
+                }
+                None => false,
+            };
+        }
+    }
+    trailers
+        .into_iter()
+        .map(|(key, value)| (key, unfold(value.trim())))
+        .collect()
+}
+
+/// Strip the trailer block, if any, from a commit message, along with the comments and blank
+/// lines before it and anything after a scissors line.
+pub fn strip(message: &str, separators: &str) -> String {
+    let (mut lines, start) = split(message, separators);
+    if let Some(start) = start {
+        lines.truncate(start);
+    }
+    while let Some(line) = lines.last() {
+        if is_comment(line) || is_blank(line) {
+            lines.pop();
+        } else {
+            break;
+        }
+    }
+    lines.join("\n")
+}
+
+/// Rewrite `message` to carry the lines of `source` (after its title) that start with any of
+/// `keys`, like `Differential Revision: https://...`, copied verbatim with their continuation
+/// lines. Such lines can't be trailers when their keys have spaces, so they go in a paragraph of
+/// their own just before the trailer block (or at the end, if there is none), leaving the trailer
+/// block for Git to recognize. Any such lines already in `message` are moved there.
+pub fn carry(source: &str, message: &str, keys: &[String], separators: &str) -> String {
+    if keys.is_empty() {
+        return message.to_string();
+    }
+    let (carried, _) = partition(source, keys, separators);
+    let (moved, rest) = partition(message, keys, separators);
+    if carried.is_empty() && moved.is_empty() {
+        return message.to_string();
+    }
+    let rest = rest.join("\n");
+    let (mut lines, start) = split(&rest, separators);
+    match start {
+        Some(start) => {
+            let paragraph = carried.iter().copied().chain(std::iter::once(""));
+            lines.splice(start..start, paragraph);
+        }
+        None => {
+            lines.push("");
+            lines.extend(carried);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Remove the lines of `message` (after its title) that start with any of `keys`, along with
+/// their continuation lines, so that they don't hide the trailer block that they're in.
+pub fn remove(message: &str, keys: &[String], separators: &str) -> String {
+    if keys.is_empty() {
+        return message.to_string();
+    }
+    let (_, rest) = partition(message, keys, separators);
+    rest.join("\n") + "\n"
+}
+
+/// Split the lines of a message into those after its title that start with any of `keys`, along
+/// with their continuation lines, and the rest. Blank lines left doubled by the split are
+/// collapsed.
+fn partition<'a>(
+    message: &'a str,
+    keys: &[String],
+    separators: &str,
+) -> (Vec<&'a str>, Vec<&'a str>) {
+    let starts_with_key = |line: &str| {
+        keys.iter().any(|key| {
+            line.get(..key.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(key))
+                && line[key.len()..]
+                    .trim_start_matches([' ', '\t'])
+                    .starts_with(|c| separators.contains(c))
+        })
+    };
+    let mut matched = Vec::new();
+    let mut rest: Vec<&str> = Vec::new();
+    let mut in_title = true;
+    let mut continuing = false;
+    for line in message.lines() {
+        if in_title && is_blank(line) {
+            in_title = false;
+        }
+        if !in_title
+            && (starts_with_key(line)
+                || (continuing && !is_blank(line) && starts_with_whitespace(line)))
+        {
+            matched.push(line);
+            continuing = true;
+            continue;
+        }
+        continuing = false;
+        if is_blank(line) && rest.last().is_some_and(|last| is_blank(last)) {
+            continue;
+        }
+        rest.push(line);
+    }
+    while rest.last().is_some_and(|last| is_blank(last)) {
+        rest.pop();
+    }
+    (matched, rest)
+}
+
+/// Split a commit message into its lines, without trailing blank lines, comments, or anything
+/// after a scissors line, and find the index of the line that starts its trailer block, if any.
+fn split<'a>(message: &'a str, separators: &str) -> (Vec<&'a str>, Option<usize>) {
+    let mut lines: Vec<&str> = message
+        .lines()
+        .take_while(|line| *line != SCISSORS_LINE)
+        .collect();
+    while let Some(line) = lines.last() {
+        if is_comment(line) || is_blank(line) {
+            lines.pop();
+        } else {
+            break;
+        }
+    }
+
+    let end_of_title = lines
+        .iter()
+        .position(|line| !is_comment(line) && is_blank(line))
+        .unwrap_or(lines.len());
+    let start = block_start(&lines[end_of_title..], separators).map(|start| end_of_title + start);
+    (lines, start)
+}
+
+/// Find the start of the trailer block among `lines`, which should begin with the blank line that
+/// ends the title (if any), or `None` if the last paragraph is not a trailer block.
+fn block_start(lines: &[&str], separators: &str) -> Option<usize> {
+    let mut recognized_prefix = false;
+    let mut trailer_lines = 0;
+    let mut non_trailer_lines = 0;
+    // Whitespace-indented lines seen since the last trailer line, which are continuations if the
+    // line before them is a trailer line and count against the block otherwise.
+    let mut possible_continuation_lines = 0;
+    for (i, line) in lines.iter().enumerate().rev() {
+        if is_comment(line) {
+            non_trailer_lines += possible_continuation_lines;
+            possible_continuation_lines = 0;
+        } else if is_blank(line) {
+            non_trailer_lines += possible_continuation_lines;
+            let is_block = trailer_lines > 0
+                && (non_trailer_lines == 0
+                    || (recognized_prefix && trailer_lines * 3 >= non_trailer_lines));
+            return if is_block { Some(i + 1) } else { None };
+        } else if GIT_GENERATED_PREFIXES
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+        {
+            trailer_lines += 1;
+            possible_continuation_lines = 0;
+            recognized_prefix = true;
+        } else if starts_with_whitespace(line) {
+            possible_continuation_lines += 1;
+        } else if find_separator(line, separators).is_some() {
+            trailer_lines += 1;
+            possible_continuation_lines = 0;
+        } else {
+            non_trailer_lines += 1 + possible_continuation_lines;
+            possible_continuation_lines = 0;
+        }
+    }
+    // Reached the title without finding a blank line.
+    None
+}
+
+/// Find the index of the separator in a trailer line like `Key: value` or `Key = value`. The key
+/// must be non-empty and consist of alphanumerics and hyphens, optionally followed by whitespace.
+fn find_separator(line: &str, separators: &str) -> Option<usize> {
+    let mut whitespace_found = false;
+    for (i, c) in line.char_indices() {
+        if separators.contains(c) {
+            return if i >= 1 { Some(i) } else { None };
+        }
+        if !whitespace_found && (c.is_ascii_alphanumeric() || c == '-') {
+            continue;
+        }
+        if i != 0 && (c == ' ' || c == '\t') {
+            whitespace_found = true;
+            continue;
+        }
+        break;
+    }
+    None
+}
+
+/// Collapse each line break in a folded trailer value, along with the indentation after it, to a
+/// single space.
+fn unfold(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+            result.push(' ');
+        } else {
+            result.push(c);
+        }
+    }
+    result.trim().to_string()
+}
+
+fn is_comment(line: &str) -> bool {
+    line.starts_with(COMMENT_CHAR)
+}
+
+fn is_blank(line: &str) -> bool {
+    line.chars().all(|c| c.is_ascii_whitespace())
+}
+
+fn starts_with_whitespace(line: &str) -> bool {
+    line.starts_with(|c: char| c.is_ascii_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trailers(message: &str) -> Vec<(String, String)> {
+        parse(message, ":")
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn simple_block() {
+        assert_eq!(
+            trailers("Subject\n\nBody.\n\nKey: value\nOther-Key:  spaced \n"),
+            pairs(&[("Key", "value"), ("Other-Key", "spaced")])
+        );
+    }
+
+    #[test]
+    fn whitespace_before_separator() {
+        assert_eq!(
+            trailers("Subject\n\nKey : value\n"),
+            pairs(&[("Key", "value")])
+        );
+    }
+
+    #[test]
+    fn continuation_lines() {
+        assert_eq!(
+            trailers("Subject\n\nKey: first\n  second\n\tthird\nNext: value\n"),
+            pairs(&[("Key", "first second third"), ("Next", "value")])
+        );
+    }
+
+    #[test]
+    fn continuation_after_non_trailer_counts_against_block() {
+        // The indented line continues the prose line, not a trailer, so the block is two prose
+        // lines and one trailer line with no Git-generated prefix.
+        assert_eq!(
+            trailers("Subject\n\nProse.\n  more\nKey: value\n"),
+            pairs(&[])
+        );
+    }
+
+    #[test]
+    fn configured_separators() {
+        let message = "Subject\n\nKey = value\nOther: value\n";
+        assert_eq!(parse(message, ":"), pairs(&[]));
+        assert_eq!(
+            parse(message, ":="),
+            pairs(&[("Key", "value"), ("Other", "value")])
+        );
+        assert_eq!(
+            parse("Subject\n\nKey # value\n", "#"),
+            pairs(&[("Key", "value")])
+        );
+    }
+
+    #[test]
+    fn syntax_leaves_out_pass_through_lines() {
+        // A key with a space is no trailer, so the line hides the block unless left out.
+        let message = "Subject\n\nDifferential Revision: https://example.com/D1\nKey: value\n";
+        assert_eq!(Syntax::default().parse(message), pairs(&[]));
+        let syntax = Syntax {
+            pass_through: vec!["Differential Revision".to_string()],
+            ..Syntax::default()
+        };
+        assert_eq!(syntax.parse(message), pairs(&[("Key", "value")]));
+    }
+
+    #[test]
+    fn separator_needs_a_key() {
+        assert_eq!(trailers("Subject\n\n: value\n"), pairs(&[]));
+        assert_eq!(trailers("Subject\n\nTwo words: value\n"), pairs(&[]));
+    }
+
+    #[test]
+    fn quarter_of_lines_with_git_prefix() {
+        // Two trailer lines, one Git-generated, make a block with up to six other lines.
+        let prose = "Prose.\n".repeat(6);
+        let message = format!(
+            "Subject\n\n{}Key: value\nSigned-off-by: A <a@example.com>\n",
+            prose
+        );
+        assert_eq!(
+            trailers(&message),
+            pairs(&[("Key", "value"), ("Signed-off-by", "A <a@example.com>")])
+        );
+        let prose = "Prose.\n".repeat(7);
+        let message = format!(
+            "Subject\n\n{}Key: value\nSigned-off-by: A <a@example.com>\n",
+            prose
+        );
+        assert_eq!(trailers(&message), pairs(&[]));
+    }
+
+    #[test]
+    fn mixed_block_without_git_prefix() {
+        assert_eq!(trailers("Subject\n\nProse.\nKey: value\n"), pairs(&[]));
+    }
+
+    #[test]
+    fn cherry_pick_line_is_git_generated() {
+        assert_eq!(
+            trailers("Subject\n\nProse.\nKey: value\n(cherry picked from commit 1234)\n"),
+            pairs(&[("Key", "value")])
+        );
+    }
+
+    #[test]
+    fn dashes_are_not_a_divider() {
+        // Commit messages have no patch after them, so, as with `--no-divider`, a `---` line
+        // doesn't end the message.
+        assert_eq!(
+            trailers("Subject\n\n---\n\nKey: value\n"),
+            pairs(&[("Key", "value")])
+        );
+        assert_eq!(trailers("Subject\n\nKey: value\n---\n"), pairs(&[]));
+    }
+
+    #[test]
+    fn comment_lines() {
+        assert_eq!(
+            trailers("Subject\n\nKey: value\n# comment\nOther: value\n\n# Trailing comment\n#\n"),
+            pairs(&[("Key", "value"), ("Other", "value")])
+        );
+        // A comment ends a continuation.
+        assert_eq!(
+            trailers("Subject\n\nKey: value\n# comment\n  indented\n"),
+            pairs(&[])
+        );
+    }
+
+    #[test]
+    fn scissors_line() {
+        assert_eq!(
+            trailers(&format!(
+                "Subject\n\nKey: value\n{}\nOther: value\n",
+                SCISSORS_LINE
+            )),
+            pairs(&[("Key", "value")])
+        );
+    }
+
+    #[test]
+    fn no_blank_line_before_block() {
+        // The title is never a trailer block, even if it runs into the trailers.
+        assert_eq!(trailers("Key: value\n"), pairs(&[]));
+        assert_eq!(trailers("Subject\nKey: value\n"), pairs(&[]));
+    }
+
+    #[test]
+    fn only_last_paragraph() {
+        assert_eq!(trailers("Subject\n\nKey: value\n\nProse.\n"), pairs(&[]));
+    }
+
+    #[test]
+    fn strip_block() {
+        assert_eq!(
+            strip("Subject\n\nBody.\n\nKey: value\n", ":"),
+            "Subject\n\nBody."
+        );
+        assert_eq!(strip("Subject\n\nBody.\n", ":"), "Subject\n\nBody.");
+    }
+}
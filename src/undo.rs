@@ -0,0 +1,51 @@
+use crate::config::Config;
+use crate::err;
+use crate::git::{GitStore, Head};
+use crate::journal;
+
+pub const SUBCOMMAND: &str = "undo";
+
+const CLI_ARG_DRY_RUN: &str = "dry_run";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Rolls back the last integration run in this repository")
+        .long_about(
+            "Rolls back the last integration run in this repository, as recorded in the \
+             integration journal: checks out what was checked out before the run, and deletes \
+             the run's journal entries, which are the only local refs to the commits it created. \
+             Run it again to roll back the run before that. Remote branches are not changed: if \
+             the run pushed, see `git dx rollback-round`.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_DRY_RUN)
+                .help("Show what would be rolled back without changing anything")
+                .long("--dry-run")
+                .short("-n"),
+        )
+}
+
+pub fn run(git: &mut GitStore, _config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let dry_run = matches.is_present(CLI_ARG_DRY_RUN);
+    let entries = journal::last_run(git)?;
+    if entries.is_empty() {
+        return Err(err::Error::NothingToUndo);
+    }
+    if let Some(ref head) = entries[0].head {
+        let head = Head::parse(head);
+        if dry_run {
+            info!("would check out {}", head);
+        } else {
+            git.check_out_head(&head)?;
+            git.invalidate_refs();
+            info!("checked out {}", head);
+        }
+    }
+    for entry in &entries {
+        if !dry_run {
+            git.delete_ref(&entry.refname, "git-dx: undo")?;
+        }
+        println!("{}\t{}", entry.refname, entry.remote_commit);
+    }
+    Ok(())
+}
@@ -0,0 +1,169 @@
+//! Templates for the subject of "update patch" commits, like `{branch}: {message} (round {n})`,
+//! set with `dx.updateMessage`.
+
+use std::process::Command;
+
+use crate::err;
+use crate::git::GitStore;
+use crate::log::CommandExt;
+
+/// Config option giving the update message template.
+pub const TEMPLATE_CONFIG: &str = "dx.updateMessage";
+
+/// Template used when `dx.updateMessage` is not set.
+const DEFAULT_TEMPLATE: &str = "[{branch}: {message}]";
+
+/// Description of updates used when none is given with `--message` or in the editor.
+pub const DEFAULT_MESSAGE: &str = "update patch";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    /// The branch key.
+    Branch,
+    /// The description given with `--message` or in the editor, or `update patch`.
+    Message,
+    /// The object ID of the source commit.
+    Source,
+    /// The review round that this update starts: 1 for the commit that created the target
+    /// branch, 2 for the first update, and so on.
+    Round,
+}
+
+/// Values of the variables in an update message template.
+pub struct Vars<'a> {
+    pub branch: &'a str,
+    pub message: &'a str,
+    pub source: &'a str,
+    pub round: usize,
+}
+
+impl Default for UpdateTemplate {
+    fn default() -> Self {
+        UpdateTemplate::parse(DEFAULT_TEMPLATE).expect("default update template")
+    }
+}
+
+impl UpdateTemplate {
+    /// Parse a template, which may mention `{branch}`, `{message}`, `{source}`, and `{n}`.
+    pub fn parse(template: &str) -> err::Result<UpdateTemplate> {
+        let invalid = |reason: &str| err::Error::InvalidUpdateTemplate {
+            template: template.to_string(),
+            reason: reason.to_string(),
+        };
+        if template.contains('\n') {
+            return Err(invalid("must be a single line"));
+        }
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            let (literal, after) = match rest.find('{') {
+                Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+                None => (rest, None),
+            };
+            if literal.contains('}') {
+                return Err(invalid("unmatched `}`"));
+            }
+            if !literal.is_empty() {
+                parts.push(Part::Literal(literal.to_string()));
+            }
+            rest = match after {
+                None => "",
+                Some(after) => {
+                    let (name, after) = after
+                        .split_once('}')
+                        .ok_or_else(|| invalid("unmatched `{`"))?;
+                    parts.push(match name {
+                        "branch" => Part::Branch,
+                        "message" => Part::Message,
+                        "source" => Part::Source,
+                        "n" => Part::Round,
+                        _ => {
+                            return Err(invalid(&format!(
+                                "unknown variable `{{{}}}`; use `{{branch}}`, `{{message}}`, \
+                                 `{{source}}`, or `{{n}}`",
+                                name
+                            )))
+                        }
+                    });
+                    after
+                }
+            };
+        }
+        Ok(UpdateTemplate { parts })
+    }
+
+    /// Whether the template mentions `{n}`, which takes some work to compute.
+    pub fn uses_round(&self) -> bool {
+        self.parts.contains(&Part::Round)
+    }
+
+    /// Form an update message subject from the given values.
+    pub fn expand(&self, vars: &Vars) -> String {
+        let mut result = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => result.push_str(s),
+                Part::Branch => result.push_str(vars.branch),
+                Part::Message => result.push_str(vars.message),
+                Part::Source => result.push_str(vars.source),
+                Part::Round => result.push_str(&vars.round.to_string()),
+            }
+        }
+        result
+    }
+}
+
+/// Ask for a description of the update to `target_branch` in the user's editor, pre-filled with
+/// `initial` and, as comments, a summary of what changed between the `old` and `new` trees.
+/// Fails if the description is left empty, as `git commit` does.
+pub fn edit(
+    git: &GitStore,
+    target_branch: &str,
+    initial: &str,
+    old: &str,
+    new: &str,
+) -> err::Result<String> {
+    let mut contents = format!(
+        "{}\n\n# Describe this update to {}. Only the first line is used, and lines\n\
+         # starting with '#' are ignored. An empty description aborts integration.\n#\n",
+        initial, target_branch
+    );
+    for line in git.diff_stat(old, new)?.lines() {
+        contents.push_str(&format!("#{}\n", line));
+    }
+    let path = git.git_path("DX_UPDATE_MSG")?;
+    std::fs::write(&path, contents)?;
+    let editor = git.editor()?;
+    // Run the editor through the shell, as Git does, since it may include arguments.
+    let status = Command::new(git.shell()?)
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(&path)
+        .logged_spawn()
+        .and_then(|mut child| crate::cancel::wait(&mut child));
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+    let empty = || err::Error::EmptyUpdateMessage {
+        branch: target_branch.to_string(),
+    };
+    if !status?.success() {
+        return Err(err::Error::GitContract(format!(
+            "editor {:?} failed; aborting integration of {}",
+            editor, target_branch
+        )));
+    }
+    edited?
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+        .ok_or_else(empty)
+}
@@ -0,0 +1,97 @@
+use crate::config::Config;
+use crate::err;
+use crate::forge;
+use crate::git::GitStore;
+
+pub const SUBCOMMAND: &str = "web";
+
+const CLI_ARG_FORK: &str = "fork";
+const CLI_ARG_PRINT: &str = "print";
+const CLI_ARG_REMOTE: &str = "remote";
+const CLI_ARG_TARGET: &str = "target";
+
+pub fn subcommand<'a, 'b>() -> clap::App<'a, 'b> {
+    clap::SubCommand::with_name(SUBCOMMAND)
+        .about("Opens the pull request for a change in a web browser")
+        .long_about(
+            "Opens the pull request (on GitLab, the merge request) for a change in a web \
+             browser, as `git web--browse` does. The change is given by a source commit, whose \
+             target branch is found from its branch directive, or by a target branch name. The \
+             pull request is the one recorded by `git dx pr`, if any, else the open one that \
+             the code host has for the branch.",
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_TARGET)
+                .help("Source commit or target branch whose pull request to open")
+                .default_value("HEAD")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_PRINT)
+                .help("Print the URL instead of opening it")
+                .long("--print"),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_REMOTE)
+                .help("Remote that the pull request is on")
+                .short("-r")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name(CLI_ARG_FORK)
+                .help("Remote that holds integration branches, if not the `-r` remote")
+                .value_name("remote")
+                .long("--fork")
+                .takes_value(true),
+        )
+}
+
+pub fn run(git: &mut GitStore, config: &Config, matches: &clap::ArgMatches) -> err::Result<()> {
+    let remote = crate::remote_name(git, matches.value_of(CLI_ARG_REMOTE))?;
+    let remotes = crate::Remotes::new(&remote, matches.value_of(CLI_ARG_FORK));
+    let target = matches.value_of(CLI_ARG_TARGET).unwrap();
+    let branch = target_branch(git, config, &remotes, target)?;
+    let forge = forge::for_remote(git, remotes.read)?
+        .ok_or_else(|| err::Error::NoForge(remotes.read.to_string()))?;
+    let url = match crate::pr::recorded(git, &branch)? {
+        Some(number) => forge.pull_request_url(number),
+        None => {
+            let head_owner = forge::head_owner(git, forge.as_ref(), &remotes)?;
+            forge
+                .find_pull_request(&head_owner, &branch)?
+                .ok_or(err::Error::NoPullRequest(branch))?
+                .url
+        }
+    };
+    if matches.is_present(CLI_ARG_PRINT) {
+        println!("{}", url);
+    } else {
+        git.web_browse(&url)?;
+    }
+    Ok(())
+}
+
+/// Resolve `target` to a target branch: that of the commit it names, if that has a branch
+/// directive, else `target` itself if it is a remote target branch.
+fn target_branch(
+    git: &mut GitStore,
+    config: &Config,
+    remotes: &crate::Remotes,
+    target: &str,
+) -> err::Result<String> {
+    if let Some(oid) = git.rev_parse_commit(target)? {
+        let commit = git.commit(&oid)?.clone();
+        if let Some(branch) = crate::branch_name(git, config, &commit.oid, &commit.trailers)? {
+            return Ok(branch);
+        }
+        if remotes.target_oid(git, target)?.is_none() {
+            return Err(err::Error::MissingTrailer {
+                oid: commit.oid.to_string(),
+                key: config.branch_trailer.clone(),
+            });
+        }
+    } else if remotes.target_oid(git, target)?.is_none() {
+        return Err(err::Error::NoSuchCommit(target.to_string()));
+    }
+    Ok(target.to_string())
+}
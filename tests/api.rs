@@ -0,0 +1,130 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use git_dx::cancel::Token;
+use git_dx::err::Error;
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let out = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .expect("run git");
+    assert!(out.status.success(), "git {:?}: {:?}", args, out);
+    String::from_utf8(out.stdout).unwrap().trim().to_string()
+}
+
+/// Set up a client with a source commit for `wchargin-foo`, and a server to integrate it into.
+fn set_up(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("git-dx-api-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    git(&root, &["init", "--quiet", "--bare", "server"]);
+    git(&root, &["init", "--quiet", "client"]);
+    let client = root.join("client");
+    let server = root.join("server");
+    git(
+        &client,
+        &["remote", "add", "origin", server.to_str().unwrap()],
+    );
+    std::fs::write(client.join("a"), "a\n").unwrap();
+    git(&client, &["add", "a"]);
+    git(&client, &["commit", "--quiet", "-m", "Create a"]);
+    git(&client, &["push", "--quiet", "origin", "master"]);
+    git(&client, &["remote", "set-head", "origin", "master"]);
+    std::fs::write(client.join("b"), "b\n").unwrap();
+    git(&client, &["add", "b"]);
+    git(
+        &client,
+        &[
+            "commit",
+            "--quiet",
+            "-m",
+            "Create b",
+            "-m",
+            "wchargin-branch: foo",
+        ],
+    );
+    client
+}
+
+#[cfg(unix)]
+#[test]
+fn integrate_and_cancel() {
+    for (key, value) in [
+        ("GIT_AUTHOR_NAME", "A U Thor"),
+        ("GIT_AUTHOR_EMAIL", "author@example.com"),
+        ("GIT_COMMITTER_NAME", "C O Mitter"),
+        ("GIT_COMMITTER_EMAIL", "committer@example.com"),
+        ("GIT_CONFIG_NOSYSTEM", "1"),
+    ] {
+        std::env::set_var(key, value);
+    }
+    std::env::set_var("HOME", std::env::temp_dir());
+
+    // A cancelled token stops the run before it changes anything.
+    let client = set_up("cancelled");
+    let head = git(&client, &["rev-parse", "HEAD"]);
+    let token = Token::new();
+    token.cancel();
+    let result = git_dx::api::integrate(&client, &["--push"], &token);
+    assert!(
+        matches!(result.as_ref().map_err(Error::root), Err(Error::Cancelled)),
+        "{:?}",
+        result
+    );
+    assert_eq!(git(&client, &["rev-parse", "HEAD"]), head);
+    assert_eq!(git(&client, &["symbolic-ref", "HEAD"]), "refs/heads/master");
+
+    // Cancelling partway kills the running command and returns to the original `HEAD`.
+    use std::os::unix::fs::PermissionsExt;
+    let hook = client.join(".git/dx/hooks/pre-integrate");
+    std::fs::create_dir_all(hook.parent().unwrap()).unwrap();
+    std::fs::write(&hook, "#!/bin/sh\nexec sleep 10\n").unwrap();
+    std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let token = Token::new();
+    let canceller = token.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(200));
+        canceller.cancel();
+    });
+    let start = Instant::now();
+    let result = git_dx::api::integrate(&client, &["--push"], &token);
+    assert!(
+        matches!(result.as_ref().map_err(Error::root), Err(Error::Cancelled)),
+        "{:?}",
+        result
+    );
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(git(&client, &["symbolic-ref", "HEAD"]), "refs/heads/master");
+    std::fs::remove_file(&hook).unwrap();
+
+    // Otherwise, it runs as the command would.
+    let lines = git_dx::api::integrate(&client, &["--push"], &Token::new()).unwrap();
+    assert_eq!(lines.len(), 1);
+    assert!(
+        lines[0].starts_with(&format!("pushed\t{}\twchargin-foo\t", head)),
+        "{:?}",
+        lines
+    );
+    git(
+        &client,
+        &["rev-parse", "--verify", "refs/remotes/origin/wchargin-foo"],
+    );
+
+    let result = git_dx::api::integrate(&client, &["HEAD", "HEAD~"], &Token::new());
+    assert!(
+        matches!(result, Err(Error::InvalidArguments(_))),
+        "{:?}",
+        result
+    );
+    let result = git_dx::api::integrate(&client, &["--bogus"], &Token::new());
+    assert!(
+        matches!(result, Err(Error::InvalidArguments(_))),
+        "{:?}",
+        result
+    );
+
+    let _ = std::fs::remove_dir_all(client.parent().unwrap());
+}